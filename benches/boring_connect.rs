@@ -0,0 +1,89 @@
+//! Measures the cost of opening new TLS connections with an impersonated
+//! profile, against a local server.
+//!
+//! Every request uses a fresh connection and session resumption is turned
+//! off, so each iteration pays for a full handshake plus whatever the client
+//! does to prepare it.
+//!
+//! ```text
+//! cargo bench --bench boring_connect
+//! ```
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use boring::ssl::{SslAcceptor, SslMethod};
+use chromimic::impersonate::Impersonate;
+
+#[path = "../tests/support/cert.rs"]
+mod cert;
+
+const WARMUP: usize = 20;
+const ITERATIONS: usize = 500;
+
+async fn serve() -> SocketAddr {
+    let (cert, key) = cert::self_signed();
+
+    let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+    acceptor.set_certificate(&cert).unwrap();
+    acceptor.set_private_key(&key).unwrap();
+    let acceptor = Arc::new(acceptor.build());
+
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (tcp, _) = listener.accept().await.unwrap();
+            tcp.set_nodelay(true).unwrap();
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let tls = match tokio_boring::accept(&acceptor, tcp).await {
+                    Ok(tls) => tls,
+                    Err(_) => return,
+                };
+                let svc = hyper::service::service_fn(|_| async {
+                    Ok::<_, Infallible>(hyper::Response::new(hyper::Body::empty()))
+                });
+                let _ = hyper::server::conn::Http::new()
+                    .serve_connection(tls, svc)
+                    .await;
+            });
+        }
+    });
+    addr
+}
+
+#[tokio::main]
+async fn main() {
+    let addr = serve().await;
+    let url = format!("https://{}/", addr);
+
+    for ver in [Impersonate::Chrome120, Impersonate::Safari17_2_1] {
+        let client = chromimic::Client::builder()
+            .impersonate(ver)
+            .danger_accept_invalid_certs(true)
+            .tls_session_resumption(false)
+            .pool_max_idle_per_host(0)
+            .tcp_nodelay(true)
+            .no_proxy()
+            .build()
+            .unwrap();
+
+        let mut elapsed = Duration::ZERO;
+        for i in 0..WARMUP + ITERATIONS {
+            let start = Instant::now();
+            client.get(&url).send().await.unwrap();
+            if i >= WARMUP {
+                elapsed += start.elapsed();
+            }
+        }
+
+        println!(
+            "{:<16} {:>8.1} µs/connection",
+            ver.to_string(),
+            elapsed.as_secs_f64() * 1e6 / ITERATIONS as f64
+        );
+    }
+}
@@ -1,4 +1,6 @@
 #![deny(warnings)]
+#[cfg(feature = "http3")]
+use chromimic as reqwest;
 
 // This is using the `tokio` runtime. You'll need the following dependency:
 //
@@ -12,6 +14,7 @@ async fn main() -> Result<(), reqwest::Error> {
 
     async fn get<T: IntoUrl + Clone>(url: T) -> reqwest::Result<Response> {
         Client::builder()
+            .use_rustls_tls()
             .http3_prior_knowledge()
             .build()?
             .get(url)
@@ -1,9 +1,11 @@
 #[cfg(any(feature = "native-tls", feature = "__rustls",))]
 use std::any::Any;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
-use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::{Arc, Weak};
 use std::time::Duration;
-use std::{collections::HashMap, convert::TryInto, net::SocketAddr};
+use std::collections::{HashMap, VecDeque};
+use std::{convert::TryInto, net::SocketAddr};
 use std::{fmt, str};
 
 use bytes::Bytes;
@@ -27,30 +29,44 @@ use super::request::{Request, RequestBuilder};
 use super::response::Response;
 use super::Body;
 #[cfg(feature = "http3")]
-use crate::async_impl::h3_client::connect::H3Connector;
+use crate::async_impl::h3_client::connect::{H3Connector, NoUdpRelay};
 #[cfg(feature = "http3")]
 use crate::async_impl::h3_client::{H3Client, H3ResponseFuture};
-use crate::connect::{Connector, ImpersonateContext};
+#[cfg(feature = "__boring")]
+use crate::connect::BoringTlsOptions;
+use crate::connect::{is_proxy_error, Connector, ImpersonateContext};
 #[cfg(feature = "cookies")]
 use crate::cookie;
+#[cfg(feature = "digest-auth")]
+use crate::digest;
 #[cfg(feature = "hickory-dns")]
 use crate::dns::hickory::HickoryDnsResolver;
-use crate::dns::{gai::GaiResolver, DnsResolverWithOverrides, DynResolver, Resolve};
+#[cfg(feature = "hickory-dns")]
+use crate::dns::HickoryConfig;
+use crate::dns::{
+    gai::GaiResolver, DnsResolverWithIpPreference, DnsResolverWithOverrides, DynResolver,
+    IpPreference, Resolve,
+};
 use crate::error;
 #[cfg(feature = "impersonate")]
 use crate::impersonate::profile::ClientProfile;
 #[cfg(feature = "__impersonate")]
-use crate::impersonate::{configure_impersonate, Impersonate};
+use crate::impersonate::{
+    configure_impersonate, get_config_from_ver, ApplicationSettings, Impersonate,
+};
 use crate::into_url::{expect_uri, try_uri};
+use crate::pac::Owner;
+use crate::proxy_pool::Pick;
 use crate::redirect::{self, remove_sensitive_headers};
 #[cfg(feature = "__tls")]
 use crate::tls::{self, TlsBackend};
 #[cfg(feature = "__tls")]
 use crate::Certificate;
-#[cfg(any(feature = "native-tls", feature = "__rustls"))]
+#[cfg(any(feature = "native-tls", feature = "__rustls", feature = "__boring"))]
 use crate::Identity;
-use crate::{IntoUrl, Method, Proxy, StatusCode, Url};
+use crate::{IntoUrl, Method, Proxy, ProxyPool, StatusCode, Url};
 use log::{debug, trace};
+use once_cell::sync::OnceCell;
 #[cfg(feature = "http3")]
 use quinn::TransportConfig;
 #[cfg(feature = "http3")]
@@ -74,12 +90,30 @@ pub struct Client {
     inner: Arc<ClientRef>,
 }
 
+/// A `Client` that doesn't keep it alive, for the parts of it that send
+/// requests with it.
+#[derive(Clone)]
+pub(crate) struct WeakClient {
+    inner: Weak<ClientRef>,
+}
+
+impl WeakClient {
+    pub(crate) fn upgrade(&self) -> Option<Client> {
+        self.inner.upgrade().map(|inner| Client { inner })
+    }
+}
+
 /// A `ClientBuilder` can be used to create a `Client` with custom configuration.
 #[must_use]
 pub struct ClientBuilder {
     config: Config,
+    error: Option<crate::Error>,
+    // The client this builder was derived from, whose connections are
+    // reused if the transport settings are left unchanged.
+    base: Option<Arc<ClientRef>>,
 }
 
+#[derive(Clone, Copy, PartialEq)]
 enum HttpVersionPref {
     Http1,
     Http2,
@@ -88,10 +122,16 @@ enum HttpVersionPref {
     All,
 }
 
+#[derive(Clone)]
 struct Config {
     // NOTE: When adding a new field, update `fmt::Debug for ClientBuilder`
+    // and, if it affects connections, `Config::same_transport`
     accepts: Accepts,
     headers: HeaderMap,
+    // The part of `headers` set with `user_agent` and `default_headers`,
+    // which per-request profiles keep.
+    #[cfg(feature = "impersonate")]
+    user_headers: HeaderMap,
     #[cfg(feature = "native-tls")]
     hostname_verification: bool,
     #[cfg(feature = "__tls")]
@@ -103,9 +143,10 @@ struct Config {
     pool_idle_timeout: Option<Duration>,
     pool_max_idle_per_host: usize,
     tcp_keepalive: Option<Duration>,
-    #[cfg(any(feature = "native-tls", feature = "__rustls"))]
+    #[cfg(any(feature = "native-tls", feature = "__rustls", feature = "__boring"))]
     identity: Option<Identity>,
     proxies: Vec<Proxy>,
+    proxy_pool: Option<ProxyPool>,
     auto_sys_proxy: bool,
     redirect_policy: redirect::Policy,
     referer: bool,
@@ -122,6 +163,12 @@ struct Config {
     tls_info: bool,
     #[cfg(feature = "__tls")]
     tls: TlsBackend,
+    #[cfg(feature = "__tls")]
+    certificate_pins: tls::CertificatePins,
+    #[cfg(feature = "__boring")]
+    tls_session_resumption: bool,
+    #[cfg(feature = "__boring")]
+    tls_session_cache: Option<tls::TlsSessionCache>,
     http_version_pref: HttpVersionPref,
     http09_responses: bool,
     http1_title_case_headers: bool,
@@ -145,8 +192,11 @@ struct Config {
     #[cfg(feature = "cookies")]
     cookie_store: Option<Arc<dyn cookie::CookieStore>>,
     hickory_dns: bool,
-    error: Option<crate::Error>,
+    #[cfg(feature = "hickory-dns")]
+    hickory_config: Option<HickoryConfig>,
     https_only: bool,
+    #[cfg(feature = "digest-auth")]
+    digest_auth: digest::ClientCredentials,
     #[cfg(feature = "http3")]
     tls_enable_early_data: bool,
     #[cfg(feature = "http3")]
@@ -157,14 +207,31 @@ struct Config {
     quic_receive_window: Option<VarInt>,
     #[cfg(feature = "http3")]
     quic_send_window: Option<u64>,
+    #[cfg(feature = "http3")]
+    http3_proxy_fallback: bool,
     dns_overrides: HashMap<String, Vec<SocketAddr>>,
     dns_resolver: Option<Arc<dyn Resolve>>,
+    // The resolver built from the DNS settings above, only set on the
+    // snapshot a client keeps to share it with clients derived from it.
+    built_resolver: Option<Arc<dyn Resolve>>,
+    https_dns_records: bool,
+    ip_preference: Option<IpPreference>,
     #[cfg(feature = "impersonate")]
     profile: ClientProfile,
     #[cfg(feature = "impersonate")]
+    impersonate: Option<Impersonate>,
+    #[cfg(feature = "impersonate")]
     enable_ech_grease: bool,
     #[cfg(feature = "impersonate")]
+    ech_configs: tls::EchConfigs,
+    #[cfg(feature = "impersonate")]
     permute_extensions: bool,
+    #[cfg(feature = "impersonate")]
+    application_settings: Option<ApplicationSettings>,
+    #[cfg(feature = "impersonate")]
+    tls_early_data: Option<bool>,
+    #[cfg(feature = "impersonate")]
+    profile_early_data: bool,
 }
 
 impl Default for ClientBuilder {
@@ -183,9 +250,10 @@ impl ClientBuilder {
 
         ClientBuilder {
             config: Config {
-                error: None,
                 accepts: Accepts::default(),
                 headers,
+                #[cfg(feature = "impersonate")]
+                user_headers: HeaderMap::new(),
                 #[cfg(feature = "native-tls")]
                 hostname_verification: true,
                 #[cfg(feature = "__tls")]
@@ -200,6 +268,7 @@ impl ClientBuilder {
                 // to no longer error when an option fails.
                 tcp_keepalive: None, //Some(Duration::from_secs(60)),
                 proxies: Vec::new(),
+                proxy_pool: None,
                 auto_sys_proxy: true,
                 redirect_policy: redirect::Policy::default(),
                 referer: true,
@@ -208,7 +277,7 @@ impl ClientBuilder {
                 root_certs: Vec::new(),
                 #[cfg(feature = "__tls")]
                 tls_built_in_root_certs: true,
-                #[cfg(any(feature = "native-tls", feature = "__rustls"))]
+                #[cfg(any(feature = "native-tls", feature = "__rustls", feature = "__boring"))]
                 identity: None,
                 #[cfg(feature = "__tls")]
                 min_tls_version: None,
@@ -218,6 +287,12 @@ impl ClientBuilder {
                 tls_info: false,
                 #[cfg(feature = "__tls")]
                 tls: TlsBackend::default(),
+                #[cfg(feature = "__tls")]
+                certificate_pins: tls::CertificatePins::default(),
+                #[cfg(feature = "__boring")]
+                tls_session_resumption: true,
+                #[cfg(feature = "__boring")]
+                tls_session_cache: None,
                 http_version_pref: HttpVersionPref::All,
                 http09_responses: false,
                 http1_title_case_headers: false,
@@ -239,9 +314,13 @@ impl ClientBuilder {
                 local_address_ipv4: None,
                 nodelay: true,
                 hickory_dns: cfg!(feature = "hickory-dns"),
+                #[cfg(feature = "hickory-dns")]
+                hickory_config: None,
                 #[cfg(feature = "cookies")]
                 cookie_store: None,
                 https_only: false,
+                #[cfg(feature = "digest-auth")]
+                digest_auth: digest::ClientCredentials::default(),
                 dns_overrides: HashMap::new(),
                 #[cfg(feature = "http3")]
                 tls_enable_early_data: false,
@@ -253,14 +332,31 @@ impl ClientBuilder {
                 quic_receive_window: None,
                 #[cfg(feature = "http3")]
                 quic_send_window: None,
+                #[cfg(feature = "http3")]
+                http3_proxy_fallback: false,
                 dns_resolver: None,
+                built_resolver: None,
+                https_dns_records: true,
+                ip_preference: None,
                 #[cfg(feature = "impersonate")]
                 profile: ClientProfile::Chrome,
                 #[cfg(feature = "impersonate")]
+                impersonate: None,
+                #[cfg(feature = "impersonate")]
                 enable_ech_grease: false,
                 #[cfg(feature = "impersonate")]
+                ech_configs: tls::EchConfigs::default(),
+                #[cfg(feature = "impersonate")]
                 permute_extensions: false,
+                #[cfg(feature = "impersonate")]
+                application_settings: None,
+                #[cfg(feature = "impersonate")]
+                tls_early_data: None,
+                #[cfg(feature = "impersonate")]
+                profile_early_data: false,
             },
+            error: None,
+            base: None,
         }
     }
 
@@ -268,6 +364,7 @@ impl ClientBuilder {
     #[cfg(feature = "__impersonate")]
     pub fn impersonate(mut self, ver: Impersonate) -> ClientBuilder {
         self.config.profile = ver.profile();
+        self.config.impersonate = Some(ver);
         configure_impersonate(ver, self)
     }
 
@@ -275,17 +372,50 @@ impl ClientBuilder {
     #[cfg(feature = "__impersonate")]
     pub fn impersonate_websocket(mut self, ver: Impersonate) -> ClientBuilder {
         self.config.profile = ver.profile();
+        self.config.impersonate = Some(ver);
         self = self.http1_only();
         configure_impersonate(ver, self)
     }
 
-    /// Enable Encrypted Client Hello (Secure SNI)
+    /// Send a GREASE Encrypted Client Hello extension, as Chrome does.
+    ///
+    /// The extension looks like ECH but doesn't hide anything: the server
+    /// name is still sent in clear. Use
+    /// [`ech_config`](ClientBuilder::ech_config) to actually encrypt it.
     #[cfg(feature = "__impersonate")]
     pub fn enable_ech_grease(mut self) -> ClientBuilder {
         self.config.enable_ech_grease = true;
         self
     }
 
+    /// Encrypt the ClientHello sent to `host` with Encrypted Client Hello.
+    ///
+    /// `config_list` is the host's `ECHConfigList`, as published in the
+    /// `ech` parameter of its DNS HTTPS record. The server name and the
+    /// rest of the ClientHello are then only visible to the server, while
+    /// on-path observers see the public name from the config.
+    ///
+    /// Like Chrome, once a config is given, connections to hosts without
+    /// one send [GREASE](ClientBuilder::enable_ech_grease) instead. If a
+    /// server rejects ECH, for instance because its keys were rotated, the
+    /// connection is retried once with the configs it sent back, or without
+    /// ECH if it has turned it off.
+    ///
+    /// ECH isn't supported over HTTP/3.
+    ///
+    /// # Errors
+    ///
+    /// Building the client fails if `host` isn't a domain name or
+    /// `config_list` isn't an `ECHConfigList`, or if the client only speaks
+    /// HTTP/3.
+    #[cfg(feature = "__impersonate")]
+    pub fn ech_config(mut self, host: &str, config_list: impl Into<Vec<u8>>) -> ClientBuilder {
+        if let Err(e) = self.config.ech_configs.add(host, config_list.into()) {
+            self.error = Some(e);
+        }
+        self
+    }
+
     /// Enable TLS permute_extensions
     #[cfg(feature = "__impersonate")]
     pub fn permute_extensions(mut self) -> ClientBuilder {
@@ -293,6 +423,49 @@ impl ClientBuilder {
         self
     }
 
+    /// Send the first request of resumed TLS 1.3 connections as early data.
+    ///
+    /// This saves a round trip on connections to hosts the client has
+    /// talked to before, using a session from the
+    /// [session cache](ClientBuilder::tls_session_cache). Only connections
+    /// opened for `GET`, `HEAD` and `OPTIONS` requests that don't go through
+    /// a proxy, HTTP or SOCKS, send early data, and they're pooled apart from
+    /// the others so no other request is ever sent first on them. Responses
+    /// on them carry a [`tls::EarlyData`] extension telling whether the
+    /// server accepted it; if it didn't, the request was sent again after the
+    /// handshake.
+    ///
+    /// Early data can be replayed by an attacker, so servers may act on such
+    /// a request more than once.
+    ///
+    /// Defaults to what the impersonated browser does, so it's on for the
+    /// Chrome and Edge profiles, including ones set for a single request.
+    /// This setting overrides the profile, whether it's set before or after
+    /// [`ClientBuilder::impersonate`], and applies to per-request profiles
+    /// too.
+    #[cfg(feature = "__impersonate")]
+    pub fn tls_early_data(mut self, enabled: bool) -> ClientBuilder {
+        self.config.tls_early_data = Some(enabled);
+        self
+    }
+
+    /// Whether the impersonated browser sends early data, unless
+    /// [`ClientBuilder::tls_early_data`] says otherwise.
+    #[cfg(feature = "__impersonate")]
+    pub(crate) fn profile_early_data(mut self, enabled: bool) -> ClientBuilder {
+        self.config.profile_early_data = enabled;
+        self
+    }
+
+    #[cfg(feature = "__impersonate")]
+    pub(crate) fn application_settings(
+        mut self,
+        settings: Option<ApplicationSettings>,
+    ) -> ClientBuilder {
+        self.config.application_settings = settings;
+        self
+    }
+
     /// Returns a `Client` that uses this `ClientBuilder` configuration.
     ///
     /// # Errors
@@ -302,14 +475,95 @@ impl ClientBuilder {
     pub fn build(self) -> crate::Result<Client> {
         let config = self.config;
 
-        if let Some(err) = config.error {
+        if let Some(err) = self.error {
             return Err(err);
         }
 
+        #[cfg(feature = "impersonate")]
+        if let Some(ref settings) = config.application_settings {
+            settings.check()?;
+        }
+
+        #[cfg(feature = "http3")]
+        if config.http_version_pref == HttpVersionPref::Http3 && !config.certificate_pins.is_empty()
+        {
+            return Err(crate::error::builder(
+                "certificate pins aren't supported over HTTP/3",
+            ));
+        }
+
+        #[cfg(feature = "http3")]
+        if config.http_version_pref == HttpVersionPref::Http3 && config.proxy_pool.is_some() {
+            return Err(crate::error::builder("proxy pools aren't supported over HTTP/3"));
+        }
+
+        #[cfg(all(feature = "http3", feature = "impersonate"))]
+        if config.http_version_pref == HttpVersionPref::Http3 && !config.ech_configs.is_empty() {
+            return Err(crate::error::builder("ECH isn't supported over HTTP/3"));
+        }
+
+        // It wouldn't be used.
+        #[cfg(feature = "hickory-dns")]
+        if config.dns_resolver.is_some() && config.hickory_config.is_some() {
+            return Err(crate::error::builder(
+                "hickory_config can't be used with a dns_resolver",
+            ));
+        }
+
+        // A derived client with unchanged transport settings shares the
+        // connection pools of the client it was derived from.
+        if let Some(base) = self.base.as_ref().filter(|base| config.same_transport(&base.config)) {
+            return Ok(Client {
+                inner: Arc::new(ClientRef {
+                    accepts: config.accepts,
+                    #[cfg(feature = "cookies")]
+                    cookie_store: config.cookie_store.clone(),
+                    #[cfg(feature = "http3")]
+                    h3_client: base.h3_client.clone(),
+                    hyper: base.hyper.clone(),
+                    headers: config.headers.clone(),
+                    redirect_policy: config.redirect_policy.clone(),
+                    referer: config.referer,
+                    request_timeout: config.timeout,
+                    proxies: base.proxies.clone(),
+                    proxies_maybe_http_headers: base.proxies_maybe_http_headers,
+                    proxy_pool: config.proxy_pool.clone(),
+                    https_only: config.https_only,
+                    #[cfg(feature = "digest-auth")]
+                    digest_sessions: digest::Sessions::default(),
+                    #[cfg(feature = "impersonate")]
+                    impersonate: base.impersonate.clone(),
+                    routed: base.routed.clone(),
+                    config,
+                }),
+            });
+        }
+
+        // Clients derived with the same DNS settings share the resolver, and
+        // any cache it holds.
+        let base_resolver = self
+            .base
+            .as_ref()
+            .filter(|base| config.same_resolver(&base.config))
+            .and_then(|base| base.config.built_resolver.clone());
+        let resolver = match base_resolver {
+            Some(resolver) => resolver,
+            None => config.build_resolver(),
+        };
+
+        // Kept to derive new clients from this one.
+        let mut snapshot = config.clone();
+
         let mut proxies = config.proxies;
         if config.auto_sys_proxy {
             proxies.push(Proxy::system());
         }
+        // PAC scripts resolve hosts, and are fetched, with this client.
+        let pac_client = Arc::new(OnceCell::new());
+        let pac_owner = Owner::new(DynResolver::new(resolver.clone()), pac_client.clone());
+        for proxy in &mut proxies {
+            proxy.set_pac_owner(&pac_owner);
+        }
         let proxies = Arc::new(proxies);
 
         #[allow(unused)]
@@ -322,24 +576,12 @@ impl ClientBuilder {
                 headers.get(USER_AGENT).cloned()
             }
 
-            let mut resolver: Arc<dyn Resolve> = match config.hickory_dns {
-                false => Arc::new(GaiResolver::new()),
-                #[cfg(feature = "hickory-dns")]
-                true => Arc::new(HickoryDnsResolver::default()),
-                #[cfg(not(feature = "hickory-dns"))]
-                true => unreachable!("hickory-dns shouldn't be enabled unless the feature is"),
-            };
-            if let Some(dns_resolver) = config.dns_resolver {
-                resolver = dns_resolver;
-            }
-            if !config.dns_overrides.is_empty() {
-                resolver = Arc::new(DnsResolverWithOverrides::new(
-                    resolver,
-                    config.dns_overrides,
-                ));
-            }
+            snapshot.built_resolver = Some(resolver.clone());
             let mut http = HttpConnector::new_with_resolver(DynResolver::new(resolver.clone()));
             http.set_connect_timeout(config.connect_timeout);
+            if let Some(preference) = config.ip_preference {
+                http.set_happy_eyeballs_timeout(preference.happy_eyeballs_timeout());
+            }
 
             #[cfg(all(feature = "http3", feature = "__rustls"))]
             let build_h3_connector =
@@ -351,7 +593,9 @@ impl ClientBuilder {
                  quic_send_window,
                  local_address_v4: Option<Ipv4Addr>,
                  local_address_v6: Option<Ipv6Addr>,
-                 http_version_pref: &HttpVersionPref| {
+                 http_version_pref: &HttpVersionPref,
+                 https_records: bool,
+                 proxies: Arc<Vec<Proxy>>| {
                     let mut transport_config = TransportConfig::default();
 
                     if let Some(max_idle_timeout) = quic_max_idle_timeout {
@@ -381,6 +625,8 @@ impl ClientBuilder {
                         tls,
                         local_address,
                         transport_config,
+                        https_records,
+                        proxies,
                     );
 
                     match res {
@@ -399,45 +645,52 @@ impl ClientBuilder {
             match config.tls {
                 #[cfg(feature = "__boring")]
                 TlsBackend::BoringTls(tls) => {
-                    #[cfg(feature = "boring-tls-native-roots")]
-                    let tls = Arc::new(move |h2: bool| {
-                        let mut builder = tls.clone()(h2);
+                    let tls = boring_tls_with_native_roots(tls);
 
-                        use boring::x509::X509;
-                        let certs = rustls_native_certs::load_native_certs().unwrap();
-
-                        let cert_store = builder.cert_store_mut();
-
-                        for certificate in certs {
-                            // I know this is bad but what do I have to do else
-
-                            let boring_cert = X509::from_der(&certificate.as_ref()).unwrap();
-
-                            cert_store.add_cert(boring_cert).unwrap();
-                        }
-                        builder
-                    });
+                    let sessions = if config.tls_session_resumption {
+                        Some(config.tls_session_cache.clone().unwrap_or_default())
+                    } else {
+                        None
+                    };
 
-                    let connector = Connector::new_boring_tls(
-                        http,
+                    let options = BoringTlsOptions {
                         tls,
-                        proxies.clone(),
-                        user_agent(&config.headers),
-                        config.local_address_ipv4,
-                        config.local_address_ipv6,
-                        config.nodelay,
-                        config.tls_info,
-                        ImpersonateContext {
+                        sessions,
+                        tls_info: config.tls_info,
+                        impersonate_context: ImpersonateContext {
                             profile: config.profile,
+                            impersonate: config.impersonate,
                             certs_verification: config.certs_verification,
                             enable_ech_grease: config.enable_ech_grease,
                             permute_extensions: config.permute_extensions,
+                            application_settings: config.application_settings,
+                            early_data: config
+                                .tls_early_data
+                                .unwrap_or(config.profile_early_data),
+                            ech_configs: Arc::new(config.ech_configs),
                             h2: match config.http_version_pref {
                                 HttpVersionPref::Http1 => false,
                                 HttpVersionPref::Http2 | HttpVersionPref::All => true,
+                                #[cfg(feature = "http3")]
+                                HttpVersionPref::Http3 => true,
                             },
                         },
+                    };
+                    let mut connector = Connector::new_boring_tls(
+                        http,
+                        options,
+                        proxies.clone(),
+                        user_agent(&config.headers),
+                        config.local_address_ipv4,
+                        config.local_address_ipv6,
+                        config.nodelay,
                     );
+                    if let Some(id) = config.identity {
+                        connector.set_boring_identity(id.into_boring()?);
+                    }
+                    if config.https_dns_records {
+                        connector.set_https_records(DynResolver::new(resolver.clone()));
+                    }
 
                     connector
                 }
@@ -546,6 +799,8 @@ impl ClientBuilder {
                             config.local_address_ipv4,
                             config.local_address_ipv6,
                             &config.http_version_pref,
+                            config.https_dns_records,
+                            proxies.clone(),
                         )?;
                     }
 
@@ -683,9 +938,13 @@ impl ClientBuilder {
                     {
                         tls.enable_early_data = config.tls_enable_early_data;
 
+                        // Requests upgraded to HTTP/3 by HTTPS records go
+                        // over QUIC too, whatever is offered over TCP.
+                        let mut h3_tls = tls.clone();
+                        h3_tls.alpn_protocols = vec!["h3".into()];
                         h3_connector = build_h3_connector(
                             resolver,
-                            tls.clone(),
+                            h3_tls,
                             config.quic_max_idle_timeout,
                             config.quic_stream_receive_window,
                             config.quic_receive_window,
@@ -693,6 +952,8 @@ impl ClientBuilder {
                             config.local_address_ipv4,
                             config.local_address_ipv6,
                             &config.http_version_pref,
+                            config.https_dns_records,
+                            proxies.clone(),
                         )?;
                     }
 
@@ -721,38 +982,34 @@ impl ClientBuilder {
 
         connector.set_timeout(config.connect_timeout);
         connector.set_verbose(config.connection_verbose);
+        #[cfg(feature = "socks")]
+        connector.set_ip_preference(config.ip_preference);
+        #[cfg(feature = "socks")]
+        connector.set_socks_resolver(DynResolver::new(resolver.clone()));
+        // QUIC handshakes don't check pins, so pinned clients stick to TCP,
+        // whatever the server advertises.
+        #[cfg(feature = "http3")]
+        if !config.certificate_pins.is_empty() {
+            h3_connector = None;
+        }
+        #[cfg(feature = "http3")]
+        if config.http_version_pref == HttpVersionPref::Http3 && h3_connector.is_none() {
+            return Err(crate::error::builder("HTTP/3 requires the rustls backend"));
+        }
+        #[cfg(feature = "__tls")]
+        connector.set_certificate_pins(config.certificate_pins);
 
         let mut builder = hyper::Client::builder();
         if matches!(config.http_version_pref, HttpVersionPref::Http2) {
             builder.http2_only(true);
         }
 
-        if let Some(http2_initial_stream_window_size) = config.http2_initial_stream_window_size {
-            builder.http2_initial_stream_window_size(http2_initial_stream_window_size);
-        }
-        if let Some(http2_initial_connection_window_size) =
-            config.http2_initial_connection_window_size
-        {
-            builder.http2_initial_connection_window_size(http2_initial_connection_window_size);
-        }
         if config.http2_adaptive_window {
             builder.http2_adaptive_window(true);
         }
         if let Some(http2_max_frame_size) = config.http2_max_frame_size {
             builder.http2_max_frame_size(http2_max_frame_size);
         }
-        if let Some(max) = config.http2_max_concurrent_streams {
-            builder.http2_max_concurrent_streams(max);
-        }
-        if let Some(max) = config.http2_max_header_list_size {
-            builder.http2_max_header_list_size(max);
-        }
-        if let Some(opt) = config.http2_enable_push {
-            builder.http2_enable_push(opt);
-        }
-        if let Some(max) = config.http2_header_table_size {
-            builder.http2_header_table_size(max);
-        }
         if let Some(http2_keep_alive_interval) = config.http2_keep_alive_interval {
             builder.http2_keep_alive_interval(http2_keep_alive_interval);
         }
@@ -763,7 +1020,6 @@ impl ClientBuilder {
             builder.http2_keep_alive_while_idle(true);
         }
 
-        builder.http2_agent_profile(config.profile.into());
         builder.pool_idle_timeout(config.pool_idle_timeout);
         builder.pool_max_idle_per_host(config.pool_max_idle_per_host);
         connector.set_keepalive(config.tcp_keepalive);
@@ -788,9 +1044,55 @@ impl ClientBuilder {
             builder.http1_allow_spaces_after_header_name_in_responses(true);
         }
 
-        let proxies_maybe_http_auth = proxies.iter().any(|p| p.maybe_has_http_auth());
+        // Every proxy of the pool keeps its client, whatever other routes
+        // requests take.
+        let routed_capacity =
+            MAX_ROUTED_CLIENTS + config.proxy_pool.as_ref().map_or(0, ProxyPool::len);
+
+        // Per-request profiles start from the shared settings above and
+        // apply their own HTTP/2 fingerprint.
+        #[cfg(feature = "impersonate")]
+        let impersonate = Arc::new(ImpersonateClients {
+            connector: connector.clone(),
+            builder: builder.clone(),
+            routed_capacity,
+            early_data: config.tls_early_data,
+            clients: Mutex::new(HashMap::new()),
+        });
+
+        if let Some(http2_initial_stream_window_size) = config.http2_initial_stream_window_size {
+            builder.http2_initial_stream_window_size(http2_initial_stream_window_size);
+        }
+        if let Some(http2_initial_connection_window_size) =
+            config.http2_initial_connection_window_size
+        {
+            builder.http2_initial_connection_window_size(http2_initial_connection_window_size);
+        }
+        if let Some(max) = config.http2_max_concurrent_streams {
+            builder.http2_max_concurrent_streams(max);
+        }
+        if let Some(max) = config.http2_max_header_list_size {
+            builder.http2_max_header_list_size(max);
+        }
+        if let Some(opt) = config.http2_enable_push {
+            builder.http2_enable_push(opt);
+        }
+        if let Some(max) = config.http2_header_table_size {
+            builder.http2_header_table_size(max);
+        }
+        #[cfg(feature = "impersonate")]
+        builder.http2_agent_profile(config.profile.into());
+
+        let proxies_maybe_http_headers = proxies
+            .iter()
+            .any(|p| p.maybe_has_http_auth() || p.extra_headers().is_some());
+        let routed = Arc::new(RoutedClients::new(
+            connector.clone(),
+            builder.clone(),
+            routed_capacity,
+        ));
 
-        Ok(Client {
+        let client = Client {
             inner: Arc::new(ClientRef {
                 accepts: config.accepts,
                 #[cfg(feature = "cookies")]
@@ -804,16 +1106,26 @@ impl ClientBuilder {
                     }
                     None => None,
                 },
-                hyper: builder.build(connector),
+                // Requests that may send early data use a routed client.
+                hyper: builder.build(connector.routed(None, None, false)),
                 headers: config.headers,
                 redirect_policy: config.redirect_policy,
                 referer: config.referer,
                 request_timeout: config.timeout,
                 proxies,
-                proxies_maybe_http_auth,
+                proxies_maybe_http_headers,
+                proxy_pool: config.proxy_pool,
                 https_only: config.https_only,
+                #[cfg(feature = "digest-auth")]
+                digest_sessions: digest::Sessions::default(),
+                #[cfg(feature = "impersonate")]
+                impersonate,
+                routed,
+                config: snapshot,
             }),
-        })
+        };
+        let _ = pac_client.set(client.downgrade());
+        Ok(client)
     }
 
     // Higher-level options
@@ -845,10 +1157,12 @@ impl ClientBuilder {
     {
         match value.try_into() {
             Ok(value) => {
+                #[cfg(feature = "impersonate")]
+                self.config.user_headers.insert(USER_AGENT, value.clone());
                 self.config.headers.insert(USER_AGENT, value);
             }
             Err(e) => {
-                self.config.error = Some(crate::error::builder(e.into()));
+                self.error = Some(crate::error::builder(e.into()));
             }
         };
         self
@@ -899,6 +1213,8 @@ impl ClientBuilder {
     /// ```
     pub fn default_headers(mut self, headers: HeaderMap) -> ClientBuilder {
         for (key, value) in headers.iter() {
+            #[cfg(feature = "impersonate")]
+            self.config.user_headers.insert(key, value.clone());
             self.config.headers.insert(key, value.clone());
         }
         self
@@ -906,6 +1222,8 @@ impl ClientBuilder {
 
     #[cfg(feature = "__browser_common")]
     pub(crate) fn replace_default_headers(mut self, headers: HeaderMap) -> ClientBuilder {
+        #[cfg(feature = "impersonate")]
+        self.config.user_headers.clear();
         self.config.headers = headers;
         self
     }
@@ -951,6 +1269,79 @@ impl ClientBuilder {
         self
     }
 
+    /// Answer HTTP Digest challenges with these credentials.
+    ///
+    /// A request getting a `401 Unauthorized` response with a Digest
+    /// challenge is sent again, once, with an `Authorization` header
+    /// answering it. Later requests to the origin then authorize up front,
+    /// counting their uses of the server's nonce, until it challenges them
+    /// again. MD5 and SHA-256 are supported, with or without `-sess`, and so
+    /// are the `auth` and `auth-int` qualities of protection. A request whose
+    /// body is a stream can't be sent again, so it gets the `401` response.
+    ///
+    /// Credentials set for a host with
+    /// [`ClientBuilder::digest_auth_for_host`], or for a request with
+    /// [`RequestBuilder::digest_auth`], take precedence. An `Authorization`
+    /// header set on a request is only replaced when it's challenged.
+    ///
+    /// Like an `Authorization` header, these credentials aren't used once a
+    /// request is redirected to another origin. Challenges of other schemes,
+    /// such as Negotiate, are returned to the caller.
+    ///
+    /// ```rust
+    /// # use chromimic as reqwest;
+    /// # fn run() -> Result<(), reqwest::Error> {
+    /// let client = reqwest::Client::builder()
+    ///     .digest_auth("user", "passwd")
+    ///     .build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Optional
+    ///
+    /// This requires the optional `digest-auth` feature to be enabled.
+    #[cfg(feature = "digest-auth")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "digest-auth")))]
+    pub fn digest_auth<U, P>(mut self, username: U, password: P) -> ClientBuilder
+    where
+        U: fmt::Display,
+        P: fmt::Display,
+    {
+        self.config
+            .digest_auth
+            .set_any(digest::Credentials::new(username, password));
+        self
+    }
+
+    /// Answer HTTP Digest challenges of `host` with these credentials.
+    ///
+    /// The host is matched against the URL's, whatever the port, and its
+    /// credentials take precedence over the ones set with
+    /// [`ClientBuilder::digest_auth`]. Challenges are answered as described
+    /// there.
+    ///
+    /// # Optional
+    ///
+    /// This requires the optional `digest-auth` feature to be enabled.
+    #[cfg(feature = "digest-auth")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "digest-auth")))]
+    pub fn digest_auth_for_host<U, P>(
+        mut self,
+        host: &str,
+        username: U,
+        password: P,
+    ) -> ClientBuilder
+    where
+        U: fmt::Display,
+        P: fmt::Display,
+    {
+        self.config
+            .digest_auth
+            .set_host(host, digest::Credentials::new(username, password));
+        self
+    }
+
     /// Enable auto gzip decompression by checking the `Content-Encoding` response header.
     ///
     /// If auto gzip decompression is turned on:
@@ -1155,6 +1546,25 @@ impl ClientBuilder {
         self
     }
 
+    /// Spread requests across the proxies of `pool`.
+    ///
+    /// Requests whose URL no proxy of the pool intercepts use the proxies
+    /// added with [`ClientBuilder::proxy`]. See [`ProxyPool`] for how the
+    /// proxy of each request is picked, and how failing ones are ejected.
+    ///
+    /// # Note
+    ///
+    /// Setting a pool will disable the automatic usage of the "system" proxy.
+    ///
+    /// # Errors
+    ///
+    /// Building fails if HTTP/3 was asked for.
+    pub fn proxy_pool(mut self, pool: ProxyPool) -> ClientBuilder {
+        self.config.proxy_pool = Some(pool);
+        self.config.auto_sys_proxy = false;
+        self
+    }
+
     // Timeout options
 
     /// Enables a request timeout.
@@ -1272,6 +1682,9 @@ impl ClientBuilder {
     }
 
     /// Only use HTTP/3.
+    ///
+    /// This requires the rustls backend, see
+    /// [`use_rustls_tls`](ClientBuilder::use_rustls_tls).
     #[cfg(feature = "http3")]
     #[cfg_attr(docsrs, doc(cfg(all(reqwest_unstable, feature = "http3",))))]
     pub fn http3_prior_knowledge(mut self) -> ClientBuilder {
@@ -1426,6 +1839,35 @@ impl ClientBuilder {
         self
     }
 
+    /// Sets which IP versions connections are made over, and in which order.
+    ///
+    /// Resolved addresses are filtered and ordered by `preference` for
+    /// direct connections, connections to proxies, SOCKS proxies resolving
+    /// names locally, and HTTP/3. A `socks5h` proxy resolves names itself,
+    /// so it's not affected, and a SOCKS4 proxy only reaches IPv4
+    /// addresses: it fails with `V6Only` and ignores the other preferences.
+    /// Addresses given to [`resolve`](ClientBuilder::resolve) are used as
+    /// they are.
+    ///
+    /// By default, addresses are tried in the resolver's order, racing the
+    /// other IP version after 300ms.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use chromimic as reqwest;
+    /// use reqwest::dns::IpPreference;
+    ///
+    /// let client = reqwest::Client::builder()
+    ///     .ip_version(IpPreference::V4Only)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn ip_version(mut self, preference: IpPreference) -> ClientBuilder {
+        self.config.ip_preference = Some(preference);
+        self
+    }
+
     /// Set that all sockets have `SO_KEEPALIVE` set with the supplied duration.
     ///
     /// If `None`, the option will not be set.
@@ -1488,10 +1930,17 @@ impl ClientBuilder {
     ///
     /// # Optional
     ///
-    /// This requires the optional `native-tls` or `rustls-tls(-...)` feature to be
-    /// enabled.
-    #[cfg(any(feature = "native-tls", feature = "__rustls"))]
-    #[cfg_attr(docsrs, doc(cfg(any(feature = "native-tls", feature = "rustls-tls"))))]
+    /// This requires the optional `native-tls`, `rustls-tls(-...)` or
+    /// `boring-tls(-...)` feature to be enabled.
+    #[cfg(any(feature = "native-tls", feature = "__rustls", feature = "__boring"))]
+    #[cfg_attr(
+        docsrs,
+        doc(cfg(any(
+            feature = "native-tls",
+            feature = "rustls-tls",
+            feature = "boring-tls"
+        )))
+    )]
     pub fn identity(mut self, identity: Identity) -> ClientBuilder {
         self.config.identity = Some(identity);
         self
@@ -1573,16 +2022,48 @@ impl ClientBuilder {
         self
     }
 
-    /// Set the minimum required TLS version for connections.
+    /// Pins the public keys of servers matching `host`.
     ///
-    /// By default the TLS backend's own default is used.
+    /// After every TLS handshake with a matching host, including HTTPS
+    /// proxies, the SHA-256 hash of the `SubjectPublicKeyInfo` of the
+    /// server's leaf certificate must be one of `spki_sha256`. If it isn't,
+    /// the connection is dropped and the request fails with an error for
+    /// which [`Error::is_certificate_pin`](crate::Error::is_certificate_pin)
+    /// returns `true`.
+    ///
+    /// `host` is a hostname or IP address, or a wildcard like `*.example.com`
+    /// which matches every subdomain of `example.com` but not `example.com`
+    /// itself. An exact pattern wins over wildcards, and a longer wildcard
+    /// over a shorter one. Pinning a pattern again adds to its hashes, which
+    /// allows pinning a backup key.
+    ///
+    /// Pins are checked even with
+    /// [`danger_accept_invalid_certs`](ClientBuilder::danger_accept_invalid_certs),
+    /// so self-signed servers can be pinned rather than trusted blindly.
+    ///
+    /// The hash of a certificate's key can be computed with
+    /// `openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | openssl dgst -sha256`.
     ///
     /// # Errors
     ///
-    /// A value of `tls::Version::TLS_1_3` will cause an error with the
-    /// `native-tls`/`default-tls` backend. This does not mean the version
-    /// isn't supported, just that it can't be set as a minimum due to
-    /// technical limitations.
+    /// Building the client fails if `host` isn't a valid pattern or
+    /// `spki_sha256` is empty, or if the client only speaks HTTP/3, whose
+    /// connections can't be pinned yet. A client with pins otherwise never
+    /// upgrades to HTTP/3, for any host.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use chromimic as reqwest;
+    /// # fn run() -> Result<(), reqwest::Error> {
+    /// let primary = [0x5a; 32];
+    /// let backup = [0xa5; 32];
+    /// let client = reqwest::Client::builder()
+    ///     .pin_certificate("api.example.com", [primary, backup])
+    ///     .build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
     ///
     /// # Optional
     ///
@@ -1597,20 +2078,26 @@ impl ClientBuilder {
             feature = "rustls-tls"
         )))
     )]
-    pub fn min_tls_version(mut self, version: tls::Version) -> ClientBuilder {
-        self.config.min_tls_version = Some(version);
+    pub fn pin_certificate<I>(mut self, host: &str, spki_sha256: I) -> ClientBuilder
+    where
+        I: IntoIterator<Item = [u8; 32]>,
+    {
+        let hashes = spki_sha256.into_iter().collect();
+        if let Err(e) = self.config.certificate_pins.add(host, hashes) {
+            self.error = Some(e);
+        }
         self
     }
 
-    /// Set the maximum allowed TLS version for connections.
+    /// Set the minimum required TLS version for connections.
     ///
-    /// By default there's no maximum.
+    /// By default the TLS backend's own default is used.
     ///
     /// # Errors
     ///
     /// A value of `tls::Version::TLS_1_3` will cause an error with the
     /// `native-tls`/`default-tls` backend. This does not mean the version
-    /// isn't supported, just that it can't be set as a maximum due to
+    /// isn't supported, just that it can't be set as a minimum due to
     /// technical limitations.
     ///
     /// # Optional
@@ -1626,8 +2113,37 @@ impl ClientBuilder {
             feature = "rustls-tls"
         )))
     )]
-    pub fn max_tls_version(mut self, version: tls::Version) -> ClientBuilder {
-        self.config.max_tls_version = Some(version);
+    pub fn min_tls_version(mut self, version: tls::Version) -> ClientBuilder {
+        self.config.min_tls_version = Some(version);
+        self
+    }
+
+    /// Set the maximum allowed TLS version for connections.
+    ///
+    /// By default there's no maximum.
+    ///
+    /// # Errors
+    ///
+    /// A value of `tls::Version::TLS_1_3` will cause an error with the
+    /// `native-tls`/`default-tls` backend. This does not mean the version
+    /// isn't supported, just that it can't be set as a maximum due to
+    /// technical limitations.
+    ///
+    /// # Optional
+    ///
+    /// This requires the optional `default-tls`, `native-tls`, or `rustls-tls(-...)`
+    /// feature to be enabled.
+    #[cfg(feature = "__tls")]
+    #[cfg_attr(
+        docsrs,
+        doc(cfg(any(
+            feature = "default-tls",
+            feature = "native-tls",
+            feature = "rustls-tls"
+        )))
+    )]
+    pub fn max_tls_version(mut self, version: tls::Version) -> ClientBuilder {
+        self.config.max_tls_version = Some(version);
         self
     }
 
@@ -1749,6 +2265,46 @@ impl ClientBuilder {
         self
     }
 
+    /// Controls whether TLS sessions are cached and resumed.
+    ///
+    /// Browsers resume sessions with hosts they've talked to before, using
+    /// TLS 1.2 session tickets or IDs and TLS 1.3 pre-shared keys. Sessions
+    /// are cached per host and port, and never shared between impersonated
+    /// profiles.
+    ///
+    /// Defaults to `true`.
+    ///
+    /// # Optional
+    ///
+    /// This requires the optional `boring-tls(-...)` feature to be enabled.
+    #[cfg(feature = "__boring")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "boring-tls")))]
+    pub fn tls_session_resumption(mut self, enabled: bool) -> ClientBuilder {
+        self.config.tls_session_resumption = enabled;
+        self
+    }
+
+    /// Sets the cache TLS sessions are stored in and resumed from.
+    ///
+    /// By default every `Client` gets its own cache. Passing the same cache
+    /// to several builders shares sessions between their clients, though a
+    /// session is only resumed over the route and as the profile it was
+    /// negotiated with (see [`TlsSessionCache`](tls::TlsSessionCache)). Keeping
+    /// a handle allows exporting the sessions with
+    /// [`TlsSessionCache::export`](tls::TlsSessionCache::export).
+    ///
+    /// This has no effect if session resumption is disabled.
+    ///
+    /// # Optional
+    ///
+    /// This requires the optional `boring-tls(-...)` feature to be enabled.
+    #[cfg(feature = "__boring")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "boring-tls")))]
+    pub fn tls_session_cache(mut self, cache: tls::TlsSessionCache) -> ClientBuilder {
+        self.config.tls_session_cache = Some(cache);
+        self
+    }
+
     /// Restrict the Client to be used with HTTPS only requests.
     ///
     /// Defaults to false.
@@ -1771,6 +2327,37 @@ impl ClientBuilder {
         self
     }
 
+    /// Configures the hickory-dns async resolver, and enables it.
+    ///
+    /// By default it uses the system's nameservers and options, read from
+    /// `/etc/resolv.conf` on Unix. Building fails if a resolver is also set
+    /// with [`dns_resolver`](ClientBuilder::dns_resolver).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use chromimic as reqwest;
+    /// use reqwest::dns::HickoryConfig;
+    ///
+    /// # fn doc() -> Result<(), reqwest::Error> {
+    /// let client = reqwest::Client::builder()
+    ///     .hickory_config(HickoryConfig::new().nameserver_udp(([1, 1, 1, 1], 53).into()))
+    ///     .build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Optional
+    ///
+    /// This requires the optional `hickory-dns` feature to be enabled
+    #[cfg(feature = "hickory-dns")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "hickory-dns")))]
+    pub fn hickory_config(mut self, config: HickoryConfig) -> ClientBuilder {
+        self.config.hickory_dns = true;
+        self.config.hickory_config = Some(config);
+        self
+    }
+
     /// Disables the hickory-dns async resolver.
     ///
     /// This method exists even if the optional `hickory-dns` feature is not enabled.
@@ -1825,6 +2412,33 @@ impl ClientBuilder {
         self
     }
 
+    /// Follow the DNS HTTPS records of hosts, as browsers do.
+    ///
+    /// Before connecting to an `https` URL, the client looks up the
+    /// [HTTPS records](crate::dns::HttpsRecord) of its host and picks the
+    /// preferred one it can use. The connection then goes to the record's
+    /// target name and port, and its ClientHello is encrypted with the
+    /// record's ECH config, unless one was given to
+    /// [`ech_config`](ClientBuilder::ech_config). With HTTP/3 enabled,
+    /// records advertising `h3` make requests use it, and their address
+    /// hints are tried when the target's addresses can't be resolved. Hosts
+    /// with pins or ECH configs, records carrying ECH configs and requests
+    /// with their own profile stay on TCP.
+    ///
+    /// Only resolvers implementing [`Resolve::resolve_https`] find records:
+    /// the hickory-dns one does, the default `getaddrinfo` one doesn't.
+    /// Hosts with [overridden addresses](ClientBuilder::resolve) and
+    /// connections through proxies don't use them. Failed lookups are
+    /// ignored.
+    ///
+    /// This only affects the BoringSSL backend and HTTP/3.
+    ///
+    /// Default is `true`.
+    pub fn https_dns_records(mut self, enabled: bool) -> ClientBuilder {
+        self.config.https_dns_records = enabled;
+        self
+    }
+
     /// Whether to send data on the first flight ("early data") in TLS 1.3 handshakes
     /// for HTTP/3 connections.
     ///
@@ -1885,6 +2499,24 @@ impl ClientBuilder {
         self.config.quic_send_window = Some(value);
         self
     }
+
+    /// Sets whether HTTP/3 requests whose proxy can't relay QUIC are sent
+    /// over TCP instead.
+    ///
+    /// HTTP/3 requests through a SOCKS5 proxy tunnel QUIC with
+    /// `UDP ASSOCIATE`. Through any other proxy, or a SOCKS5 proxy refusing
+    /// to relay UDP, they fail by default rather than going direct, which
+    /// would reveal the client's address. With the fallback, they're sent
+    /// through the proxy over HTTP/1.1 or HTTP/2, unless their body is a
+    /// stream.
+    ///
+    /// Default is `false`.
+    #[cfg(feature = "http3")]
+    #[cfg_attr(docsrs, doc(cfg(all(reqwest_unstable, feature = "http3",))))]
+    pub fn http3_proxy_fallback(mut self, enabled: bool) -> ClientBuilder {
+        self.config.http3_proxy_fallback = enabled;
+        self
+    }
 }
 
 type HyperClient = hyper::Client<Connector, super::body::ImplStream>;
@@ -1915,6 +2547,53 @@ impl Client {
         ClientBuilder::new().build().expect("Client::new()")
     }
 
+    pub(crate) fn downgrade(&self) -> WeakClient {
+        WeakClient {
+            inner: Arc::downgrade(&self.inner),
+        }
+    }
+
+    /// Creates a `ClientBuilder` starting from this client's configuration.
+    ///
+    /// Clients built from it share this client's cookie store and DNS
+    /// resolver. As long as no connection-level setting (TLS, HTTP/2,
+    /// proxies, impersonation, ...) is changed, they also share its
+    /// connection pool.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use chromimic as reqwest;
+    /// # use std::time::Duration;
+    /// # fn doc() -> Result<(), reqwest::Error> {
+    /// let client = reqwest::Client::new();
+    /// let slow = client
+    ///     .to_builder()
+    ///     .timeout(Duration::from_secs(60))
+    ///     .build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn to_builder(&self) -> ClientBuilder {
+        ClientBuilder {
+            config: self.inner.config.clone(),
+            error: None,
+            base: Some(self.inner.clone()),
+        }
+    }
+
+    /// Derives a new `Client` from this one, see [`Client::to_builder`].
+    ///
+    /// # Errors
+    ///
+    /// This method fails if the derived `ClientBuilder` fails to build.
+    pub fn with<F>(&self, f: F) -> crate::Result<Client>
+    where
+        F: FnOnce(ClientBuilder) -> ClientBuilder,
+    {
+        f(self.to_builder()).build()
+    }
+
     /// Creates a `ClientBuilder` to configure a `Client`.
     ///
     /// This is the same as `ClientBuilder::new()`.
@@ -2008,20 +2687,124 @@ impl Client {
         self.execute_request(request)
     }
 
-    pub(super) fn execute_request(&self, req: Request) -> Pending {
+    pub(super) fn execute_request(&self, mut req: Request) -> Pending {
+        #[cfg(feature = "impersonate")]
+        let impersonated = match req.impersonate() {
+            Some(ver) => match self.inner.impersonate.get(ver, self.inner.accepts) {
+                Ok(client) => Some(client),
+                Err(err) => return Pending::new_err(err.with_url(req.url().clone())),
+            },
+            None => None,
+        };
+        #[cfg(feature = "impersonate")]
+        let (hyper, default_headers, accepts) = match impersonated {
+            Some(ref client) => (&client.hyper, &client.headers, client.accepts),
+            None => (&self.inner.hyper, &self.inner.headers, self.inner.accepts),
+        };
+        #[cfg(not(feature = "impersonate"))]
+        let (hyper, default_headers, accepts) =
+            (&self.inner.hyper, &self.inner.headers, self.inner.accepts);
+
+        let resolve_to = req.resolve_to();
+        let proxies = req.proxies_mut().take().map(Arc::new);
+        let session = req.proxy_session_mut().take();
+        #[cfg(feature = "digest-auth")]
+        let digest_auth = req.digest_auth_mut().take();
         let (method, url, mut headers, body, timeout, version) = req.pieces();
+        // Only requests left on the default version may go over HTTP/3
+        // without asking.
+        #[cfg(feature = "http3")]
+        let upgradable = version.is_none();
+        let version = version.unwrap_or_default();
         if url.scheme() != "http" && url.scheme() != "https" {
             return Pending::new_err(error::url_bad_scheme(url));
         }
+        let uri = expect_uri(&url);
+
+        #[cfg(feature = "http3")]
+        if version == http::Version::HTTP_3 {
+            if resolve_to.is_some() {
+                return Pending::new_err(
+                    error::builder("resolve_to isn't supported over HTTP/3").with_url(url),
+                );
+            }
+            if proxies.is_some() {
+                return Pending::new_err(
+                    error::builder("per-request proxies aren't supported over HTTP/3")
+                        .with_url(url),
+                );
+            }
+            if self.inner.proxy_pool.is_some() {
+                return Pending::new_err(
+                    error::builder("proxy pools aren't supported over HTTP/3").with_url(url),
+                );
+            }
+        }
+
+        // Requests with their own proxies don't use the pool.
+        let pool_pick = match (&proxies, &self.inner.proxy_pool) {
+            (None, Some(pool)) => pool.pick(&uri, session.as_deref()),
+            _ => None,
+        };
+        let proxies = proxies.or_else(|| pool_pick.as_ref().map(|pick| pick.proxies().clone()));
+
+        // A request with its own proxies goes through them wherever it's
+        // redirected, while a pinned one connects to its address as long as
+        // it stays on its origin.
+        #[cfg(feature = "impersonate")]
+        let routed_clients = match impersonated {
+            Some(ref client) => &client.routed,
+            None => &*self.inner.routed,
+        };
+        #[cfg(not(feature = "impersonate"))]
+        let routed_clients = &*self.inner.routed;
+        // Connections that may send early data only ever carry requests
+        // that are safe to replay, so they're pooled apart.
+        let early_data = proxies.is_none()
+            && routed_clients.connector.early_data()
+            && matches!(method, Method::GET | Method::HEAD | Method::OPTIONS);
+        let routed = match proxies {
+            Some(ref proxies) => Some(routed_clients.get(Some(proxies), None, false)),
+            None if early_data => Some(routed_clients.get(None, None, true)),
+            None => None,
+        };
+        let pinned = resolve_to.map(|mut addr| {
+            if addr.port() == 0 {
+                addr.set_port(url.port_or_known_default().unwrap_or(80));
+            }
+            PinnedClient {
+                origin: url.origin(),
+                hyper: routed_clients.get(proxies.as_ref(), Some(addr), early_data),
+            }
+        });
+        let hyper = match (&pinned, &routed) {
+            (Some(pinned), _) => &pinned.hyper,
+            (None, Some(routed)) => routed,
+            (None, None) => hyper,
+        };
+        let proxies = proxies.as_ref().unwrap_or(&self.inner.proxies);
 
         // check if we're in https_only mode and check the scheme of the current URL
         if self.inner.https_only && url.scheme() != "https" {
             return Pending::new_err(error::url_bad_scheme(url));
         }
 
+        // A per-request profile's headers give way to the defaults set on
+        // the builder, which keep the profile's order where they overlap.
+        #[cfg(feature = "impersonate")]
+        let user_headers = impersonated.as_ref().map(|_| &self.inner.config.user_headers);
+        #[cfg(not(feature = "impersonate"))]
+        let user_headers: Option<&HeaderMap> = None;
+
         // insert default headers in the request headers
         // without overwriting already appended headers.
-        for (key, value) in &self.inner.headers {
+        for (key, value) in default_headers {
+            let value = user_headers.and_then(|user| user.get(key)).unwrap_or(value);
+            if let Entry::Vacant(entry) = headers.entry(key) {
+                entry.insert(value.clone());
+            }
+        }
+        for (key, value) in user_headers.into_iter().flatten() {
             if let Entry::Vacant(entry) = headers.entry(key) {
                 entry.insert(value.clone());
             }
@@ -2037,7 +2820,7 @@ impl Client {
             }
         }
 
-        let accept_encoding = self.inner.accepts.as_str();
+        let accept_encoding = accepts.as_str();
 
         if let Some(accept_encoding) = accept_encoding {
             if !headers.contains_key(ACCEPT_ENCODING) && !headers.contains_key(RANGE) {
@@ -2045,8 +2828,6 @@ impl Client {
             }
         }
 
-        let uri = expect_uri(&url);
-
         let (reusable, body) = match body {
             Some(body) => {
                 let (reusable, body) = body.try_reuse();
@@ -2055,7 +2836,49 @@ impl Client {
             None => (None, Body::empty()),
         };
 
-        self.proxy_auth(&uri, &mut headers);
+        #[cfg(feature = "digest-auth")]
+        let digest = DigestState {
+            authorized: self.inner.digest_authorize(
+                digest_auth.as_ref(),
+                true,
+                &method,
+                &url,
+                &mut headers,
+                &reusable,
+            ),
+            credentials: digest_auth,
+            any_host: true,
+            answered: false,
+        };
+
+        self.proxy_headers(proxies, &uri, &mut headers);
+
+        // Per-request profiles and ECH configs only apply to TCP
+        // handshakes, so they keep requests there.
+        #[cfg(all(feature = "http3", feature = "impersonate"))]
+        let tcp_only = impersonated.is_some()
+            || uri.host().map_or(false, |host| routed_clients.connector.has_ech_config(host));
+        #[cfg(all(feature = "http3", not(feature = "impersonate")))]
+        let tcp_only = false;
+
+        // Like browsers, send requests to hosts whose DNS HTTPS records
+        // advertise h3 over HTTP/3, unless a version was asked for.
+        // Pinned clients have no HTTP/3 client.
+        #[cfg(feature = "http3")]
+        let h3_probe = match self.inner.h3_client {
+            Some(ref h3_client)
+                if upgradable
+                    && !tcp_only
+                    && resolve_to.is_none()
+                    && uri.scheme() == Some(&http::uri::Scheme::HTTPS)
+                    && !proxies
+                        .iter()
+                        .any(|p| p.intercept(&uri).is_some() || p.pac_for(&uri).is_some()) =>
+            {
+                Some(h3_client.advertised(&uri))
+            }
+            _ => None,
+        };
 
         let builder = hyper::Request::builder()
             .method(method.clone())
@@ -2069,12 +2892,14 @@ impl Client {
                 *req.headers_mut() = headers.clone();
                 ResponseFuture::H3(self.inner.h3_client.as_ref().unwrap().request(req))
             }
+            #[cfg(feature = "http3")]
+            _ if h3_probe.is_some() => ResponseFuture::H3Probe(h3_probe.unwrap(), Some(body)),
             _ => {
                 let mut req = builder
                     .body(body.into_stream())
                     .expect("valid request parts");
                 *req.headers_mut() = headers.clone();
-                ResponseFuture::Default(self.inner.hyper.request(req))
+                ResponseFuture::Default(hyper.request(req))
             }
         };
 
@@ -2095,6 +2920,17 @@ impl Client {
                 retry_count: 0,
 
                 client: self.inner.clone(),
+                #[cfg(feature = "impersonate")]
+                impersonated,
+                #[cfg(not(feature = "impersonate"))]
+                impersonated: None,
+                routed,
+                pinned,
+                pool_pick,
+                #[cfg(feature = "digest-auth")]
+                digest,
+                #[cfg(not(feature = "digest-auth"))]
+                digest: DigestState,
 
                 in_flight,
                 timeout,
@@ -2102,26 +2938,35 @@ impl Client {
         }
     }
 
-    fn proxy_auth(&self, dst: &Uri, headers: &mut HeaderMap) {
-        if !self.inner.proxies_maybe_http_auth {
+    fn proxy_headers(&self, proxies: &Arc<Vec<Proxy>>, dst: &Uri, headers: &mut HeaderMap) {
+        // The client's proxies are known not to have any.
+        if Arc::ptr_eq(proxies, &self.inner.proxies) && !self.inner.proxies_maybe_http_headers {
             return;
         }
 
-        // Only set the header here if the destination scheme is 'http',
-        // since otherwise, the header will be included in the CONNECT tunnel
+        // Only set the headers here if the destination scheme is 'http',
+        // since otherwise, they will be included in the CONNECT tunnel
         // request instead.
         if dst.scheme() != Some(&Scheme::HTTP) {
             return;
         }
 
-        if headers.contains_key(PROXY_AUTHORIZATION) {
-            return;
-        }
-
-        for proxy in self.inner.proxies.iter() {
+        for proxy in proxies.iter() {
             if proxy.is_match(dst) {
-                if let Some(header) = proxy.http_basic_auth(dst) {
-                    headers.insert(PROXY_AUTHORIZATION, header);
+                if !headers.contains_key(PROXY_AUTHORIZATION) {
+                    if let Some(header) = proxy.http_basic_auth(dst) {
+                        headers.insert(PROXY_AUTHORIZATION, header);
+                    }
+                }
+
+                if let Some(extra) = proxy.extra_headers().filter(|_| !proxy.is_pac()) {
+                    for name in extra.keys() {
+                        if !headers.contains_key(name) {
+                            for value in extra.get_all(name) {
+                                headers.append(name.clone(), value.clone());
+                            }
+                        }
+                    }
                 }
 
                 break;
@@ -2175,6 +3020,157 @@ impl fmt::Debug for ClientBuilder {
 }
 
 impl Config {
+    /// Whether a client built from this config can reuse the connections of
+    /// a client built from `other`.
+    /// Builds the resolver the DNS settings describe.
+    fn build_resolver(&self) -> Arc<dyn Resolve> {
+        let mut resolver: Arc<dyn Resolve> = match self.hickory_dns {
+            false => Arc::new(GaiResolver::new()),
+            #[cfg(feature = "hickory-dns")]
+            true => match self.hickory_config {
+                Some(ref hickory_config) => {
+                    Arc::new(HickoryDnsResolver::new(hickory_config.clone()))
+                }
+                None => Arc::new(HickoryDnsResolver::default()),
+            },
+            #[cfg(not(feature = "hickory-dns"))]
+            true => unreachable!("hickory-dns shouldn't be enabled unless the feature is"),
+        };
+        if let Some(ref dns_resolver) = self.dns_resolver {
+            resolver = dns_resolver.clone();
+        }
+        if let Some(preference) = self.ip_preference {
+            resolver = Arc::new(DnsResolverWithIpPreference::new(resolver, preference));
+        }
+        if !self.dns_overrides.is_empty() {
+            resolver = Arc::new(DnsResolverWithOverrides::new(
+                resolver,
+                self.dns_overrides.clone(),
+            ));
+        }
+        resolver
+    }
+
+    /// Whether the DNS settings build the same resolver.
+    fn same_resolver(&self, other: &Config) -> bool {
+        let same_source = match (&self.dns_resolver, &other.dns_resolver) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => self.hickory_dns == other.hickory_dns,
+            _ => false,
+        };
+        #[cfg(feature = "hickory-dns")]
+        let same_source = same_source && self.hickory_config == other.hickory_config;
+
+        same_source
+            && self.dns_overrides == other.dns_overrides
+            && self.ip_preference == other.ip_preference
+    }
+
+    fn same_transport(&self, other: &Config) -> bool {
+        #[allow(unused_mut)]
+        let mut same = self.same_resolver(other)
+            && self.https_dns_records == other.https_dns_records
+            // the user agent is also sent in proxy CONNECT requests
+            && self.headers.get(USER_AGENT) == other.headers.get(USER_AGENT)
+            && self.connect_timeout == other.connect_timeout
+            && self.connection_verbose == other.connection_verbose
+            && self.pool_idle_timeout == other.pool_idle_timeout
+            && self.pool_max_idle_per_host == other.pool_max_idle_per_host
+            && self.tcp_keepalive == other.tcp_keepalive
+            && self.proxies == other.proxies
+            && match (&self.proxy_pool, &other.proxy_pool) {
+                (Some(a), Some(b)) => a.same(b),
+                (None, None) => true,
+                _ => false,
+            }
+            && self.auto_sys_proxy == other.auto_sys_proxy
+            && self.http_version_pref == other.http_version_pref
+            && self.http09_responses == other.http09_responses
+            && self.http1_title_case_headers == other.http1_title_case_headers
+            && self.http1_allow_obsolete_multiline_headers_in_responses
+                == other.http1_allow_obsolete_multiline_headers_in_responses
+            && self.http1_ignore_invalid_headers_in_responses
+                == other.http1_ignore_invalid_headers_in_responses
+            && self.http1_allow_spaces_after_header_name_in_responses
+                == other.http1_allow_spaces_after_header_name_in_responses
+            && self.http2_initial_stream_window_size == other.http2_initial_stream_window_size
+            && self.http2_initial_connection_window_size
+                == other.http2_initial_connection_window_size
+            && self.http2_adaptive_window == other.http2_adaptive_window
+            && self.http2_max_frame_size == other.http2_max_frame_size
+            && self.http2_max_concurrent_streams == other.http2_max_concurrent_streams
+            && self.http2_max_header_list_size == other.http2_max_header_list_size
+            && self.http2_enable_push == other.http2_enable_push
+            && self.http2_header_table_size == other.http2_header_table_size
+            && self.http2_keep_alive_interval == other.http2_keep_alive_interval
+            && self.http2_keep_alive_timeout == other.http2_keep_alive_timeout
+            && self.http2_keep_alive_while_idle == other.http2_keep_alive_while_idle
+            && self.local_address_ipv6 == other.local_address_ipv6
+            && self.local_address_ipv4 == other.local_address_ipv4
+            && self.nodelay == other.nodelay;
+
+        #[cfg(feature = "__tls")]
+        {
+            same = same
+                && self.certs_verification == other.certs_verification
+                && self.tls_sni == other.tls_sni
+                && self.root_certs.len() == other.root_certs.len()
+                && self
+                    .root_certs
+                    .iter()
+                    .zip(&other.root_certs)
+                    .all(|(a, b)| a.same(b))
+                && self.tls_built_in_root_certs == other.tls_built_in_root_certs
+                && self.min_tls_version == other.min_tls_version
+                && self.max_tls_version == other.max_tls_version
+                && self.tls_info == other.tls_info
+                && self.tls.same(&other.tls)
+                && self.certificate_pins == other.certificate_pins;
+        }
+        #[cfg(feature = "__boring")]
+        {
+            same = same
+                && self.tls_session_resumption == other.tls_session_resumption
+                && match (&self.tls_session_cache, &other.tls_session_cache) {
+                    (Some(a), Some(b)) => a.same(b),
+                    (None, None) => true,
+                    _ => false,
+                };
+        }
+        #[cfg(feature = "native-tls")]
+        {
+            same = same && self.hostname_verification == other.hostname_verification;
+        }
+        #[cfg(any(feature = "native-tls", feature = "__rustls", feature = "__boring"))]
+        {
+            same = same && self.identity.is_none() && other.identity.is_none();
+        }
+        #[cfg(feature = "http3")]
+        {
+            same = same
+                && self.tls_enable_early_data == other.tls_enable_early_data
+                && self.quic_max_idle_timeout == other.quic_max_idle_timeout
+                && self.quic_stream_receive_window == other.quic_stream_receive_window
+                && self.quic_receive_window == other.quic_receive_window
+                && self.quic_send_window == other.quic_send_window
+                && self.http3_proxy_fallback == other.http3_proxy_fallback;
+        }
+        #[cfg(feature = "impersonate")]
+        {
+            same = same
+                && self.profile == other.profile
+                && self.impersonate == other.impersonate
+                && self.enable_ech_grease == other.enable_ech_grease
+                && self.ech_configs == other.ech_configs
+                && self.permute_extensions == other.permute_extensions
+                && self.application_settings == other.application_settings
+                && self.tls_early_data == other.tls_early_data
+                && self.profile_early_data == other.profile_early_data;
+        }
+
+        same
+    }
+
     fn fmt_fields(&self, f: &mut fmt::DebugStruct<'_, '_>) {
         // Instead of deriving Debug, only print fields when their output
         // would provide relevant or interesting data.
@@ -2186,12 +3182,23 @@ impl Config {
             }
         }
 
+        #[cfg(feature = "digest-auth")]
+        {
+            if !self.digest_auth.is_empty() {
+                f.field("digest_auth", &self.digest_auth);
+            }
+        }
+
         f.field("accepts", &self.accepts);
 
         if !self.proxies.is_empty() {
             f.field("proxies", &self.proxies);
         }
 
+        if let Some(ref proxy_pool) = self.proxy_pool {
+            f.field("proxy_pool", proxy_pool);
+        }
+
         if !self.redirect_policy.is_default() {
             f.field("redirect_policy", &self.redirect_policy);
         }
@@ -2270,6 +3277,28 @@ impl Config {
             f.field("tls_sni", &self.tls_sni);
 
             f.field("tls_info", &self.tls_info);
+
+            if !self.certificate_pins.is_empty() {
+                f.field("certificate_pins", &self.certificate_pins);
+            }
+        }
+
+        #[cfg(feature = "__boring")]
+        {
+            if !self.tls_session_resumption {
+                f.field("tls_session_resumption", &false);
+            }
+
+            if let Some(ref cache) = self.tls_session_cache {
+                f.field("tls_session_cache", cache);
+            }
+        }
+
+        #[cfg(feature = "impersonate")]
+        {
+            if !self.ech_configs.is_empty() {
+                f.field("ech_configs", &self.ech_configs);
+            }
         }
 
         #[cfg(all(feature = "native-tls-crate", feature = "__rustls"))]
@@ -2281,11 +3310,28 @@ impl Config {
             f.field("dns_overrides", &self.dns_overrides);
         }
 
+        #[cfg(feature = "hickory-dns")]
+        if let Some(ref hickory_config) = self.hickory_config {
+            f.field("hickory_config", hickory_config);
+        }
+
+        if !self.https_dns_records {
+            f.field("https_dns_records", &false);
+        }
+
+        if let Some(ref ip_preference) = self.ip_preference {
+            f.field("ip_preference", ip_preference);
+        }
+
         #[cfg(feature = "http3")]
         {
             if self.tls_enable_early_data {
                 f.field("tls_enable_early_data", &true);
             }
+
+            if self.http3_proxy_fallback {
+                f.field("http3_proxy_fallback", &true);
+            }
         }
     }
 }
@@ -2302,11 +3348,211 @@ struct ClientRef {
     referer: bool,
     request_timeout: Option<Duration>,
     proxies: Arc<Vec<Proxy>>,
-    proxies_maybe_http_auth: bool,
+    proxies_maybe_http_headers: bool,
+    proxy_pool: Option<ProxyPool>,
     https_only: bool,
+    #[cfg(feature = "digest-auth")]
+    digest_sessions: digest::Sessions,
+    #[cfg(feature = "impersonate")]
+    impersonate: Arc<ImpersonateClients>,
+    routed: Arc<RoutedClients>,
+    config: Config,
+}
+
+/// Clients for per-request impersonation, built on first use of each profile.
+///
+/// They share the connector's resolver and proxies, but each profile gets its
+/// own connection pool.
+#[cfg(feature = "impersonate")]
+struct ImpersonateClients {
+    connector: Connector,
+    builder: hyper::client::Builder,
+    routed_capacity: usize,
+    /// The client's `tls_early_data`, overriding the profiles'.
+    early_data: Option<bool>,
+    clients: Mutex<HashMap<Impersonate, Arc<ImpersonatedClient>>>,
+}
+
+#[cfg_attr(not(feature = "impersonate"), allow(dead_code))]
+struct ImpersonatedClient {
+    accepts: Accepts,
+    headers: HeaderMap,
+    hyper: HyperClient,
+    routed: RoutedClients,
+}
+
+#[cfg(feature = "impersonate")]
+impl ImpersonateClients {
+    fn get(&self, ver: Impersonate, accepts: Accepts) -> crate::Result<Arc<ImpersonatedClient>> {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(&ver) {
+            return Ok(client.clone());
+        }
+
+        let settings = get_config_from_ver(ver);
+        if let Some(ref alps) = settings.application_settings {
+            alps.check()?;
+        }
+
+        let connector = self
+            .connector
+            .impersonated(
+                boring_tls_with_native_roots(settings.tls_builder_func),
+                settings.headers.get(USER_AGENT).cloned(),
+                ver,
+                settings.application_settings,
+                self.early_data.unwrap_or(settings.early_data),
+            )
+            .ok_or_else(|| {
+                error::builder("per-request impersonation requires the BoringSSL backend")
+            })?;
+
+        let http2 = settings.http2;
+        let mut builder = self.builder.clone();
+        if let Some(sz) = http2.initial_stream_window_size {
+            builder.http2_initial_stream_window_size(sz);
+        }
+        if let Some(sz) = http2.initial_connection_window_size {
+            builder.http2_initial_connection_window_size(sz);
+        }
+        if let Some(max) = http2.max_concurrent_streams {
+            builder.http2_max_concurrent_streams(max);
+        }
+        if let Some(max) = http2.max_header_list_size {
+            builder.http2_max_header_list_size(max);
+        }
+        if let Some(opt) = http2.enable_push {
+            builder.http2_enable_push(opt);
+        }
+        if let Some(max) = http2.header_table_size {
+            builder.http2_header_table_size(max);
+        }
+        builder.http2_agent_profile(ver.profile().into());
+
+        let mut accepts = accepts;
+        accepts.gzip = settings.gzip;
+        accepts.brotli = settings.brotli;
+
+        let client = Arc::new(ImpersonatedClient {
+            accepts,
+            headers: settings.headers,
+            routed: RoutedClients::new(connector.clone(), builder.clone(), self.routed_capacity),
+            hyper: builder.build(connector.routed(None, None, false)),
+        });
+        clients.insert(ver, client.clone());
+        Ok(client)
+    }
+}
+
+/// Clients for requests sent through their own proxies, or pinned to an
+/// address with `RequestBuilder::resolve_to`, or allowed to send TLS early
+/// data, built on first use of each route.
+///
+/// Each has its own connection pool, so connections are pooled by the
+/// proxies they go through, credentials included, and by the address
+/// connected to rather than by host. Once there are `capacity` of them, the
+/// least recently used is dropped: `MAX_ROUTED_CLIENTS`, plus one for each
+/// proxy of the client's pool.
+struct RoutedClients {
+    connector: Connector,
+    builder: hyper::client::Builder,
+    capacity: usize,
+    // From the least recently used.
+    clients: Mutex<VecDeque<(Route, HyperClient)>>,
+}
+
+#[derive(PartialEq)]
+struct Route {
+    proxies: Option<Arc<Vec<Proxy>>>,
+    connect_to: Option<SocketAddr>,
+    early_data: bool,
+}
+
+const MAX_ROUTED_CLIENTS: usize = 256;
+
+impl RoutedClients {
+    fn new(
+        connector: Connector,
+        builder: hyper::client::Builder,
+        capacity: usize,
+    ) -> RoutedClients {
+        RoutedClients {
+            connector,
+            builder,
+            capacity,
+            clients: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn get(
+        &self,
+        proxies: Option<&Arc<Vec<Proxy>>>,
+        connect_to: Option<SocketAddr>,
+        early_data: bool,
+    ) -> HyperClient {
+        let route = Route {
+            proxies: proxies.cloned(),
+            connect_to,
+            early_data,
+        };
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(i) = clients.iter().position(|(r, _)| *r == route) {
+            let used = clients.remove(i).expect("position in range");
+            let client = used.1.clone();
+            clients.push_back(used);
+            return client;
+        }
+
+        if clients.len() >= self.capacity {
+            clients.pop_front();
+        }
+        let connector = self.connector.routed(route.proxies.clone(), connect_to, early_data);
+        let client = self.builder.build(connector);
+        clients.push_back((route, client.clone()));
+        client
+    }
+}
+
+/// The client of a request pinned with `RequestBuilder::resolve_to`, used
+/// while the request stays on the origin it was pinned for.
+struct PinnedClient {
+    origin: url::Origin,
+    hyper: HyperClient,
 }
 
 impl ClientRef {
+    /// Authorizes a request up front with the last Digest challenge of its
+    /// origin, unless it has an `Authorization` header. Returns whether it
+    /// did.
+    #[cfg(feature = "digest-auth")]
+    fn digest_authorize(
+        &self,
+        credentials: Option<&digest::Credentials>,
+        any_host: bool,
+        method: &Method,
+        url: &Url,
+        headers: &mut HeaderMap,
+        body: &Option<Option<Bytes>>,
+    ) -> bool {
+        if headers.contains_key(crate::header::AUTHORIZATION) {
+            return false;
+        }
+        let credentials = credentials.or_else(|| self.config.digest_auth.get(url, any_host));
+        let credentials = match credentials {
+            Some(credentials) => credentials,
+            None => return false,
+        };
+        let space = url.origin().ascii_serialization();
+        let request = digest_request(method, url, body);
+        match self.digest_sessions.authorize(&space, credentials, &request) {
+            Some(header) => {
+                headers.insert(crate::header::AUTHORIZATION, header);
+                true
+            }
+            None => false,
+        }
+    }
+
     fn fmt_fields(&self, f: &mut fmt::DebugStruct<'_, '_>) {
         // Instead of deriving Debug, only print fields when their output
         // would provide relevant or interesting data.
@@ -2324,6 +3570,10 @@ impl ClientRef {
             f.field("proxies", &self.proxies);
         }
 
+        if let Some(ref proxy_pool) = self.proxy_pool {
+            f.field("proxy_pool", proxy_pool);
+        }
+
         if !self.redirect_policy.is_default() {
             f.field("redirect_policy", &self.redirect_policy);
         }
@@ -2364,6 +3614,11 @@ pin_project! {
         retry_count: usize,
 
         client: Arc<ClientRef>,
+        impersonated: Option<Arc<ImpersonatedClient>>,
+        routed: Option<HyperClient>,
+        pinned: Option<PinnedClient>,
+        pool_pick: Option<Pick>,
+        digest: DigestState,
 
         #[pin]
         in_flight: ResponseFuture,
@@ -2372,13 +3627,57 @@ pin_project! {
     }
 }
 
+/// The Digest authentication of a pending request.
+#[cfg(feature = "digest-auth")]
+struct DigestState {
+    /// The request's own credentials, until it's redirected to another
+    /// origin.
+    credentials: Option<digest::Credentials>,
+    /// Whether the client's credentials for any host still apply, until
+    /// the request is redirected to another origin.
+    any_host: bool,
+    /// Whether the `Authorization` header was set by the client.
+    authorized: bool,
+    /// Whether a challenge was answered since the last redirect.
+    answered: bool,
+}
+
+#[cfg(not(feature = "digest-auth"))]
+struct DigestState;
+
 enum ResponseFuture {
     Default(HyperResponseFuture),
     #[cfg(feature = "http3")]
     H3(H3ResponseFuture),
+    /// Looking up whether the host advertises HTTP/3 before sending the
+    /// body with one client or the other.
+    #[cfg(feature = "http3")]
+    H3Probe(Pin<Box<dyn Future<Output = bool> + Send>>, Option<Body>),
 }
 
 impl PendingRequest {
+    fn hyper(&self) -> &HyperClient {
+        if let Some(ref pinned) = self.pinned {
+            if self.url.origin() == pinned.origin {
+                return &pinned.hyper;
+            }
+        }
+        if let Some(ref routed) = self.routed {
+            return routed;
+        }
+        match self.impersonated {
+            Some(ref client) => &client.hyper,
+            None => &self.client.hyper,
+        }
+    }
+
+    fn accepts(&self) -> Accepts {
+        match self.impersonated {
+            Some(ref client) => client.accepts,
+            None => self.client.accepts,
+        }
+    }
+
     fn in_flight(self: Pin<&mut Self>) -> Pin<&mut ResponseFuture> {
         self.project().in_flight
     }
@@ -2395,6 +3694,54 @@ impl PendingRequest {
         self.project().headers
     }
 
+    /// Sends the request with the HTTP/3 client if `h3`, or else the
+    /// default one.
+    #[cfg(feature = "http3")]
+    fn dispatch(&self, h3: bool, body: Body) -> ResponseFuture {
+        let builder = hyper::Request::builder()
+            .method(self.method.clone())
+            .uri(expect_uri(&self.url));
+        if h3 {
+            let mut req = builder.body(body).expect("valid request parts");
+            *req.headers_mut() = self.headers.clone();
+            ResponseFuture::H3(
+                self.client
+                    .h3_client
+                    .as_ref()
+                    .expect("H3 client must exists, otherwise we can't have a h3 request here")
+                    .request(req),
+            )
+        } else {
+            let mut req = builder
+                .body(body.into_stream())
+                .expect("valid request parts");
+            *req.headers_mut() = self.headers.clone();
+            ResponseFuture::Default(self.hyper().request(req))
+        }
+    }
+
+    /// Sends an HTTP/3 request over TCP if its proxy couldn't relay QUIC,
+    /// and the client falls back.
+    #[cfg(feature = "http3")]
+    fn proxy_fallback(mut self: Pin<&mut Self>, err: &crate::Error) -> bool {
+        if !self.client.config.http3_proxy_fallback || !NoUdpRelay::is_cause_of(err) {
+            return false;
+        }
+
+        let body = match self.body {
+            Some(Some(ref body)) => Body::reusable(body.clone()),
+            Some(None) => {
+                debug!("can't fall back to TCP, the body isn't reusable");
+                return false;
+            }
+            None => Body::empty(),
+        };
+        debug!("{}, falling back to TCP", err);
+        let in_flight = self.dispatch(false, body);
+        *self.as_mut().in_flight().get_mut() = in_flight;
+        true
+    }
+
     fn retry_error(mut self: Pin<&mut Self>, err: &(dyn std::error::Error + 'static)) -> bool {
         if !is_retryable_error(err) {
             return false;
@@ -2417,6 +3764,12 @@ impl PendingRequest {
         }
         self.retry_count += 1;
 
+        self.resend(body);
+        true
+    }
+
+    /// Sends the request again, with the same client as before.
+    fn resend(mut self: Pin<&mut Self>, body: Body) {
         let uri = expect_uri(&self.url);
 
         *self.as_mut().in_flight().get_mut() = match *self.as_mut().in_flight().as_ref() {
@@ -2443,14 +3796,72 @@ impl PendingRequest {
                     .body(body.into_stream())
                     .expect("valid request parts");
                 *req.headers_mut() = self.headers.clone();
-                ResponseFuture::Default(self.client.hyper.request(req))
+                ResponseFuture::Default(self.hyper().request(req))
             }
         };
+    }
 
+    /// Sends the request again answering the Digest challenge of a `401`
+    /// response, unless one was answered already.
+    #[cfg(feature = "digest-auth")]
+    fn answer_digest(mut self: Pin<&mut Self>, challenges: &HeaderMap) -> bool {
+        if self.digest.answered {
+            return false;
+        }
+        let credentials = match self.digest.credentials.as_ref() {
+            Some(credentials) => credentials.clone(),
+            None => match self.client.config.digest_auth.get(&self.url, self.digest.any_host) {
+                Some(credentials) => credentials.clone(),
+                None => return false,
+            },
+        };
+        let body = match self.body {
+            Some(Some(ref body)) => Body::reusable(body.clone()),
+            Some(None) => {
+                debug!("can't answer the Digest challenge, the body isn't reusable");
+                return false;
+            }
+            None => Body::empty(),
+        };
+
+        let space = self.url.origin().ascii_serialization();
+        let request = digest_request(&self.method, &self.url, &self.body);
+        let header = match self.client.digest_sessions.answer(
+            &space,
+            challenges.get_all(crate::header::WWW_AUTHENTICATE),
+            &credentials,
+            &request,
+        ) {
+            Some(header) => header,
+            None => return false,
+        };
+        debug!("answering the Digest challenge of {}", space);
+        self.headers.insert(crate::header::AUTHORIZATION, header);
+        self.digest.authorized = true;
+        self.digest.answered = true;
+        self.resend(body);
         true
     }
 }
 
+/// What the Digest response to a challenge covers of a request.
+#[cfg(feature = "digest-auth")]
+fn digest_request<'a>(
+    method: &'a Method,
+    url: &'a Url,
+    body: &'a Option<Option<Bytes>>,
+) -> digest::Request<'a> {
+    digest::Request {
+        method: method.as_str(),
+        uri: &url[url::Position::BeforePath..url::Position::AfterQuery],
+        body: match body {
+            Some(Some(body)) => Some(body),
+            Some(None) => None,
+            None => Some(b""),
+        },
+    }
+}
+
 fn is_retryable_error(err: &(dyn std::error::Error + 'static)) -> bool {
     #[cfg(feature = "http3")]
     if let Some(cause) = err.source() {
@@ -2504,6 +3915,10 @@ impl Future for PendingRequest {
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         if let Some(delay) = self.as_mut().timeout().as_mut().as_pin_mut() {
             if let Poll::Ready(()) = delay.poll(cx) {
+                // A proxy that hangs is as bad as one that can't be reached.
+                if let Some(ref pick) = self.pool_pick {
+                    pick.failed();
+                }
                 return Poll::Ready(Err(
                     crate::error::request(crate::error::TimedOut).with_url(self.url.clone())
                 ));
@@ -2511,9 +3926,25 @@ impl Future for PendingRequest {
         }
 
         loop {
+            #[cfg(feature = "http3")]
+            if let ResponseFuture::H3Probe(probe, body) = self.as_mut().in_flight().get_mut() {
+                let h3 = match probe.as_mut().poll(cx) {
+                    Poll::Ready(h3) => h3,
+                    Poll::Pending => return Poll::Pending,
+                };
+                let body = body.take().expect("H3Probe polled after completion");
+                let in_flight = self.dispatch(h3, body);
+                *self.as_mut().in_flight().get_mut() = in_flight;
+            }
+
             let res = match self.as_mut().in_flight().get_mut() {
                 ResponseFuture::Default(r) => match Pin::new(r).poll(cx) {
                     Poll::Ready(Err(e)) => {
+                        // Only failures reaching or going through the proxy
+                        // count against it, not those of the origin.
+                        if let (true, Some(pick)) = (is_proxy_error(&e), &self.pool_pick) {
+                            pick.failed();
+                        }
                         if self.as_mut().retry_error(&e) {
                             continue;
                         }
@@ -2527,6 +3958,9 @@ impl Future for PendingRequest {
                 #[cfg(feature = "http3")]
                 ResponseFuture::H3(r) => match Pin::new(r).poll(cx) {
                     Poll::Ready(Err(e)) => {
+                        if self.as_mut().proxy_fallback(&e) {
+                            continue;
+                        }
                         if self.as_mut().retry_error(&e) {
                             continue;
                         }
@@ -2537,8 +3971,14 @@ impl Future for PendingRequest {
                     Poll::Ready(Ok(res)) => res,
                     Poll::Pending => return Poll::Pending,
                 },
+                #[cfg(feature = "http3")]
+                ResponseFuture::H3Probe(..) => unreachable!("H3Probe is replaced above"),
             };
 
+            if let Some(ref pick) = self.pool_pick {
+                pick.response(res.status());
+            }
+
             #[cfg(feature = "cookies")]
             {
                 if let Some(ref cookie_store) = self.client.cookie_store {
@@ -2549,6 +3989,12 @@ impl Future for PendingRequest {
                     }
                 }
             }
+            #[cfg(feature = "digest-auth")]
+            if res.status() == StatusCode::UNAUTHORIZED && self.as_mut().answer_digest(res.headers())
+            {
+                continue;
+            }
+
             let should_redirect = match res.status() {
                 StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND | StatusCode::SEE_OTHER => {
                     self.body = None;
@@ -2635,6 +4081,27 @@ impl Future for PendingRequest {
                                 std::mem::replace(self.as_mut().headers(), HeaderMap::new());
 
                             remove_sensitive_headers(&mut headers, &self.url, &self.urls);
+
+                            #[cfg(feature = "digest-auth")]
+                            {
+                                if self.digest.authorized {
+                                    headers.remove(crate::header::AUTHORIZATION);
+                                }
+                                if self.urls.last().map(Url::origin) != Some(self.url.origin()) {
+                                    self.digest.credentials = None;
+                                    self.digest.any_host = false;
+                                }
+                                let authorized = self.client.digest_authorize(
+                                    self.digest.credentials.as_ref(),
+                                    self.digest.any_host,
+                                    &self.method,
+                                    &self.url,
+                                    &mut headers,
+                                    &self.body,
+                                );
+                                self.digest.authorized = authorized;
+                                self.digest.answered = false;
+                            }
                             let uri = expect_uri(&self.url);
                             let body = match self.body {
                                 Some(Some(ref body)) => Body::reusable(body.clone()),
@@ -2673,7 +4140,7 @@ impl Future for PendingRequest {
                                             .expect("valid request parts");
                                         *req.headers_mut() = headers.clone();
                                         std::mem::swap(self.as_mut().headers(), &mut headers);
-                                        ResponseFuture::Default(self.client.hyper.request(req))
+                                        ResponseFuture::Default(self.hyper().request(req))
                                     }
                                 };
 
@@ -2692,7 +4159,7 @@ impl Future for PendingRequest {
             let res = Response::new(
                 res,
                 self.url.clone(),
-                self.client.accepts,
+                self.accepts(),
                 self.timeout.take(),
             );
             return Poll::Ready(Ok(res));
@@ -2725,6 +4192,32 @@ fn make_referer(next: &Url, previous: &Url) -> Option<HeaderValue> {
     referer.as_str().parse().ok()
 }
 
+#[cfg(feature = "__boring")]
+fn boring_tls_with_native_roots(
+    tls: Arc<dyn Fn(bool) -> boring::ssl::SslConnectorBuilder + Send + Sync>,
+) -> Arc<dyn Fn(bool) -> boring::ssl::SslConnectorBuilder + Send + Sync> {
+    #[cfg(feature = "boring-tls-native-roots")]
+    let tls = Arc::new(move |h2: bool| {
+        let mut builder = tls.clone()(h2);
+
+        use boring::x509::X509;
+        let certs = rustls_native_certs::load_native_certs().unwrap();
+
+        let cert_store = builder.cert_store_mut();
+
+        for certificate in certs {
+            // I know this is bad but what do I have to do else
+
+            let boring_cert = X509::from_der(&certificate.as_ref()).unwrap();
+
+            cert_store.add_cert(boring_cert).unwrap();
+        }
+        builder
+    });
+
+    tls
+}
+
 #[cfg(feature = "cookies")]
 fn add_cookie_header(headers: &mut HeaderMap, cookie_store: &dyn cookie::CookieStore, url: &Url) {
     if let Some(header) = cookie_store.cookies(url) {
@@ -2745,4 +4238,76 @@ mod tests {
         assert!(err.is_builder());
         assert_eq!(url_str, err.url().unwrap().as_str());
     }
+
+    #[cfg(feature = "impersonate")]
+    #[test]
+    fn derived_client_shares_pool_unless_transport_changes() {
+        use super::Client;
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        let client = Client::builder().no_proxy().build().unwrap();
+
+        let same = client.with(|b| b.timeout(Duration::from_secs(1))).unwrap();
+        assert!(Arc::ptr_eq(&client.inner.impersonate, &same.inner.impersonate));
+        assert_eq!(same.inner.request_timeout, Some(Duration::from_secs(1)));
+
+        let other = client.with(|b| b.http1_only()).unwrap();
+        assert!(!Arc::ptr_eq(&client.inner.impersonate, &other.inner.impersonate));
+
+        let resolver = |c: &Client| c.inner.config.built_resolver.clone().unwrap();
+        assert!(Arc::ptr_eq(&resolver(&client), &resolver(&other)));
+    }
+
+    #[cfg(feature = "impersonate")]
+    #[test]
+    fn derived_client_rebuilds_changed_proxies_and_dns() {
+        use super::Client;
+        use crate::{dns::IpPreference, Proxy, ProxyPool};
+        use std::sync::Arc;
+
+        let pool = ProxyPool::builder()
+            .proxy(Proxy::all("http://127.0.0.1:1").unwrap())
+            .build()
+            .unwrap();
+        let client = Client::builder().no_proxy().proxy_pool(pool.clone()).build().unwrap();
+
+        let same = client.with(|b| b.proxy_pool(pool.clone())).unwrap();
+        assert!(Arc::ptr_eq(&client.inner.impersonate, &same.inner.impersonate));
+
+        let other_pool = ProxyPool::builder()
+            .proxy(Proxy::all("http://127.0.0.1:1").unwrap())
+            .build()
+            .unwrap();
+        let other = client.with(|b| b.proxy_pool(other_pool)).unwrap();
+        assert!(!Arc::ptr_eq(&client.inner.impersonate, &other.inner.impersonate));
+
+        let resolver = |c: &Client| c.inner.config.built_resolver.clone().unwrap();
+        let other = client.with(|b| b.ip_version(IpPreference::V4Only)).unwrap();
+        assert!(!Arc::ptr_eq(&resolver(&client), &resolver(&other)));
+        assert!(!Arc::ptr_eq(&client.inner.impersonate, &other.inner.impersonate));
+    }
+
+    #[test]
+    fn routed_clients_drop_least_recently_used() {
+        use super::{Client, RoutedClients};
+        use std::net::SocketAddr;
+
+        let client = Client::builder().no_proxy().build().unwrap();
+        let connector = client.inner.routed.connector.clone();
+        let routed = RoutedClients::new(connector, hyper::Client::builder(), 2);
+        let addr = |port| Some(SocketAddr::from(([127, 0, 0, 1], port)));
+
+        for port in [1, 2, 1, 3] {
+            routed.get(None, addr(port), false);
+        }
+        let kept = routed
+            .clients
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(route, _)| route.connect_to)
+            .collect::<Vec<_>>();
+        assert_eq!(kept, [addr(1), addr(3)]);
+    }
 }
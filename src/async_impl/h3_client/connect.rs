@@ -1,12 +1,25 @@
 use crate::async_impl::h3_client::dns::resolve;
-use crate::dns::DynResolver;
+use crate::connection_info::ConnectionInfo;
+#[cfg(feature = "socks")]
+use crate::async_impl::h3_client::socks::RelayedSocket;
+#[cfg(feature = "socks")]
+use crate::connect::socks::{self, UdpTarget};
+use crate::dns::{DynResolver, HttpsRecord};
 use crate::error::BoxError;
+use crate::pac::{Pac, Route};
+use crate::proxy::ProxyScheme;
+use crate::tls::TlsDetails;
+use crate::Proxy;
 use bytes::Bytes;
+use futures_util::future;
 use h3::client::SendRequest;
 use h3_quinn::{Connection, OpenStreams};
 use http::Uri;
 use hyper::client::connect::dns::Name;
+use once_cell::sync::OnceCell;
 use quinn::{ClientConfig, Endpoint, TransportConfig};
+use std::error::Error as StdError;
+use std::fmt;
 use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
 use std::sync::Arc;
@@ -14,20 +27,63 @@ use std::sync::Arc;
 type H3Connection = (
     h3::client::Connection<Connection, Bytes>,
     SendRequest<OpenStreams, Bytes>,
+    ConnectionInfo,
 );
 
 #[derive(Clone)]
 pub(crate) struct H3Connector {
     resolver: DynResolver,
     endpoint: Endpoint,
+    https_records: bool,
+    proxies: Arc<Vec<Proxy>>,
+    // For the endpoints of connections relayed by a proxy.
+    #[cfg_attr(not(feature = "socks"), allow(dead_code))]
+    config: ClientConfig,
+    #[cfg_attr(not(feature = "socks"), allow(dead_code))]
+    local_addr: Option<IpAddr>,
 }
 
+/// The proxy of an HTTP/3 request can't relay its QUIC connection.
+///
+/// Rather than going direct, the request fails, unless the client falls back
+/// to TCP.
+#[derive(Debug)]
+pub(crate) struct NoUdpRelay {
+    proxy: String,
+    reason: String,
+}
+
+impl NoUdpRelay {
+    /// Returns whether `err`, or one of its sources, is a `NoUdpRelay`.
+    pub(crate) fn is_cause_of(mut err: &(dyn StdError + 'static)) -> bool {
+        loop {
+            if err.is::<NoUdpRelay>() {
+                return true;
+            }
+            match err.source() {
+                Some(source) => err = source,
+                None => return false,
+            }
+        }
+    }
+}
+
+impl fmt::Display for NoUdpRelay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HTTP/3 can't go through {}: {}", self.proxy, self.reason)
+    }
+}
+
+impl StdError for NoUdpRelay {}
+
 impl H3Connector {
     pub fn new(
         resolver: DynResolver,
         tls: rustls::ClientConfig,
         local_addr: Option<IpAddr>,
         transport_config: TransportConfig,
+        https_records: bool,
+        proxies: Arc<Vec<Proxy>>,
     ) -> Result<H3Connector, BoxError> {
         let mut config = ClientConfig::new(Arc::new(tls));
         // FIXME: Replace this when there is a setter.
@@ -39,30 +95,189 @@ impl H3Connector {
         };
 
         let mut endpoint = Endpoint::client(socket_addr)?;
-        endpoint.set_default_client_config(config);
+        endpoint.set_default_client_config(config.clone());
 
-        Ok(Self { resolver, endpoint })
+        Ok(Self {
+            resolver,
+            endpoint,
+            https_records,
+            proxies,
+            config,
+            local_addr,
+        })
     }
 
     pub async fn connect(&mut self, dest: Uri) -> Result<H3Connection, BoxError> {
         let host = dest.host().ok_or("destination must have a host")?;
         let port = dest.port_u16().unwrap_or(443);
 
+        let proxies = self.proxies.clone();
+        for proxy in proxies.iter() {
+            if let Some(pac) = proxy.pac_for(&dest) {
+                return self.connect_via_pac(pac, &dest, host, port).await;
+            }
+            if let Some(proxy) = proxy.intercept(&dest) {
+                return self.proxied_connect(proxy, host, port).await;
+            }
+        }
+        self.direct_connect(host, port).await
+    }
+
+    /// Connects where the PAC script says, failing over to the next route
+    /// when one doesn't work, like TCP connections do.
+    async fn connect_via_pac(
+        &mut self,
+        pac: &Pac,
+        dest: &Uri,
+        host: &str,
+        port: u16,
+    ) -> Result<H3Connection, BoxError> {
+        let mut last_err = None;
+        for route in pac.routes(dest).await? {
+            let result = match route {
+                Route::Direct => self.direct_connect(host, port).await,
+                Route::Proxy(ref proxy) => self.proxied_connect(proxy.clone(), host, port).await,
+            };
+            // A proxy that can't relay UDP may still work for TCP.
+            match result {
+                Err(ref e) if NoUdpRelay::is_cause_of(&**e) => {}
+                _ => pac.report(&route, result.is_ok()),
+            }
+            match result {
+                Ok(conn) => return Ok(conn),
+                Err(e) => {
+                    log::debug!("PAC route {:?} to {:?} failed: {}", route, dest, e);
+                    last_err = Some(e);
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| "no PAC route to connect by".into()))
+    }
+
+    async fn direct_connect(&mut self, host: &str, port: u16) -> Result<H3Connection, BoxError> {
         let addrs = if let Some(addr) = IpAddr::from_str(host).ok() {
             // If the host is already an IP address, skip resolving.
             vec![SocketAddr::new(addr, port)]
         } else {
-            let addrs = resolve(&mut self.resolver, Name::from_str(host)?).await?;
-            let addrs = addrs.map(|mut addr| {
-                addr.set_port(port);
-                addr
-            });
-            addrs.collect()
+            // Like browsers, look up the HTTPS record alongside the host's
+            // addresses. A record advertising h3 may send the connection to
+            // another name and port.
+            let mut resolver = self.resolver.clone();
+            let (record, resolved) = future::join(
+                self.h3_record(host),
+                resolve(&mut resolver, Name::from_str(host)?),
+            )
+            .await;
+            let (target, port) = match record {
+                Some(ref record) => (
+                    record.target().unwrap_or(host),
+                    record.port().unwrap_or(port),
+                ),
+                None => (host, port),
+            };
+            let resolved = match target == host {
+                true => resolved,
+                false => resolve(&mut self.resolver, Name::from_str(target)?).await,
+            };
+
+            match resolved {
+                Ok(addrs) => addrs
+                    .map(|mut addr| {
+                        addr.set_port(port);
+                        addr
+                    })
+                    .collect(),
+                // The record's address hints are only a fallback.
+                Err(e) => match record {
+                    Some(ref record) if !record.ip_hints().is_empty() => record
+                        .ip_hints()
+                        .into_iter()
+                        .map(|ip| SocketAddr::new(ip, port))
+                        .collect(),
+                    _ => return Err(e),
+                },
+            }
         };
 
         self.remote_connect(addrs, host).await
     }
 
+    /// Connects through `proxy`, which has to be SOCKS5 to relay QUIC.
+    ///
+    /// HTTPS records aren't followed.
+    #[cfg_attr(not(feature = "socks"), allow(unused_variables))]
+    async fn proxied_connect(
+        &mut self,
+        proxy: ProxyScheme,
+        host: &str,
+        port: u16,
+    ) -> Result<H3Connection, BoxError> {
+        #[cfg(feature = "socks")]
+        if let ProxyScheme::Socks5 {
+            addr,
+            ref auth,
+            remote_dns,
+        } = proxy
+        {
+            let target = match IpAddr::from_str(host) {
+                Ok(ip) => UdpTarget::Addr(SocketAddr::new(ip, port)),
+                Err(_) if remote_dns => UdpTarget::Domain(host.to_owned(), port),
+                Err(_) => {
+                    let mut addrs = resolve(&mut self.resolver, Name::from_str(host)?).await?;
+                    let ip = addrs.next().ok_or("no address to connect to")?.ip();
+                    UdpTarget::Addr(SocketAddr::new(ip, port))
+                }
+            };
+
+            let auth = auth.as_ref().map(|(u, p)| (u.as_str(), p.as_str()));
+            let (control, relay) = match socks::udp_associate(addr, auth).await {
+                Ok(association) => association,
+                Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+                    return Err(Box::new(NoUdpRelay {
+                        proxy: format!("{:?}", proxy),
+                        reason: e.to_string(),
+                    }))
+                }
+                Err(e) => return Err(format!("socks connect error: {}", e).into()),
+            };
+            let socket = RelayedSocket::bind(control, relay, &target, self.local_addr).await?;
+            let mut endpoint = Endpoint::new_with_abstract_socket(
+                quinn::EndpointConfig::default(),
+                None,
+                socket,
+                Arc::new(quinn::TokioRuntime),
+            )?;
+            endpoint.set_default_client_config(self.config.clone());
+
+            let conn = endpoint.connect(relay, host)?.await?;
+            return handshake(conn).await;
+        }
+
+        Err(Box::new(NoUdpRelay {
+            proxy: format!("{:?}", proxy),
+            reason: "only SOCKS5 proxies relay UDP".into(),
+        }))
+    }
+
+    /// Returns the preferred HTTPS record of `host` advertising HTTP/3, if
+    /// the client follows them.
+    ///
+    /// Failed lookups are ignored, as browsers do.
+    pub async fn h3_record(&self, host: &str) -> Option<HttpsRecord> {
+        if !self.https_records || IpAddr::from_str(host).is_ok() {
+            return None;
+        }
+
+        let records = match self.resolver.resolve_https(Name::from_str(host).ok()?).await {
+            Ok(records) => records,
+            Err(e) => {
+                log::debug!("HTTPS record lookup for {} failed: {}", host, e);
+                return None;
+            }
+        };
+        crate::dns::svcb::select(&records, &["h3"]).cloned()
+    }
+
     async fn remote_connect(
         &mut self,
         addrs: Vec<SocketAddr>,
@@ -71,10 +286,7 @@ impl H3Connector {
         let mut err = None;
         for addr in addrs {
             match self.endpoint.connect(addr, server_name)?.await {
-                Ok(new_conn) => {
-                    let quinn_conn = Connection::new(new_conn);
-                    return Ok(h3::client::new(quinn_conn).await?);
-                }
+                Ok(new_conn) => return handshake(new_conn).await,
                 Err(e) => err = Some(e),
             }
         }
@@ -85,3 +297,13 @@ impl H3Connector {
         }
     }
 }
+
+/// Sets up HTTP/3 over a new QUIC connection.
+async fn handshake(conn: quinn::Connection) -> Result<H3Connection, BoxError> {
+    let info = ConnectionInfo {
+        tls: Some(Arc::new(OnceCell::with_value(TlsDetails::from_quic(&conn)))),
+        ..ConnectionInfo::default()
+    };
+    let (driver, tx) = h3::client::new(Connection::new(conn)).await?;
+    Ok((driver, tx, info))
+}
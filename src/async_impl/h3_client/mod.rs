@@ -3,13 +3,15 @@
 pub(crate) mod connect;
 pub(crate) mod dns;
 mod pool;
+#[cfg(feature = "socks")]
+mod socks;
 
 use crate::async_impl::h3_client::pool::{Key, Pool, PoolClient};
 use crate::error::{BoxError, Error, Kind};
 use crate::{error, Body};
 use connect::H3Connector;
 use futures_util::future;
-use http::{Request, Response};
+use http::{Request, Response, Uri};
 use hyper::Body as HyperBody;
 use log::trace;
 use std::future::Future;
@@ -41,8 +43,8 @@ impl H3Client {
 
         let dest = pool::domain_as_uri(key.clone());
         self.pool.connecting(key.clone())?;
-        let (driver, tx) = self.connector.connect(dest).await?;
-        Ok(self.pool.new_connection(key, driver, tx))
+        let (driver, tx, info) = self.connector.connect(dest).await?;
+        Ok(self.pool.new_connection(key, driver, tx, info))
     }
 
     async fn send_request(
@@ -60,6 +62,23 @@ impl H3Client {
             .map_err(|e| Error::new(Kind::Request, Some(e)))
     }
 
+    /// Returns whether the DNS HTTPS records of `uri`'s host advertise
+    /// HTTP/3, so a request to it can use this client.
+    ///
+    /// Records carrying an ECH config don't count: QUIC handshakes here
+    /// can't encrypt the ClientHello, which TCP ones would.
+    pub fn advertised(&self, uri: &Uri) -> Pin<Box<dyn Future<Output = bool> + Send>> {
+        let connector = self.connector.clone();
+        let host = uri.host().map(str::to_owned);
+        Box::pin(async move {
+            let record = match host {
+                Some(host) => connector.h3_record(&host).await,
+                None => None,
+            };
+            record.map_or(false, |record| record.ech_config_list().is_none())
+        })
+    }
+
     pub fn request(&self, mut req: Request<Body>) -> H3ResponseFuture {
         let pool_key = match pool::extract_domain(req.uri_mut()) {
             Ok(s) => s,
@@ -5,6 +5,7 @@ use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::time::Instant;
 
+use crate::connection_info::ConnectionInfo;
 use crate::error::{BoxError, Error, Kind};
 use crate::Body;
 use bytes::Buf;
@@ -73,6 +74,7 @@ impl Pool {
         key: Key,
         mut driver: h3::client::Connection<Connection, Bytes>,
         tx: SendRequest<OpenStreams, Bytes>,
+        info: ConnectionInfo,
     ) -> PoolClient {
         let (close_tx, close_rx) = std::sync::mpsc::channel();
         tokio::spawn(async move {
@@ -84,7 +86,7 @@ impl Pool {
 
         let mut inner = self.inner.lock().unwrap();
 
-        let client = PoolClient::new(tx);
+        let client = PoolClient::new(tx, info);
         let conn = PoolConnection::new(client.clone(), close_rx);
         inner.insert(key.clone(), conn);
 
@@ -115,11 +117,12 @@ impl PoolInner {
 #[derive(Clone)]
 pub struct PoolClient {
     inner: SendRequest<OpenStreams, Bytes>,
+    info: ConnectionInfo,
 }
 
 impl PoolClient {
-    pub fn new(tx: SendRequest<OpenStreams, Bytes>) -> Self {
-        Self { inner: tx }
+    pub fn new(tx: SendRequest<OpenStreams, Bytes>, info: ConnectionInfo) -> Self {
+        Self { inner: tx, info }
     }
 
     pub async fn send_request(
@@ -139,7 +142,8 @@ impl PoolClient {
 
         stream.finish().await?;
 
-        let resp = stream.recv_response().await?;
+        let mut resp = stream.recv_response().await?;
+        resp.extensions_mut().insert(self.info.clone());
 
         let mut resp_body = Vec::new();
         while let Some(chunk) = stream.recv_data().await? {
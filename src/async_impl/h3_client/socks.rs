@@ -0,0 +1,135 @@
+//! QUIC through the UDP relay of a SOCKS5 proxy
+
+use std::fmt;
+use std::io::{self, IoSliceMut};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::task::{Context, Poll};
+
+use futures_util::ready;
+use quinn::udp::{RecvMeta, Transmit, UdpState};
+use quinn::AsyncUdpSocket;
+use tokio::io::ReadBuf;
+use tokio::net::{TcpStream, UdpSocket};
+
+use crate::connect::socks::{udp_header, udp_header_len, UdpTarget};
+
+/// A UDP socket sending every datagram to one target through a SOCKS5
+/// relay, which QUIC sees as its peer.
+pub(super) struct RelayedSocket {
+    io: UdpSocket,
+    relay: SocketAddr,
+    header: Vec<u8>,
+    // The association ends once it's closed.
+    _control: TcpStream,
+}
+
+impl RelayedSocket {
+    pub(super) async fn bind(
+        control: TcpStream,
+        relay: SocketAddr,
+        target: &UdpTarget,
+        local_ip: Option<IpAddr>,
+    ) -> io::Result<RelayedSocket> {
+        let local_ip = match local_ip {
+            Some(ip) if ip.is_ipv4() == relay.is_ipv4() => ip,
+            _ if relay.is_ipv4() => Ipv4Addr::UNSPECIFIED.into(),
+            _ => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let io = UdpSocket::bind(SocketAddr::new(local_ip, 0)).await?;
+        io.connect(relay).await?;
+        Ok(RelayedSocket {
+            io,
+            relay,
+            header: udp_header(target)?,
+            _control: control,
+        })
+    }
+}
+
+impl RelayedSocket {
+    /// Prefixes `segment` with the SOCKS5 header addressing the target.
+    fn datagram(&self, segment: &[u8]) -> Vec<u8> {
+        let mut datagram = Vec::with_capacity(self.header.len() + segment.len());
+        datagram.extend_from_slice(&self.header);
+        datagram.extend_from_slice(segment);
+        datagram
+    }
+}
+
+impl AsyncUdpSocket for RelayedSocket {
+    fn poll_send(
+        &self,
+        _state: &UdpState,
+        cx: &mut Context,
+        transmits: &[Transmit],
+    ) -> Poll<io::Result<usize>> {
+        let mut sent = 0;
+        for transmit in transmits {
+            // Batches are split, as the relay takes one datagram at a time.
+            let segment_size = transmit.segment_size.unwrap_or(transmit.contents.len());
+            let mut segments = transmit.contents.chunks(segment_size.max(1));
+            let first = segments.next().unwrap_or_default();
+            match self.io.poll_send(cx, &self.datagram(first)) {
+                Poll::Ready(Ok(_)) => (),
+                Poll::Ready(Err(e)) if sent == 0 => return Poll::Ready(Err(e)),
+                Poll::Pending if sent == 0 => return Poll::Pending,
+                Poll::Ready(Err(_)) | Poll::Pending => return Poll::Ready(Ok(sent)),
+            }
+            // Once a transmit is partly sent it counts as sent, so the
+            // segments the socket has no room for are dropped, like any lost
+            // datagram, rather than sent twice.
+            for segment in segments {
+                if let Err(e) = self.io.try_send(&self.datagram(segment)) {
+                    log::trace!("dropping a datagram to {}: {}", self.relay, e);
+                    break;
+                }
+            }
+            sent += 1;
+        }
+        Poll::Ready(Ok(sent))
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let len = {
+                let mut buf = ReadBuf::new(&mut bufs[0]);
+                ready!(self.io.poll_recv(cx, &mut buf))?;
+                buf.filled().len()
+            };
+            let header_len = match udp_header_len(&bufs[0][..len]) {
+                Some(header_len) => header_len,
+                None => {
+                    log::debug!("dropping a malformed datagram from {}", self.relay);
+                    continue;
+                }
+            };
+            bufs[0].copy_within(header_len..len, 0);
+            let len = len - header_len;
+            meta[0] = RecvMeta {
+                addr: self.relay,
+                len,
+                stride: len,
+                ecn: None,
+                dst_ip: None,
+            };
+            return Poll::Ready(Ok(1));
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.io.local_addr()
+    }
+}
+
+impl fmt::Debug for RelayedSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RelayedSocket")
+            .field("relay", &self.relay)
+            .finish()
+    }
+}
@@ -22,7 +22,7 @@ use crate::impersonate::Impersonate;
 use crate::tls;
 #[cfg(feature = "__tls")]
 use crate::Certificate;
-#[cfg(any(feature = "native-tls", feature = "__rustls"))]
+#[cfg(any(feature = "native-tls", feature = "__rustls", feature = "__boring"))]
 use crate::Identity;
 use crate::{async_impl, header, redirect, IntoUrl, Method, Proxy};
 
@@ -102,6 +102,14 @@ impl ClientBuilder {
         self.with_inner(move |inner| inner.enable_ech_grease())
     }
 
+    /// Encrypt the ClientHello sent to `host` with Encrypted Client Hello.
+    ///
+    /// See [`async_impl::ClientBuilder::ech_config`](crate::ClientBuilder::ech_config).
+    #[cfg(feature = "__impersonate")]
+    pub fn ech_config(self, host: &str, config_list: impl Into<Vec<u8>>) -> ClientBuilder {
+        self.with_inner(move |inner| inner.ech_config(host, config_list))
+    }
+
 
     /// Enable TLS permute_extensions
     #[cfg(feature = "__impersonate")]
@@ -237,6 +245,36 @@ impl ClientBuilder {
         self.with_inner(|inner| inner.cookie_provider(cookie_store))
     }
 
+    /// Answer HTTP Digest challenges of every host with these credentials.
+    ///
+    /// # Optional
+    ///
+    /// This requires the optional `digest-auth` feature to be enabled.
+    #[cfg(feature = "digest-auth")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "digest-auth")))]
+    pub fn digest_auth<U, P>(self, username: U, password: P) -> ClientBuilder
+    where
+        U: fmt::Display,
+        P: fmt::Display,
+    {
+        self.with_inner(move |inner| inner.digest_auth(username, password))
+    }
+
+    /// Answer HTTP Digest challenges of `host` with these credentials.
+    ///
+    /// # Optional
+    ///
+    /// This requires the optional `digest-auth` feature to be enabled.
+    #[cfg(feature = "digest-auth")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "digest-auth")))]
+    pub fn digest_auth_for_host<U, P>(self, host: &str, username: U, password: P) -> ClientBuilder
+    where
+        U: fmt::Display,
+        P: fmt::Display,
+    {
+        self.with_inner(move |inner| inner.digest_auth_for_host(host, username, password))
+    }
+
     /// Enable auto gzip decompression by checking the `Content-Encoding` response header.
     ///
     /// If auto gzip decompresson is turned on:
@@ -399,6 +437,13 @@ impl ClientBuilder {
         self.with_inner(move |inner| inner.no_proxy())
     }
 
+    /// Spread requests across the proxies of `pool`.
+    ///
+    /// See [`crate::ClientBuilder::proxy_pool`].
+    pub fn proxy_pool(self, pool: crate::ProxyPool) -> ClientBuilder {
+        self.with_inner(move |inner| inner.proxy_pool(pool))
+    }
+
     // Timeout options
 
     /// Set a timeout for connect, read and write operations of a `Client`.
@@ -592,6 +637,13 @@ impl ClientBuilder {
         self.with_inner(move |inner| inner.local_addresses(addr_ipv4, addr_ipv6))
     }
 
+    /// Sets which IP versions connections are made over, and in which order.
+    ///
+    /// See [`async_impl::ClientBuilder::ip_version`](crate::ClientBuilder::ip_version).
+    pub fn ip_version(self, preference: crate::dns::IpPreference) -> ClientBuilder {
+        self.with_inner(move |inner| inner.ip_version(preference))
+    }
+
     /// Set that all sockets have `SO_KEEPALIVE` set with the supplied duration.
     ///
     /// If `None`, the option will not be set.
@@ -673,10 +725,17 @@ impl ClientBuilder {
     ///
     /// # Optional
     ///
-    /// This requires the optional `native-tls` or `rustls-tls(-...)` feature to be
-    /// enabled.
-    #[cfg(any(feature = "native-tls", feature = "__rustls"))]
-    #[cfg_attr(docsrs, doc(cfg(any(feature = "native-tls", feature = "rustls-tls"))))]
+    /// This requires the optional `native-tls`, `rustls-tls(-...)` or
+    /// `boring-tls(-...)` feature to be enabled.
+    #[cfg(any(feature = "native-tls", feature = "__rustls", feature = "__boring"))]
+    #[cfg_attr(
+        docsrs,
+        doc(cfg(any(
+            feature = "native-tls",
+            feature = "rustls-tls",
+            feature = "boring-tls"
+        )))
+    )]
     pub fn identity(self, identity: Identity) -> ClientBuilder {
         self.with_inner(move |inner| inner.identity(identity))
     }
@@ -881,6 +940,15 @@ impl ClientBuilder {
         self.with_inner(|inner| inner.hickory_dns(enable))
     }
 
+    /// Configures the hickory-dns async resolver, and enables it.
+    ///
+    /// See [`crate::ClientBuilder::hickory_config`].
+    #[cfg(feature = "hickory-dns")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "hickory-dns")))]
+    pub fn hickory_config(self, config: crate::dns::HickoryConfig) -> ClientBuilder {
+        self.with_inner(move |inner| inner.hickory_config(config))
+    }
+
     /// Disables the hickory-dns async resolver.
     ///
     /// This method exists even if the optional `hickory-dns` feature is not enabled.
@@ -921,6 +989,13 @@ impl ClientBuilder {
         self.with_inner(|inner| inner.resolve_to_addrs(domain, addrs))
     }
 
+    /// Follow the DNS HTTPS records of hosts, as browsers do.
+    ///
+    /// See [`async_impl::ClientBuilder::https_dns_records`](crate::ClientBuilder::https_dns_records).
+    pub fn https_dns_records(self, enabled: bool) -> ClientBuilder {
+        self.with_inner(|inner| inner.https_dns_records(enabled))
+    }
+
     // private
 
     fn with_inner<F>(mut self, func: F) -> ClientBuilder
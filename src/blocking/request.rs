@@ -129,7 +129,13 @@ impl Request {
         };
         let mut req = Request::new(self.method().clone(), self.url().clone());
         *req.headers_mut() = self.headers().clone();
-        *req.version_mut() = self.version().clone();
+        *req.inner.explicit_version_mut() = self.inner.explicit_version();
+        #[cfg(feature = "__impersonate")]
+        {
+            *req.inner.impersonate_mut() = self.inner.impersonate();
+        }
+        *req.inner.resolve_to_mut() = self.inner.resolve_to();
+        *req.inner.proxies_mut() = self.inner.proxies().map(<[_]>::to_vec);
         req.body = body;
         Some(req)
     }
@@ -269,6 +275,36 @@ impl RequestBuilder {
         self.header_sensitive(crate::header::AUTHORIZATION, header_value, true)
     }
 
+    /// Answer HTTP Digest challenges to this request with these credentials.
+    ///
+    /// ```rust
+    /// # use chromimic as reqwest;
+    /// # fn run() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = reqwest::blocking::Client::new();
+    /// let resp = client.get("http://httpbin.org/digest-auth/auth/user/passwd")
+    ///     .digest_auth("user", "passwd")
+    ///     .send()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Optional
+    ///
+    /// This requires the optional `digest-auth` feature to be enabled.
+    #[cfg(feature = "digest-auth")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "digest-auth")))]
+    pub fn digest_auth<U, P>(mut self, username: U, password: P) -> RequestBuilder
+    where
+        U: fmt::Display,
+        P: fmt::Display,
+    {
+        if let Ok(ref mut req) = self.request {
+            *req.inner.digest_auth_mut() =
+                Some(crate::digest::Credentials::new(username, password));
+        }
+        self
+    }
+
     /// Enable HTTP bearer authentication.
     ///
     /// ```rust
@@ -410,6 +446,61 @@ impl RequestBuilder {
         self
     }
 
+    /// Impersonate another browser for this request only.
+    ///
+    /// See [`crate::RequestBuilder::impersonate`].
+    #[cfg(feature = "__impersonate")]
+    pub fn impersonate(mut self, ver: crate::impersonate::Impersonate) -> RequestBuilder {
+        if let Ok(ref mut req) = self.request {
+            *req.inner.impersonate_mut() = Some(ver);
+        }
+        self
+    }
+
+    /// Connect to `addr` for this request instead of resolving its host.
+    ///
+    /// See [`crate::RequestBuilder::resolve_to`].
+    pub fn resolve_to(mut self, addr: std::net::SocketAddr) -> RequestBuilder {
+        if let Ok(ref mut req) = self.request {
+            *req.inner.resolve_to_mut() = Some(addr);
+        }
+        self
+    }
+
+    /// Send this request through `proxy` instead of the client's proxies.
+    ///
+    /// See [`crate::RequestBuilder::proxy`].
+    pub fn proxy(mut self, proxy: crate::Proxy) -> RequestBuilder {
+        if let Ok(ref mut req) = self.request {
+            req.inner
+                .proxies_mut()
+                .get_or_insert_with(Vec::new)
+                .push(proxy);
+        }
+        self
+    }
+
+    /// Send this request without any proxy, whatever the client's.
+    ///
+    /// See [`crate::RequestBuilder::no_proxy`].
+    pub fn no_proxy(mut self) -> RequestBuilder {
+        if let Ok(ref mut req) = self.request {
+            *req.inner.proxies_mut() = Some(Vec::new());
+        }
+        self
+    }
+
+    /// Sets the session of this request, for a client whose proxy pool
+    /// sticks to a proxy per session.
+    ///
+    /// See [`crate::RequestBuilder::proxy_session`].
+    pub fn proxy_session(mut self, session: impl Into<String>) -> RequestBuilder {
+        if let Ok(ref mut req) = self.request {
+            *req.inner.proxy_session_mut() = Some(session.into());
+        }
+        self
+    }
+
     /// Send a form body.
     ///
     /// Sets the body to the url encoded serialization of the passed value,
@@ -1,21 +1,24 @@
 #[cfg(feature = "__boring")]
-use boring::ssl::{ConnectConfiguration, SslConnectorBuilder};
+use boring::ssl::{ConnectConfiguration, SslConnector, SslConnectorBuilder};
 #[cfg(feature = "__boring")]
 use foreign_types::ForeignTypeRef;
 #[cfg(feature = "__tls")]
-use http::header::HeaderValue;
+use http::header::{HeaderName, HeaderValue, PROXY_AUTHORIZATION};
+use http::HeaderMap;
 use http::uri::{Authority, Scheme};
 use http::Uri;
 use hyper::client::connect::{Connected, Connection};
 use hyper::service::Service;
 #[cfg(feature = "native-tls-crate")]
 use native_tls_crate::{TlsConnector, TlsConnectorBuilder};
+#[cfg(feature = "__tls")]
+use once_cell::sync::OnceCell;
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
 use pin_project_lite::pin_project;
 use std::future::Future;
 use std::io::{self, IoSlice};
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
@@ -26,12 +29,24 @@ use self::boring_tls_conn::BoringTlsConn;
 #[cfg(feature = "default-tls")]
 use self::native_tls_conn::NativeTlsConn;
 #[cfg(feature = "__rustls")]
-use self::rustls_tls_conn::RustlsTlsConn;
+use self::rustls_tls_conn::{MaybeHttpsStream, RustlsTlsConn};
+use crate::connection_info::{ConnectionInfo, Http2Sniffer};
 use crate::dns::DynResolver;
+#[cfg(feature = "__boring")]
+use crate::dns::HttpsRecord;
+#[cfg(feature = "socks")]
+use crate::dns::IpPreference;
 use crate::error::BoxError;
 #[cfg(feature = "impersonate")]
-use crate::impersonate::profile::ClientProfile;
+use crate::impersonate::profile::{ClientProfile, Impersonate};
+#[cfg(feature = "impersonate")]
+use crate::impersonate::ApplicationSettings;
+use crate::pac::{Pac, Route};
 use crate::proxy::{Proxy, ProxyScheme};
+#[cfg(feature = "__tls")]
+use crate::tls::CertificatePins;
+#[cfg(feature = "__boring")]
+use crate::tls::{BoringIdentity, EchConfigs, SessionKey, TlsSessionCache};
 
 pub(crate) type HttpConnector = hyper::client::HttpConnector<DynResolver>;
 
@@ -41,12 +56,21 @@ pub(crate) struct Connector {
     proxies: Arc<Vec<Proxy>>,
     verbose: verbose::Wrapper,
     timeout: Option<Duration>,
+    connect_to: Option<SocketAddr>,
+    #[cfg(feature = "socks")]
+    ip_preference: Option<IpPreference>,
+    #[cfg(feature = "socks")]
+    socks_resolver: Option<DynResolver>,
     #[cfg(feature = "__tls")]
     nodelay: bool,
     #[cfg(feature = "__tls")]
     tls_info: bool,
     #[cfg(feature = "__tls")]
     user_agent: Option<HeaderValue>,
+    #[cfg(feature = "__tls")]
+    certificate_pins: Arc<CertificatePins>,
+    #[cfg(feature = "__boring")]
+    https_records: Option<DynResolver>,
     #[cfg(feature = "impersonate")]
     impersonate_context: ImpersonateContext
 }
@@ -66,17 +90,154 @@ enum Inner {
     #[cfg(feature = "__boring")]
     BoringTls {
         http: HttpConnector,
-        tls: Arc<dyn Fn(bool) -> SslConnectorBuilder + Send + Sync>,
+        tls: BoringConnectors,
     },
 }
 
+/// The `SslConnector`s built from one TLS backend function.
+///
+/// Building a connector loads the root store and sets up the profile's
+/// cipher, signature algorithm and curve lists, so each one is built on first
+/// use and shared by every connection after. Only per-connection options are
+/// applied in `Connector::boring_config`.
+#[cfg(feature = "__boring")]
+#[derive(Clone)]
+struct BoringConnectors {
+    tls: Arc<dyn Fn(bool) -> SslConnectorBuilder + Send + Sync>,
+    sessions: Option<TlsSessionCache>,
+    identity: Option<BoringIdentity>,
+    // Keyed by (h2, certs_verification); the root store is part of `tls`.
+    built: Arc<std::sync::Mutex<std::collections::HashMap<(bool, bool), SslConnector>>>,
+}
+
+#[cfg(feature = "__boring")]
+impl BoringConnectors {
+    fn new(
+        tls: Arc<dyn Fn(bool) -> SslConnectorBuilder + Send + Sync>,
+        sessions: Option<TlsSessionCache>,
+    ) -> BoringConnectors {
+        BoringConnectors {
+            tls,
+            sessions,
+            identity: None,
+            built: Arc::default(),
+        }
+    }
+
+    fn get(
+        &self,
+        h2: bool,
+        certs_verification: bool,
+    ) -> Result<SslConnector, boring::error::ErrorStack> {
+        let mut built = self.built.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(connector) = built.get(&(h2, certs_verification)) {
+            return Ok(connector.clone());
+        }
+
+        let mut builder = (self.tls)(h2);
+        if !certs_verification {
+            builder.set_verify(boring::ssl::SslVerifyMode::NONE);
+        }
+        if let Some(ref identity) = self.identity {
+            identity.add_to_boring(&mut builder)?;
+        }
+        if let Some(ref sessions) = self.sessions {
+            sessions.attach(&mut builder);
+        }
+        let connector = builder.build();
+        built.insert((h2, certs_verification), connector.clone());
+        Ok(connector)
+    }
+}
+
+/// The TLS options of a connector using BoringSSL.
+#[cfg(feature = "__boring")]
+pub(crate) struct BoringTlsOptions {
+    pub tls: Arc<dyn Fn(bool) -> SslConnectorBuilder + Send + Sync>,
+    pub sessions: Option<TlsSessionCache>,
+    pub tls_info: bool,
+    pub impersonate_context: ImpersonateContext,
+}
+
 #[derive(Clone)]
 pub(crate) struct ImpersonateContext {
     pub profile: ClientProfile,
+    // The browser asked for, if `profile` isn't just the default.
+    pub impersonate: Option<Impersonate>,
     pub enable_ech_grease: bool,
     pub permute_extensions: bool,
+    pub application_settings: Option<ApplicationSettings>,
     pub certs_verification: bool,
     pub h2: bool,
+    pub early_data: bool,
+    pub ech_configs: Arc<EchConfigs>,
+}
+
+/// The context of connectors built without BoringSSL, which don't
+/// impersonate anything.
+#[cfg(feature = "impersonate")]
+impl Default for ImpersonateContext {
+    fn default() -> ImpersonateContext {
+        ImpersonateContext {
+            profile: ClientProfile::Chrome,
+            impersonate: None,
+            enable_ech_grease: false,
+            permute_extensions: false,
+            application_settings: None,
+            certs_verification: true,
+            h2: true,
+            early_data: false,
+            ech_configs: Arc::default(),
+        }
+    }
+}
+
+/// What a handshake does about Encrypted Client Hello.
+#[cfg(feature = "__boring")]
+enum Ech {
+    /// Encrypt the ClientHello with the `ECHConfigList` configured for the
+    /// host, or else the one from its DNS HTTPS record. If there's neither
+    /// and some hosts have configs, send GREASE.
+    Configured(Option<Vec<u8>>),
+    /// Encrypt it with the retry configs a server sent when rejecting ECH.
+    Retry(Vec<u8>),
+    /// The server turned ECH off, so don't offer it.
+    Disabled,
+}
+
+/// Returns how to retry a handshake that failed because the server rejected
+/// ECH, or `None` if it failed for another reason.
+#[cfg(feature = "__boring")]
+fn ech_rejected<S: std::fmt::Debug>(err: &tokio_boring::HandshakeError<S>) -> Option<Ech> {
+    let mut source = std::error::Error::source(err);
+    let rejected = loop {
+        match source {
+            Some(err) => match err.downcast_ref::<boring::error::ErrorStack>() {
+                Some(stack) => {
+                    break stack.errors().iter().any(|e| {
+                        boring_sys::ERR_GET_LIB(e.code()) == boring_sys::ERR_LIB_SSL.0 as i32
+                            && boring_sys::ERR_GET_REASON(e.code())
+                                == boring_sys::SSL_R_ECH_REJECTED
+                    })
+                }
+                None => source = err.source(),
+            },
+            None => break false,
+        }
+    };
+    if !rejected {
+        return None;
+    }
+
+    let ssl = err.ssl()?;
+    let mut configs = std::ptr::null();
+    let mut len = 0;
+    unsafe { boring_sys::SSL_get0_ech_retry_configs(ssl.as_ptr(), &mut configs, &mut len) };
+    if len == 0 {
+        Some(Ech::Disabled)
+    } else {
+        Some(Ech::Retry(unsafe { std::slice::from_raw_parts(configs, len) }.to_vec()))
+    }
 }
 
 #[cfg(feature = "__boring")]
@@ -99,17 +260,23 @@ fn tls_add_application_settings(conf: &mut ConnectConfiguration, ctx: &Impersona
             if ctx.h2 {
                 conf.set_alpn_protos(b"\x02h2\x08http/1.1").unwrap();
 
-                const ALPN_H2: &str = "h2";
-                const ALPN_H2_LENGTH: usize = 2;
-                unsafe {
-                    boring_sys::SSL_add_application_settings(
-                        conf.as_ptr(),
-                        ALPN_H2.as_ptr(),
-                        ALPN_H2_LENGTH,
-                        std::ptr::null(),
-                        0,
-                    );
-                };
+                if let Some(ref settings) = ctx.application_settings {
+                    for protocol in settings.protocols {
+                        let payload: &[u8] = match *protocol {
+                            "h2" => &settings.payload,
+                            _ => &[],
+                        };
+                        unsafe {
+                            boring_sys::SSL_add_application_settings(
+                                conf.as_ptr(),
+                                protocol.as_ptr(),
+                                protocol.len(),
+                                payload.as_ptr(),
+                                payload.len(),
+                            );
+                        };
+                    }
+                }
             }
         }
         ClientProfile::OkHttp | ClientProfile::Safari | ClientProfile::Firefox => {}
@@ -137,6 +304,11 @@ impl Connector {
         Connector {
             inner: Inner::Http(http),
             verbose: verbose::OFF,
+            connect_to: None,
+            #[cfg(feature = "socks")]
+            ip_preference: None,
+            #[cfg(feature = "socks")]
+            socks_resolver: None,
             proxies,
             timeout: None,
         }
@@ -184,26 +356,32 @@ impl Connector {
             inner: Inner::DefaultTls(http, tls),
             proxies,
             verbose: verbose::OFF,
+            connect_to: None,
+            #[cfg(feature = "socks")]
+            ip_preference: None,
+            #[cfg(feature = "socks")]
+            socks_resolver: None,
             timeout: None,
             nodelay,
             tls_info,
             user_agent,
+            certificate_pins: Arc::default(),
+            #[cfg(feature = "__boring")]
+            https_records: None,
             #[cfg(feature = "impersonate")]
-            client_profile: ClientProfile::Chrome
+            impersonate_context: ImpersonateContext::default(),
         }
     }
 
     #[cfg(feature = "__boring")]
     pub(crate) fn new_boring_tls(
         mut http: HttpConnector,
-        tls: Arc<dyn Fn(bool) -> SslConnectorBuilder + Send + Sync>,
+        options: BoringTlsOptions,
         proxies: Arc<Vec<Proxy>>,
         user_agent: Option<HeaderValue>,
         local_addr_v4: Option<Ipv4Addr>,
         local_addr_v6: Option<Ipv6Addr>,
         nodelay: bool,
-        tls_info: bool,
-        impersonate_context: ImpersonateContext,
     ) -> Connector {
 
         match (local_addr_v4, local_addr_v6) {
@@ -215,19 +393,30 @@ impl Connector {
         http.enforce_http(false);
 
         Connector {
-            inner: Inner::BoringTls { http, tls },
+            inner: Inner::BoringTls {
+                http,
+                tls: BoringConnectors::new(options.tls, options.sessions),
+            },
             proxies,
             verbose: verbose::OFF,
+            connect_to: None,
+            #[cfg(feature = "socks")]
+            ip_preference: None,
+            #[cfg(feature = "socks")]
+            socks_resolver: None,
             timeout: None,
             nodelay,
             user_agent,
-            tls_info,
-            impersonate_context
+            tls_info: options.tls_info,
+            certificate_pins: Arc::default(),
+            #[cfg(feature = "__boring")]
+            https_records: None,
+            impersonate_context: options.impersonate_context,
         }
     }
 
     #[cfg(feature = "__rustls")]
-    pub(crate) fn new_rustls_tls<T>(
+    pub(crate) fn new_rustls_tls(
         mut http: HttpConnector,
         tls: rustls::ClientConfig,
         proxies: Arc<Vec<Proxy>>,
@@ -263,11 +452,20 @@ impl Connector {
             },
             proxies,
             verbose: verbose::OFF,
+            connect_to: None,
+            #[cfg(feature = "socks")]
+            ip_preference: None,
+            #[cfg(feature = "socks")]
+            socks_resolver: None,
             timeout: None,
             nodelay,
             tls_info,
             user_agent,
-            client_profile: ClientProfile::Chrome
+            certificate_pins: Arc::default(),
+            #[cfg(feature = "__boring")]
+            https_records: None,
+            #[cfg(feature = "impersonate")]
+            impersonate_context: ImpersonateContext::default(),
         }
     }
 
@@ -279,14 +477,332 @@ impl Connector {
         self.verbose.0 = enabled;
     }
 
+    /// Returns a copy of this connector going through `proxies` instead of
+    /// its own, if any, and whose direct connections all go to `connect_to`,
+    /// if any, for requests sent with `RequestBuilder::proxy` or pinned with
+    /// `RequestBuilder::resolve_to`.
+    ///
+    /// Unless `early_data` is true, its connections don't send TLS early
+    /// data even if the client allows it.
+    pub(crate) fn routed(
+        &self,
+        proxies: Option<Arc<Vec<Proxy>>>,
+        connect_to: Option<SocketAddr>,
+        early_data: bool,
+    ) -> Connector {
+        let mut connector = self.clone();
+        if let Some(proxies) = proxies {
+            connector.proxies = proxies;
+        }
+        connector.connect_to = connect_to;
+        #[cfg(feature = "impersonate")]
+        {
+            connector.impersonate_context.early_data &= early_data;
+        }
+        #[cfg(not(feature = "impersonate"))]
+        let _ = early_data;
+        connector
+    }
+
+    /// Whether the client or profile allows sending TLS early data.
+    pub(crate) fn early_data(&self) -> bool {
+        #[cfg(feature = "impersonate")]
+        return self.impersonate_context.early_data;
+        #[cfg(not(feature = "impersonate"))]
+        false
+    }
+
+    /// Whether an ECH config was given for `host`.
+    #[cfg(all(feature = "http3", feature = "impersonate"))]
+    pub(crate) fn has_ech_config(&self, host: &str) -> bool {
+        self.impersonate_context.ech_configs.get(host).is_some()
+    }
+
+    /// Orders the addresses of SOCKS destinations resolved locally.
+    #[cfg(feature = "socks")]
+    pub(crate) fn set_ip_preference(&mut self, preference: Option<IpPreference>) {
+        self.ip_preference = preference;
+    }
+
+    /// Resolves SOCKS destinations that aren't resolved by the proxy with
+    /// `resolver`, like direct connections.
+    #[cfg(feature = "socks")]
+    pub(crate) fn set_socks_resolver(&mut self, resolver: DynResolver) {
+        self.socks_resolver = Some(resolver);
+    }
+
+    /// Sets the client certificate presented by BoringSSL connections.
+    #[cfg(feature = "__boring")]
+    #[allow(irrefutable_let_patterns)]
+    pub(crate) fn set_boring_identity(&mut self, identity: BoringIdentity) {
+        if let Inner::BoringTls { tls, .. } = &mut self.inner {
+            tls.identity = Some(identity);
+            tls.built = Arc::default();
+        }
+    }
+
+    #[cfg(feature = "__tls")]
+    pub(crate) fn set_certificate_pins(&mut self, pins: CertificatePins) {
+        self.certificate_pins = Arc::new(pins);
+    }
+
+    /// Makes direct connections follow the DNS HTTPS records `resolver`
+    /// finds.
+    #[cfg(feature = "__boring")]
+    pub(crate) fn set_https_records(&mut self, resolver: DynResolver) {
+        self.https_records = Some(resolver);
+    }
+
+    /// Returns a copy of this connector that handshakes as another profile.
+    ///
+    /// Returns `None` if the connector isn't backed by BoringSSL.
+    #[cfg(feature = "impersonate")]
+    pub(crate) fn impersonated(
+        &self,
+        tls: Arc<dyn Fn(bool) -> SslConnectorBuilder + Send + Sync>,
+        user_agent: Option<HeaderValue>,
+        impersonate: Impersonate,
+        application_settings: Option<ApplicationSettings>,
+        early_data: bool,
+    ) -> Option<Connector> {
+        match &self.inner {
+            Inner::BoringTls { http, tls: current } => {
+                // Sessions are kept per profile, so the cache can be shared.
+                let mut tls = BoringConnectors::new(tls, current.sessions.clone());
+                tls.identity = current.identity.clone();
+
+                let mut connector = self.clone();
+                connector.inner = Inner::BoringTls {
+                    http: http.clone(),
+                    tls,
+                };
+                connector.user_agent = user_agent;
+                connector.impersonate_context.profile = impersonate.profile();
+                connector.impersonate_context.impersonate = Some(impersonate);
+                connector.impersonate_context.application_settings = application_settings;
+                connector.impersonate_context.early_data = early_data;
+                Some(connector)
+            }
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+
+    /// Creates the configuration for a TLS handshake with `host`, offering a
+    /// session cached for the same `route` and profile if there is one.
+    ///
+    /// Early data is only sent if the connector allows it (see
+    /// [`Connector::routed`]) and `early_data` is true.
+    #[cfg(feature = "__boring")]
+    fn boring_config(
+        &self,
+        tls: &BoringConnectors,
+        host: &str,
+        port: u16,
+        route: String,
+        early_data: bool,
+        ech: &Ech,
+    ) -> Result<ConnectConfiguration, BoxError> {
+        let ctx = &self.impersonate_context;
+        let mut conf = tls.get(ctx.h2, ctx.certs_verification)?.configure()?;
+        tls_add_application_settings(&mut conf, ctx);
+        let ech_config_list = match *ech {
+            Ech::Configured(ref from_dns) => {
+                if !ctx.ech_configs.is_empty() {
+                    // Like Chrome, send GREASE to hosts without a config, so
+                    // real ECH doesn't stand out.
+                    unsafe { boring_sys::SSL_set_enable_ech_grease(conf.as_ptr(), 1) };
+                }
+                ctx.ech_configs.get(host).or(from_dns.as_deref())
+            }
+            Ech::Retry(ref configs) => Some(configs.as_slice()),
+            Ech::Disabled => {
+                unsafe { boring_sys::SSL_set_enable_ech_grease(conf.as_ptr(), 0) };
+                None
+            }
+        };
+        if let Some(list) = ech_config_list {
+            let ret = unsafe {
+                boring_sys::SSL_set1_ech_config_list(conf.as_ptr(), list.as_ptr(), list.len())
+            };
+            if ret != 1 {
+                return Err(boring::error::ErrorStack::get().into());
+            }
+        }
+        if ctx.early_data && early_data {
+            unsafe { boring_sys::SSL_set_early_data_enabled(conf.as_ptr(), 1) };
+        }
+        if let Some(ref sessions) = tls.sessions {
+            let profile = self
+                .impersonate_context
+                .impersonate
+                .map(|impersonate| impersonate.to_string())
+                .unwrap_or_default();
+            sessions.resume(&mut conf, SessionKey::new(host, port, route, profile))?;
+        }
+        Ok(conf)
+    }
+
+    /// Connects to `dst`, handshaking TLS if it's an `https` URI.
+    ///
+    /// With an HTTPS `record`, the TCP connection goes to its target and
+    /// port, and the handshake uses its ECH config. A `pinned` address
+    /// overrides both.
+    #[cfg(feature = "__boring")]
+    async fn boring_connect(
+        &self,
+        http: &mut HttpConnector,
+        tls: &BoringConnectors,
+        dst: Uri,
+        early_data: bool,
+        record: Option<&HttpsRecord>,
+        pinned: Option<SocketAddr>,
+    ) -> Result<hyper_boring::MaybeHttpsStream<tokio::net::TcpStream>, BoxError> {
+        if dst.scheme() != Some(&Scheme::HTTPS) {
+            return Ok(hyper_boring::MaybeHttpsStream::Http(http.call(dst).await?));
+        }
+
+        // The handshake is driven here rather than by hyper-boring's
+        // `HttpsConnector`, which would build a new `SslConnector` and
+        // session cache for every connection.
+        let host = tls_host(dst.host().ok_or("no host in url")?).to_owned();
+        let port = dst.port_u16().unwrap_or(443);
+        let (endpoint, ech) = match (pinned, record) {
+            (Some(addr), _) => (format!("https://{}/", addr).parse()?, None),
+            (None, Some(record)) => {
+                let target = record.target().unwrap_or(&host);
+                let endpoint = format!("https://{}:{}/", target, record.port().unwrap_or(port));
+                let endpoint = endpoint.parse().map_err(|_| "invalid HTTPS record target")?;
+                (endpoint, record.ech_config_list().map(<[u8]>::to_vec))
+            }
+            (None, None) => (dst, None),
+        };
+        let stream = self
+            .boring_handshake(tls, &host, port, early_data, ech, || {
+                let tcp = http.call(endpoint.clone());
+                async move { Ok::<_, BoxError>((tcp.await?, String::new())) }
+            })
+            .await?;
+        Ok(hyper_boring::MaybeHttpsStream::Https(stream))
+    }
+
+    /// Looks up the HTTPS record to connect to `dst` with, if the client
+    /// follows them.
+    ///
+    /// Failed lookups are ignored, as browsers do.
+    #[cfg(feature = "__boring")]
+    async fn https_record(&self, dst: &Uri) -> Option<HttpsRecord> {
+        let resolver = self.https_records.as_ref()?;
+        if dst.scheme() != Some(&Scheme::HTTPS) {
+            return None;
+        }
+        let host = dst.host()?;
+        if host.starts_with('[') || host.parse::<IpAddr>().is_ok() {
+            return None;
+        }
+
+        let name = host.parse().ok()?;
+        let records = match resolver.resolve_https(name).await {
+            Ok(records) => records,
+            Err(e) => {
+                log::debug!("HTTPS record lookup for {} failed: {}", host, e);
+                return None;
+            }
+        };
+        let protocols: &[&str] = if self.impersonate_context.h2 {
+            &["h2", "http/1.1"]
+        } else {
+            &["http/1.1"]
+        };
+        crate::dns::svcb::select(&records, protocols).cloned()
+    }
+
+    /// Checks the certificate of `host` on a connection made by the
+    /// native-tls or rustls backend against its pins.
+    #[cfg(any(feature = "default-tls", feature = "__rustls"))]
+    fn check_pins<T: TlsInfoFactory>(&self, host: &str, io: &T) -> Result<(), BoxError> {
+        self.certificate_pins
+            .check(host, || io.tls_info().and_then(|info| info.peer_certificate))
+    }
+
+    /// Handshakes TLS with `host` over a stream opened by `connect`, then
+    /// checks its pins.
+    ///
+    /// Along with the stream, `connect` returns the route it took, which
+    /// tells which cached sessions it can resume (see [`SessionKey::new`]).
+    ///
+    /// If the server rejects ECH, a second stream is opened to try again
+    /// with its retry configs, or without ECH if it sent none. BoringSSL only
+    /// reports the rejection after checking the server's certificate for the
+    /// ECH public name, so it can be trusted.
+    #[cfg(feature = "__boring")]
+    async fn boring_handshake<S, F, Fut, E>(
+        &self,
+        tls: &BoringConnectors,
+        host: &str,
+        port: u16,
+        early_data: bool,
+        ech_config_list: Option<Vec<u8>>,
+        mut connect: F,
+    ) -> Result<tokio_boring::SslStream<S>, BoxError>
+    where
+        S: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Sync + Unpin + 'static,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<(S, String), E>>,
+        E: Into<BoxError>,
+    {
+        let ech = Ech::Configured(ech_config_list);
+        let (stream, route) = connect().await.map_err(Into::into)?;
+        let conf = self.boring_config(tls, host, port, route, early_data, &ech)?;
+        let stream = match tokio_boring::connect(conf, host, stream).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                let ech = match ech_rejected(&err) {
+                    Some(ech) => ech,
+                    None => return Err(err.into()),
+                };
+                log::debug!(
+                    "{} rejected ECH, retrying {}",
+                    host,
+                    match ech {
+                        Ech::Retry(_) => "with its retry configs",
+                        _ => "without ECH",
+                    }
+                );
+
+                let (stream, route) = connect().await.map_err(Into::into)?;
+                let conf = self.boring_config(tls, host, port, route, early_data, &ech)?;
+                tokio_boring::connect(conf, host, stream).await?
+            }
+        };
+        self.certificate_pins.check(host, || {
+            stream
+                .ssl()
+                .peer_certificate()
+                .and_then(|c| c.to_der().ok())
+        })?;
+        Ok(stream)
+    }
+
     #[cfg(feature = "socks")]
     async fn connect_socks(&self, dst: Uri, proxy: ProxyScheme) -> Result<Conn, BoxError> {
         let dns = match proxy {
             ProxyScheme::Socks5 {
                 remote_dns: false, ..
-            } => socks::DnsResolve::Local,
+            }
+            | ProxyScheme::Socks4 {
+                remote_dns: false, ..
+            } => {
+                let resolver = self.socks_resolver.clone().unwrap_or_else(|| {
+                    DynResolver::new(Arc::new(crate::dns::gai::GaiResolver::new()))
+                });
+                socks::DnsResolve::Local(resolver, self.ip_preference)
+            }
             ProxyScheme::Socks5 {
                 remote_dns: true, ..
+            }
+            | ProxyScheme::Socks4 {
+                remote_dns: true, ..
             } => socks::DnsResolve::Proxy,
             ProxyScheme::Http { .. } | ProxyScheme::Https { .. } => {
                 unreachable!("connect_socks is only called for socks proxies");
@@ -298,14 +814,20 @@ impl Connector {
             Inner::DefaultTls(_http, tls) => {
                 if dst.scheme() == Some(&Scheme::HTTPS) {
                     let host = dst.host().ok_or("no host in url")?.to_string();
-                    let conn = socks::connect(proxy, dst, dns).await?;
+                    let conn = socks::connect(proxy, dst, dns)
+                        .await
+                        .map_err(ProxyError::wrap)?;
                     let tls_connector = tokio_native_tls::TlsConnector::from(tls.clone());
-                    let io = tls_connector.connect(&host, conn).await?;
+                    let io = NativeTlsConn {
+                        inner: tls_connector.connect(&host, conn).await?,
+                    };
+                    self.check_pins(&host, &io)?;
                     return Ok(Conn {
-                       
-                        inner: self.verbose.wrap(NativeTlsConn { inner: io }),
+                        inner: self.verbose.wrap(io),
                         is_proxy: false,
                         tls_info: self.tls_info,
+                        http2: Http2Sniffer::default(),
+                        proxy_headers: None,
                     });
                 }
             }
@@ -317,37 +839,49 @@ impl Connector {
 
                     let tls = tls_proxy.clone();
                     let host = dst.host().ok_or("no host in url")?.to_string();
-                    let conn = socks::connect(proxy, dst, dns).await?;
+                    let conn = socks::connect(proxy, dst, dns)
+                        .await
+                        .map_err(ProxyError::wrap)?;
                     let server_name = rustls::ServerName::try_from(host.as_str())
                         .map_err(|_| "Invalid Server Name")?;
-                    let io = RustlsConnector::from(tls)
-                        .connect(server_name, conn)
-                        .await?;
+                    let io = RustlsTlsConn {
+                        inner: RustlsConnector::from(tls)
+                            .connect(server_name, conn)
+                            .await?,
+                    };
+                    self.check_pins(&host, &io)?;
                     return Ok(Conn {
-                        inner: self.verbose.wrap(RustlsTlsConn { inner: io }),
+                        inner: self.verbose.wrap(io),
                         is_proxy: false,
                         tls_info: false,
+                        http2: Http2Sniffer::default(),
+                        proxy_headers: None,
                     });
                 }
             }
             #[cfg(feature = "__boring")]
             Inner::BoringTls { tls, .. } => {
                 if dst.scheme() == Some(&Scheme::HTTPS) {
-                    let host = dst.host().ok_or("no host in url")?.to_string();
-                    let conn = socks::connect(proxy, dst, dns).await?;
-                    let mut tls_connector = tls(self.impersonate_context.h2);
-                    if !self.impersonate_context.certs_verification {
-                        tls_connector.set_verify(boring::ssl::SslVerifyMode::NONE);
-                    }
-
-                    let mut conf = tls_connector.build().configure()?;
-                    tls_add_application_settings(&mut conf, &self.impersonate_context);
-
-                    let io = tokio_boring::connect(conf, &host, conn).await?;
+                    let host = tls_host(dst.host().ok_or("no host in url")?).to_owned();
+                    let port = dst.port_u16().unwrap_or(443);
+                    let route = proxy_route(&proxy, None);
+                    // Like tunnels, SOCKS proxies don't get early data.
+                    let io = self
+                        .boring_handshake(tls, &host, port, false, None, || {
+                            let tcp = socks::connect(proxy.clone(), dst.clone(), dns.clone());
+                            let route = route.clone();
+                            async move {
+                                let tcp = tcp.await.map_err(ProxyError::wrap)?;
+                                Ok::<_, BoxError>((tcp, route))
+                            }
+                        })
+                        .await?;
                     return Ok(Conn {
-                        inner: self.verbose.wrap(BoringTlsConn { inner: io }),
+                        inner: self.verbose.wrap(BoringTlsConn::new(io)),
                         is_proxy: false,
                         tls_info: self.tls_info,
+                        http2: Http2Sniffer::default(),
+                        proxy_headers: None,
                     });
                 }
             }
@@ -355,14 +889,41 @@ impl Connector {
             Inner::Http(_) => (),
         }
 
-        socks::connect(proxy, dst, dns).await.map(|tcp| Conn {
+        let tcp = socks::connect(proxy, dst, dns)
+            .await
+            .map_err(ProxyError::wrap)?;
+        Ok(Conn {
             inner: self.verbose.wrap(tcp),
             is_proxy: false,
             tls_info: false,
+            http2: Http2Sniffer::default(),
+            proxy_headers: None,
         })
     }
 
+    /// Rewrites `dst` to connect to `addr` instead of its host.
+    ///
+    /// `https` URIs are left alone for BoringSSL, which connects to the
+    /// address itself while handshaking with the host; the other backends
+    /// only take a URI.
+    fn pinned_dst(&self, dst: Uri, addr: SocketAddr) -> Result<Uri, BoxError> {
+        if dst.scheme() != Some(&Scheme::HTTPS) {
+            return Ok(format!("http://{}/", addr).parse()?);
+        }
+        match self.inner {
+            #[cfg(feature = "__boring")]
+            Inner::BoringTls { .. } => Ok(dst),
+            #[allow(unreachable_patterns)]
+            _ => Err("resolve_to with https requires the BoringSSL backend".into()),
+        }
+    }
+
     async fn connect_with_maybe_proxy(self, dst: Uri, is_proxy: bool) -> Result<Conn, BoxError> {
+        let dst = match self.connect_to {
+            Some(addr) if !is_proxy => self.pinned_dst(dst, addr)?,
+            _ => dst,
+        };
+
         match self.inner {
             #[cfg(not(feature = "__tls"))]
             Inner::Http(mut http) => {
@@ -371,6 +932,8 @@ impl Connector {
                     inner: self.verbose.wrap(io),
                     is_proxy,
                     tls_info: false,
+                    http2: Http2Sniffer::default(),
+                    proxy_headers: None,
                 })
             }
             #[cfg(feature = "default-tls")]
@@ -384,6 +947,7 @@ impl Connector {
                     http.set_nodelay(true);
                 }
 
+                let host = dst.host().unwrap_or_default().to_owned();
                 let tls_connector = tokio_native_tls::TlsConnector::from(tls.clone());
                 let mut http = hyper_tls::HttpsConnector::from((http, tls_connector));
                 let io = http.call(dst).await?;
@@ -392,21 +956,29 @@ impl Connector {
                     if !self.nodelay {
                         stream.get_ref().get_ref().get_ref().set_nodelay(false)?;
                     }
+                    let io = NativeTlsConn { inner: stream };
+                    self.check_pins(&host, &io)?;
                     Ok(Conn {
-                        inner: self.verbose.wrap(NativeTlsConn { inner: stream }),
+                        inner: self.verbose.wrap(io),
                         is_proxy,
                         tls_info: self.tls_info,
+                        http2: Http2Sniffer::default(),
+                        proxy_headers: None,
                     })
                 } else {
                     Ok(Conn {
                         inner: self.verbose.wrap(io),
                         is_proxy,
                         tls_info: false,
+                        http2: Http2Sniffer::default(),
+                        proxy_headers: None,
                     })
                 }
             }
             #[cfg(feature = "__rustls")]
-            Inner::RustlsTls { http, tls, .. } => {
+            Inner::RustlsTls {
+                ref http, ref tls, ..
+            } => {
                 let mut http = http.clone();
 
                 // Disable Nagle's algorithm for TLS handshake
@@ -416,29 +988,36 @@ impl Connector {
                     http.set_nodelay(true);
                 }
 
-                let mut http = hyper_rustls::HttpsConnector::from((http, tls.clone()));
-                let io = http.call(dst).await?;
+                let host = tls_host(dst.host().unwrap_or_default()).to_owned();
+                let io = rustls_connect(&mut http, tls, dst).await?;
 
-                if let hyper_rustls::MaybeHttpsStream::Https(stream) = io {
+                if let MaybeHttpsStream::Https(stream) = io {
+                    let stream = *stream;
                     if !self.nodelay {
                         let (io, _) = stream.get_ref();
                         io.set_nodelay(false)?;
                     }
+                    let io = RustlsTlsConn { inner: stream };
+                    self.check_pins(&host, &io)?;
                     Ok(Conn {
-                        inner: self.verbose.wrap(RustlsTlsConn { inner: stream }),
+                        inner: self.verbose.wrap(io),
                         is_proxy,
                         tls_info: self.tls_info,
+                        http2: Http2Sniffer::default(),
+                        proxy_headers: None,
                     })
                 } else {
                     Ok(Conn {
                         inner: self.verbose.wrap(io),
                         is_proxy,
                         tls_info: false,
+                        http2: Http2Sniffer::default(),
+                        proxy_headers: None,
                     })
                 }
             }
             #[cfg(feature = "__boring")]
-            Inner::BoringTls { http, tls } => {
+            Inner::BoringTls { ref http, ref tls } => {
                 let mut http = http.clone();
 
                 // Disable Nagle's algorithm for TLS handshake
@@ -448,20 +1027,16 @@ impl Connector {
                     http.set_nodelay(true);
                 }
 
-                let mut tls_connector = tls(self.impersonate_context.h2);
-                if !self.impersonate_context.certs_verification {
-                    tls_connector.set_verify(boring::ssl::SslVerifyMode::NONE);
-                }
-                let mut http = hyper_boring::HttpsConnector::with_connector(http, tls_connector)?;
-
-                let context = self.impersonate_context.clone();
-                http.set_callback(move |conf, _| {
-                    let context = &context;
-                    tls_add_application_settings(conf, context);
-                    Ok(())
-                });
+                let pinned = self.connect_to.filter(|_| !is_proxy);
+                let record = match is_proxy || pinned.is_some() {
+                    true => None,
+                    false => self.https_record(&dst).await,
+                };
 
-                let io = http.call(dst).await?;
+                // Whatever goes through a proxy isn't known to be replayable.
+                let io = self
+                    .boring_connect(&mut http, tls, dst, !is_proxy, record.as_ref(), pinned)
+                    .await?;
 
                 if let hyper_boring::MaybeHttpsStream::Https(stream) = io {
                     if !self.nodelay {
@@ -469,33 +1044,75 @@ impl Connector {
                         stream_ref.set_nodelay(false)?;
                     }
                     Ok(Conn {
-                        inner: self.verbose.wrap(BoringTlsConn { inner: stream }),
+                        inner: self.verbose.wrap(BoringTlsConn::new(stream)),
                         is_proxy,
                         tls_info: self.tls_info,
+                        http2: Http2Sniffer::default(),
+                        proxy_headers: None,
                     })
                 } else {
                     Ok(Conn {
                         inner: self.verbose.wrap(io),
                         is_proxy,
                         tls_info: self.tls_info,
+                        http2: Http2Sniffer::default(),
+                        proxy_headers: None,
                     })
                 }
             }
         }
     }
 
+    /// Connects where the PAC script says, failing over to the next proxy
+    /// when one doesn't work, each given the whole timeout.
+    async fn connect_via_pac(
+        self,
+        dst: Uri,
+        pac: Pac,
+        timeout: Option<Duration>,
+    ) -> Result<Conn, BoxError> {
+        let routes = with_timeout(pac.routes(&dst), timeout).await?;
+        let mut last_err = None;
+        for route in routes {
+            let connector = self.clone();
+            let result = match route {
+                Route::Direct => {
+                    with_timeout(connector.connect_with_maybe_proxy(dst.clone(), false), timeout)
+                        .await
+                }
+                Route::Proxy(ref scheme) => {
+                    with_timeout(connector.connect_via_proxy(dst.clone(), scheme.clone()), timeout)
+                        .await
+                }
+            };
+            pac.report(&route, result.is_ok());
+            match result {
+                Ok(conn) => return Ok(conn),
+                Err(e) => {
+                    log::debug!("PAC route {:?} to {:?} failed: {}", route, dst, e);
+                    last_err = Some(e);
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| "no PAC route to connect by".into()))
+    }
+
     async fn connect_via_proxy(
         self,
         dst: Uri,
         proxy_scheme: ProxyScheme,
     ) -> Result<Conn, BoxError> {
         log::debug!("proxy({:?}) intercepts '{:?}'", proxy_scheme, dst);
+        #[cfg(feature = "__boring")]
+        let session_route = proxy_route(&proxy_scheme, self.tunnel_proxy(&dst));
 
         let (proxy_dst, _auth) = match proxy_scheme {
             ProxyScheme::Http { host, auth } => (into_uri(Scheme::HTTP, host), auth),
             ProxyScheme::Https { host, auth } => (into_uri(Scheme::HTTPS, host), auth),
             #[cfg(feature = "socks")]
-            ProxyScheme::Socks5 { .. } => return self.connect_socks(dst, proxy_scheme).await,
+            ProxyScheme::Socks5 { .. } | ProxyScheme::Socks4 { .. } => {
+                return self.connect_socks(dst, proxy_scheme).await
+            }
         };
 
         #[cfg(feature = "__tls")]
@@ -509,25 +1126,28 @@ impl Connector {
                     let port = dst.port().map(|p| p.as_u16()).unwrap_or(443);
                     let http = http.clone();
                     let tls_connector = tokio_native_tls::TlsConnector::from(tls.clone());
-                    let mut http = hyper_tls::HttpsConnector::from((http, tls_connector));
-                    let conn = http.call(proxy_dst).await?;
+                    let http = hyper_tls::HttpsConnector::from((http, tls_connector));
+                    let connect = || {
+                        let mut http = http.clone();
+                        http.call(proxy_dst.clone())
+                    };
                     log::trace!("tunneling HTTPS over proxy");
-                    let tunneled = tunnel(
-                        conn,
-                        host.ok_or("no host in url")?.to_string(),
-                        port,
-                        self.user_agent.clone(),
-                        auth,
-                    )
-                        .await?;
+                    let (tunneled, proxy_headers) = self
+                        .open_tunnel(&dst, connect, host.ok_or("no host in url")?, port, auth)
+                        .await
+                        .map_err(ProxyError::wrap)?;
                     let tls_connector = tokio_native_tls::TlsConnector::from(tls.clone());
-                    let io = tls_connector
-                        .connect(host.ok_or("no host in url")?, tunneled)
-                        .await?;
+                    let host = host.ok_or("no host in url")?;
+                    let io = NativeTlsConn {
+                        inner: tls_connector.connect(host, tunneled).await?,
+                    };
+                    self.check_pins(host, &io)?;
                     return Ok(Conn {
-                        inner: self.verbose.wrap(NativeTlsConn { inner: io }),
+                        inner: self.verbose.wrap(io),
                         is_proxy: false,
                         tls_info: false,
+                        http2: Http2Sniffer::default(),
+                        proxy_headers: Some(Arc::new(proxy_headers)),
                     });
                 }
             }
@@ -542,73 +1162,92 @@ impl Connector {
                     use std::convert::TryFrom;
                     use tokio_rustls::TlsConnector as RustlsConnector;
 
-                    let host = dst.host().ok_or("no host in url")?.to_string();
+                    let host = tls_host(dst.host().ok_or("no host in url")?).to_owned();
                     let port = dst.port().map(|r| r.as_u16()).unwrap_or(443);
-                    let http = http.clone();
-                    let mut http = hyper_rustls::HttpsConnector::from((http, tls_proxy.clone()));
                     let tls = tls.clone();
-                    let conn = http.call(proxy_dst).await?;
+                    let connect = || {
+                        let mut http = http.clone();
+                        let proxy_dst = proxy_dst.clone();
+                        async move { rustls_connect(&mut http, tls_proxy, proxy_dst).await }
+                    };
                     log::trace!("tunneling HTTPS over proxy");
                     let maybe_server_name =
                         ServerName::try_from(host.as_str()).map_err(|_| "Invalid Server Name");
-                    let tunneled = tunnel(conn, host, port, self.user_agent.clone(), auth).await?;
+                    let (tunneled, proxy_headers) =
+                        self.open_tunnel(&dst, connect, &host, port, auth)
+                            .await
+                            .map_err(ProxyError::wrap)?;
                     let server_name = maybe_server_name?;
-                    let io = RustlsConnector::from(tls)
-                        .connect(server_name, tunneled)
-                        .await?;
+                    let io = RustlsTlsConn {
+                        inner: RustlsConnector::from(tls)
+                            .connect(server_name, tunneled)
+                            .await?,
+                    };
+                    self.check_pins(&host, &io)?;
 
                     return Ok(Conn {
-                        inner: self.verbose.wrap(RustlsTlsConn { inner: io }),
+                        inner: self.verbose.wrap(io),
                         is_proxy: false,
                         tls_info: false,
+                        http2: Http2Sniffer::default(),
+                        proxy_headers: Some(Arc::new(proxy_headers)),
                     });
                 }
             }
             #[cfg(feature = "__boring")]
             Inner::BoringTls { http, tls } => {
                 if dst.scheme() == Some(&Scheme::HTTPS) {
-                    let host = dst.host().to_owned();
+                    let host = dst.host().ok_or("no host in url")?;
                     let port = dst.port().map(|p| p.as_u16()).unwrap_or(443);
-                    let http = http.clone();
-                    let mut tls_connector = tls(self.impersonate_context.h2);
-                    if !self.impersonate_context.certs_verification {
-                        tls_connector.set_verify(boring::ssl::SslVerifyMode::NONE);
-                    }
-                    
-                    let mut http =
-                        hyper_boring::HttpsConnector::with_connector(http, tls_connector)?;
-
-                    let context = self.impersonate_context.clone();
-                    http.set_callback(move |conf, _| {
-                        let context = &context;
-                        tls_add_application_settings(conf, context);
-                        Ok(())
-                    });
-                    let conn = http.call(proxy_dst).await?;
-                    log::trace!("tunneling HTTPS over proxy");
-                    let tunneled = tunnel(
-                        conn,
-                        host.ok_or("no host in url")?.to_string(),
-                        port,
-                        self.user_agent.clone(),
-                        auth,
-                    )
-                        .await?;
-
-                    let mut tls_connector = tls(self.impersonate_context.h2);
-                    if !self.impersonate_context.certs_verification {
-                        tls_connector.set_verify(boring::ssl::SslVerifyMode::NONE);
-                    }
- 
-                    let mut conf = tls_connector.build().configure()?;
-                    tls_add_application_settings(&mut conf, &self.impersonate_context);
-
-                    let io = tokio_boring::connect(conf, host.ok_or("no host in url")?, tunneled)
+                    let this = &self;
+                    let dst = &dst;
+                    let proxy_dst = &proxy_dst;
+                    let session_route = &session_route;
+                    // The handshake may open the tunnel more than once; the
+                    // last proxy response is the one the connection got.
+                    let proxy_headers = std::sync::Mutex::new(None);
+                    let proxy_headers_slot = &proxy_headers;
+                    let open_tunnel = || {
+                        let auth = auth.clone();
+                        async move {
+                            let connect = || {
+                                let mut http = http.clone();
+                                async move {
+                                    this.boring_connect(
+                                        &mut http,
+                                        tls,
+                                        proxy_dst.clone(),
+                                        false,
+                                        None,
+                                        None,
+                                    )
+                                    .await
+                                }
+                            };
+                            log::trace!("tunneling HTTPS over proxy");
+                            let (tunneled, headers) =
+                                this.open_tunnel(dst, connect, host, port, auth)
+                                    .await
+                                    .map_err(ProxyError::wrap)?;
+                            *proxy_headers_slot.lock().unwrap_or_else(|e| e.into_inner()) =
+                                Some(headers);
+                            Ok::<_, BoxError>((tunneled, session_route.clone()))
+                        }
+                    };
+
+                    // Whatever goes through a proxy isn't known to be
+                    // replayable, so tunnels don't get early data.
+                    let io = self
+                        .boring_handshake(tls, tls_host(host), port, false, None, open_tunnel)
                         .await?;
+                    let proxy_headers =
+                        proxy_headers.into_inner().unwrap_or_else(|e| e.into_inner());
                     return Ok(Conn {
-                        inner: self.verbose.wrap(BoringTlsConn { inner: io }),
+                        inner: self.verbose.wrap(BoringTlsConn::new(io)),
                         is_proxy: false,
                         tls_info: self.tls_info,
+                        http2: Http2Sniffer::default(),
+                        proxy_headers: proxy_headers.map(Arc::new),
                     });
                 }
             }
@@ -616,7 +1255,85 @@ impl Connector {
             Inner::Http(_) => (),
         }
 
-        self.connect_with_maybe_proxy(proxy_dst, true).await
+        self.connect_with_maybe_proxy(proxy_dst, true)
+            .await
+            .map_err(ProxyError::wrap)
+    }
+
+    /// Opens a tunnel to `host:port` through the proxy reached by `connect`,
+    /// answering a Digest challenge of the proxy on a new connection.
+    ///
+    /// Returns the headers of the proxy's response along with the tunnel.
+    #[cfg(feature = "__tls")]
+    async fn open_tunnel<T, F, Fut>(
+        &self,
+        dst: &Uri,
+        mut connect: F,
+        host: &str,
+        port: u16,
+        auth: Option<HeaderValue>,
+    ) -> Result<(T, HeaderMap), BoxError>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, BoxError>>,
+    {
+        let proxy = self.tunnel_proxy(dst);
+        let extra = proxy.and_then(Proxy::extra_headers);
+
+        #[cfg(feature = "digest-auth")]
+        if let Some(digest) = proxy.and_then(Proxy::digest) {
+            let auth = digest.authorize(host, port).or_else(|| auth.clone());
+            let headers = self.connect_headers(host, port, extra, auth)?;
+            let err = match tunnel(connect().await?, host, port, headers).await {
+                Err(err) => err,
+                tunneled => return tunneled,
+            };
+            let auth = match err
+                .downcast_ref::<ProxyAuthRequired>()
+                .and_then(|challenged| digest.answer(&challenged.challenges, host, port))
+            {
+                Some(auth) => auth,
+                None => return Err(err),
+            };
+            log::debug!("answering the Digest challenge of the proxy to {}:{}", host, port);
+            let headers = self.connect_headers(host, port, extra, Some(auth))?;
+            return tunnel(connect().await?, host, port, headers).await;
+        }
+
+        let headers = self.connect_headers(host, port, extra, auth)?;
+        tunnel(connect().await?, host, port, headers).await
+    }
+
+    /// The proxy `dst` is tunneled through, unless it's chosen by a PAC
+    /// script.
+    #[cfg(feature = "__tls")]
+    fn tunnel_proxy(&self, dst: &Uri) -> Option<&Proxy> {
+        self.proxies
+            .iter()
+            .find(|prox| prox.pac_for(dst).is_some() || prox.intercept(dst).is_some())
+            .filter(|prox| !prox.is_pac())
+    }
+
+    /// The headers of a CONNECT request to `host:port`, sent in the order
+    /// of the impersonated browser.
+    #[cfg(feature = "__tls")]
+    fn connect_headers(
+        &self,
+        host: &str,
+        port: u16,
+        extra: Option<&HeaderMap>,
+        auth: Option<HeaderValue>,
+    ) -> Result<HeaderMap, BoxError> {
+        #[cfg(feature = "impersonate")]
+        let style = match self.impersonate_context {
+            ref ctx if ctx.impersonate.is_some() => ConnectStyle::of(ctx.profile),
+            _ => ConnectStyle::Plain,
+        };
+        #[cfg(not(feature = "impersonate"))]
+        let style = ConnectStyle::Plain;
+
+        connect_headers(style, host, port, self.user_agent.clone(), extra, auth)
     }
 
     pub fn set_keepalive(&mut self, dur: Option<Duration>) {
@@ -633,6 +1350,84 @@ impl Connector {
     }
 }
 
+/// Strips the brackets around an IPv6 literal, which the TLS backends
+/// wouldn't recognize as an IP address when verifying the certificate.
+#[cfg(any(feature = "__boring", feature = "__rustls"))]
+fn tls_host(host: &str) -> &str {
+    match host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+        Some(ip) if ip.parse::<Ipv6Addr>().is_ok() => ip,
+        _ => host,
+    }
+}
+
+/// Connects to `dst`, handshaking TLS with `tls` if it's an `https` URI.
+///
+/// The handshake is driven here rather than by hyper-rustls, which is built
+/// for another hyper.
+#[cfg(feature = "__rustls")]
+async fn rustls_connect(
+    http: &mut HttpConnector,
+    tls: &Arc<rustls::ClientConfig>,
+    dst: Uri,
+) -> Result<MaybeHttpsStream, BoxError> {
+    use std::convert::TryFrom;
+
+    let https = dst.scheme() == Some(&Scheme::HTTPS);
+    let host = tls_host(dst.host().ok_or("no host in url")?).to_owned();
+    let tcp = http.call(dst).await?;
+    if !https {
+        return Ok(MaybeHttpsStream::Http(tcp));
+    }
+    let server_name =
+        rustls::ServerName::try_from(host.as_str()).map_err(|_| "Invalid Server Name")?;
+    let tls = tokio_rustls::TlsConnector::from(tls.clone())
+        .connect(server_name, tcp)
+        .await?;
+    Ok(MaybeHttpsStream::Https(Box::new(tls)))
+}
+
+/// The route of a connection through a proxy, for the session cache.
+///
+/// Besides the proxy's address, it covers what the proxy may tell the
+/// identities it gives out apart by: the credentials and headers sent to
+/// it. They're hashed, so exported sessions don't carry them.
+#[cfg(feature = "__boring")]
+fn proxy_route(scheme: &ProxyScheme, proxy: Option<&Proxy>) -> String {
+    let mut identity = boring::sha::Sha256::new();
+    let mut field = |value: &[u8]| {
+        identity.update(&(value.len() as u32).to_be_bytes());
+        identity.update(value);
+    };
+    match scheme {
+        ProxyScheme::Http { auth, .. } | ProxyScheme::Https { auth, .. } => {
+            field(auth.as_ref().map_or(&[][..], HeaderValue::as_bytes));
+        }
+        #[cfg(feature = "socks")]
+        ProxyScheme::Socks5 { auth, .. } => {
+            if let Some((username, password)) = auth {
+                field(username.as_bytes());
+                field(password.as_bytes());
+            }
+        }
+        #[cfg(feature = "socks")]
+        ProxyScheme::Socks4 { user_id, .. } => {
+            field(user_id.as_deref().unwrap_or_default().as_bytes());
+        }
+    }
+    #[cfg(feature = "digest-auth")]
+    if let Some(digest) = proxy.and_then(Proxy::digest) {
+        field(digest.username().as_bytes());
+    }
+    for (name, value) in proxy.and_then(Proxy::extra_headers).into_iter().flatten() {
+        field(name.as_str().as_bytes());
+        field(value.as_bytes());
+    }
+
+    let hash = identity.finish();
+    let hash: String = hash[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{:?}#{}", scheme, hash)
+}
+
 fn into_uri(scheme: Scheme, host: Authority) -> Uri {
     // TODO: Should the `http` crate get `From<(Scheme, Authority)> for Uri`?
     Uri::builder()
@@ -670,17 +1465,21 @@ impl Service<Uri> for Connector {
     fn call(&mut self, dst: Uri) -> Self::Future {
         log::debug!("starting new connection: {:?}", dst);
         let timeout = self.timeout;
+        let connector = self.clone();
         for prox in self.proxies.iter() {
+            if let Some(pac) = prox.pac_for(&dst) {
+                return Box::pin(connector.connect_via_pac(dst, pac.clone(), timeout));
+            }
             if let Some(proxy_scheme) = prox.intercept(&dst) {
                 return Box::pin(with_timeout(
-                    self.clone().connect_via_proxy(dst, proxy_scheme),
+                    connector.connect_via_proxy(dst, proxy_scheme),
                     timeout,
                 ));
             }
         }
 
         Box::pin(with_timeout(
-            self.clone().connect_with_maybe_proxy(dst, false),
+            connector.connect_with_maybe_proxy(dst, false),
             timeout,
         ))
     }
@@ -689,6 +1488,11 @@ impl Service<Uri> for Connector {
 #[cfg(feature = "__tls")]
 trait TlsInfoFactory {
     fn tls_info(&self) -> Option<crate::tls::TlsInfo>;
+
+    /// Returns what the handshake negotiated, filled in once it completes.
+    fn tls_details(&self) -> Option<Arc<OnceCell<crate::tls::TlsDetails>>> {
+        None
+    }
 }
 
 #[cfg(feature = "__boring")]
@@ -700,6 +1504,10 @@ impl TlsInfoFactory for BoringTlsConn<tokio::net::TcpStream> {
             .and_then(|c| c.to_der().ok());
         Some(crate::tls::TlsInfo { peer_certificate })    
     }
+
+    fn tls_details(&self) -> Option<Arc<OnceCell<crate::tls::TlsDetails>>> {
+        Some(self.details.clone())
+    }
 }
 
 #[cfg(feature = "__boring")]
@@ -715,6 +1523,16 @@ impl TlsInfoFactory for hyper_boring::MaybeHttpsStream<tokio::net::TcpStream> {
             hyper_boring::MaybeHttpsStream::Http(_) => None,
         }
     }
+
+    fn tls_details(&self) -> Option<Arc<OnceCell<crate::tls::TlsDetails>>> {
+        match self {
+            hyper_boring::MaybeHttpsStream::Https(tls) => {
+                let details = crate::tls::TlsDetails::from_boring(tls.ssl());
+                Some(Arc::new(OnceCell::with_value(details)))
+            }
+            hyper_boring::MaybeHttpsStream::Http(_) => None,
+        }
+    }
 }
 
 #[cfg(feature = "__boring")]
@@ -730,6 +1548,10 @@ impl TlsInfoFactory for BoringTlsConn<hyper_boring::MaybeHttpsStream<tokio::net:
             hyper_boring::MaybeHttpsStream::Http(_) => None,
         }
     }
+
+    fn tls_details(&self) -> Option<Arc<OnceCell<crate::tls::TlsDetails>>> {
+        Some(self.details.clone())
+    }
 }
 
 
@@ -778,11 +1600,11 @@ impl TlsInfoFactory for tokio_native_tls::TlsStream<tokio::net::TcpStream> {
 }
 
 #[cfg(feature = "__rustls")]
-impl TlsInfoFactory for hyper_rustls::MaybeHttpsStream<tokio::net::TcpStream> {
+impl TlsInfoFactory for MaybeHttpsStream {
     fn tls_info(&self) -> Option<crate::tls::TlsInfo> {
         match self {
-            hyper_rustls::MaybeHttpsStream::Https(tls) => tls.tls_info(),
-            hyper_rustls::MaybeHttpsStream::Http(_) => None,
+            MaybeHttpsStream::Https(tls) => tls.tls_info(),
+            MaybeHttpsStream::Http(_) => None,
         }
     }
 }
@@ -801,9 +1623,7 @@ impl TlsInfoFactory for tokio_rustls::TlsStream<tokio::net::TcpStream> {
 }
 
 #[cfg(feature = "__rustls")]
-impl TlsInfoFactory
-    for tokio_rustls::client::TlsStream<hyper_rustls::MaybeHttpsStream<tokio::net::TcpStream>>
-{
+impl TlsInfoFactory for tokio_rustls::client::TlsStream<MaybeHttpsStream> {
     fn tls_info(&self) -> Option<crate::tls::TlsInfo> {
         let peer_certificate = self
             .get_ref()
@@ -858,12 +1678,20 @@ pin_project! {
         is_proxy: bool,
         // Only needed for __tls, but #[cfg()] on fields breaks pin_project!
         tls_info: bool,
+        http2: Http2Sniffer,
+        proxy_headers: Option<Arc<HeaderMap>>,
     }
 }
 
 impl Connection for Conn {
     fn connected(&self) -> Connected {
-        let connected = self.inner.connected().proxy(self.is_proxy);
+        let info = ConnectionInfo {
+            #[cfg(feature = "__tls")]
+            tls: self.inner.tls_details(),
+            http2_settings: self.http2.settings(),
+            proxy_headers: self.proxy_headers.clone(),
+        };
+        let connected = self.inner.connected().proxy(self.is_proxy).extra(info);
         #[cfg(feature = "__tls")]
         if self.tls_info {
             if let Some(tls_info) = self.inner.tls_info() {
@@ -886,7 +1714,14 @@ impl AsyncRead for Conn {
         buf: &mut ReadBuf<'_>,
     ) -> Poll<io::Result<()>> {
         let this = self.project();
-        AsyncRead::poll_read(this.inner, cx, buf)
+        if !this.http2.wants_reads() {
+            return AsyncRead::poll_read(this.inner, cx, buf);
+        }
+
+        let filled = buf.filled().len();
+        let res = AsyncRead::poll_read(this.inner, cx, buf);
+        this.http2.read(&buf.filled()[filled..]);
+        res
     }
 }
 
@@ -897,8 +1732,14 @@ impl AsyncWrite for Conn {
         buf: &[u8],
     ) -> Poll<Result<usize, io::Error>> {
         let this = self.project();
-        AsyncWrite::poll_write(this.inner, cx, buf)
-    }
+        let res = AsyncWrite::poll_write(this.inner, cx, buf);
+        if this.http2.wants_writes() {
+            if let Poll::Ready(Ok(n)) = res {
+                this.http2.written(&buf[..n]);
+            }
+        }
+        res
+    }
 
     fn poll_write_vectored(
         self: Pin<&mut Self>,
@@ -906,7 +1747,20 @@ impl AsyncWrite for Conn {
         bufs: &[IoSlice<'_>],
     ) -> Poll<Result<usize, io::Error>> {
         let this = self.project();
-        AsyncWrite::poll_write_vectored(this.inner, cx, bufs)
+        let res = AsyncWrite::poll_write_vectored(this.inner, cx, bufs);
+        if this.http2.wants_writes() {
+            if let Poll::Ready(Ok(mut n)) = res {
+                for buf in bufs {
+                    let len = n.min(buf.len());
+                    this.http2.written(&buf[..len]);
+                    n -= len;
+                    if n == 0 || !this.http2.wants_writes() {
+                        break;
+                    }
+                }
+            }
+        }
+        res
     }
 
     fn is_write_vectored(&self) -> bool {
@@ -926,39 +1780,146 @@ impl AsyncWrite for Conn {
 
 pub(crate) type Connecting = Pin<Box<dyn Future<Output = Result<Conn, BoxError>> + Send>>;
 
+/// How a browser shapes the CONNECT requests it tunnels HTTPS with.
 #[cfg(feature = "__tls")]
-async fn tunnel<T>(
-    mut conn: T,
-    host: String,
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ConnectStyle {
+    /// `Host`, then `User-Agent`, when not impersonating or when the
+    /// browser's own order isn't known, as for Safari.
+    Plain,
+    Chromium,
+    Firefox,
+    OkHttp,
+}
+
+#[cfg(feature = "__tls")]
+impl ConnectStyle {
+    #[cfg(feature = "impersonate")]
+    fn of(profile: ClientProfile) -> ConnectStyle {
+        match profile {
+            ClientProfile::Chrome | ClientProfile::Edge => ConnectStyle::Chromium,
+            ClientProfile::Firefox => ConnectStyle::Firefox,
+            ClientProfile::OkHttp => ConnectStyle::OkHttp,
+            // CFNetwork isn't Chromium, and its order hasn't been checked.
+            ClientProfile::Safari => ConnectStyle::Plain,
+        }
+    }
+}
+
+/// The headers of a CONNECT request to `host:port`, in the order `style`
+/// sends them, followed by the proxy's `extra` headers and `auth`.
+///
+/// An extra header replaces one of the same name where it stands, and one
+/// named `Proxy-Authorization` wins over `auth`.
+#[cfg(feature = "__tls")]
+fn connect_headers(
+    style: ConnectStyle,
+    host: &str,
     port: u16,
     user_agent: Option<HeaderValue>,
+    extra: Option<&HeaderMap>,
     auth: Option<HeaderValue>,
-) -> Result<T, BoxError>
+) -> Result<HeaderMap, BoxError> {
+    use http::header::{CONNECTION, HOST, USER_AGENT};
+
+    let authority = Some(HeaderValue::from_str(&format!("{}:{}", host, port))?);
+    let proxy_connection = HeaderName::from_static("proxy-connection");
+    let keep_alive = Some(HeaderValue::from_static("keep-alive"));
+    let order = match style {
+        ConnectStyle::Plain => vec![(HOST, authority), (USER_AGENT, user_agent)],
+        ConnectStyle::Chromium => vec![
+            (HOST, authority),
+            (proxy_connection, keep_alive),
+            (USER_AGENT, user_agent),
+        ],
+        ConnectStyle::Firefox => vec![
+            (USER_AGENT, user_agent),
+            (proxy_connection, keep_alive.clone()),
+            (CONNECTION, keep_alive),
+            (HOST, authority),
+        ],
+        ConnectStyle::OkHttp => vec![
+            (HOST, authority),
+            (proxy_connection, Some(HeaderValue::from_static("Keep-Alive"))),
+            (USER_AGENT, user_agent),
+        ],
+    };
+    let mut headers: HeaderMap = order
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value?)))
+        .collect();
+
+    if let Some(extra) = extra {
+        for name in extra.keys() {
+            let mut values = extra.get_all(name).iter();
+            if let Some(value) = values.next() {
+                headers.insert(name.clone(), value.clone());
+            }
+            for value in values {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+    }
+
+    if let Some(auth) = auth {
+        if !headers.contains_key(PROXY_AUTHORIZATION) {
+            headers.insert(PROXY_AUTHORIZATION, auth);
+        }
+    }
+
+    Ok(headers)
+}
+
+/// Writes `name` the way browsers do on HTTP/1.1, like `Proxy-Connection`.
+#[cfg(feature = "__tls")]
+fn write_title_case(buf: &mut Vec<u8>, name: &HeaderName) {
+    let mut upper = true;
+    for &b in name.as_str().as_bytes() {
+        buf.push(if upper { b.to_ascii_uppercase() } else { b });
+        upper = b == b'-';
+    }
+}
+
+/// Parses the headers of a proxy's response to a CONNECT, skipping those
+/// that aren't valid.
+#[cfg(feature = "__tls")]
+fn response_headers(head: &[u8]) -> HeaderMap {
+    let mut headers = [httparse::EMPTY_HEADER; 64];
+    let mut res = httparse::Response::new(&mut headers);
+    let _ = res.parse(head);
+    res.headers
+        .iter()
+        .filter_map(|header| {
+            let name = HeaderName::from_bytes(header.name.as_bytes()).ok()?;
+            let value = HeaderValue::from_bytes(header.value).ok()?;
+            Some((name, value))
+        })
+        .collect()
+}
+
+/// Sends a CONNECT request to `host:port` with `headers` over `conn`.
+///
+/// Returns the tunnel along with the headers of the proxy's response.
+#[cfg(feature = "__tls")]
+async fn tunnel<T>(
+    mut conn: T,
+    host: &str,
+    port: u16,
+    headers: HeaderMap,
+) -> Result<(T, HeaderMap), BoxError>
     where
         T: AsyncRead + AsyncWrite + Unpin,
 {
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-    let mut buf = format!(
-        "\
-         CONNECT {0}:{1} HTTP/1.1\r\n\
-         Host: {0}:{1}\r\n\
-         ",
-        host, port
-    )
-        .into_bytes();
-
-    // user-agent
-    if let Some(user_agent) = user_agent {
-        buf.extend_from_slice(b"User-Agent: ");
-        buf.extend_from_slice(user_agent.as_bytes());
-        buf.extend_from_slice(b"\r\n");
-    }
+    let mut buf = format!("CONNECT {}:{} HTTP/1.1\r\n", host, port).into_bytes();
 
-    // proxy-authorization
-    if let Some(value) = auth {
-        log::debug!("tunnel to {}:{} using basic auth", host, port);
-        buf.extend_from_slice(b"Proxy-Authorization: ");
+    if headers.contains_key(PROXY_AUTHORIZATION) {
+        log::debug!("tunnel to {}:{} using proxy authorization", host, port);
+    }
+    for (name, value) in headers.iter() {
+        write_title_case(&mut buf, name);
+        buf.extend_from_slice(b": ");
         buf.extend_from_slice(value.as_bytes());
         buf.extend_from_slice(b"\r\n");
     }
@@ -982,13 +1943,22 @@ async fn tunnel<T>(
         let recvd = &buf[..pos];
         if recvd.starts_with(b"HTTP/1.1 200") || recvd.starts_with(b"HTTP/1.0 200") {
             if recvd.ends_with(b"\r\n\r\n") {
-                return Ok(conn);
+                return Ok((conn, response_headers(recvd)));
             }
             if pos == buf.len() {
                 return Err("proxy headers too long for tunnel".into());
             }
             // else read more
         } else if recvd.starts_with(b"HTTP/1.1 407") {
+            // The challenges are read from the whole head.
+            #[cfg(feature = "digest-auth")]
+            {
+                if !recvd.windows(4).any(|w| w == b"\r\n\r\n") && pos < buf.len() {
+                    continue;
+                }
+                return Err(Box::new(ProxyAuthRequired::parse(recvd)));
+            }
+            #[cfg(not(feature = "digest-auth"))]
             return Err("proxy authentication required".into());
         } else {
             return Err("unsuccessful tunnel".into());
@@ -996,11 +1966,75 @@ async fn tunnel<T>(
     }
 }
 
+/// A failure to reach a proxy, or to go through it, rather than one of the
+/// connection to the origin. It reads as the error it wraps.
+#[derive(Debug)]
+struct ProxyError(BoxError);
+
+impl ProxyError {
+    fn wrap(err: BoxError) -> BoxError {
+        Box::new(ProxyError(err))
+    }
+}
+
+impl std::fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for ProxyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// Returns whether `err` comes from reaching or going through a proxy.
+pub(crate) fn is_proxy_error(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if err.is::<ProxyError>() {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
 #[cfg(feature = "__tls")]
 fn tunnel_eof() -> BoxError {
     "unexpected eof while tunneling".into()
 }
 
+/// A `407` response to a CONNECT, with the challenges of the proxy.
+#[cfg(all(feature = "__tls", feature = "digest-auth"))]
+#[derive(Debug)]
+struct ProxyAuthRequired {
+    challenges: Vec<HeaderValue>,
+}
+
+#[cfg(all(feature = "__tls", feature = "digest-auth"))]
+impl ProxyAuthRequired {
+    fn parse(head: &[u8]) -> ProxyAuthRequired {
+        let challenges = response_headers(head)
+            .get_all(http::header::PROXY_AUTHENTICATE)
+            .iter()
+            .cloned()
+            .collect();
+        ProxyAuthRequired { challenges }
+    }
+}
+
+#[cfg(all(feature = "__tls", feature = "digest-auth"))]
+impl std::fmt::Display for ProxyAuthRequired {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("proxy authentication required")
+    }
+}
+
+#[cfg(all(feature = "__tls", feature = "digest-auth"))]
+impl std::error::Error for ProxyAuthRequired {}
+
 #[cfg(feature = "default-tls")]
 mod native_tls_conn {
     use super::TlsInfoFactory;
@@ -1116,6 +2150,7 @@ mod rustls_tls_conn {
         task::{Context, Poll},
     };
     use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+    use tokio::net::TcpStream;
     use tokio_rustls::client::TlsStream;
 
     pin_project! {
@@ -1124,6 +2159,93 @@ mod rustls_tls_conn {
         }
     }
 
+    /// A connection made by the rustls backend, over TLS if it's to an
+    /// `https` URI.
+    pub(super) enum MaybeHttpsStream {
+        Http(TcpStream),
+        Https(Box<TlsStream<TcpStream>>),
+    }
+
+    impl Connection for MaybeHttpsStream {
+        fn connected(&self) -> Connected {
+            match self {
+                MaybeHttpsStream::Http(tcp) => tcp.connected(),
+                MaybeHttpsStream::Https(tls) => {
+                    let (tcp, conn) = tls.get_ref();
+                    if conn.alpn_protocol() == Some(b"h2") {
+                        tcp.connected().negotiated_h2()
+                    } else {
+                        tcp.connected()
+                    }
+                }
+            }
+        }
+    }
+
+    impl AsyncRead for MaybeHttpsStream {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<tokio::io::Result<()>> {
+            match self.get_mut() {
+                MaybeHttpsStream::Http(tcp) => Pin::new(tcp).poll_read(cx, buf),
+                MaybeHttpsStream::Https(tls) => Pin::new(tls).poll_read(cx, buf),
+            }
+        }
+    }
+
+    impl AsyncWrite for MaybeHttpsStream {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &[u8],
+        ) -> Poll<Result<usize, tokio::io::Error>> {
+            match self.get_mut() {
+                MaybeHttpsStream::Http(tcp) => Pin::new(tcp).poll_write(cx, buf),
+                MaybeHttpsStream::Https(tls) => Pin::new(tls).poll_write(cx, buf),
+            }
+        }
+
+        fn poll_write_vectored(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            bufs: &[IoSlice<'_>],
+        ) -> Poll<Result<usize, io::Error>> {
+            match self.get_mut() {
+                MaybeHttpsStream::Http(tcp) => Pin::new(tcp).poll_write_vectored(cx, bufs),
+                MaybeHttpsStream::Https(tls) => Pin::new(tls).poll_write_vectored(cx, bufs),
+            }
+        }
+
+        fn is_write_vectored(&self) -> bool {
+            match self {
+                MaybeHttpsStream::Http(tcp) => tcp.is_write_vectored(),
+                MaybeHttpsStream::Https(tls) => tls.is_write_vectored(),
+            }
+        }
+
+        fn poll_flush(
+            self: Pin<&mut Self>,
+            cx: &mut Context,
+        ) -> Poll<Result<(), tokio::io::Error>> {
+            match self.get_mut() {
+                MaybeHttpsStream::Http(tcp) => Pin::new(tcp).poll_flush(cx),
+                MaybeHttpsStream::Https(tls) => Pin::new(tls).poll_flush(cx),
+            }
+        }
+
+        fn poll_shutdown(
+            self: Pin<&mut Self>,
+            cx: &mut Context,
+        ) -> Poll<Result<(), tokio::io::Error>> {
+            match self.get_mut() {
+                MaybeHttpsStream::Http(tcp) => Pin::new(tcp).poll_shutdown(cx),
+                MaybeHttpsStream::Https(tls) => Pin::new(tls).poll_shutdown(cx),
+            }
+        }
+    }
+
     impl<T: Connection + AsyncRead + AsyncWrite + Unpin> Connection for RustlsTlsConn<T> {
         fn connected(&self) -> Connected {
             if self.inner.get_ref().1.alpn_protocol() == Some(b"h2") {
@@ -1191,7 +2313,7 @@ mod rustls_tls_conn {
         }
     }
 
-    impl TlsInfoFactory for RustlsTlsConn<hyper_rustls::MaybeHttpsStream<tokio::net::TcpStream>> {
+    impl TlsInfoFactory for RustlsTlsConn<MaybeHttpsStream> {
         fn tls_info(&self) -> Option<crate::tls::TlsInfo> {
             self.inner.tls_info()
         }
@@ -1200,6 +2322,7 @@ mod rustls_tls_conn {
 
 #[cfg(feature = "__boring")]
 mod boring_tls_conn {
+    use futures_core::ready;
     use hyper::client::connect::{Connected, Connection};
     use pin_project_lite::pin_project;
     use std::{
@@ -1210,28 +2333,162 @@ mod boring_tls_conn {
     use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
     use tokio_boring::SslStream;
 
+    use crate::tls::{EarlyData, TlsDetails};
+    use foreign_types::ForeignTypeRef;
+    use once_cell::sync::OnceCell;
+    use std::sync::Arc;
+
     pin_project! {
         pub(super) struct BoringTlsConn<T> {
             #[pin] pub(super) inner: SslStream<T>,
+            early_data: Option<Box<EarlyDataState>>,
+            // Filled once the handshake completes, which is after the
+            // connection is handed to hyper if it sends early data.
+            pub(super) details: Arc<OnceCell<TlsDetails>>,
+        }
+    }
+
+    /// Bookkeeping for a connection whose handshake isn't confirmed yet
+    /// because it's sending 0-RTT early data.
+    struct EarlyDataState {
+        info: EarlyData,
+        // Everything written as early data, sent again if the server rejects it.
+        sent: Vec<u8>,
+        // How much of `sent` has been replayed after a rejection.
+        replayed: Option<usize>,
+        // Only the first flight is sent early: once it's flushed, later writes
+        // wait for the handshake, so they can't belong to a request that
+        // isn't safe to replay.
+        flushed: bool,
+        // A byte read while driving the handshake, not yet returned.
+        read_ahead: Option<u8>,
+    }
+
+    impl<T: AsyncRead + AsyncWrite + Unpin> BoringTlsConn<T> {
+        pub(super) fn new(inner: SslStream<T>) -> BoringTlsConn<T> {
+            // SAFETY: the pointer is valid for as long as `inner`.
+            let in_early_data = unsafe { boring_sys::SSL_in_early_data(inner.ssl().as_ptr()) } == 1;
+            let details = Arc::new(OnceCell::new());
+            let early_data = if in_early_data {
+                Some(Box::new(EarlyDataState {
+                    info: EarlyData::new(),
+                    sent: Vec::new(),
+                    replayed: None,
+                    flushed: false,
+                    read_ahead: None,
+                }))
+            } else {
+                let _ = details.set(TlsDetails::from_boring(inner.ssl()));
+                None
+            };
+            BoringTlsConn {
+                inner,
+                early_data,
+                details,
+            }
+        }
+    }
+
+    impl<T: AsyncRead + AsyncWrite + Unpin> BoringTlsConn<T> {
+        /// Drives the handshake until early data has been accepted, or
+        /// rejected and written again.
+        fn poll_confirmed(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            let mut this = self.project();
+            let state = match this.early_data {
+                Some(state) => state,
+                None => return Poll::Ready(Ok(())),
+            };
+
+            loop {
+                if let Some(ref mut offset) = state.replayed {
+                    if *offset < state.sent.len() {
+                        let n = ready!(this
+                            .inner
+                            .as_mut()
+                            .poll_write(cx, &state.sent[*offset..]))?;
+                        *offset += n;
+                        continue;
+                    }
+                    ready!(this.inner.as_mut().poll_flush(cx))?;
+                    let _ = this.details.set(TlsDetails::from_boring(this.inner.ssl()));
+                    *this.early_data = None;
+                    return Poll::Ready(Ok(()));
+                }
+
+                let ssl = this.inner.ssl().as_ptr();
+                // SAFETY: the pointer is valid for as long as `inner`.
+                if unsafe { boring_sys::SSL_in_early_data(ssl) } == 0 {
+                    state
+                        .info
+                        .set_accepted(unsafe { boring_sys::SSL_early_data_accepted(ssl) } == 1);
+                    let _ = this.details.set(TlsDetails::from_boring(this.inner.ssl()));
+                    if state.read_ahead.is_none() {
+                        *this.early_data = None;
+                    }
+                    return Poll::Ready(Ok(()));
+                }
+
+                // Reading completes the handshake before returning any data.
+                let mut byte = [0u8];
+                let mut buf = ReadBuf::new(&mut byte);
+                match ready!(this.inner.as_mut().poll_read(cx, &mut buf)) {
+                    Ok(()) => {
+                        state.read_ahead = buf.filled().first().copied();
+                        if unsafe { boring_sys::SSL_in_early_data(ssl) } == 1 {
+                            // The peer closed before finishing the handshake.
+                            return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                        }
+                    }
+                    Err(e) if is_early_data_rejected(&e) => {
+                        // SAFETY: the pointer is valid for as long as `inner`.
+                        unsafe { boring_sys::SSL_reset_early_data_reject(ssl) };
+                        state.info.set_accepted(false);
+                        state.replayed = Some(0);
+                    }
+                    Err(e) => return Poll::Ready(Err(e)),
+                }
+            }
         }
     }
 
+    fn is_early_data_rejected(err: &io::Error) -> bool {
+        err.get_ref()
+            .and_then(|e| e.downcast_ref::<boring::ssl::Error>())
+            .map_or(false, |e| {
+                e.code().as_raw() == boring_sys::SSL_ERROR_EARLY_DATA_REJECTED
+            })
+    }
+
     impl<T: Connection + AsyncRead + AsyncWrite + Unpin> Connection for BoringTlsConn<T> {
         fn connected(&self) -> Connected {
-            if self.inner.ssl().selected_alpn_protocol() == Some(b"h2") {
+            let connected = if self.inner.ssl().selected_alpn_protocol() == Some(b"h2") {
                 self.inner.get_ref().connected().negotiated_h2()
             } else {
                 self.inner.get_ref().connected()
+            };
+            match self.early_data {
+                Some(ref state) => connected.extra(state.info.clone()),
+                None => connected,
             }
         }
     }
 
     impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for BoringTlsConn<T> {
         fn poll_read(
-            self: Pin<&mut Self>,
+            mut self: Pin<&mut Self>,
             cx: &mut Context,
             buf: &mut ReadBuf<'_>,
         ) -> Poll<tokio::io::Result<()>> {
+            if self.early_data.is_some() {
+                ready!(self.as_mut().poll_confirmed(cx))?;
+                let this = self.as_mut().project();
+                if let Some(mut state) = this.early_data.take() {
+                    if let Some(byte) = state.read_ahead.take() {
+                        buf.put_slice(&[byte]);
+                        return Poll::Ready(Ok(()));
+                    }
+                }
+            }
             let this = self.project();
             AsyncRead::poll_read(this.inner, cx, buf)
         }
@@ -1239,10 +2496,22 @@ mod boring_tls_conn {
 
     impl<T: AsyncRead + AsyncWrite + Unpin> AsyncWrite for BoringTlsConn<T> {
         fn poll_write(
-            self: Pin<&mut Self>,
+            mut self: Pin<&mut Self>,
             cx: &mut Context,
             buf: &[u8],
         ) -> Poll<Result<usize, tokio::io::Error>> {
+            if let Some(ref state) = self.early_data {
+                if state.flushed || state.replayed.is_some() {
+                    ready!(self.as_mut().poll_confirmed(cx))?;
+                } else {
+                    let this = self.project();
+                    let n = ready!(this.inner.poll_write(cx, buf))?;
+                    if let Some(state) = this.early_data {
+                        state.sent.extend_from_slice(&buf[..n]);
+                    }
+                    return Poll::Ready(Ok(n));
+                }
+            }
             let this = self.project();
             AsyncWrite::poll_write(this.inner, cx, buf)
         }
@@ -1252,6 +2521,10 @@ mod boring_tls_conn {
             cx: &mut Context<'_>,
             bufs: &[IoSlice<'_>],
         ) -> Poll<Result<usize, io::Error>> {
+            if self.early_data.is_some() {
+                let buf = bufs.iter().find(|b| !b.is_empty()).map_or(&[][..], |b| &**b);
+                return self.poll_write(cx, buf);
+            }
             let this = self.project();
             AsyncWrite::poll_write_vectored(this.inner, cx, bufs)
         }
@@ -1265,7 +2538,11 @@ mod boring_tls_conn {
             cx: &mut Context,
         ) -> Poll<Result<(), tokio::io::Error>> {
             let this = self.project();
-            AsyncWrite::poll_flush(this.inner, cx)
+            ready!(AsyncWrite::poll_flush(this.inner, cx))?;
+            if let Some(state) = this.early_data {
+                state.flushed = true;
+            }
+            Poll::Ready(Ok(()))
         }
 
         fn poll_shutdown(
@@ -1279,19 +2556,24 @@ mod boring_tls_conn {
 }
 
 #[cfg(feature = "socks")]
-mod socks {
+pub(crate) mod socks {
     use std::io;
-    use std::net::ToSocketAddrs;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
     use http::Uri;
+    use hyper::service::Service;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
     use tokio::net::TcpStream;
     use tokio_socks::tcp::Socks5Stream;
 
-    use super::{BoxError, Scheme};
+    use super::{BoxError, IpPreference, Scheme};
+    use crate::dns::{Addrs, DynResolver};
     use crate::proxy::ProxyScheme;
 
+    #[derive(Clone)]
     pub(super) enum DnsResolve {
-        Local,
+        /// Resolve the destination with the client's resolver.
+        Local(DynResolver, Option<IpPreference>),
         Proxy,
     }
 
@@ -1311,8 +2593,24 @@ mod socks {
             _ => 80u16,
         };
 
-        if let DnsResolve::Local = dns {
-            let maybe_new_target = (host.as_str(), port).to_socket_addrs()?.next();
+        if let DnsResolve::Local(resolver, preference) = dns {
+            let mut addrs = resolve(resolver, &host).await?;
+            let maybe_new_target = match (&proxy, preference) {
+                // SOCKS4 only connects to IPv4 addresses, in the resolver's
+                // order.
+                (ProxyScheme::Socks4 { .. }, Some(IpPreference::V6Only)) => {
+                    return Err(format!("SOCKS4 proxies can't connect to {} over IPv6", host).into())
+                }
+                (ProxyScheme::Socks4 { .. }, _) => match addrs.find(SocketAddr::is_ipv4) {
+                    Some(addr) => Some(addr),
+                    None => return Err(format!("no IPv4 address for {}", host).into()),
+                },
+                (_, Some(preference)) => match preference.apply(addrs).first() {
+                    Some(addr) => Some(*addr),
+                    None => return Err(preference.no_address(&host)),
+                },
+                (_, None) => addrs.next(),
+            };
             if let Some(new_target) = maybe_new_target {
                 host = new_target.ip().to_string();
             }
@@ -1320,6 +2618,13 @@ mod socks {
 
         let (socket_addr, auth) = match proxy {
             ProxyScheme::Socks5 { addr, auth, .. } => (addr, auth),
+            ProxyScheme::Socks4 { addr, user_id, .. } => {
+                let mut stream = TcpStream::connect(addr).await?;
+                socks4_connect(&mut stream, &host, port, user_id.as_deref())
+                    .await
+                    .map_err(|e| format!("socks connect error: {}", e))?;
+                return Ok(stream);
+            }
             _ => unreachable!(),
         };
 
@@ -1341,14 +2646,392 @@ mod socks {
 
         Ok(stream.into_inner())
     }
+
+    /// Resolves `host` like the HTTP connector does: IP literals as they are,
+    /// names with `resolver`.
+    async fn resolve(mut resolver: DynResolver, host: &str) -> Result<Addrs, BoxError> {
+        let literal = host.trim_start_matches('[').trim_end_matches(']');
+        if let Ok(ip) = literal.parse::<IpAddr>() {
+            return Ok(Box::new(std::iter::once(SocketAddr::new(ip, 0))));
+        }
+        resolver.call(host.parse()?).await
+    }
+
+    /// Asks a SOCKS4 proxy to connect to `host`, with the SOCKS4a extension
+    /// if it isn't an IPv4 address.
+    async fn socks4_connect<S>(
+        stream: &mut S,
+        host: &str,
+        port: u16,
+        user_id: Option<&str>,
+    ) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let user_id = user_id.unwrap_or_default();
+        if user_id.contains('\0') || host.contains('\0') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "NUL in SOCKS4 request",
+            ));
+        }
+
+        // VN, CD (CONNECT), DSTPORT, DSTIP, USERID, NUL
+        let mut request = vec![4, 1];
+        request.extend_from_slice(&port.to_be_bytes());
+        let ip = host.parse::<Ipv4Addr>().ok();
+        match ip {
+            Some(ip) => request.extend_from_slice(&ip.octets()),
+            // 0.0.0.x, x non-zero, tells SOCKS4a proxies the host follows.
+            None => request.extend_from_slice(&[0, 0, 0, 1]),
+        }
+        request.extend_from_slice(user_id.as_bytes());
+        request.push(0);
+        if ip.is_none() {
+            request.extend_from_slice(host.as_bytes());
+            request.push(0);
+        }
+        stream.write_all(&request).await?;
+
+        // VN, CD, DSTPORT, DSTIP
+        let mut reply = [0; 8];
+        stream.read_exact(&mut reply).await?;
+        let reason = match reply[1] {
+            0x5a => return Ok(()),
+            0x5b => "request rejected or failed",
+            0x5c => "the proxy couldn't reach identd on the client",
+            0x5d => "identd didn't confirm the user ID",
+            _ => "invalid reply",
+        };
+        Err(io::Error::new(io::ErrorKind::Other, reason))
+    }
+
+    /// Asks the SOCKS5 proxy at `proxy` to relay UDP datagrams.
+    ///
+    /// Returns the control connection, whose closing ends the association,
+    /// and the address of the relay. A proxy refusing to relay UDP is an
+    /// error of kind `Unsupported`.
+    ///
+    /// tokio-socks only issues CONNECT and BIND, so the handshake is done
+    /// here.
+    #[cfg_attr(not(feature = "http3"), allow(dead_code))]
+    pub(crate) async fn udp_associate(
+        proxy: SocketAddr,
+        auth: Option<(&str, &str)>,
+    ) -> io::Result<(TcpStream, SocketAddr)> {
+        let mut stream = TcpStream::connect(proxy).await?;
+        socks5_auth(&mut stream, auth).await?;
+
+        // VER, CMD (UDP ASSOCIATE), RSV, then the address the datagrams
+        // will come from, unknown yet.
+        stream.write_all(&[5, 3, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+        let mut reply = [0; 3];
+        stream.read_exact(&mut reply).await?;
+        match reply[1] {
+            0 => (),
+            // not allowed by ruleset, command not supported
+            code @ 2 | code @ 7 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("the proxy refused UDP ASSOCIATE: {}", reply_reason(code)),
+                ))
+            }
+            code => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("UDP ASSOCIATE failed: {}", reply_reason(code)),
+                ))
+            }
+        }
+
+        let mut relay = read_addr(&mut stream).await?;
+        // Proxies relaying on all their addresses may answer with none.
+        if relay.ip().is_unspecified() {
+            relay.set_ip(proxy.ip());
+        }
+        Ok((stream, relay))
+    }
+
+    /// Negotiates how to authenticate with a SOCKS5 proxy, and does.
+    async fn socks5_auth<S>(stream: &mut S, auth: Option<(&str, &str)>) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        // VER, NMETHODS, then no authentication and maybe username/password
+        let methods: &[u8] = match auth {
+            Some(_) => &[5, 2, 0, 2],
+            None => &[5, 1, 0],
+        };
+        stream.write_all(methods).await?;
+        let mut choice = [0; 2];
+        stream.read_exact(&mut choice).await?;
+        let (username, password) = match (choice[1], auth) {
+            (0, _) => return Ok(()),
+            (2, Some(auth)) => auth,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "no acceptable authentication method",
+                ))
+            }
+        };
+
+        // RFC 1929: VER, ULEN, UNAME, PLEN, PASSWD
+        if username.len() > 255 || password.len() > 255 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "SOCKS5 credentials are too long",
+            ));
+        }
+        let mut request = vec![1, username.len() as u8];
+        request.extend_from_slice(username.as_bytes());
+        request.push(password.len() as u8);
+        request.extend_from_slice(password.as_bytes());
+        stream.write_all(&request).await?;
+        let mut status = [0; 2];
+        stream.read_exact(&mut status).await?;
+        if status[1] != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the proxy rejected the credentials",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Reads the ATYP, ADDR and PORT fields of a SOCKS5 reply.
+    async fn read_addr<S>(stream: &mut S) -> io::Result<SocketAddr>
+    where
+        S: AsyncRead + Unpin,
+    {
+        let ip = match stream.read_u8().await? {
+            1 => {
+                let mut octets = [0; 4];
+                stream.read_exact(&mut octets).await?;
+                IpAddr::from(Ipv4Addr::from(octets))
+            }
+            4 => {
+                let mut octets = [0; 16];
+                stream.read_exact(&mut octets).await?;
+                IpAddr::from(Ipv6Addr::from(octets))
+            }
+            3 => {
+                let mut name = vec![0; usize::from(stream.read_u8().await?)];
+                stream.read_exact(&mut name).await?;
+                let port = stream.read_u16().await?;
+                let name = String::from_utf8(name)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                return tokio::net::lookup_host((name.as_str(), port))
+                    .await?
+                    .next()
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::Other, format!("no address for {}", name))
+                    });
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid address type",
+                ))
+            }
+        };
+        let port = stream.read_u16().await?;
+        Ok(SocketAddr::new(ip, port))
+    }
+
+    fn reply_reason(code: u8) -> &'static str {
+        match code {
+            1 => "general failure",
+            2 => "not allowed by ruleset",
+            3 => "network unreachable",
+            4 => "host unreachable",
+            5 => "connection refused",
+            6 => "TTL expired",
+            7 => "command not supported",
+            8 => "address type not supported",
+            _ => "unknown error",
+        }
+    }
+
+    /// Where a datagram relayed by a SOCKS5 proxy goes.
+    #[cfg_attr(not(feature = "http3"), allow(dead_code))]
+    #[derive(Clone, Debug)]
+    pub(crate) enum UdpTarget {
+        Addr(SocketAddr),
+        /// Resolved by the proxy.
+        Domain(String, u16),
+    }
+
+    /// Returns the header of the datagrams sent to `target` through a
+    /// SOCKS5 relay.
+    #[cfg_attr(not(feature = "http3"), allow(dead_code))]
+    pub(crate) fn udp_header(target: &UdpTarget) -> io::Result<Vec<u8>> {
+        // RSV, FRAG
+        let mut header = vec![0, 0, 0];
+        let port = match target {
+            UdpTarget::Addr(SocketAddr::V4(addr)) => {
+                header.push(1);
+                header.extend_from_slice(&addr.ip().octets());
+                addr.port()
+            }
+            UdpTarget::Addr(SocketAddr::V6(addr)) => {
+                header.push(4);
+                header.extend_from_slice(&addr.ip().octets());
+                addr.port()
+            }
+            UdpTarget::Domain(name, port) => {
+                if name.len() > 255 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "host too long"));
+                }
+                header.push(3);
+                header.push(name.len() as u8);
+                header.extend_from_slice(name.as_bytes());
+                *port
+            }
+        };
+        header.extend_from_slice(&port.to_be_bytes());
+        Ok(header)
+    }
+
+    /// Returns the length of the header of a datagram received from a
+    /// SOCKS5 relay, or `None` if it's malformed or a fragment, which
+    /// aren't supported.
+    #[cfg_attr(not(feature = "http3"), allow(dead_code))]
+    pub(crate) fn udp_header_len(datagram: &[u8]) -> Option<usize> {
+        if datagram.len() < 4 || datagram[2] != 0 {
+            return None;
+        }
+        let len = match datagram[3] {
+            1 => 10,
+            4 => 22,
+            3 => 7 + usize::from(*datagram.get(4)?),
+            _ => return None,
+        };
+        Some(len).filter(|&len| len <= datagram.len())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::sync::Arc;
+
+        use super::*;
+        use crate::dns::gai::GaiResolver;
+
+        async fn exchange(
+            host: &str,
+            user_id: Option<&str>,
+            code: u8,
+        ) -> (Vec<u8>, io::Result<()>) {
+            let (mut client, mut proxy) = tokio::io::duplex(1024);
+            proxy.write_all(&[0, code, 0, 0, 0, 0, 0, 0]).await.unwrap();
+            let result = socks4_connect(&mut client, host, 443, user_id).await;
+            drop(client);
+            let mut request = Vec::new();
+            proxy.read_to_end(&mut request).await.unwrap();
+            (request, result)
+        }
+
+        #[tokio::test]
+        async fn socks4() {
+            let (request, result) = exchange("10.0.0.1", Some("alice"), 0x5a).await;
+            result.unwrap();
+            assert_eq!(request, b"\x04\x01\x01\xbb\x0a\x00\x00\x01alice\x00");
+        }
+
+        #[tokio::test]
+        async fn socks4a() {
+            let (request, result) = exchange("example.com", None, 0x5a).await;
+            result.unwrap();
+            assert_eq!(
+                request,
+                b"\x04\x01\x01\xbb\x00\x00\x00\x01\x00example.com\x00"
+            );
+
+            let (_, result) = exchange("example.com", None, 0x5b).await;
+            assert!(result.unwrap_err().to_string().contains("rejected"));
+        }
+
+        #[tokio::test]
+        async fn socks4_v6_only() {
+            let proxy = ProxyScheme::Socks4 {
+                addr: ([127, 0, 0, 1], 1).into(),
+                user_id: None,
+                remote_dns: false,
+            };
+            let dst = "http://127.0.0.1/".parse().unwrap();
+            let resolver = DynResolver::new(Arc::new(GaiResolver::new()));
+            let dns = DnsResolve::Local(resolver, Some(IpPreference::V6Only));
+            let err = connect(proxy, dst, dns).await.unwrap_err();
+            assert!(err.to_string().contains("over IPv6"), "{}", err);
+        }
+
+        /// Answers a UDP ASSOCIATE request for Aladdin with `reply`.
+        async fn udp_proxy(reply: &'static [u8]) -> SocketAddr {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut methods = [0; 4];
+                stream.read_exact(&mut methods).await.unwrap();
+                assert_eq!(methods, [5, 2, 0, 2]);
+                stream.write_all(&[5, 2]).await.unwrap();
+
+                let mut auth = [0; 21];
+                stream.read_exact(&mut auth).await.unwrap();
+                assert_eq!(&auth, b"\x01\x07Aladdin\x0bopen sesame");
+                stream.write_all(&[1, 0]).await.unwrap();
+
+                let mut request = [0; 10];
+                stream.read_exact(&mut request).await.unwrap();
+                assert_eq!(request[..2], [5, 3]);
+                stream.write_all(reply).await.unwrap();
+                let _ = stream.read_u8().await;
+            });
+            addr
+        }
+
+        #[tokio::test]
+        async fn udp_associate() {
+            let auth = Some(("Aladdin", "open sesame"));
+            let proxy = udp_proxy(b"\x05\x00\x00\x01\x00\x00\x00\x00\x04\x38").await;
+            let (_control, relay) = super::udp_associate(proxy, auth).await.unwrap();
+            assert_eq!(relay, SocketAddr::new(proxy.ip(), 1080));
+
+            let proxy = udp_proxy(b"\x05\x07\x00\x01\x00\x00\x00\x00\x00\x00").await;
+            let err = super::udp_associate(proxy, auth).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        }
+
+        #[test]
+        fn udp_headers() {
+            let target = UdpTarget::Addr(([10, 0, 0, 1], 443).into());
+            let header = udp_header(&target).unwrap();
+            assert_eq!(header, [0, 0, 0, 1, 10, 0, 0, 1, 1, 187]);
+            assert_eq!(udp_header_len(&header), Some(10));
+
+            let target = UdpTarget::Domain("example.com".into(), 443);
+            let mut datagram = udp_header(&target).unwrap();
+            assert_eq!(datagram[3..5], [3, 11]);
+            datagram.push(0xc0);
+            assert_eq!(udp_header_len(&datagram), Some(18));
+
+            // fragments, truncated
+            assert_eq!(udp_header_len(&[0, 0, 1, 1, 10, 0, 0, 1, 1, 187]), None);
+            assert_eq!(udp_header_len(&datagram[..10]), None);
+        }
+    }
 }
 
 mod verbose {
     use hyper::client::connect::{Connected, Connection};
+    #[cfg(feature = "__tls")]
+    use once_cell::sync::OnceCell;
     use std::cmp::min;
     use std::fmt;
     use std::io::{self, IoSlice};
     use std::pin::Pin;
+    #[cfg(feature = "__tls")]
+    use std::sync::Arc;
     use std::task::{Context, Poll};
     use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
@@ -1458,6 +3141,10 @@ mod verbose {
         fn tls_info(&self) -> Option<crate::tls::TlsInfo> {
             self.inner.tls_info()
         }
+
+        fn tls_details(&self) -> Option<Arc<OnceCell<crate::tls::TlsDetails>>> {
+            self.inner.tls_details()
+        }
     }
 
     struct Escape<'a>(&'a [u8]);
@@ -1513,8 +3200,9 @@ mod verbose {
 #[cfg(feature = "__tls")]
 #[cfg(test)]
 mod tests {
-    use super::tunnel;
+    use super::{connect_headers, tunnel, ConnectStyle};
     use crate::proxy;
+    use http::header::{HeaderMap, HeaderValue};
     use std::io::{Read, Write};
     use std::net::TcpListener;
     use std::thread;
@@ -1564,8 +3252,9 @@ mod tests {
         }};
     }
 
-    fn ua() -> Option<http::header::HeaderValue> {
-        Some(http::header::HeaderValue::from_static(TUNNEL_UA))
+    fn headers(host: &str, port: u16, auth: Option<HeaderValue>) -> HeaderMap {
+        let ua = Some(HeaderValue::from_static(TUNNEL_UA));
+        connect_headers(ConnectStyle::Plain, host, port, ua, None, auth).unwrap()
     }
 
     #[test]
@@ -1580,7 +3269,7 @@ mod tests {
             let tcp = TcpStream::connect(&addr).await?;
             let host = addr.ip().to_string();
             let port = addr.port();
-            tunnel(tcp, host, port, ua(), None).await
+            tunnel(tcp, &host, port, headers(&host, port, None)).await
         };
 
         rt.block_on(f).unwrap();
@@ -1598,7 +3287,7 @@ mod tests {
             let tcp = TcpStream::connect(&addr).await?;
             let host = addr.ip().to_string();
             let port = addr.port();
-            tunnel(tcp, host, port, ua(), None).await
+            tunnel(tcp, &host, port, headers(&host, port, None)).await
         };
 
         rt.block_on(f).unwrap_err();
@@ -1616,7 +3305,7 @@ mod tests {
             let tcp = TcpStream::connect(&addr).await?;
             let host = addr.ip().to_string();
             let port = addr.port();
-            tunnel(tcp, host, port, ua(), None).await
+            tunnel(tcp, &host, port, headers(&host, port, None)).await
         };
 
         rt.block_on(f).unwrap_err();
@@ -1640,7 +3329,7 @@ mod tests {
             let tcp = TcpStream::connect(&addr).await?;
             let host = addr.ip().to_string();
             let port = addr.port();
-            tunnel(tcp, host, port, ua(), None).await
+            tunnel(tcp, &host, port, headers(&host, port, None)).await
         };
 
         let error = rt.block_on(f).unwrap_err();
@@ -1662,16 +3351,129 @@ mod tests {
             let tcp = TcpStream::connect(&addr).await?;
             let host = addr.ip().to_string();
             let port = addr.port();
-            tunnel(
-                tcp,
-                host,
-                port,
-                ua(),
-                Some(proxy::encode_basic_auth("Aladdin", "open sesame")),
-            )
-                .await
+            let auth = proxy::encode_basic_auth("Aladdin", "open sesame");
+            tunnel(tcp, &host, port, headers(&host, port, Some(auth))).await
+        };
+
+        rt.block_on(f).unwrap();
+    }
+
+    #[test]
+    fn test_tunnel_response_headers() {
+        let addr = mock_tunnel!(
+            b"\
+            HTTP/1.1 200 Connection established\r\n\
+            X-Exit-Ip: 203.0.113.7\r\n\
+            \r\n\
+        "
+        );
+
+        let rt = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("new rt");
+        let f = async move {
+            let tcp = TcpStream::connect(&addr).await?;
+            let host = addr.ip().to_string();
+            let port = addr.port();
+            tunnel(tcp, &host, port, headers(&host, port, None)).await
+        };
+
+        let (_, headers) = rt.block_on(f).unwrap();
+        assert_eq!(headers["x-exit-ip"], "203.0.113.7");
+    }
+
+    #[test]
+    fn connect_headers_follow_the_browser() {
+        let ua = || Some(HeaderValue::from_static("ua"));
+        let mut extra = HeaderMap::new();
+        extra.insert("user-agent", HeaderValue::from_static("vendor"));
+        extra.insert("x-session", HeaderValue::from_static("1"));
+        let auth = || Some(HeaderValue::from_static("Basic Zm9v"));
+
+        let names = |style| {
+            connect_headers(style, "example.com", 443, ua(), Some(&extra), auth())
+                .unwrap()
+                .iter()
+                .map(|(name, value)| format!("{}: {}", name, value.to_str().unwrap()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names(ConnectStyle::Chromium),
+            [
+                "host: example.com:443",
+                "proxy-connection: keep-alive",
+                "user-agent: vendor",
+                "x-session: 1",
+                "proxy-authorization: Basic Zm9v",
+            ]
+        );
+        assert_eq!(
+            names(ConnectStyle::Firefox),
+            [
+                "user-agent: vendor",
+                "proxy-connection: keep-alive",
+                "connection: keep-alive",
+                "host: example.com:443",
+                "x-session: 1",
+                "proxy-authorization: Basic Zm9v",
+            ]
+        );
+    }
+
+    #[cfg(feature = "impersonate")]
+    #[test]
+    fn connect_style_of_profiles() {
+        use crate::impersonate::profile::ClientProfile;
+
+        assert_eq!(ConnectStyle::of(ClientProfile::Chrome), ConnectStyle::Chromium);
+        assert_eq!(ConnectStyle::of(ClientProfile::Edge), ConnectStyle::Chromium);
+        assert_eq!(ConnectStyle::of(ClientProfile::Firefox), ConnectStyle::Firefox);
+        assert_eq!(ConnectStyle::of(ClientProfile::Safari), ConnectStyle::Plain);
+    }
+
+    #[test]
+    fn connect_headers_are_title_cased() {
+        let addr = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            thread::spawn(move || {
+                let (mut sock, _) = listener.accept().unwrap();
+                let mut buf = [0u8; 4096];
+                let n = sock.read(&mut buf).unwrap();
+                let expected = format!(
+                    "\
+                     CONNECT {0}:{1} HTTP/1.1\r\n\
+                     Host: {0}:{1}\r\n\
+                     Proxy-Connection: Keep-Alive\r\n\
+                     X-Session-Id: 1\r\n\
+                     \r\n\
+                     ",
+                    addr.ip(),
+                    addr.port()
+                );
+                assert_eq!(&buf[..n], expected.as_bytes());
+                sock.write_all(TUNNEL_OK).unwrap();
+            });
+            addr
+        };
+
+        let rt = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("new rt");
+        let f = async move {
+            let tcp = TcpStream::connect(&addr).await?;
+            let host = addr.ip().to_string();
+            let port = addr.port();
+            let mut extra = HeaderMap::new();
+            extra.insert("x-session-id", HeaderValue::from_static("1"));
+            let headers =
+                connect_headers(ConnectStyle::OkHttp, &host, port, None, Some(&extra), None)?;
+            tunnel(tcp, &host, port, headers).await
         };
 
         rt.block_on(f).unwrap();
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,281 @@
+//! Details of the connection a response was received on.
+
+use std::fmt;
+use std::sync::Arc;
+
+use http::HeaderMap;
+use once_cell::sync::OnceCell;
+
+/// Hyper extension describing the connection a response came in on.
+///
+/// Every response carries one. It tells what was negotiated with the
+/// server, which helps comparing how a server treats this client with how
+/// it treats a browser.
+///
+/// # Example
+///
+/// ```no_run
+/// # use chromimic as reqwest;
+/// # async fn run() -> Result<(), reqwest::Error> {
+/// let res = reqwest::get("https://example.com").await?;
+/// if let Some(info) = res.extensions().get::<reqwest::ConnectionInfo>() {
+///     println!("{:?}", info);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct ConnectionInfo {
+    // Filled once the handshake completes, which may be after the
+    // connection was handed to hyper if it sent early data.
+    #[cfg(feature = "__tls")]
+    pub(crate) tls: Option<Arc<OnceCell<crate::tls::TlsDetails>>>,
+    pub(crate) http2_settings: Arc<OnceCell<Http2Settings>>,
+    pub(crate) proxy_headers: Option<Arc<HeaderMap>>,
+}
+
+impl ConnectionInfo {
+    /// Returns what was negotiated by TLS, if the connection uses it.
+    ///
+    /// This is only available with the BoringSSL backend, and for HTTP/3,
+    /// whose connections only tell the version, ALPN protocol and
+    /// certificates.
+    #[cfg(feature = "__tls")]
+    pub fn tls(&self) -> Option<&crate::tls::TlsDetails> {
+        self.tls.as_ref().and_then(|tls| tls.get())
+    }
+
+    /// Returns the first SETTINGS frame the server sent, if the connection
+    /// speaks HTTP/2.
+    pub fn http2_settings(&self) -> Option<&Http2Settings> {
+        self.http2_settings.get()
+    }
+
+    /// Returns the headers of the proxy's response to the `CONNECT` request,
+    /// if the connection is tunneled through an HTTP proxy.
+    ///
+    /// Some proxies report the exit IP or session they assigned there.
+    pub fn proxy_headers(&self) -> Option<&HeaderMap> {
+        self.proxy_headers.as_deref()
+    }
+}
+
+impl fmt::Debug for ConnectionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut f = f.debug_struct("ConnectionInfo");
+        #[cfg(feature = "__tls")]
+        f.field("tls", &self.tls());
+        f.field("http2_settings", &self.http2_settings.get())
+            .field("proxy_headers", &self.proxy_headers)
+            .finish()
+    }
+}
+
+/// The parameters of an HTTP/2 SETTINGS frame.
+///
+/// https://www.rfc-editor.org/rfc/rfc9113#section-6.5.2
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Http2Settings {
+    entries: Vec<(u16, u32)>,
+}
+
+impl Http2Settings {
+    /// Returns every parameter in the order it was sent, including unknown
+    /// and repeated ones.
+    pub fn entries(&self) -> &[(u16, u32)] {
+        &self.entries
+    }
+
+    /// Returns the value of the parameter `id`, as applied: the last one
+    /// sent wins.
+    pub fn get(&self, id: u16) -> Option<u32> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|(entry, _)| *entry == id)
+            .map(|&(_, value)| value)
+    }
+
+    /// Returns `SETTINGS_HEADER_TABLE_SIZE`.
+    pub fn header_table_size(&self) -> Option<u32> {
+        self.get(0x1)
+    }
+
+    /// Returns `SETTINGS_ENABLE_PUSH`.
+    pub fn enable_push(&self) -> Option<bool> {
+        self.get(0x2).map(|value| value != 0)
+    }
+
+    /// Returns `SETTINGS_MAX_CONCURRENT_STREAMS`.
+    pub fn max_concurrent_streams(&self) -> Option<u32> {
+        self.get(0x3)
+    }
+
+    /// Returns `SETTINGS_INITIAL_WINDOW_SIZE`.
+    pub fn initial_window_size(&self) -> Option<u32> {
+        self.get(0x4)
+    }
+
+    /// Returns `SETTINGS_MAX_FRAME_SIZE`.
+    pub fn max_frame_size(&self) -> Option<u32> {
+        self.get(0x5)
+    }
+
+    /// Returns `SETTINGS_MAX_HEADER_LIST_SIZE`.
+    pub fn max_header_list_size(&self) -> Option<u32> {
+        self.get(0x6)
+    }
+}
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+const FRAME_HEADER_LEN: usize = 9;
+const SETTINGS: u8 = 0x4;
+const ACK: u8 = 0x1;
+// Far more than the parameters defined so far take.
+const MAX_SETTINGS_LEN: usize = 1024;
+
+/// Picks the server's SETTINGS frame out of the bytes read from a
+/// connection.
+///
+/// It waits for the client to write the HTTP/2 connection preface, so it
+/// works whether HTTP/2 was negotiated by ALPN or used with prior
+/// knowledge. The server's first frame has to be SETTINGS, after which the
+/// sniffer gets out of the way.
+#[derive(Default)]
+pub(crate) struct Http2Sniffer {
+    state: SniffState,
+    buf: Vec<u8>,
+    settings: Arc<OnceCell<Http2Settings>>,
+}
+
+#[derive(Default, PartialEq)]
+enum SniffState {
+    // `buf` holds the part of the preface written so far.
+    #[default]
+    Preface,
+    Settings,
+    Done,
+}
+
+impl Http2Sniffer {
+    pub(crate) fn settings(&self) -> Arc<OnceCell<Http2Settings>> {
+        self.settings.clone()
+    }
+
+    pub(crate) fn wants_writes(&self) -> bool {
+        self.state == SniffState::Preface
+    }
+
+    pub(crate) fn wants_reads(&self) -> bool {
+        self.state == SniffState::Settings
+    }
+
+    /// Feeds bytes the client wrote.
+    pub(crate) fn written(&mut self, data: &[u8]) {
+        if self.state != SniffState::Preface {
+            return;
+        }
+
+        let matched = self.buf.len();
+        let n = data.len().min(PREFACE.len() - matched);
+        if data[..n] != PREFACE[matched..matched + n] {
+            self.finish();
+            return;
+        }
+        self.buf.extend_from_slice(&data[..n]);
+        if self.buf.len() == PREFACE.len() {
+            self.buf.clear();
+            self.state = SniffState::Settings;
+        }
+    }
+
+    /// Feeds bytes read from the server.
+    pub(crate) fn read(&mut self, data: &[u8]) {
+        if self.state != SniffState::Settings {
+            return;
+        }
+
+        self.buf.extend_from_slice(data);
+        if self.buf.len() < FRAME_HEADER_LEN {
+            return;
+        }
+
+        let len = u32::from_be_bytes([0, self.buf[0], self.buf[1], self.buf[2]]) as usize;
+        let kind = self.buf[3];
+        let flags = self.buf[4];
+        if kind != SETTINGS || flags & ACK != 0 || len % 6 != 0 || len > MAX_SETTINGS_LEN {
+            self.finish();
+            return;
+        }
+        if self.buf.len() < FRAME_HEADER_LEN + len {
+            return;
+        }
+
+        let entries = self.buf[FRAME_HEADER_LEN..FRAME_HEADER_LEN + len]
+            .chunks(6)
+            .map(|entry| {
+                let id = u16::from_be_bytes([entry[0], entry[1]]);
+                let value = u32::from_be_bytes([entry[2], entry[3], entry[4], entry[5]]);
+                (id, value)
+            })
+            .collect();
+        let _ = self.settings.set(Http2Settings { entries });
+        self.finish();
+    }
+
+    fn finish(&mut self) {
+        self.state = SniffState::Done;
+        self.buf = Vec::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Http2Sniffer, PREFACE};
+
+    const SETTINGS: &[u8] = &[
+        0x00, 0x00, 0x0c, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, // header
+        0x00, 0x03, 0x00, 0x00, 0x00, 0x64, // MAX_CONCURRENT_STREAMS 100
+        0x00, 0x04, 0x00, 0x01, 0x00, 0x00, // INITIAL_WINDOW_SIZE 65536
+    ];
+
+    #[test]
+    fn sniffs_settings_in_pieces() {
+        let mut sniffer = Http2Sniffer::default();
+        sniffer.written(&PREFACE[..10]);
+        assert!(sniffer.wants_writes());
+        sniffer.written(&[&PREFACE[10..], b"more frames"].concat());
+        assert!(sniffer.wants_reads());
+
+        for chunk in SETTINGS.chunks(5) {
+            sniffer.read(chunk);
+        }
+        assert!(!sniffer.wants_reads());
+
+        let settings = sniffer.settings();
+        let settings = settings.get().expect("settings");
+        assert_eq!(settings.entries(), &[(3, 100), (4, 65536)]);
+        assert_eq!(settings.max_concurrent_streams(), Some(100));
+        assert_eq!(settings.initial_window_size(), Some(65536));
+        assert_eq!(settings.header_table_size(), None);
+    }
+
+    #[test]
+    fn ignores_http1() {
+        let mut sniffer = Http2Sniffer::default();
+        sniffer.written(b"GET / HTTP/1.1\r\n");
+        assert!(!sniffer.wants_writes());
+        assert!(!sniffer.wants_reads());
+        sniffer.read(SETTINGS);
+        assert!(sniffer.settings().get().is_none());
+    }
+
+    #[test]
+    fn gives_up_on_other_frames() {
+        let mut sniffer = Http2Sniffer::default();
+        sniffer.written(PREFACE);
+        sniffer.read(&[0x00, 0x00, 0x00, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00]);
+        assert!(!sniffer.wants_reads());
+        assert!(sniffer.settings().get().is_none());
+    }
+}
@@ -0,0 +1,604 @@
+//! HTTP Digest access authentication (RFC 7616)
+
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use http::header::HeaderValue;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+/// A username and password answering Digest challenges.
+#[derive(Clone, PartialEq)]
+pub(crate) struct Credentials {
+    username: String,
+    password: String,
+}
+
+impl Credentials {
+    pub(crate) fn new<U: fmt::Display, P: fmt::Display>(username: U, password: P) -> Credentials {
+        Credentials {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .finish()
+    }
+}
+
+/// The Digest credentials of a client, for every host or some of them.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ClientCredentials {
+    any: Option<Credentials>,
+    hosts: HashMap<String, Credentials>,
+}
+
+impl ClientCredentials {
+    pub(crate) fn set_any(&mut self, credentials: Credentials) {
+        self.any = Some(credentials);
+    }
+
+    pub(crate) fn set_host(&mut self, host: &str, credentials: Credentials) {
+        self.hosts.insert(host_key(host), credentials);
+    }
+
+    /// The credentials of requests to `url`, if any. The ones set for any
+    /// host are left out unless `any_host`.
+    pub(crate) fn get(&self, url: &Url, any_host: bool) -> Option<&Credentials> {
+        url.host_str()
+            .and_then(|host| self.hosts.get(&host_key(host)))
+            .or(self.any.as_ref().filter(|_| any_host))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.any.is_none() && self.hosts.is_empty()
+    }
+}
+
+fn host_key(host: &str) -> String {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_ascii_lowercase()
+}
+
+/// The Digest credentials of a proxy, answering its challenges to CONNECT.
+///
+/// Clones share the session, so every tunnel through the proxy counts its
+/// uses of the nonce.
+#[derive(Clone)]
+pub(crate) struct ProxyAuth {
+    credentials: Credentials,
+    sessions: Arc<Sessions>,
+}
+
+impl ProxyAuth {
+    pub(crate) fn new(credentials: Credentials) -> ProxyAuth {
+        ProxyAuth {
+            credentials,
+            sessions: Arc::new(Sessions::default()),
+        }
+    }
+
+    pub(crate) fn username(&self) -> &str {
+        &self.credentials.username
+    }
+
+    /// Authorizes a CONNECT to `host:port` with the last challenge of the
+    /// proxy, if any.
+    pub(crate) fn authorize(&self, host: &str, port: u16) -> Option<HeaderValue> {
+        let authority = format!("{}:{}", host, port);
+        self.sessions
+            .authorize("", &self.credentials, &Request::connect(&authority))
+    }
+
+    /// Answers the Digest challenge among the `Proxy-Authenticate` values of
+    /// a 407 response to a CONNECT to `host:port`.
+    pub(crate) fn answer(
+        &self,
+        challenges: &[HeaderValue],
+        host: &str,
+        port: u16,
+    ) -> Option<HeaderValue> {
+        let authority = format!("{}:{}", host, port);
+        self.sessions.answer(
+            "",
+            challenges,
+            &self.credentials,
+            &Request::connect(&authority),
+        )
+    }
+}
+
+impl PartialEq for ProxyAuth {
+    fn eq(&self, other: &ProxyAuth) -> bool {
+        self.credentials == other.credentials
+    }
+}
+
+impl fmt::Debug for ProxyAuth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("ProxyAuth").field(&self.credentials).finish()
+    }
+}
+
+/// What a Digest response covers of a request.
+pub(crate) struct Request<'a> {
+    pub(crate) method: &'a str,
+    /// The request target, or the authority of a CONNECT.
+    pub(crate) uri: &'a str,
+    /// The body, unless it's a stream, which `auth-int` can't protect.
+    pub(crate) body: Option<&'a [u8]>,
+}
+
+impl<'a> Request<'a> {
+    fn connect(authority: &'a str) -> Request<'a> {
+        Request {
+            method: "CONNECT",
+            uri: authority,
+            body: Some(b""),
+        }
+    }
+}
+
+/// Digest sessions by protection space, such as an origin.
+///
+/// A session keeps the last challenge of its space, so later requests there
+/// can authorize up front, counting their uses of the nonce.
+#[derive(Default)]
+pub(crate) struct Sessions(Mutex<HashMap<String, Session>>);
+
+impl Sessions {
+    /// Authorizes a request in `space` with its last challenge, if any.
+    pub(crate) fn authorize(
+        &self,
+        space: &str,
+        credentials: &Credentials,
+        request: &Request<'_>,
+    ) -> Option<HeaderValue> {
+        self.0
+            .lock()
+            .unwrap()
+            .get_mut(space)?
+            .respond(credentials, request)
+    }
+
+    /// Answers the strongest Digest challenge among `challenges` the request
+    /// can answer, starting a new session in `space`.
+    pub(crate) fn answer<'h>(
+        &self,
+        space: &str,
+        challenges: impl IntoIterator<Item = &'h HeaderValue>,
+        credentials: &Credentials,
+        request: &Request<'_>,
+    ) -> Option<HeaderValue> {
+        let mut session = Session::new(Challenge::pick(challenges, request)?);
+        let header = session.respond(credentials, request)?;
+        self.0.lock().unwrap().insert(space.to_owned(), session);
+        Some(header)
+    }
+}
+
+impl fmt::Debug for Sessions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sessions").finish()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Algorithm {
+    Md5,
+    Sha256,
+}
+
+impl Algorithm {
+    fn hash(self, data: &[u8]) -> String {
+        let hash = match self {
+            Algorithm::Md5 => Md5::digest(data).to_vec(),
+            Algorithm::Sha256 => Sha256::digest(data).to_vec(),
+        };
+        hash.iter().fold(String::with_capacity(64), |mut hex, b| {
+            let _ = write!(hex, "{:02x}", b);
+            hex
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Qop {
+    Auth,
+    AuthInt,
+}
+
+impl Qop {
+    fn as_str(self) -> &'static str {
+        match self {
+            Qop::Auth => "auth",
+            Qop::AuthInt => "auth-int",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Challenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: Algorithm,
+    sess: bool,
+    /// Whether the server named the algorithm, which is then echoed.
+    named: bool,
+    qop: Option<Qop>,
+    userhash: bool,
+}
+
+impl Challenge {
+    /// Picks the strongest of the Digest challenges in `values` that
+    /// `request` can answer, the first one among equals.
+    fn pick<'h>(
+        values: impl IntoIterator<Item = &'h HeaderValue>,
+        request: &Request<'_>,
+    ) -> Option<Challenge> {
+        values
+            .into_iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(parse_challenges)
+            .filter(|challenge| challenge.qop != Some(Qop::AuthInt) || request.body.is_some())
+            .min_by_key(|challenge| challenge.algorithm != Algorithm::Sha256)
+    }
+
+    fn from_params(params: Vec<(String, String)>) -> Option<Challenge> {
+        let mut realm = None;
+        let mut nonce = None;
+        let mut opaque = None;
+        let mut algorithm = None;
+        let mut qop = None;
+        let mut userhash = false;
+        for (name, value) in params {
+            match &*name {
+                "realm" => realm = Some(value),
+                "nonce" => nonce = Some(value),
+                "opaque" => opaque = Some(value),
+                "algorithm" => algorithm = Some(value),
+                "qop" => qop = Some(value),
+                "userhash" => userhash = value.eq_ignore_ascii_case("true"),
+                _ => (),
+            }
+        }
+
+        let (algorithm, sess, named) = match algorithm {
+            None => (Algorithm::Md5, false, false),
+            Some(name) => match &*name.to_ascii_uppercase() {
+                "MD5" => (Algorithm::Md5, false, true),
+                "MD5-SESS" => (Algorithm::Md5, true, true),
+                "SHA-256" => (Algorithm::Sha256, false, true),
+                "SHA-256-SESS" => (Algorithm::Sha256, true, true),
+                _ => return None,
+            },
+        };
+        // Without a quality of protection, it's the RFC 2069 digest.
+        let qop = match qop {
+            None => None,
+            Some(options) => {
+                let offers = |qop: Qop| {
+                    options
+                        .split(',')
+                        .any(|option| option.trim().eq_ignore_ascii_case(qop.as_str()))
+                };
+                if offers(Qop::Auth) {
+                    Some(Qop::Auth)
+                } else if offers(Qop::AuthInt) {
+                    Some(Qop::AuthInt)
+                } else {
+                    return None;
+                }
+            }
+        };
+
+        Some(Challenge {
+            realm: realm?,
+            nonce: nonce?,
+            opaque,
+            algorithm,
+            sess,
+            named,
+            qop,
+            userhash,
+        })
+    }
+
+    fn algorithm_name(&self) -> &'static str {
+        match (self.algorithm, self.sess) {
+            (Algorithm::Md5, false) => "MD5",
+            (Algorithm::Md5, true) => "MD5-sess",
+            (Algorithm::Sha256, false) => "SHA-256",
+            (Algorithm::Sha256, true) => "SHA-256-sess",
+        }
+    }
+}
+
+/// Parses the Digest challenges of a `WWW-Authenticate` or
+/// `Proxy-Authenticate` value, skipping the other schemes.
+fn parse_challenges(value: &str) -> Vec<Challenge> {
+    let is_space = |c: char| c == ' ' || c == '\t';
+    let mut challenges = Vec::new();
+    // The parameters of the Digest challenge being parsed.
+    let mut params: Option<Vec<(String, String)>> = None;
+    let mut rest = value;
+    loop {
+        rest = rest.trim_start_matches(|c| c == ',' || is_space(c));
+        let end = rest.find(|c: char| !is_token_char(c)).unwrap_or(rest.len());
+        let (name, after) = rest.split_at(end);
+        if name.is_empty() {
+            // The end, or the padding of another scheme's token68.
+            match after.chars().next() {
+                Some(c) => {
+                    rest = &after[c.len_utf8()..];
+                    continue;
+                }
+                None => break,
+            }
+        }
+
+        match after.trim_start_matches(is_space).strip_prefix('=') {
+            Some(value) => {
+                let value = value.trim_start_matches(is_space);
+                let (value, after) = match value.strip_prefix('"') {
+                    Some(quoted) => unquote(quoted),
+                    None => {
+                        let end = value
+                            .find(|c| c == ',' || is_space(c))
+                            .unwrap_or(value.len());
+                        (value[..end].to_owned(), &value[end..])
+                    }
+                };
+                if let Some(ref mut params) = params {
+                    params.push((name.to_ascii_lowercase(), value));
+                }
+                rest = after;
+            }
+            None => {
+                challenges.extend(params.take().and_then(Challenge::from_params));
+                if name.eq_ignore_ascii_case("digest") {
+                    params = Some(Vec::new());
+                }
+                rest = after;
+            }
+        }
+    }
+    challenges.extend(params.take().and_then(Challenge::from_params));
+    challenges
+}
+
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
+/// Reads a quoted string after its opening quote, returning what follows.
+fn unquote(quoted: &str) -> (String, &str) {
+    let mut value = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return (value, &quoted[i + 1..]),
+            '\\' => value.extend(chars.next().map(|(_, c)| c)),
+            c => value.push(c),
+        }
+    }
+    (value, "")
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The last challenge of a protection space, and the uses of its nonce.
+struct Session {
+    challenge: Challenge,
+    cnonce: String,
+    nc: u32,
+}
+
+impl Session {
+    fn new(challenge: Challenge) -> Session {
+        let cnonce = format!(
+            "{:016x}{:016x}",
+            crate::util::fast_random(),
+            crate::util::fast_random()
+        );
+        Session {
+            challenge,
+            cnonce,
+            nc: 0,
+        }
+    }
+
+    fn respond(&mut self, credentials: &Credentials, request: &Request<'_>) -> Option<HeaderValue> {
+        let challenge = &self.challenge;
+        let hash = |data: String| challenge.algorithm.hash(data.as_bytes());
+
+        let a2 = match challenge.qop {
+            Some(Qop::AuthInt) => format!(
+                "{}:{}:{}",
+                request.method,
+                request.uri,
+                challenge.algorithm.hash(request.body?)
+            ),
+            _ => format!("{}:{}", request.method, request.uri),
+        };
+        let mut ha1 = hash(format!(
+            "{}:{}:{}",
+            credentials.username, challenge.realm, credentials.password
+        ));
+        if challenge.sess {
+            ha1 = hash(format!("{}:{}:{}", ha1, challenge.nonce, self.cnonce));
+        }
+        self.nc = self.nc.wrapping_add(1);
+        let nc = format!("{:08x}", self.nc);
+        let response = match challenge.qop {
+            Some(qop) => hash(format!(
+                "{}:{}:{}:{}:{}:{}",
+                ha1,
+                challenge.nonce,
+                nc,
+                self.cnonce,
+                qop.as_str(),
+                hash(a2)
+            )),
+            None => hash(format!("{}:{}:{}", ha1, challenge.nonce, hash(a2))),
+        };
+
+        let username = if challenge.userhash {
+            hash(format!("{}:{}", credentials.username, challenge.realm))
+        } else {
+            credentials.username.clone()
+        };
+        let mut header = format!(
+            "Digest username={}, realm={}, uri={}",
+            quote(&username),
+            quote(&challenge.realm),
+            quote(request.uri)
+        );
+        if challenge.named {
+            let _ = write!(header, ", algorithm={}", challenge.algorithm_name());
+        }
+        let _ = write!(header, ", nonce={}", quote(&challenge.nonce));
+        if let Some(qop) = challenge.qop {
+            let _ = write!(
+                header,
+                ", nc={}, cnonce={}, qop={}",
+                nc,
+                quote(&self.cnonce),
+                qop.as_str()
+            );
+        }
+        let _ = write!(header, ", response=\"{}\"", response);
+        if let Some(ref opaque) = challenge.opaque {
+            let _ = write!(header, ", opaque={}", quote(opaque));
+        }
+        if challenge.userhash {
+            header.push_str(", userhash=true");
+        }
+
+        let mut header = HeaderValue::from_str(&header).ok()?;
+        header.set_sensitive(true);
+        Some(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RFC7616_SHA256: &str = "Digest realm=\"http-auth@example.org\", \
+        qop=\"auth, auth-int\", algorithm=SHA-256, \
+        nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+        opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"";
+    const RFC7616_MD5: &str = "Digest realm=\"http-auth@example.org\", \
+        qop=\"auth, auth-int\", algorithm=MD5, \
+        nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+        opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"";
+
+    fn get<'a>(uri: &'a str) -> Request<'a> {
+        Request {
+            method: "GET",
+            uri,
+            body: Some(b""),
+        }
+    }
+
+    fn respond(value: &str, cnonce: &str, credentials: &Credentials) -> String {
+        let challenge =
+            Challenge::pick(&[HeaderValue::from_str(value).unwrap()], &get("")).expect("challenge");
+        let mut session = Session::new(challenge);
+        session.cnonce = cnonce.to_owned();
+        let header = session
+            .respond(credentials, &get("/dir/index.html"))
+            .unwrap();
+        header.to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn rfc7616_responses() {
+        let credentials = Credentials::new("Mufasa", "Circle of Life");
+        let cnonce = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+
+        assert_eq!(
+            respond(RFC7616_SHA256, cnonce, &credentials),
+            "Digest username=\"Mufasa\", realm=\"http-auth@example.org\", \
+             uri=\"/dir/index.html\", algorithm=SHA-256, \
+             nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", nc=00000001, \
+             cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", qop=auth, \
+             response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\", \
+             opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\""
+        );
+        assert!(respond(RFC7616_MD5, cnonce, &credentials)
+            .contains("response=\"8ca523f5e9506fed4657c9700eebdbec\""));
+    }
+
+    #[test]
+    fn rfc2617_response() {
+        let challenge = "Digest realm=\"testrealm@host.com\", qop=\"auth,auth-int\", \
+            nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", \
+            opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"";
+        let header = respond(
+            challenge,
+            "0a4f113b",
+            &Credentials::new("Mufasa", "Circle Of Life"),
+        );
+        assert!(!header.contains("algorithm="));
+        assert!(header.contains("response=\"6629fae49393a05397450978507c4ef1\""));
+    }
+
+    #[test]
+    fn picks_the_strongest_challenge() {
+        let values = [
+            HeaderValue::from_static("Negotiate abc==, Basic realm=\"x\""),
+            HeaderValue::from_str(RFC7616_MD5).unwrap(),
+            HeaderValue::from_str(RFC7616_SHA256).unwrap(),
+        ];
+        let challenge = Challenge::pick(&values, &get("/")).unwrap();
+        assert_eq!(challenge.algorithm, Algorithm::Sha256);
+        assert_eq!(challenge.qop, Some(Qop::Auth));
+
+        let value = HeaderValue::from_static(
+            "Basic realm=\"a, b\", Digest realm=\"r\", nonce=n, algorithm=SHA-512-256, \
+             Digest realm=\"r\\\"s\", nonce=\"m\", algorithm=md5-sess, qop=auth-int",
+        );
+        let challenge = Challenge::pick(&[value.clone()], &get("/")).unwrap();
+        assert_eq!(challenge.realm, "r\"s");
+        assert_eq!(challenge.nonce, "m");
+        assert!(challenge.sess);
+        assert_eq!(challenge.qop, Some(Qop::AuthInt));
+
+        // A streaming body can't be protected.
+        let mut request = get("/");
+        request.body = None;
+        assert_eq!(Challenge::pick(&[value], &request), None);
+        let value = HeaderValue::from_static("Digest realm=\"r\", qop=\"auth-conf\", nonce=\"n\"");
+        assert_eq!(Challenge::pick(&[value], &get("/")), None);
+    }
+
+    #[test]
+    fn counts_nonce_uses() {
+        let sessions = Sessions::default();
+        let credentials = Credentials::new("Mufasa", "Circle of Life");
+        assert!(sessions.authorize("a", &credentials, &get("/")).is_none());
+
+        let challenge = HeaderValue::from_str(RFC7616_SHA256).unwrap();
+        let first = sessions
+            .answer("a", &[challenge], &credentials, &get("/"))
+            .unwrap();
+        assert!(first.is_sensitive());
+        assert!(first.to_str().unwrap().contains("nc=00000001"));
+        let second = sessions.authorize("a", &credentials, &get("/")).unwrap();
+        assert!(second.to_str().unwrap().contains("nc=00000002"));
+        assert!(sessions.authorize("b", &credentials, &get("/")).is_none());
+    }
+}
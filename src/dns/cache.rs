@@ -0,0 +1,710 @@
+//! Caching of DNS answers, shared across connections and clients
+
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::future::{BoxFuture, FutureExt, Shared};
+use tokio::time::Instant;
+
+use super::{Addrs, HttpsRecord, Name, Resolve, Resolving, ResolvingHttps, ResolvingWithTtl};
+use crate::error::BoxError;
+
+/// A [`Resolve`] caching the answers of another one.
+///
+/// Addresses are kept for as long as their records' TTL, when the resolver
+/// knows it (hickory-dns and [`DohResolver`] do), or for a configured TTL
+/// otherwise (getaddrinfo). HTTPS records are always kept for the configured
+/// TTL. Either way, it's clamped between a minimum and a maximum.
+///
+/// Concurrent lookups of a name that isn't cached are made once. Failures
+/// can be cached too, and expired answers can keep being served while they
+/// are refreshed in the background.
+///
+/// Every `Client` given the same `Arc` shares the cache, and the handle
+/// kept by the caller can inspect and invalidate it.
+///
+/// ```
+/// # use chromimic as reqwest;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use reqwest::dns::CachingResolver;
+///
+/// # fn run() -> Result<(), reqwest::Error> {
+/// let cache = Arc::new(
+///     CachingResolver::builder()
+///         .min_ttl(Duration::from_secs(30))
+///         .negative_ttl(Duration::from_secs(5))
+///         .stale_while_revalidate(Duration::from_secs(60))
+///         .build(),
+/// );
+///
+/// let a = reqwest::Client::builder().dns_resolver(cache.clone()).build()?;
+/// let b = reqwest::Client::builder().dns_resolver(cache.clone()).build()?;
+/// # drop((a, b));
+///
+/// println!("{:?}", cache.stats());
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`DohResolver`]: super::DohResolver
+#[derive(Clone)]
+pub struct CachingResolver {
+    inner: Arc<Inner>,
+}
+
+/// A builder for a [`CachingResolver`].
+#[must_use]
+pub struct CachingResolverBuilder {
+    resolver: Option<Arc<dyn Resolve>>,
+    config: Config,
+}
+
+/// Counters of a [`CachingResolver`], since it was built.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    hits: u64,
+    stale_hits: u64,
+    negative_hits: u64,
+    misses: u64,
+    evictions: u64,
+    entries: usize,
+}
+
+struct Inner {
+    resolver: Arc<dyn Resolve>,
+    config: Config,
+    state: Mutex<State>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Config {
+    ttl: Duration,
+    min_ttl: Duration,
+    max_ttl: Duration,
+    negative_ttl: Duration,
+    stale: Duration,
+    max_entries: usize,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<Key, Entry>,
+    stats: CacheStats,
+    next_lookup: u64,
+}
+
+type Key = (String, Kind);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Kind {
+    Addrs,
+    Https,
+}
+
+#[derive(Clone)]
+enum Answer {
+    Addrs(Vec<SocketAddr>),
+    Https(Arc<[HttpsRecord]>),
+    Error(Arc<dyn StdError + Send + Sync>),
+}
+
+/// A cached failure, handed to every lookup it answers.
+struct CachedError(Arc<dyn StdError + Send + Sync>);
+
+// The answer and how long it's still valid.
+type Lookup = Shared<BoxFuture<'static, (Answer, Duration)>>;
+
+#[derive(Default)]
+struct Entry {
+    answer: Option<(Answer, Instant)>,
+    // The lookup in flight, by the number it was started with.
+    lookup: Option<(u64, Lookup)>,
+}
+
+impl CachingResolver {
+    /// Creates a builder for a `CachingResolver`.
+    ///
+    /// The answers cached are those of the resolver a `Client` uses by
+    /// default, unless another one is set with
+    /// [`CachingResolverBuilder::resolver`].
+    pub fn builder() -> CachingResolverBuilder {
+        CachingResolverBuilder {
+            resolver: None,
+            config: Config {
+                ttl: Duration::from_secs(60),
+                min_ttl: Duration::ZERO,
+                max_ttl: Duration::from_secs(24 * 60 * 60),
+                negative_ttl: Duration::ZERO,
+                stale: Duration::ZERO,
+                max_entries: 10_000,
+            },
+        }
+    }
+
+    /// Returns the counters of the cache.
+    pub fn stats(&self) -> CacheStats {
+        let state = self.inner.state.lock().unwrap();
+        CacheStats {
+            entries: state.entries.len(),
+            ..state.stats.clone()
+        }
+    }
+
+    /// Forgets the answers for `name`, so it's looked up again the next
+    /// time. Lookups in flight for it aren't cached.
+    pub fn invalidate(&self, name: &str) {
+        let name = normalize(name);
+        let mut state = self.inner.state.lock().unwrap();
+        for kind in [Kind::Addrs, Kind::Https] {
+            state.entries.remove(&(name.clone(), kind));
+        }
+    }
+
+    /// Forgets every answer.
+    pub fn clear(&self) {
+        self.inner.state.lock().unwrap().entries.clear();
+    }
+}
+
+impl Resolve for CachingResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolving = self.resolve_with_ttl(name);
+        Box::pin(async move { Ok(resolving.await?.0) })
+    }
+
+    fn resolve_with_ttl(&self, name: Name) -> ResolvingWithTtl {
+        let answer = Inner::get(&self.inner, name, Kind::Addrs);
+        Box::pin(async move {
+            match answer.await {
+                (Answer::Addrs(addrs), ttl) => {
+                    let addrs: Addrs = Box::new(addrs.into_iter());
+                    Ok((addrs, Some(ttl)))
+                }
+                (answer, _) => Err(answer.into_error()),
+            }
+        })
+    }
+
+    fn resolve_https(&self, name: Name) -> ResolvingHttps {
+        let answer = Inner::get(&self.inner, name, Kind::Https);
+        Box::pin(async move {
+            match answer.await {
+                (Answer::Https(records), _) => Ok(records.to_vec()),
+                (answer, _) => Err(answer.into_error()),
+            }
+        })
+    }
+}
+
+impl Inner {
+    fn get(this: &Arc<Inner>, name: Name, kind: Kind) -> BoxFuture<'static, (Answer, Duration)> {
+        let key = (normalize(name.as_str()), kind);
+        let now = Instant::now();
+
+        let mut guard = this.state.lock().unwrap();
+        let state = &mut *guard;
+        if let Some(entry) = state.entries.get_mut(&key) {
+            if let Some((answer, expires)) = &entry.answer {
+                if now < *expires {
+                    match answer {
+                        Answer::Error(_) => state.stats.negative_hits += 1,
+                        _ => state.stats.hits += 1,
+                    }
+                    let answer = (answer.clone(), *expires - now);
+                    return futures_util::future::ready(answer).boxed();
+                }
+
+                // Failures aren't served stale, they'd rather be retried.
+                let stale =
+                    !matches!(answer, Answer::Error(_)) && now < *expires + this.config.stale;
+                if stale {
+                    state.stats.stale_hits += 1;
+                    let answer = (answer.clone(), Duration::ZERO);
+                    if entry.lookup.is_none() {
+                        let id = state.next_lookup;
+                        state.next_lookup += 1;
+                        let lookup = Inner::lookup(this, name, key, id);
+                        entry.lookup = Some((id, lookup.clone()));
+                        tokio::spawn(lookup);
+                    }
+                    return futures_util::future::ready(answer).boxed();
+                }
+            }
+
+            state.stats.misses += 1;
+            if let Some((_, lookup)) = &entry.lookup {
+                return lookup.clone().boxed();
+            }
+        } else {
+            state.stats.misses += 1;
+            if state.entries.len() >= this.config.max_entries {
+                this.evict(state, now);
+            }
+            // Lookups in flight that are waited for aren't evicted, so when
+            // they fill the cache, this one is made without it.
+            if state.entries.len() >= this.config.max_entries {
+                let lookup = Inner::lookup(this, name, key, state.next_lookup);
+                state.next_lookup += 1;
+                return lookup.boxed();
+            }
+        }
+
+        let id = state.next_lookup;
+        state.next_lookup += 1;
+        let lookup = Inner::lookup(this, name, key.clone(), id);
+        state.entries.entry(key).or_default().lookup = Some((id, lookup.clone()));
+        lookup.boxed()
+    }
+
+    fn lookup(this: &Arc<Inner>, name: Name, key: Key, id: u64) -> Lookup {
+        let this = this.clone();
+        async move {
+            let (answer, ttl) = match key.1 {
+                Kind::Addrs => match this.resolver.resolve_with_ttl(name).await {
+                    Ok((addrs, ttl)) => (Answer::Addrs(addrs.collect()), ttl),
+                    Err(e) => (Answer::Error(e.into()), None),
+                },
+                Kind::Https => match this.resolver.resolve_https(name).await {
+                    Ok(records) => (Answer::Https(records.into()), None),
+                    Err(e) => (Answer::Error(e.into()), None),
+                },
+            };
+
+            let ttl = match answer {
+                Answer::Error(_) => this.config.negative_ttl,
+                _ => ttl
+                    .unwrap_or(this.config.ttl)
+                    .max(this.config.min_ttl)
+                    .min(this.config.max_ttl),
+            };
+            this.store(&key, id, answer, ttl)
+        }
+        .boxed()
+        .shared()
+    }
+
+    fn store(&self, key: &Key, id: u64, answer: Answer, ttl: Duration) -> (Answer, Duration) {
+        let mut state = self.state.lock().unwrap();
+        let entry = match state.entries.get_mut(key) {
+            // Unless it was invalidated meanwhile.
+            Some(entry) if matches!(entry.lookup, Some((lookup, _)) if lookup == id) => entry,
+            _ => return (answer, ttl),
+        };
+        entry.lookup = None;
+
+        let now = Instant::now();
+        let failed = matches!(answer, Answer::Error(_));
+        match &entry.answer {
+            // A stale answer beats a failure to refresh it.
+            Some((Answer::Addrs(_), expires)) | Some((Answer::Https(_), expires))
+                if failed && now < *expires + self.config.stale => {}
+            _ if ttl.is_zero() && (failed || self.config.stale.is_zero()) => {
+                state.entries.remove(key);
+            }
+            _ => entry.answer = Some((answer.clone(), now + ttl)),
+        }
+        (answer, ttl)
+    }
+
+    /// Makes room for an entry: drops the ones past serving, and if that's not
+    /// enough, the one expiring first.
+    ///
+    /// Lookups in flight are kept while something waits for them. The others
+    /// were given up, by timeouts or cancelled requests, and nothing drives
+    /// them anymore.
+    fn evict(&self, state: &mut State, now: Instant) {
+        let stale = self.config.stale;
+        let before = state.entries.len();
+        state.entries.retain(|_, entry| {
+            if matches!(entry.lookup, Some((_, ref lookup)) if lookup.strong_count() == Some(1)) {
+                entry.lookup = None;
+            }
+            match &entry.answer {
+                _ if entry.lookup.is_some() => true,
+                Some((_, expires)) => now < *expires + stale,
+                None => false,
+            }
+        });
+
+        if state.entries.len() >= self.config.max_entries {
+            let first = state
+                .entries
+                .iter()
+                .filter(|(_, entry)| entry.lookup.is_none())
+                .filter_map(|(key, entry)| Some((key, entry.answer.as_ref()?.1)))
+                .min_by_key(|&(_, expires)| expires)
+                .map(|(key, _)| key.clone());
+            if let Some(key) = first {
+                state.entries.remove(&key);
+            }
+        }
+        state.stats.evictions += (before - state.entries.len()) as u64;
+    }
+}
+
+impl Answer {
+    fn into_error(self) -> BoxError {
+        match self {
+            Answer::Error(e) => match e.downcast_ref::<io::Error>() {
+                Some(io) => Box::new(io::Error::new(io.kind(), CachedError(e.clone()))),
+                None => Box::new(CachedError(e)),
+            },
+            _ => "unexpected answer in DNS cache".into(),
+        }
+    }
+}
+
+impl fmt::Debug for CachedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for CachedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl StdError for CachedError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.0.source()
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+impl fmt::Debug for CachingResolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CachingResolver")
+            .field("config", &self.inner.config)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl CachingResolverBuilder {
+    /// Caches the answers of `resolver`.
+    pub fn resolver<R: Resolve + 'static>(mut self, resolver: Arc<R>) -> CachingResolverBuilder {
+        self.resolver = Some(resolver as _);
+        self
+    }
+
+    /// Sets how long answers are cached when the resolver doesn't know their
+    /// TTL.
+    ///
+    /// Default is 60 seconds.
+    pub fn ttl(mut self, ttl: Duration) -> CachingResolverBuilder {
+        self.config.ttl = ttl;
+        self
+    }
+
+    /// Sets the shortest time answers are cached, whatever their TTL.
+    ///
+    /// Default is zero.
+    pub fn min_ttl(mut self, ttl: Duration) -> CachingResolverBuilder {
+        self.config.min_ttl = ttl;
+        self
+    }
+
+    /// Sets the longest time answers are cached, whatever their TTL. It wins
+    /// over `min_ttl`.
+    ///
+    /// Default is a day.
+    pub fn max_ttl(mut self, ttl: Duration) -> CachingResolverBuilder {
+        self.config.max_ttl = ttl;
+        self
+    }
+
+    /// Sets how long a failed lookup is cached, including names that don't
+    /// exist.
+    ///
+    /// Default is zero: failures aren't cached.
+    pub fn negative_ttl(mut self, ttl: Duration) -> CachingResolverBuilder {
+        self.config.negative_ttl = ttl;
+        self
+    }
+
+    /// Sets how long past its expiry an answer keeps being served while it's
+    /// looked up again in the background.
+    ///
+    /// If the lookup fails, the expired answer keeps being served until
+    /// that time is over.
+    ///
+    /// Default is zero: expired answers are looked up before being used.
+    pub fn stale_while_revalidate(mut self, duration: Duration) -> CachingResolverBuilder {
+        self.config.stale = duration;
+        self
+    }
+
+    /// Sets the number of answers kept, addresses and HTTPS records of a
+    /// name counting as two.
+    ///
+    /// Lookups in flight take an entry too, and aren't evicted while they're
+    /// waited for: while they fill the cache, other names are looked up
+    /// without it.
+    ///
+    /// Default is 10,000.
+    pub fn max_entries(mut self, max: usize) -> CachingResolverBuilder {
+        self.config.max_entries = max;
+        self
+    }
+
+    /// Returns a `CachingResolver` using this builder's configuration.
+    pub fn build(self) -> CachingResolver {
+        let resolver = self.resolver.unwrap_or_else(default_resolver);
+        CachingResolver {
+            inner: Arc::new(Inner {
+                resolver,
+                config: self.config,
+                state: Mutex::default(),
+            }),
+        }
+    }
+}
+
+impl fmt::Debug for CachingResolverBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CachingResolverBuilder")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+fn default_resolver() -> Arc<dyn Resolve> {
+    #[cfg(feature = "hickory-dns")]
+    let resolver = Arc::new(super::hickory::HickoryDnsResolver::default());
+    #[cfg(not(feature = "hickory-dns"))]
+    let resolver = Arc::new(super::gai::GaiResolver::new());
+    resolver
+}
+
+impl CacheStats {
+    /// Returns how many lookups were answered from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Returns how many lookups were answered with an expired answer, while
+    /// it was refreshed.
+    pub fn stale_hits(&self) -> u64 {
+        self.stale_hits
+    }
+
+    /// Returns how many lookups were answered with a cached failure.
+    pub fn negative_hits(&self) -> u64 {
+        self.negative_hits
+    }
+
+    /// Returns how many lookups weren't answered from the cache, including
+    /// those that waited for another one of the same name.
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Returns how many answers were dropped to make room for others.
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    /// Returns how many answers, or lookups in flight, are in the cache.
+    pub fn entries(&self) -> usize {
+        self.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// Resolves every name to 127.0.0.1, after some time, with a 1 second
+    /// TTL. Names starting with "fail" aren't found.
+    #[derive(Default)]
+    struct Counting {
+        lookups: AtomicUsize,
+    }
+
+    impl Resolve for Counting {
+        fn resolve(&self, name: Name) -> Resolving {
+            let resolving = self.resolve_with_ttl(name);
+            Box::pin(async move { Ok(resolving.await?.0) })
+        }
+
+        fn resolve_with_ttl(&self, name: Name) -> ResolvingWithTtl {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                if name.as_str().starts_with("fail") {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, "lookup failed").into());
+                }
+                let addrs: Addrs = Box::new(std::iter::once(([127, 0, 0, 1], 0).into()));
+                Ok((addrs, Some(Duration::from_secs(1))))
+            })
+        }
+    }
+
+    fn counting_cache(
+        configure: impl FnOnce(CachingResolverBuilder) -> CachingResolverBuilder,
+    ) -> (Arc<Counting>, CachingResolver) {
+        let counting = Arc::new(Counting::default());
+        let cache = configure(CachingResolver::builder().resolver(counting.clone())).build();
+        (counting, cache)
+    }
+
+    async fn resolve(cache: &CachingResolver, name: &str) -> Result<Option<Duration>, BoxError> {
+        Ok(cache
+            .resolve_with_ttl(Name::from_str(name).unwrap())
+            .await?
+            .1)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn caches_for_ttl() {
+        let (counting, cache) = counting_cache(|b| b);
+
+        let (a, b) = futures_util::future::join(
+            resolve(&cache, "example.com"),
+            resolve(&cache, "EXAMPLE.com."),
+        )
+        .await;
+        assert_eq!(a.unwrap(), Some(Duration::from_secs(1)));
+        assert_eq!(b.unwrap(), Some(Duration::from_secs(1)));
+        assert_eq!(counting.lookups.load(Ordering::SeqCst), 1);
+
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert!(
+            resolve(&cache, "example.com").await.unwrap().unwrap() <= Duration::from_millis(500)
+        );
+        assert_eq!(counting.lookups.load(Ordering::SeqCst), 1);
+
+        tokio::time::advance(Duration::from_millis(600)).await;
+        resolve(&cache, "example.com").await.unwrap();
+        assert_eq!(counting.lookups.load(Ordering::SeqCst), 2);
+
+        cache.invalidate("example.com");
+        resolve(&cache, "example.com").await.unwrap();
+        assert_eq!(counting.lookups.load(Ordering::SeqCst), 3);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits(), stats.misses(), stats.entries()), (1, 4, 1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn clamps_ttl() {
+        let (_, cache) = counting_cache(|b| b.min_ttl(Duration::from_secs(30)));
+        assert_eq!(
+            resolve(&cache, "example.com").await.unwrap(),
+            Some(Duration::from_secs(30))
+        );
+
+        let (_, cache) = counting_cache(|b| {
+            b.min_ttl(Duration::from_secs(30))
+                .max_ttl(Duration::from_millis(100))
+        });
+        assert_eq!(
+            resolve(&cache, "example.com").await.unwrap(),
+            Some(Duration::from_millis(100))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn caches_failures() {
+        let (counting, cache) = counting_cache(|b| b);
+        resolve(&cache, "fail.example").await.unwrap_err();
+        resolve(&cache, "fail.example").await.unwrap_err();
+        assert_eq!(counting.lookups.load(Ordering::SeqCst), 2);
+        assert_eq!(cache.stats().entries(), 0);
+
+        let (counting, cache) = counting_cache(|b| b.negative_ttl(Duration::from_secs(5)));
+        resolve(&cache, "fail.example").await.unwrap_err();
+        let err = resolve(&cache, "fail.example").await.unwrap_err();
+        assert_eq!(err.to_string(), "lookup failed");
+        let io = err.downcast_ref::<io::Error>().expect("io error");
+        assert_eq!(io.kind(), io::ErrorKind::NotFound);
+        assert_eq!(counting.lookups.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.stats().negative_hits(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn serves_stale_while_revalidating() {
+        let (counting, cache) =
+            counting_cache(|b| b.stale_while_revalidate(Duration::from_secs(10)));
+        resolve(&cache, "example.com").await.unwrap();
+
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert_eq!(
+            resolve(&cache, "example.com").await.unwrap(),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(
+            resolve(&cache, "example.com").await.unwrap(),
+            Some(Duration::ZERO)
+        );
+
+        // The refresh landed, it was made once.
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(counting.lookups.load(Ordering::SeqCst), 2);
+        let ttl = resolve(&cache, "example.com").await.unwrap().unwrap();
+        assert!(ttl > Duration::from_millis(900), "{:?}", ttl);
+        assert_eq!(cache.stats().stale_hits(), 2);
+        assert_eq!(cache.stats().hits(), 1);
+
+        tokio::time::advance(Duration::from_secs(20)).await;
+        resolve(&cache, "example.com").await.unwrap();
+        assert_eq!(counting.lookups.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn evicts_first_expiring() {
+        let (_, cache) = counting_cache(|b| b.max_entries(2));
+        resolve(&cache, "a.example").await.unwrap();
+        tokio::time::advance(Duration::from_millis(100)).await;
+        resolve(&cache, "b.example").await.unwrap();
+        resolve(&cache, "c.example").await.unwrap();
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries(), stats.evictions()), (2, 1));
+        resolve(&cache, "b.example").await.unwrap();
+        assert_eq!(cache.stats().hits(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bounds_lookups_in_flight() {
+        let (counting, cache) = counting_cache(|b| b.max_entries(2));
+        let lookups = (0..5)
+            .map(|i| cache.resolve_with_ttl(Name::from_str(&format!("{}.example", i)).unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(cache.stats().entries(), 2);
+
+        for result in futures_util::future::join_all(lookups).await {
+            assert!(result.is_ok());
+        }
+        assert_eq!(counting.lookups.load(Ordering::SeqCst), 5);
+        assert_eq!(cache.stats().entries(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn evicts_abandoned_lookups() {
+        let (_, cache) = counting_cache(|b| b.max_entries(1));
+        // The request waiting for it timed out.
+        drop(cache.resolve_with_ttl(Name::from_str("a.example").unwrap()));
+        assert_eq!(cache.stats().entries(), 1);
+
+        resolve(&cache, "b.example").await.unwrap();
+        let stats = cache.stats();
+        assert_eq!((stats.entries(), stats.evictions()), (1, 1));
+        resolve(&cache, "b.example").await.unwrap();
+        assert_eq!(cache.stats().hits(), 1);
+    }
+}
@@ -0,0 +1,408 @@
+//! DNS-over-HTTPS resolution through a `Client`
+//!
+//! https://www.rfc-editor.org/rfc/rfc8484
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use http::header::{ACCEPT, CONTENT_TYPE};
+use url::Url;
+
+use super::message::{self, Answer, Message};
+use super::{Addrs, HttpsRecord, Name, Resolve, Resolving, ResolvingHttps, ResolvingWithTtl};
+use crate::error::BoxError;
+use crate::{Client, IntoUrl};
+
+const DNS_MESSAGE: &str = "application/dns-message";
+#[cfg(feature = "json")]
+const DNS_JSON: &str = "application/dns-json";
+
+/// A [`Resolve`] sending its queries to DNS-over-HTTPS servers.
+///
+/// Queries are made with a [`Client`] of your choosing, so they go out with
+/// its impersonation profile, proxy and timeouts rather than as plain UDP
+/// packets to the system resolver.
+///
+/// Upstreams are tried in turn: the one that last answered first, then the
+/// others in the order they were added. An upstream that can't be reached,
+/// responds with an HTTP error or a malformed message, or whose server
+/// fails, is skipped. A name that doesn't exist is an answer, not a failure.
+///
+/// The client sending the queries resolves the upstream hosts on its own.
+/// Don't make it use this resolver, or it never will; use an IP address in
+/// the upstream URL or [`ClientBuilder::resolve`] instead.
+///
+/// ```no_run
+/// # use chromimic as reqwest;
+/// use std::sync::Arc;
+/// use reqwest::dns::{DohFormat, DohResolver};
+///
+/// # fn run() -> Result<(), reqwest::Error> {
+/// let resolver = DohResolver::builder()
+///     .upstream("https://1.1.1.1/dns-query", DohFormat::Post)
+///     .upstream("https://8.8.8.8/dns-query", DohFormat::Get)
+///     .build()?;
+///
+/// let client = reqwest::Client::builder()
+///     .dns_resolver(Arc::new(resolver))
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`ClientBuilder::resolve`]: crate::ClientBuilder::resolve
+#[derive(Clone)]
+pub struct DohResolver {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    client: Client,
+    upstreams: Vec<Upstream>,
+    // Index of the upstream that answered last.
+    preferred: AtomicUsize,
+}
+
+struct Upstream {
+    url: Url,
+    format: DohFormat,
+}
+
+/// How queries are sent to a DNS-over-HTTPS upstream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DohFormat {
+    /// A DNS message, base64url encoded in the `dns` query parameter.
+    Get,
+    /// A DNS message as the body of a `POST` request.
+    Post,
+    /// The JSON API of Google and Cloudflare, with the `name` and `type`
+    /// query parameters.
+    #[cfg(feature = "json")]
+    Json,
+}
+
+/// A builder for a [`DohResolver`].
+#[must_use]
+pub struct DohResolverBuilder {
+    client: Option<Client>,
+    upstreams: Vec<Upstream>,
+    error: Option<crate::Error>,
+}
+
+impl DohResolver {
+    /// Creates a builder for a `DohResolver`.
+    pub fn builder() -> DohResolverBuilder {
+        DohResolverBuilder {
+            client: None,
+            upstreams: Vec::new(),
+            error: None,
+        }
+    }
+
+    async fn query(&self, name: &str, rtype: u16) -> Result<Message, BoxError> {
+        let upstreams = &self.inner.upstreams;
+        let preferred = self.inner.preferred.load(Ordering::Relaxed);
+
+        let mut last_error = None;
+        for i in 0..upstreams.len() {
+            let index = (preferred + i) % upstreams.len();
+            let upstream = &upstreams[index];
+            match self.exchange(upstream, name, rtype).await {
+                Ok(message) => {
+                    self.inner.preferred.store(index, Ordering::Relaxed);
+                    return Ok(message);
+                }
+                Err(e) => {
+                    log::debug!("DoH query to {} failed: {}", upstream.url, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| "no DoH upstream".into()))
+    }
+
+    async fn exchange(
+        &self,
+        upstream: &Upstream,
+        name: &str,
+        rtype: u16,
+    ) -> Result<Message, BoxError> {
+        let client = &self.inner.client;
+        let request = match upstream.format {
+            DohFormat::Get => {
+                let query = URL_SAFE_NO_PAD.encode(message::query(name, rtype)?);
+                let mut url = upstream.url.clone();
+                url.query_pairs_mut().append_pair("dns", &query);
+                client.get(url).header(ACCEPT, DNS_MESSAGE)
+            }
+            DohFormat::Post => client
+                .post(upstream.url.clone())
+                .header(ACCEPT, DNS_MESSAGE)
+                .header(CONTENT_TYPE, DNS_MESSAGE)
+                .body(message::query(name, rtype)?),
+            #[cfg(feature = "json")]
+            DohFormat::Json => {
+                let mut url = upstream.url.clone();
+                url.query_pairs_mut()
+                    .append_pair("name", name)
+                    .append_pair("type", &rtype.to_string());
+                client.get(url).header(ACCEPT, DNS_JSON)
+            }
+        };
+
+        let body = request.send().await?.error_for_status()?.bytes().await?;
+        let message = match upstream.format {
+            #[cfg(feature = "json")]
+            DohFormat::Json => json::parse(&body)?,
+            _ => message::parse(&body)?,
+        };
+        match message.rcode {
+            message::NOERROR | message::NXDOMAIN => Ok(message),
+            rcode => Err(format!("DNS server failed with RCODE {}", rcode).into()),
+        }
+    }
+}
+
+impl Resolve for DohResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolving = self.resolve_with_ttl(name);
+        Box::pin(async move { Ok(resolving.await?.0) })
+    }
+
+    fn resolve_with_ttl(&self, name: Name) -> ResolvingWithTtl {
+        let resolver = self.clone();
+        Box::pin(async move {
+            let (v6, v4) = futures_util::future::join(
+                resolver.query(name.as_str(), message::AAAA),
+                resolver.query(name.as_str(), message::A),
+            )
+            .await;
+
+            let mut error = None;
+            let mut addrs = Vec::new();
+            let mut ttl = None;
+            for result in [v6, v4] {
+                match result {
+                    Ok(message) if message.rcode == message::NXDOMAIN => {
+                        error = Some(format!("{} not found", name.as_str()).into())
+                    }
+                    Ok(message) => {
+                        ttl = match (ttl, message.ttl) {
+                            (Some(a), Some(b)) => Some(std::cmp::min(a, b)),
+                            (a, b) => a.or(b),
+                        };
+                        addrs.extend(message.answers.into_iter().filter_map(
+                            |answer| match answer {
+                                Answer::Ip(ip) => Some(SocketAddr::new(ip, 0)),
+                                _ => None,
+                            },
+                        ))
+                    }
+                    Err(e) => error = Some(e),
+                }
+            }
+
+            if addrs.is_empty() {
+                return Err(
+                    error.unwrap_or_else(|| format!("no addresses for {}", name.as_str()).into())
+                );
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok((addrs, ttl.map(|ttl| Duration::from_secs(ttl.into()))))
+        })
+    }
+
+    fn resolve_https(&self, name: Name) -> ResolvingHttps {
+        let resolver = self.clone();
+        Box::pin(async move {
+            let message = resolver.query(name.as_str(), message::HTTPS).await?;
+            Ok(message
+                .answers
+                .into_iter()
+                .filter_map(|answer| match answer {
+                    Answer::Https(record) => Some(record),
+                    _ => None,
+                })
+                .collect::<Vec<HttpsRecord>>())
+        })
+    }
+}
+
+impl fmt::Debug for DohResolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DohResolver")
+            .field("upstreams", &self.inner.upstreams)
+            .finish()
+    }
+}
+
+impl fmt::Debug for Upstream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({:?})", self.url, self.format)
+    }
+}
+
+impl DohResolverBuilder {
+    /// Sends the queries with `client`.
+    ///
+    /// By default, a `Client` with the default settings is used.
+    pub fn client(mut self, client: Client) -> DohResolverBuilder {
+        self.client = Some(client);
+        self
+    }
+
+    /// Adds an upstream server, queried at `url` in `format`.
+    pub fn upstream<U: IntoUrl>(mut self, url: U, format: DohFormat) -> DohResolverBuilder {
+        match url.into_url() {
+            Ok(url) => self.upstreams.push(Upstream { url, format }),
+            Err(e) => {
+                self.error.get_or_insert(e);
+            }
+        }
+        self
+    }
+
+    /// Returns a `DohResolver` using this builder's configuration.
+    ///
+    /// # Errors
+    ///
+    /// This method fails if an upstream URL was invalid, if no upstream was
+    /// added, or if the default client can't be built.
+    pub fn build(self) -> crate::Result<DohResolver> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        if self.upstreams.is_empty() {
+            return Err(crate::error::builder("a DohResolver needs an upstream"));
+        }
+        let client = match self.client {
+            Some(client) => client,
+            None => Client::builder().build()?,
+        };
+
+        Ok(DohResolver {
+            inner: Arc::new(Inner {
+                client,
+                upstreams: self.upstreams,
+                preferred: AtomicUsize::new(0),
+            }),
+        })
+    }
+}
+
+impl fmt::Debug for DohResolverBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DohResolverBuilder")
+            .field("upstreams", &self.upstreams)
+            .finish()
+    }
+}
+
+/// The JSON API, as served by Google and Cloudflare.
+///
+/// https://developers.google.com/speed/public-dns/docs/doh/json
+#[cfg(feature = "json")]
+mod json {
+    use serde_json::Value;
+    use std::convert::TryFrom;
+
+    use super::message::{self, Answer, Message};
+    use crate::error::BoxError;
+
+    pub(super) fn parse(body: &[u8]) -> Result<Message, BoxError> {
+        let response: Value = serde_json::from_slice(body)?;
+        let invalid = || BoxError::from("invalid DNS JSON response");
+
+        let rcode = response["Status"].as_u64().ok_or_else(invalid)?;
+        let records = match &response["Answer"] {
+            Value::Null => &[][..],
+            records => records.as_array().ok_or_else(invalid)?,
+        };
+
+        let mut answers = Vec::new();
+        let mut min_ttl = None;
+        for record in records {
+            let rtype = record["type"].as_u64().ok_or_else(invalid)?;
+            let data = record["data"].as_str().ok_or_else(invalid)?;
+            if let Some(ttl) = record["TTL"].as_u64() {
+                let ttl = u32::try_from(ttl).unwrap_or(u32::MAX);
+                min_ttl = Some(min_ttl.map_or(ttl, |min: u32| min.min(ttl)));
+            }
+            let answer = match rtype as u16 {
+                message::A | message::AAAA => Answer::Ip(data.parse()?),
+                // Only records in the generic `\# <length> <hex>` form are
+                // understood, as returned by Cloudflare.
+                message::HTTPS => {
+                    match generic_rdata(data).and_then(|d| message::https_rdata(&d)) {
+                        Some(answer) => answer,
+                        None => continue,
+                    }
+                }
+                _ => continue,
+            };
+            answers.push(answer);
+        }
+
+        Ok(Message {
+            rcode: rcode as u8,
+            answers,
+            ttl: min_ttl,
+        })
+    }
+
+    /// Decodes RDATA in the unknown record type format.
+    ///
+    /// https://www.rfc-editor.org/rfc/rfc3597#section-5
+    fn generic_rdata(data: &str) -> Option<Vec<u8>> {
+        let mut parts = data.split_whitespace();
+        if parts.next()? != "\\#" {
+            return None;
+        }
+        let len = parts.next()?.parse::<usize>().ok()?;
+        let hex = parts.collect::<String>();
+        if hex.len() != len * 2 {
+            return None;
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn parses_json() {
+            let body = br#"{
+                "Status": 0,
+                "Answer": [
+                    {"name": "example.com.", "type": 5, "TTL": 60, "data": "cdn.example."},
+                    {"name": "cdn.example.", "type": 1, "TTL": 60, "data": "192.0.2.1"},
+                    {"name": "cdn.example.", "type": 65, "TTL": 60, "data": "\\# 9 00 01 00 00 03 00 02 01 bb"},
+                    {"name": "cdn.example.", "type": 65, "TTL": 60, "data": "1 . alpn=h2"}
+                ]
+            }"#;
+            let message = parse(body).unwrap();
+            assert_eq!(message.rcode, message::NOERROR);
+            assert_eq!(message.answers.len(), 2);
+            assert_eq!(message.ttl, Some(60));
+            assert_eq!(message.answers[0], Answer::Ip([192, 0, 2, 1].into()));
+            match &message.answers[1] {
+                Answer::Https(record) => assert_eq!(record.port(), Some(443)),
+                answer => panic!("unexpected answer: {:?}", answer),
+            }
+
+            let message = parse(br#"{"Status": 3}"#).unwrap();
+            assert_eq!(message.rcode, message::NXDOMAIN);
+            assert!(message.answers.is_empty());
+        }
+    }
+}
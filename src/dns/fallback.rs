@@ -0,0 +1,189 @@
+//! Resolving with other resolvers when one fails
+
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{Name, Resolve, Resolving, ResolvingHttps, ResolvingWithTtl};
+use crate::error::BoxError;
+
+/// A [`Resolve`] trying resolvers one after the other, until one answers.
+///
+/// A resolver is given up on when it fails, or when it hasn't answered
+/// within the timeout if one is set. If none answers, the last error is
+/// returned. A name that doesn't exist is an error too, so it's looked up
+/// with the next resolvers.
+///
+/// ```
+/// # use chromimic as reqwest;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use reqwest::dns::{DohFormat, DohResolver, FallbackResolver, HostsFileResolver};
+///
+/// # fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// let hosts = HostsFileResolver::open("/etc/hosts")?;
+/// let doh = DohResolver::builder()
+///     .upstream("https://dns.google/dns-query", DohFormat::Post)
+///     .build()?;
+///
+/// let resolver = FallbackResolver::new(Arc::new(hosts), Arc::new(doh))
+///     .timeout(Duration::from_secs(2));
+/// let client = reqwest::Client::builder()
+///     .dns_resolver(Arc::new(resolver))
+///     .build()?;
+/// # drop(client);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct FallbackResolver {
+    resolvers: Arc<[Arc<dyn Resolve>]>,
+    timeout: Option<Duration>,
+}
+
+impl FallbackResolver {
+    /// Creates a resolver trying `primary`, then `fallback`.
+    pub fn new(primary: Arc<dyn Resolve>, fallback: Arc<dyn Resolve>) -> FallbackResolver {
+        FallbackResolver {
+            resolvers: Arc::new([primary, fallback]),
+            timeout: None,
+        }
+    }
+
+    /// Adds a resolver tried after the others.
+    pub fn or(mut self, resolver: Arc<dyn Resolve>) -> FallbackResolver {
+        let mut resolvers = self.resolvers.to_vec();
+        resolvers.push(resolver);
+        self.resolvers = resolvers.into();
+        self
+    }
+
+    /// Sets how long each resolver has to answer before the next one is
+    /// tried.
+    ///
+    /// Default is no timeout.
+    pub fn timeout(mut self, timeout: Duration) -> FallbackResolver {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Asks each resolver in turn with `lookup`, until one answers.
+    async fn first<T, F, Fut>(self, name: Name, lookup: F) -> Result<T, BoxError>
+    where
+        F: Fn(&dyn Resolve, Name) -> Fut,
+        Fut: Future<Output = Result<T, BoxError>>,
+    {
+        let mut last_err = None;
+        for resolver in self.resolvers.iter() {
+            let lookup = lookup(&**resolver, name.clone());
+            let result = match self.timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, lookup).await {
+                    Ok(result) => result,
+                    Err(_) => Err(format!("DNS lookup of {} timed out", name).into()),
+                },
+                None => lookup.await,
+            };
+            match result {
+                Ok(answer) => return Ok(answer),
+                Err(e) => {
+                    log::debug!("resolving {} failed, trying the next resolver: {}", name, e);
+                    last_err = Some(e);
+                }
+            }
+        }
+        Err(last_err.expect("there are at least two resolvers"))
+    }
+}
+
+impl Resolve for FallbackResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(
+            self.clone()
+                .first(name, |resolver, name| resolver.resolve(name)),
+        )
+    }
+
+    fn resolve_with_ttl(&self, name: Name) -> ResolvingWithTtl {
+        Box::pin(
+            self.clone()
+                .first(name, |resolver, name| resolver.resolve_with_ttl(name)),
+        )
+    }
+
+    fn resolve_https(&self, name: Name) -> ResolvingHttps {
+        Box::pin(
+            self.clone()
+                .first(name, |resolver, name| resolver.resolve_https(name)),
+        )
+    }
+}
+
+impl fmt::Debug for FallbackResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FallbackResolver")
+            .field("resolvers", &self.resolvers.len())
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::Addrs;
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+
+    struct Fixed(Option<SocketAddr>, Duration);
+
+    impl Resolve for Fixed {
+        fn resolve(&self, _name: Name) -> Resolving {
+            let (addr, delay) = (self.0, self.1);
+            Box::pin(async move {
+                tokio::time::sleep(delay).await;
+                match addr {
+                    Some(addr) => Ok(Box::new(std::iter::once(addr)) as Addrs),
+                    None => Err("no such name".into()),
+                }
+            })
+        }
+    }
+
+    async fn resolve(resolver: &FallbackResolver) -> Result<Vec<SocketAddr>, String> {
+        match resolver
+            .resolve(Name::from_str("example.com").unwrap())
+            .await
+        {
+            Ok(addrs) => Ok(addrs.collect()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn falls_back_on_error_and_timeout() {
+        let a = SocketAddr::from(([10, 0, 0, 1], 0));
+        let b = SocketAddr::from(([10, 0, 0, 2], 0));
+        let failing = Arc::new(Fixed(None, Duration::ZERO));
+        let slow = Arc::new(Fixed(Some(a), Duration::from_secs(10)));
+        let working = Arc::new(Fixed(Some(b), Duration::ZERO));
+
+        let resolver = FallbackResolver::new(failing.clone(), working.clone());
+        assert_eq!(resolve(&resolver).await, Ok(vec![b]));
+
+        let resolver = FallbackResolver::new(working.clone(), failing.clone());
+        assert_eq!(resolve(&resolver).await, Ok(vec![b]));
+
+        // Without a timeout, a slow resolver is waited for.
+        let resolver = FallbackResolver::new(slow.clone(), working.clone());
+        assert_eq!(resolve(&resolver).await, Ok(vec![a]));
+
+        let resolver = resolver.timeout(Duration::from_secs(1));
+        assert_eq!(resolve(&resolver).await, Ok(vec![b]));
+
+        let resolver = FallbackResolver::new(failing.clone(), slow)
+            .or(failing)
+            .timeout(Duration::from_secs(1));
+        assert_eq!(resolve(&resolver).await, Err("no such name".into()));
+    }
+}
@@ -0,0 +1,222 @@
+//! Filtering the addresses names resolve to
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+
+use super::{Addrs, Name, Resolve, Resolving, ResolvingHttps, ResolvingWithTtl};
+
+/// A [`Resolve`] dropping the addresses of another one that a filter
+/// rejects.
+///
+/// With [`FilterResolver::public`], a client can't be made to connect to
+/// the local network by a name resolving to it, as a protection against
+/// server-side request forgery. The addresses hinted at by HTTPS records are
+/// filtered too. A name left without any address fails to resolve.
+///
+/// URLs whose host is an IP address aren't resolved, so they have to be
+/// checked before sending the request. Names sent through `socks4://` and
+/// `socks5://` proxies are resolved, and filtered, by the client; HTTP,
+/// `socks4a://` and `socks5h://` proxies resolve them themselves.
+///
+/// ```
+/// # use chromimic as reqwest;
+/// use std::sync::Arc;
+/// use reqwest::dns::{CachingResolver, FilterResolver};
+///
+/// # fn run() -> Result<(), reqwest::Error> {
+/// let resolver = FilterResolver::public(Arc::new(CachingResolver::builder().build()));
+/// let client = reqwest::Client::builder()
+///     .dns_resolver(Arc::new(resolver))
+///     .no_proxy()
+///     .build()?;
+/// # drop(client);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct FilterResolver {
+    resolver: Arc<dyn Resolve>,
+    filter: Arc<dyn Fn(IpAddr) -> bool + Send + Sync>,
+}
+
+impl FilterResolver {
+    /// Creates a resolver keeping the addresses of `resolver` for which
+    /// `filter` returns true.
+    pub fn new<F>(resolver: Arc<dyn Resolve>, filter: F) -> FilterResolver
+    where
+        F: Fn(IpAddr) -> bool + Send + Sync + 'static,
+    {
+        FilterResolver {
+            resolver,
+            filter: Arc::new(filter),
+        }
+    }
+
+    /// Creates a resolver keeping the addresses of `resolver` that are
+    /// reachable on the internet.
+    ///
+    /// Dropped are the loopback, private, shared (carrier-grade NAT),
+    /// link-local, unique local, unspecified, broadcast, multicast,
+    /// documentation and reserved addresses, and the IPv6 addresses
+    /// embedding those: IPv4-mapped, IPv4-compatible, NAT64, 6to4 and
+    /// Teredo ones.
+    pub fn public(resolver: Arc<dyn Resolve>) -> FilterResolver {
+        FilterResolver::new(resolver, is_public)
+    }
+}
+
+impl Resolve for FilterResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolving = self.resolve_with_ttl(name);
+        Box::pin(async move { Ok(resolving.await?.0) })
+    }
+
+    fn resolve_with_ttl(&self, name: Name) -> ResolvingWithTtl {
+        let filter = self.filter.clone();
+        let resolving = self.resolver.resolve_with_ttl(name.clone());
+        Box::pin(async move {
+            let (addrs, ttl) = resolving.await?;
+            let addrs: Vec<SocketAddr> = addrs.filter(|addr| filter(addr.ip())).collect();
+            if addrs.is_empty() {
+                return Err(format!("no allowed address for {}", name).into());
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok((addrs, ttl))
+        })
+    }
+
+    fn resolve_https(&self, name: Name) -> ResolvingHttps {
+        let filter = self.filter.clone();
+        let resolving = self.resolver.resolve_https(name);
+        Box::pin(async move {
+            let mut records = resolving.await?;
+            for record in &mut records {
+                record.retain_ip_hints(|ip| filter(ip));
+            }
+            Ok(records)
+        })
+    }
+}
+
+impl fmt::Debug for FilterResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FilterResolver").finish()
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "this network", 0.0.0.0/8
+        || a == 0
+        // shared address space, 100.64.0.0/10
+        || (a == 100 && b & 0xc0 == 64)
+        // IETF protocol assignments, 192.0.0.0/24
+        || (a == 192 && b == 0 && ip.octets()[2] == 0)
+        // benchmarking, 198.18.0.0/15
+        || (a == 198 && b & 0xfe == 18)
+        // reserved, 240.0.0.0/4
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let v4 = |hi: u16, lo: u16| Ipv4Addr::from((u32::from(hi) << 16) | u32::from(lo));
+    let segments = ip.segments();
+    match segments {
+        // IPv4-mapped, ::ffff:0:0/96
+        [0, 0, 0, 0, 0, 0xffff, hi, lo] |
+        // deprecated IPv4-compatible, ::/96, including :: and ::1
+        [0, 0, 0, 0, 0, 0, hi, lo] |
+        // NAT64, 64:ff9b::/96
+        [0x64, 0xff9b, 0, 0, 0, 0, hi, lo] |
+        // 6to4, 2002::/16
+        [0x2002, hi, lo, ..] => return is_public_v4(v4(hi, lo)),
+        // Teredo, 2001::/32: the server's address, then the client's
+        // obfuscated one
+        [0x2001, 0, server_hi, server_lo, _, _, client_hi, client_lo] => {
+            return is_public_v4(v4(server_hi, server_lo))
+                && is_public_v4(v4(!client_hi, !client_lo))
+        }
+        _ => (),
+    }
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // unique local, fc00::/7
+        || segments[0] & 0xfe00 == 0xfc00
+        // link-local, fe80::/10, and deprecated site-local, fec0::/10
+        || segments[0] & 0xffc0 == 0xfe80
+        || segments[0] & 0xffc0 == 0xfec0
+        // documentation, 2001:db8::/32
+        || (segments[0] == 0x2001 && segments[1] == 0xdb8)
+        // discard-only, 100::/64
+        || (segments[0] == 0x100 && segments[1..4] == [0, 0, 0]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_addresses() {
+        for ip in [
+            "1.1.1.1",
+            "100.128.0.1",
+            "172.32.0.1",
+            "2606:4700:4700::1111",
+            "::ffff:8.8.8.8",
+            "64:ff9b::808:808",
+            "::808:808",
+            "2002:808:808::1",
+            "2001:0:4136:e378:8000:63bf:f7f7:f7f7",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+
+        for ip in [
+            "0.0.0.0",
+            "0.1.2.3",
+            "10.1.2.3",
+            "100.64.0.1",
+            "127.0.0.1",
+            "169.254.169.254",
+            "172.16.0.1",
+            "192.0.0.1",
+            "192.168.1.1",
+            "198.18.0.1",
+            "224.0.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a00:1",
+            "::7f00:1",
+            "::a9fe:a9fe",
+            "2002:7f00:1::1",
+            "2002:c0a8:101::1",
+            "2001:0:a00:1:8000:63bf:f7f7:f7f7",
+            "2001:0:4136:e378:8000:63bf:80ff:fffe",
+            "fc00::1",
+            "fd12::1",
+            "fe80::1",
+            "2001:db8::1",
+            "ff02::1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+}
@@ -1,13 +1,19 @@
 //! DNS resolution via the [hickory-resolver](https://github.com/hickory-dns/hickory-dns) crate
 
+use hickory_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+use hickory_resolver::error::ResolveErrorKind;
+use hickory_resolver::proto::rr::rdata::svcb::{SvcParamKey, SvcParamValue, SVCB};
+use hickory_resolver::proto::rr::{RData, RecordType};
 use hickory_resolver::{lookup_ip::LookupIpIntoIter, system_conf, TokioAsyncResolver};
 use once_cell::sync::OnceCell;
 
+use std::convert::TryFrom;
 use std::io;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use super::{Addrs, Name, Resolve, Resolving};
+use super::{Addrs, HttpsRecord, Name, Resolve, Resolving, ResolvingHttps, ResolvingWithTtl};
 
 /// Wrapper around an `AsyncResolver`, which implements the `Resolve` trait.
 #[derive(Debug, Default, Clone)]
@@ -16,6 +22,185 @@ pub(crate) struct HickoryDnsResolver {
     /// Tokio Runtime in initialization, so we must delay the actual
     /// construction of the resolver.
     state: Arc<OnceCell<TokioAsyncResolver>>,
+    config: Option<HickoryConfig>,
+}
+
+/// Configuration for the hickory-dns resolver, see
+/// [`ClientBuilder::hickory_config`](crate::ClientBuilder::hickory_config).
+///
+/// Without any nameserver, the system's are used, and the options set here
+/// override the system's.
+///
+/// # Example
+///
+/// ```rust
+/// # use chromimic as reqwest;
+/// use std::time::Duration;
+/// use reqwest::dns::HickoryConfig;
+///
+/// let config = HickoryConfig::new()
+///     .nameserver_udp(([9, 9, 9, 9], 53).into())
+///     .nameserver_tcp(([9, 9, 9, 9], 53).into())
+///     .timeout(Duration::from_secs(2))
+///     .attempts(3);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HickoryConfig {
+    nameservers: Vec<NameServerConfig>,
+    timeout: Option<Duration>,
+    attempts: Option<usize>,
+    ndots: Option<usize>,
+    cache_size: Option<usize>,
+    #[cfg(feature = "hickory-dnssec")]
+    dnssec: bool,
+}
+
+impl HickoryConfig {
+    /// Creates a configuration using the system's nameservers and options.
+    pub fn new() -> HickoryConfig {
+        HickoryConfig::default()
+    }
+
+    /// Adds a nameserver queried over UDP.
+    pub fn nameserver_udp(self, addr: SocketAddr) -> HickoryConfig {
+        self.nameserver(NameServerConfig::new(addr, Protocol::Udp))
+    }
+
+    /// Adds a nameserver queried over TCP.
+    pub fn nameserver_tcp(self, addr: SocketAddr) -> HickoryConfig {
+        self.nameserver(NameServerConfig::new(addr, Protocol::Tcp))
+    }
+
+    /// Adds a DNS-over-TLS nameserver, whose certificate must be valid for
+    /// `server_name` and signed by a root of the system's store.
+    ///
+    /// # Optional
+    ///
+    /// This requires the optional `hickory-dns-over-tls` feature to be enabled.
+    #[cfg(feature = "hickory-dns-over-tls")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "hickory-dns-over-tls")))]
+    pub fn nameserver_tls(self, addr: SocketAddr, server_name: impl Into<String>) -> HickoryConfig {
+        let mut nameserver = NameServerConfig::new(addr, Protocol::Tls);
+        nameserver.tls_dns_name = Some(server_name.into());
+        self.nameserver(nameserver)
+    }
+
+    /// Adds a DNS-over-QUIC nameserver, whose certificate must be valid for
+    /// `server_name` and signed by a root of the system's store.
+    ///
+    /// # Optional
+    ///
+    /// This requires the optional `hickory-dns-over-quic` feature to be enabled.
+    #[cfg(feature = "hickory-dns-over-quic")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "hickory-dns-over-quic")))]
+    pub fn nameserver_quic(
+        self,
+        addr: SocketAddr,
+        server_name: impl Into<String>,
+    ) -> HickoryConfig {
+        let mut nameserver = NameServerConfig::new(addr, Protocol::Quic);
+        nameserver.tls_dns_name = Some(server_name.into());
+        self.nameserver(nameserver)
+    }
+
+    fn nameserver(mut self, nameserver: NameServerConfig) -> HickoryConfig {
+        self.nameservers.push(nameserver);
+        self
+    }
+
+    /// Sets how long to wait for an answer to each query.
+    ///
+    /// Defaults to 5 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> HickoryConfig {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how many times a query is sent before giving up.
+    ///
+    /// Defaults to 2.
+    pub fn attempts(mut self, attempts: usize) -> HickoryConfig {
+        self.attempts = Some(attempts);
+        self
+    }
+
+    /// Sets how many dots a name needs to be looked up as is before trying
+    /// the search domains.
+    ///
+    /// Defaults to 1.
+    pub fn ndots(mut self, ndots: usize) -> HickoryConfig {
+        self.ndots = Some(ndots);
+        self
+    }
+
+    /// Sets how many answers hickory keeps in its own cache.
+    ///
+    /// Defaults to 32; 0 disables the cache.
+    pub fn cache_size(mut self, cache_size: usize) -> HickoryConfig {
+        self.cache_size = Some(cache_size);
+        self
+    }
+
+    /// Validates answers with DNSSEC, failing lookups whose answers aren't
+    /// signed.
+    ///
+    /// Defaults to false.
+    ///
+    /// # Optional
+    ///
+    /// This requires the optional `hickory-dnssec` feature to be enabled.
+    #[cfg(feature = "hickory-dnssec")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "hickory-dnssec")))]
+    pub fn dnssec(mut self, enabled: bool) -> HickoryConfig {
+        self.dnssec = enabled;
+        self
+    }
+
+    fn resolver_config(&self) -> io::Result<(ResolverConfig, ResolverOpts)> {
+        let (config, mut opts) = match self.nameservers.is_empty() {
+            true => read_system_conf()?,
+            false => (
+                ResolverConfig::from_parts(None, Vec::new(), self.nameservers.clone()),
+                ResolverOpts::default(),
+            ),
+        };
+        if let Some(timeout) = self.timeout {
+            opts.timeout = timeout;
+        }
+        if let Some(attempts) = self.attempts {
+            opts.attempts = attempts;
+        }
+        if let Some(ndots) = self.ndots {
+            opts.ndots = ndots;
+        }
+        if let Some(cache_size) = self.cache_size {
+            opts.cache_size = cache_size;
+        }
+        #[cfg(feature = "hickory-dnssec")]
+        {
+            opts.validate |= self.dnssec;
+        }
+        Ok((config, opts))
+    }
+}
+
+impl HickoryDnsResolver {
+    pub(crate) fn new(config: HickoryConfig) -> HickoryDnsResolver {
+        HickoryDnsResolver {
+            state: Arc::default(),
+            config: Some(config),
+        }
+    }
+
+    fn resolver(&self) -> io::Result<&TokioAsyncResolver> {
+        self.state.get_or_try_init(|| {
+            let (config, opts) = match self.config {
+                Some(ref config) => config.resolver_config()?,
+                None => read_system_conf()?,
+            };
+            Ok(TokioAsyncResolver::tokio(config, opts))
+        })
+    }
 }
 
 struct SocketAddrs {
@@ -24,17 +209,94 @@ struct SocketAddrs {
 
 impl Resolve for HickoryDnsResolver {
     fn resolve(&self, name: Name) -> Resolving {
+        let resolving = self.resolve_with_ttl(name);
+        Box::pin(async move { Ok(resolving.await?.0) })
+    }
+
+    fn resolve_with_ttl(&self, name: Name) -> ResolvingWithTtl {
         let resolver = self.clone();
         Box::pin(async move {
-            let resolver = resolver.state.get_or_try_init(new_resolver)?;
+            let resolver = resolver.resolver()?;
 
             let lookup = resolver.lookup_ip(name.as_str()).await?;
+            let ttl = lookup.valid_until().saturating_duration_since(Instant::now());
             let addrs: Addrs = Box::new(SocketAddrs {
                 iter: lookup.into_iter(),
             });
-            Ok(addrs)
+            Ok((addrs, Some(ttl)))
         })
     }
+
+    fn resolve_https(&self, name: Name) -> ResolvingHttps {
+        let resolver = self.clone();
+        Box::pin(async move {
+            let resolver = resolver.resolver()?;
+
+            let lookup = match resolver.lookup(name.as_str(), RecordType::HTTPS).await {
+                Ok(lookup) => lookup,
+                Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+                    return Ok(Vec::new())
+                }
+                Err(e) => return Err(e.into()),
+            };
+            Ok(lookup
+                .iter()
+                .filter_map(|rdata| match rdata {
+                    RData::HTTPS(https) => https_record(https),
+                    _ => None,
+                })
+                .collect())
+        })
+    }
+}
+
+/// Converts an HTTPS record, unless it has mandatory parameters that aren't
+/// understood, in which case it must be ignored.
+fn https_record(svcb: &SVCB) -> Option<HttpsRecord> {
+    let mut record = HttpsRecord::new(svcb.svc_priority());
+    if !svcb.target_name().is_root() {
+        record = record.with_target(svcb.target_name().to_utf8());
+    }
+
+    for (_, value) in svcb.svc_params() {
+        record = match value {
+            SvcParamValue::Mandatory(mandatory) => {
+                let understood = mandatory.0.iter().all(|key| {
+                    matches!(
+                        key,
+                        SvcParamKey::Alpn
+                            | SvcParamKey::NoDefaultAlpn
+                            | SvcParamKey::Port
+                            | SvcParamKey::Ipv4Hint
+                            | SvcParamKey::EchConfig
+                            | SvcParamKey::Ipv6Hint
+                    )
+                });
+                if !understood {
+                    return None;
+                }
+                record
+            }
+            SvcParamValue::Alpn(alpn) => record.with_alpn(alpn.0.iter().cloned()),
+            SvcParamValue::NoDefaultAlpn => record.with_no_default_alpn(),
+            SvcParamValue::Port(port) => record.with_port(*port),
+            SvcParamValue::Ipv4Hint(hint) => {
+                record.with_ip_hints(hint.0.iter().map(|a| IpAddr::V4(a.0)))
+            }
+            SvcParamValue::Ipv6Hint(hint) => {
+                record.with_ip_hints(hint.0.iter().map(|aaaa| IpAddr::V6(aaaa.0)))
+            }
+            // hickory strips the length that starts an `ECHConfigList`.
+            SvcParamValue::EchConfig(config) => match u16::try_from(config.0.len()) {
+                Ok(len) => {
+                    record.with_ech_config_list([&len.to_be_bytes()[..], &config.0].concat())
+                }
+                Err(_) => record,
+            },
+            SvcParamValue::Unknown(_) => record,
+        };
+    }
+    Some(record)
 }
 
 impl Iterator for SocketAddrs {
@@ -45,14 +307,12 @@ impl Iterator for SocketAddrs {
     }
 }
 
-/// Create a new resolver with the default configuration,
-/// which reads from `/etc/resolve.conf`.
-fn new_resolver() -> io::Result<TokioAsyncResolver> {
-    let (config, opts) = system_conf::read_system_conf().map_err(|e| {
+/// Reads the system configuration, from `/etc/resolv.conf` on Unix.
+fn read_system_conf() -> io::Result<(ResolverConfig, ResolverOpts)> {
+    system_conf::read_system_conf().map_err(|e| {
         io::Error::new(
             io::ErrorKind::Other,
             format!("error reading DNS system conf: {}", e),
         )
-    })?;
-    Ok(TokioAsyncResolver::tokio(config, opts))
+    })
 }
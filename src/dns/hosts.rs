@@ -0,0 +1,198 @@
+//! Resolving from a hosts file
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use super::{Addrs, Name, Resolve, Resolving, ResolvingHttps, ResolvingWithTtl};
+
+/// A [`Resolve`] answering from a file in the `/etc/hosts` format.
+///
+/// Each line has an IP address followed by the names it's for, and `#`
+/// starts a comment. Names are matched without regard to case, and a name
+/// on several lines resolves to all their addresses, in order.
+///
+/// Names that aren't in the file fail to resolve, unless a fallback
+/// resolver is set. The file is read when the resolver is opened, and again
+/// on [`reload`]; every `Client` given the same resolver sees the update.
+///
+/// ```
+/// # use chromimic as reqwest;
+/// use std::sync::Arc;
+/// use reqwest::dns::HostsFileResolver;
+///
+/// # fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// let hosts = HostsFileResolver::open("staging.hosts")?;
+/// let client = reqwest::Client::builder()
+///     .dns_resolver(Arc::new(hosts.clone()))
+///     .build()?;
+///
+/// // later, once the file was edited
+/// hosts.reload()?;
+/// # drop(client);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`reload`]: HostsFileResolver::reload
+#[derive(Clone)]
+pub struct HostsFileResolver {
+    inner: Arc<Inner>,
+    fallback: Option<Arc<dyn Resolve>>,
+}
+
+struct Inner {
+    path: PathBuf,
+    hosts: RwLock<HashMap<String, Vec<IpAddr>>>,
+}
+
+impl HostsFileResolver {
+    /// Creates a resolver answering from the file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// This method fails if the file can't be read.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<HostsFileResolver> {
+        let path = path.as_ref().to_owned();
+        let hosts = parse(&std::fs::read_to_string(&path)?);
+        Ok(HostsFileResolver {
+            inner: Arc::new(Inner {
+                path,
+                hosts: RwLock::new(hosts),
+            }),
+            fallback: None,
+        })
+    }
+
+    /// Resolves the names that aren't in the file with `resolver`.
+    pub fn fallback(mut self, resolver: Arc<dyn Resolve>) -> HostsFileResolver {
+        self.fallback = Some(resolver);
+        self
+    }
+
+    /// Reads the file again, replacing the entries read before.
+    ///
+    /// # Errors
+    ///
+    /// This method fails if the file can't be read, in which case the
+    /// previous entries are kept.
+    pub fn reload(&self) -> io::Result<()> {
+        let hosts = parse(&std::fs::read_to_string(&self.inner.path)?);
+        *self.inner.hosts.write().unwrap() = hosts;
+        Ok(())
+    }
+
+    fn lookup(&self, name: &Name) -> Option<Vec<IpAddr>> {
+        let hosts = self.inner.hosts.read().unwrap();
+        hosts.get(&normalize(name.as_str())).cloned()
+    }
+}
+
+impl Resolve for HostsFileResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolving = self.resolve_with_ttl(name);
+        Box::pin(async move { Ok(resolving.await?.0) })
+    }
+
+    fn resolve_with_ttl(&self, name: Name) -> ResolvingWithTtl {
+        let ips = match (self.lookup(&name), &self.fallback) {
+            (Some(ips), _) => ips,
+            (None, Some(fallback)) => return fallback.resolve_with_ttl(name),
+            (None, None) => {
+                let err = format!("{} isn't in {}", name, self.inner.path.display());
+                return Box::pin(futures_util::future::ready(Err(err.into())));
+            }
+        };
+        let addrs: Addrs = Box::new(ips.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+        // The file can change any time.
+        Box::pin(futures_util::future::ready(Ok((addrs, None))))
+    }
+
+    fn resolve_https(&self, name: Name) -> ResolvingHttps {
+        match (self.lookup(&name), &self.fallback) {
+            (None, Some(fallback)) => fallback.resolve_https(name),
+            // The file only has addresses.
+            _ => Box::pin(futures_util::future::ready(Ok(Vec::new()))),
+        }
+    }
+}
+
+impl fmt::Debug for HostsFileResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostsFileResolver")
+            .field("path", &self.inner.path)
+            .field("fallback", &self.fallback.is_some())
+            .finish()
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Parses the lines of a hosts file, skipping those that aren't valid.
+fn parse(contents: &str) -> HashMap<String, Vec<IpAddr>> {
+    let mut hosts: HashMap<String, Vec<IpAddr>> = HashMap::new();
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        let ip = match fields.next().map(str::parse::<IpAddr>) {
+            Some(Ok(ip)) => ip,
+            _ => continue,
+        };
+        for name in fields {
+            let ips = hosts.entry(normalize(name)).or_default();
+            if !ips.contains(&ip) {
+                ips.push(ip);
+            }
+        }
+    }
+    hosts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hosts_file() {
+        let hosts = parse(
+            "# comment\n\
+             127.0.0.1\tlocalhost  Local.Test # trailing comment\n\
+             ::1 localhost\n\
+             \n\
+             not-an-ip broken.test\n\
+             10.0.0.1 local.test.\n\
+             10.0.0.1 local.test\n",
+        );
+
+        let localhost: Vec<IpAddr> = vec![[127, 0, 0, 1].into(), "::1".parse().unwrap()];
+        assert_eq!(hosts["localhost"], localhost);
+        assert_eq!(
+            hosts["local.test"],
+            vec![IpAddr::from([127, 0, 0, 1]), IpAddr::from([10, 0, 0, 1])]
+        );
+        assert!(!hosts.contains_key("broken.test"));
+        assert_eq!(hosts.len(), 2);
+    }
+
+    #[test]
+    fn reloads() {
+        let path = std::env::temp_dir().join(format!("chromimic-hosts-{}", std::process::id()));
+        std::fs::write(&path, "10.0.0.1 reload.test\n").unwrap();
+        let resolver = HostsFileResolver::open(&path).unwrap();
+        let name = "reload.test".parse().unwrap();
+        assert_eq!(resolver.lookup(&name), Some(vec![[10, 0, 0, 1].into()]));
+
+        std::fs::write(&path, "10.0.0.2 reload.test\n").unwrap();
+        resolver.reload().unwrap();
+        assert_eq!(resolver.lookup(&name), Some(vec![[10, 0, 0, 2].into()]));
+
+        std::fs::remove_file(&path).unwrap();
+        resolver.reload().unwrap_err();
+        assert_eq!(resolver.lookup(&name), Some(vec![[10, 0, 0, 2].into()]));
+    }
+}
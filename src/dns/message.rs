@@ -0,0 +1,358 @@
+//! Just enough of the DNS wire format to query over HTTPS.
+//!
+//! https://www.rfc-editor.org/rfc/rfc1035#section-4
+
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use super::HttpsRecord;
+use crate::error::BoxError;
+
+pub(crate) const A: u16 = 1;
+pub(crate) const AAAA: u16 = 28;
+pub(crate) const HTTPS: u16 = 65;
+const OPT: u16 = 41;
+const CLASS_IN: u16 = 1;
+const EDNS_PADDING: u16 = 12;
+
+pub(crate) const NOERROR: u8 = 0;
+pub(crate) const NXDOMAIN: u8 = 3;
+
+// RFC 8467 recommends padding queries to a multiple of 128 bytes.
+const PADDING_BLOCK: usize = 128;
+
+/// The parts of a response the resolvers use.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Message {
+    pub(crate) rcode: u8,
+    pub(crate) answers: Vec<Answer>,
+    /// The lowest TTL of the answer section, in seconds.
+    pub(crate) ttl: Option<u32>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Answer {
+    Ip(IpAddr),
+    Https(HttpsRecord),
+}
+
+/// Encodes a recursive query for the `rtype` records of `name`, padded with
+/// EDNS(0) so its length doesn't give the name away.
+pub(crate) fn query(name: &str, rtype: u16) -> Result<Vec<u8>, BoxError> {
+    let mut msg = vec![
+        0, 0, // ID, 0 as RFC 8484 recommends for caching
+        0x01, 0x00, // RD
+        0, 1, // QDCOUNT
+        0, 0, // ANCOUNT
+        0, 0, // NSCOUNT
+        0, 1, // ARCOUNT
+    ];
+
+    let name = name.trim_end_matches('.');
+    if name.len() > 253 {
+        return Err(format!("name too long: {:?}", name).into());
+    }
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("invalid name: {:?}", name).into());
+        }
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.push(0);
+    msg.extend_from_slice(&rtype.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    // OPT pseudo-record with a padding option
+    let unpadded = msg.len() + 11 + 4;
+    let padding = (PADDING_BLOCK - unpadded % PADDING_BLOCK) % PADDING_BLOCK;
+    msg.push(0); // root name
+    msg.extend_from_slice(&OPT.to_be_bytes());
+    msg.extend_from_slice(&4096u16.to_be_bytes()); // UDP payload size
+    msg.extend_from_slice(&[0, 0, 0, 0]); // extended RCODE and flags
+    msg.extend_from_slice(&(4 + padding as u16).to_be_bytes());
+    msg.extend_from_slice(&EDNS_PADDING.to_be_bytes());
+    msg.extend_from_slice(&(padding as u16).to_be_bytes());
+    msg.resize(msg.len() + padding, 0);
+
+    Ok(msg)
+}
+
+/// Parses a response, keeping the A, AAAA and HTTPS records of its answer
+/// section.
+pub(crate) fn parse(msg: &[u8]) -> Result<Message, BoxError> {
+    let invalid = || BoxError::from("invalid DNS message");
+
+    let mut reader = Reader { msg, pos: 0 };
+    reader.skip(2).ok_or_else(invalid)?; // ID
+    let flags = reader.u16().ok_or_else(invalid)?;
+    if flags & 0x8000 == 0 {
+        return Err("DNS message isn't a response".into());
+    }
+    if flags & 0x0200 != 0 {
+        return Err("truncated DNS response".into());
+    }
+    let questions = reader.u16().ok_or_else(invalid)?;
+    let answers = reader.u16().ok_or_else(invalid)?;
+    reader.skip(4).ok_or_else(invalid)?; // NSCOUNT and ARCOUNT
+
+    for _ in 0..questions {
+        reader.skip_name().ok_or_else(invalid)?;
+        reader.skip(4).ok_or_else(invalid)?;
+    }
+
+    let mut message = Message {
+        rcode: (flags & 0x000f) as u8,
+        answers: Vec::new(),
+        ttl: None,
+    };
+    for _ in 0..answers {
+        reader.skip_name().ok_or_else(invalid)?;
+        let rtype = reader.u16().ok_or_else(invalid)?;
+        reader.skip(2).ok_or_else(invalid)?; // CLASS
+        let ttl = reader.u32().ok_or_else(invalid)?;
+        let len = reader.u16().ok_or_else(invalid)? as usize;
+        let start = reader.pos;
+        reader.skip(len).ok_or_else(invalid)?;
+
+        message.ttl = Some(message.ttl.map_or(ttl, |min| min.min(ttl)));
+
+        let rdata = &msg[start..start + len];
+        let answer = match rtype {
+            A => <[u8; 4]>::try_from(rdata)
+                .map(|ip| Answer::Ip(Ipv4Addr::from(ip).into()))
+                .map_err(|_| invalid())?,
+            AAAA => <[u8; 16]>::try_from(rdata)
+                .map(|ip| Answer::Ip(Ipv6Addr::from(ip).into()))
+                .map_err(|_| invalid())?,
+            HTTPS => match https_record(msg, start, len).ok_or_else(invalid)? {
+                Some(record) => Answer::Https(record),
+                None => continue,
+            },
+            _ => continue,
+        };
+        message.answers.push(answer);
+    }
+    Ok(message)
+}
+
+/// Parses the data of an HTTPS record on its own, returning `None` if it's
+/// malformed or must be ignored.
+#[cfg(feature = "json")]
+pub(crate) fn https_rdata(rdata: &[u8]) -> Option<Answer> {
+    https_record(rdata, 0, rdata.len())
+        .flatten()
+        .map(Answer::Https)
+}
+
+/// Parses the data of an HTTPS record found at `start` in `msg`, returning
+/// `None` inside if the record must be ignored because it has mandatory
+/// parameters that aren't understood.
+///
+/// https://www.rfc-editor.org/rfc/rfc9460#section-2.2
+fn https_record(msg: &[u8], start: usize, len: usize) -> Option<Option<HttpsRecord>> {
+    let end = start.checked_add(len).filter(|&end| end <= msg.len())?;
+    let mut reader = Reader {
+        msg: &msg[..end],
+        pos: start,
+    };
+
+    let mut record = HttpsRecord::new(reader.u16()?);
+    let target = reader.name()?;
+    if !target.is_empty() {
+        record = record.with_target(target);
+    }
+
+    while reader.pos < end {
+        let key = reader.u16()?;
+        let len = reader.u16()? as usize;
+        let value = reader.bytes(len)?;
+        record = match key {
+            // mandatory
+            0 => {
+                let understood = value.chunks(2).all(|key| {
+                    key.len() == 2 && matches!(u16::from_be_bytes([key[0], key[1]]), 1..=6)
+                });
+                if !understood {
+                    return Some(None);
+                }
+                record
+            }
+            // alpn
+            1 => {
+                let mut protocols = Vec::new();
+                let mut rest = value;
+                while let Some((&len, tail)) = rest.split_first() {
+                    let protocol = tail.get(..len as usize)?;
+                    protocols.push(String::from_utf8_lossy(protocol).into_owned());
+                    rest = &tail[len as usize..];
+                }
+                record.with_alpn(protocols)
+            }
+            // no-default-alpn
+            2 => record.with_no_default_alpn(),
+            // port
+            3 => record.with_port(u16::from_be_bytes(<[u8; 2]>::try_from(value).ok()?)),
+            // ipv4hint
+            4 if value.len() % 4 == 0 => record.with_ip_hints(
+                value
+                    .chunks(4)
+                    .map(|ip| IpAddr::from([ip[0], ip[1], ip[2], ip[3]])),
+            ),
+            // ech
+            5 => record.with_ech_config_list(value),
+            // ipv6hint
+            6 if value.len() % 16 == 0 => record.with_ip_hints(
+                value
+                    .chunks(16)
+                    .map(|ip| IpAddr::from(<[u8; 16]>::try_from(ip).unwrap())),
+            ),
+            4 | 6 => return None,
+            _ => record,
+        };
+    }
+    Some(Some(record))
+}
+
+struct Reader<'a> {
+    msg: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.msg.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.bytes(len).map(drop)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn skip_name(&mut self) -> Option<()> {
+        loop {
+            let len = *self.msg.get(self.pos)?;
+            match len {
+                0 => return self.skip(1),
+                // A pointer ends the name.
+                0xc0..=0xff => return self.skip(2),
+                _ => self.skip(1 + len as usize)?,
+            }
+        }
+    }
+
+    /// Reads a name, following compression pointers, without its final dot.
+    fn name(&mut self) -> Option<String> {
+        let mut name = String::new();
+        let mut pos = self.pos;
+        let mut end = None;
+        // Pointers can't loop more often than there are bytes to point at.
+        for _ in 0..self.msg.len() {
+            let len = *self.msg.get(pos)? as usize;
+            match len {
+                0 => {
+                    self.pos = end.unwrap_or(pos + 1);
+                    return Some(name);
+                }
+                0xc0..=0xff => {
+                    let low = *self.msg.get(pos + 1)? as usize;
+                    end.get_or_insert(pos + 2);
+                    pos = ((len & 0x3f) << 8) | low;
+                }
+                _ => {
+                    let label = self.msg.get(pos + 1..pos + 1 + len)?;
+                    if !name.is_empty() {
+                        name.push('.');
+                    }
+                    name.push_str(&String::from_utf8_lossy(label));
+                    pos += 1 + len;
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(answers: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut msg = vec![0, 0, 0x81, 0x80, 0, 1, 0, answers.len() as u8, 0, 0, 0, 0];
+        msg.extend_from_slice(b"\x07example\x03com\x00\x00\x01\x00\x01");
+        for (rtype, rdata) in answers {
+            msg.extend_from_slice(&[0xc0, 12]); // name pointing at the question
+            msg.extend_from_slice(&rtype.to_be_bytes());
+            msg.extend_from_slice(&[0, 1, 0, 0, 0x0e, 0x10]); // IN, TTL 3600
+            msg.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            msg.extend_from_slice(rdata);
+        }
+        msg
+    }
+
+    #[test]
+    fn query_is_padded() {
+        let msg = query("example.com.", AAAA).unwrap();
+        assert_eq!(msg.len() % PADDING_BLOCK, 0);
+        assert_eq!(&msg[12..25], b"\x07example\x03com\x00");
+        assert_eq!(&msg[25..29], &[0, 28, 0, 1]);
+
+        query("a..b", A).unwrap_err();
+        query(&"a".repeat(64), A).unwrap_err();
+    }
+
+    #[test]
+    fn parses_addresses() {
+        let msg = response(&[(A, &[93, 184, 216, 34]), (5, b"\x00"), (AAAA, &[0; 16])]);
+        let message = parse(&msg).unwrap();
+        assert_eq!(message.rcode, NOERROR);
+        assert_eq!(message.ttl, Some(3600));
+        assert_eq!(
+            message.answers,
+            vec![
+                Answer::Ip([93, 184, 216, 34].into()),
+                Answer::Ip(Ipv6Addr::UNSPECIFIED.into()),
+            ]
+        );
+
+        parse(&msg[..msg.len() - 1]).unwrap_err();
+        parse(&response(&[(A, &[1, 2, 3])])).unwrap_err();
+    }
+
+    #[test]
+    fn parses_https_records() {
+        let rdata: &[u8] = &[
+            0, 1, // priority
+            3, b'c', b'd', b'n', 0xc0, 20, // cdn.com, "com" compressed
+            0, 1, 0, 6, 2, b'h', b'3', 2, b'h', b'2', // alpn
+            0, 3, 0, 2, 0x01, 0xbb, // port
+            0, 4, 0, 4, 127, 0, 0, 1, // ipv4hint
+            0, 5, 0, 3, 0, 1, 0xaa, // ech
+        ];
+        let message = parse(&response(&[(HTTPS, rdata)])).unwrap();
+        let expected = HttpsRecord::new(1)
+            .with_target("cdn.com")
+            .with_alpn(["h3", "h2"])
+            .with_port(443)
+            .with_ip_hints([IpAddr::from([127, 0, 0, 1])])
+            .with_ech_config_list([0, 1, 0xaa]);
+        assert_eq!(message.answers, vec![Answer::Https(expected)]);
+
+        // An unknown mandatory key makes the record ignored.
+        let rdata: &[u8] = &[0, 1, 0, 0, 0, 0, 2, 0, 7, 0, 7, 0, 0];
+        assert!(parse(&response(&[(HTTPS, rdata)]))
+            .unwrap()
+            .answers
+            .is_empty());
+    }
+}
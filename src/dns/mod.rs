@@ -1,9 +1,28 @@
 //! DNS resolution
 
-pub use resolve::{Addrs, Resolve, Resolving, Name};
-pub(crate) use resolve::{DnsResolverWithOverrides, DynResolver};
+pub use resolve::{
+    Addrs, IpPreference, Name, Resolve, Resolving, ResolvingHttps, ResolvingWithTtl,
+};
+pub use cache::{CacheStats, CachingResolver, CachingResolverBuilder};
+pub use doh::{DohFormat, DohResolver, DohResolverBuilder};
+pub use fallback::FallbackResolver;
+pub use filter::FilterResolver;
+#[cfg(feature = "hickory-dns")]
+pub use hickory::HickoryConfig;
+pub use hosts::HostsFileResolver;
+pub use shuffle::ShuffleResolver;
+pub use svcb::HttpsRecord;
+pub(crate) use resolve::{DnsResolverWithIpPreference, DnsResolverWithOverrides, DynResolver};
 
+mod cache;
+mod doh;
+mod fallback;
+mod filter;
 pub(crate) mod gai;
+mod hosts;
+mod message;
 pub(crate) mod resolve;
+mod shuffle;
+pub(crate) mod svcb;
 #[cfg(feature = "hickory-dns")]
 pub(crate) mod hickory;
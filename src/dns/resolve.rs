@@ -7,7 +7,9 @@ use std::net::SocketAddr;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
+use super::HttpsRecord;
 use crate::error::BoxError;
 
 /// Alias for an `Iterator` trait object over `SocketAddr`.
@@ -16,6 +18,14 @@ pub type Addrs = Box<dyn Iterator<Item = SocketAddr> + Send>;
 /// Alias for the `Future` type returned by a DNS resolver.
 pub type Resolving = Pin<Box<dyn Future<Output = Result<Addrs, BoxError>> + Send>>;
 
+/// Alias for the `Future` type returned by a DNS resolver that also tells how
+/// long its addresses may be cached.
+pub type ResolvingWithTtl =
+    Pin<Box<dyn Future<Output = Result<(Addrs, Option<Duration>), BoxError>> + Send>>;
+
+/// Alias for the `Future` type returned by an HTTPS record lookup.
+pub type ResolvingHttps = Pin<Box<dyn Future<Output = Result<Vec<HttpsRecord>, BoxError>> + Send>>;
+
 /// Trait for customizing DNS resolution in reqwest.
 pub trait Resolve: Send + Sync {
     /// Performs DNS resolution on a `Name`.
@@ -27,6 +37,87 @@ pub trait Resolve: Send + Sync {
     ///  * Since trait objects cannot make use of associated types, it requires
     ///    wrapping the returned `Future` and its contained `Iterator` with `Box`.
     fn resolve(&self, name: Name) -> Resolving;
+
+    /// Performs DNS resolution like `resolve`, also returning for how long
+    /// the addresses may be cached, if the resolver knows it from the
+    /// records' TTLs.
+    ///
+    /// The default implementation calls `resolve` and doesn't know.
+    fn resolve_with_ttl(&self, name: Name) -> ResolvingWithTtl {
+        let resolving = self.resolve(name);
+        Box::pin(async move { Ok((resolving.await?, None)) })
+    }
+
+    /// Looks up the DNS HTTPS records of a `Name`.
+    ///
+    /// A name without records resolves to an empty `Vec`. The default
+    /// implementation doesn't look anything up, so clients only use the
+    /// addresses from `resolve`.
+    fn resolve_https(&self, _name: Name) -> ResolvingHttps {
+        Box::pin(futures_util::future::ready(Ok(Vec::new())))
+    }
+}
+
+/// Which IP versions a client connects over, and in which order.
+///
+/// Resolved addresses are filtered and ordered by it, for direct
+/// connections, connections to proxies, SOCKS proxies resolving locally, and
+/// HTTP/3. Addresses from [`ClientBuilder::resolve`] overrides are used as
+/// given.
+///
+/// [`ClientBuilder::resolve`]: crate::ClientBuilder::resolve
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IpPreference {
+    /// Only connects over IPv4.
+    V4Only,
+    /// Only connects over IPv6.
+    V6Only,
+    /// Tries the IPv4 addresses one after the other, then the IPv6 ones.
+    PreferV4,
+    /// Tries the IPv6 addresses one after the other, then the IPv4 ones.
+    PreferV6,
+    /// Races IPv4 against IPv6 as in RFC 8305: tries IPv6 first, and IPv4
+    /// too if no connection was made after `delay`.
+    HappyEyeballs {
+        /// How long IPv6 has before IPv4 is tried alongside it.
+        delay: Duration,
+    },
+}
+
+impl IpPreference {
+    /// Filters and orders `addrs`, keeping the resolver's order within a
+    /// version.
+    pub(crate) fn apply(self, addrs: impl Iterator<Item = SocketAddr>) -> Vec<SocketAddr> {
+        let (v6, v4): (Vec<_>, Vec<_>) = addrs.partition(SocketAddr::is_ipv6);
+        match self {
+            IpPreference::V4Only => v4,
+            IpPreference::V6Only => v6,
+            IpPreference::PreferV4 => v4.into_iter().chain(v6).collect(),
+            IpPreference::PreferV6 | IpPreference::HappyEyeballs { .. } => {
+                v6.into_iter().chain(v4).collect()
+            }
+        }
+    }
+
+    /// Returns the error for a name without any address to connect to.
+    pub(crate) fn no_address(self, name: &str) -> BoxError {
+        let version = match self {
+            IpPreference::V4Only => "IPv4 ",
+            IpPreference::V6Only => "IPv6 ",
+            _ => "",
+        };
+        format!("no {}address for {}", version, name).into()
+    }
+
+    /// Returns the delay before falling back to the other IP version, `None`
+    /// to try addresses one after the other.
+    pub(crate) fn happy_eyeballs_timeout(self) -> Option<Duration> {
+        match self {
+            IpPreference::HappyEyeballs { delay } => Some(delay),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -38,6 +129,10 @@ impl DynResolver {
     pub(crate) fn new(resolver: Arc<dyn Resolve>) -> Self {
         Self { resolver }
     }
+
+    pub(crate) fn resolve_https(&self, name: Name) -> ResolvingHttps {
+        self.resolver.resolve_https(name)
+    }
 }
 
 impl Service<Name> for DynResolver {
@@ -81,4 +176,60 @@ impl Resolve for DnsResolverWithOverrides {
             None => self.dns_resolver.resolve(name),
         }
     }
+
+    fn resolve_with_ttl(&self, name: Name) -> ResolvingWithTtl {
+        match self.overrides.get(name.as_str()) {
+            Some(dest) => {
+                let addrs: Addrs = Box::new(dest.clone().into_iter());
+                Box::pin(futures_util::future::ready(Ok((addrs, None))))
+            }
+            None => self.dns_resolver.resolve_with_ttl(name),
+        }
+    }
+
+    fn resolve_https(&self, name: Name) -> ResolvingHttps {
+        // The records could send connections elsewhere than the override.
+        if self.overrides.contains_key(name.as_str()) {
+            return Box::pin(futures_util::future::ready(Ok(Vec::new())));
+        }
+        self.dns_resolver.resolve_https(name)
+    }
+}
+
+pub(crate) struct DnsResolverWithIpPreference {
+    dns_resolver: Arc<dyn Resolve>,
+    preference: IpPreference,
+}
+
+impl DnsResolverWithIpPreference {
+    pub(crate) fn new(dns_resolver: Arc<dyn Resolve>, preference: IpPreference) -> Self {
+        DnsResolverWithIpPreference {
+            dns_resolver,
+            preference,
+        }
+    }
+}
+
+impl Resolve for DnsResolverWithIpPreference {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolving = self.resolve_with_ttl(name);
+        Box::pin(async move { Ok(resolving.await?.0) })
+    }
+
+    fn resolve_with_ttl(&self, name: Name) -> ResolvingWithTtl {
+        let resolving = self.dns_resolver.resolve_with_ttl(name.clone());
+        let preference = self.preference;
+        Box::pin(async move {
+            let (addrs, ttl) = resolving.await?;
+            let addrs = preference.apply(addrs);
+            if addrs.is_empty() {
+                return Err(preference.no_address(name.as_str()));
+            }
+            Ok((Box::new(addrs.into_iter()) as Addrs, ttl))
+        })
+    }
+
+    fn resolve_https(&self, name: Name) -> ResolvingHttps {
+        self.dns_resolver.resolve_https(name)
+    }
 }
@@ -0,0 +1,173 @@
+//! Spreading connections across the addresses of a name
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use super::{Addrs, Name, Resolve, Resolving, ResolvingHttps, ResolvingWithTtl};
+
+/// A [`Resolve`] reordering the addresses of another one, so that new
+/// connections to a name are spread across its addresses rather than all
+/// made to the first that works.
+///
+/// Resolvers usually return a name's addresses in a stable order, and
+/// connections are made to the first address that answers. This resolver
+/// either shuffles them on each lookup, or rotates them by one more address
+/// each time the same name is looked up.
+///
+/// ```
+/// # use chromimic as reqwest;
+/// use std::sync::Arc;
+/// use reqwest::dns::{CachingResolver, ShuffleResolver};
+///
+/// # fn run() -> Result<(), reqwest::Error> {
+/// // Connections are spread even though the answers are cached.
+/// let cache = CachingResolver::builder().build();
+/// let resolver = ShuffleResolver::round_robin(Arc::new(cache));
+/// let client = reqwest::Client::builder()
+///     .dns_resolver(Arc::new(resolver))
+///     .build()?;
+/// # drop(client);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ShuffleResolver {
+    resolver: Arc<dyn Resolve>,
+    // The number of lookups of each name, if rotating.
+    rotations: Option<Arc<Mutex<HashMap<String, usize>>>>,
+}
+
+// Past this many names, the rotations are forgotten and start over.
+const MAX_ROTATED_NAMES: usize = 1024;
+
+impl ShuffleResolver {
+    /// Creates a resolver shuffling the addresses `resolver` returns.
+    pub fn random(resolver: Arc<dyn Resolve>) -> ShuffleResolver {
+        ShuffleResolver {
+            resolver,
+            rotations: None,
+        }
+    }
+
+    /// Creates a resolver rotating the addresses `resolver` returns: each
+    /// lookup of a name starts one address after the previous one.
+    pub fn round_robin(resolver: Arc<dyn Resolve>) -> ShuffleResolver {
+        ShuffleResolver {
+            resolver,
+            rotations: Some(Arc::default()),
+        }
+    }
+
+    fn reorder(&self, name: &str, addrs: Addrs) -> Addrs {
+        let mut addrs: Vec<SocketAddr> = addrs.collect();
+        if addrs.len() > 1 {
+            match self.rotations {
+                Some(ref rotations) => {
+                    let mut rotations = rotations.lock().unwrap();
+                    if rotations.len() >= MAX_ROTATED_NAMES && !rotations.contains_key(name) {
+                        rotations.clear();
+                    }
+                    let lookups = rotations.entry(name.to_owned()).or_insert(0);
+                    let mid = *lookups % addrs.len();
+                    addrs.rotate_left(mid);
+                    *lookups = lookups.wrapping_add(1);
+                }
+                None => shuffle(&mut addrs),
+            }
+        }
+        Box::new(addrs.into_iter())
+    }
+}
+
+impl Resolve for ShuffleResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolving = self.resolve_with_ttl(name);
+        Box::pin(async move { Ok(resolving.await?.0) })
+    }
+
+    fn resolve_with_ttl(&self, name: Name) -> ResolvingWithTtl {
+        let this = self.clone();
+        let resolving = self.resolver.resolve_with_ttl(name.clone());
+        Box::pin(async move {
+            let (addrs, ttl) = resolving.await?;
+            Ok((this.reorder(name.as_str(), addrs), ttl))
+        })
+    }
+
+    fn resolve_https(&self, name: Name) -> ResolvingHttps {
+        self.resolver.resolve_https(name)
+    }
+}
+
+impl fmt::Debug for ShuffleResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShuffleResolver")
+            .field("round_robin", &self.rotations.is_some())
+            .finish()
+    }
+}
+
+/// Shuffles `addrs` with Fisher-Yates.
+fn shuffle(addrs: &mut [SocketAddr]) {
+    for i in (1..addrs.len()).rev() {
+        let j = (crate::util::fast_random() % (i as u64 + 1)) as usize;
+        addrs.swap(i, j);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::str::FromStr;
+
+    struct Three;
+
+    impl Resolve for Three {
+        fn resolve(&self, _name: Name) -> Resolving {
+            let addrs: Addrs = Box::new((1..=3).map(|i| SocketAddr::from(([10, 0, 0, i], 0))));
+            Box::pin(futures_util::future::ready(Ok(addrs)))
+        }
+    }
+
+    async fn first(resolver: &ShuffleResolver, name: &str) -> u8 {
+        let mut addrs = resolver
+            .resolve(Name::from_str(name).unwrap())
+            .await
+            .unwrap();
+        match addrs.next().unwrap().ip() {
+            std::net::IpAddr::V4(ip) => ip.octets()[3],
+            ip => panic!("unexpected {}", ip),
+        }
+    }
+
+    #[tokio::test]
+    async fn round_robin() {
+        let resolver = ShuffleResolver::round_robin(Arc::new(Three));
+        let mut firsts = Vec::new();
+        for _ in 0..4 {
+            firsts.push(first(&resolver, "a.test").await);
+            // Other names rotate on their own.
+            first(&resolver, "b.test").await;
+        }
+        assert_eq!(firsts, [1, 2, 3, 1]);
+    }
+
+    #[tokio::test]
+    async fn random() {
+        let resolver = ShuffleResolver::random(Arc::new(Three));
+        let mut firsts = HashSet::new();
+        for _ in 0..100 {
+            let addrs: Vec<_> = resolver
+                .resolve(Name::from_str("a.test").unwrap())
+                .await
+                .unwrap()
+                .collect();
+            assert_eq!(addrs.len(), 3);
+            firsts.insert(addrs[0]);
+        }
+        assert_eq!(firsts.len(), 3);
+    }
+}
@@ -0,0 +1,208 @@
+//! DNS HTTPS records
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// A DNS HTTPS record: how to reach the HTTPS service of a name.
+///
+/// Browsers look these up next to a host's addresses, to learn before
+/// connecting which protocols it speaks, on which port and name, and the
+/// configuration to encrypt its ClientHello with. A [`Resolve`] returns them
+/// from [`resolve_https`].
+///
+/// https://www.rfc-editor.org/rfc/rfc9460
+///
+/// [`Resolve`]: super::Resolve
+/// [`resolve_https`]: super::Resolve::resolve_https
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpsRecord {
+    priority: u16,
+    target: Option<String>,
+    alpn: Vec<String>,
+    no_default_alpn: bool,
+    port: Option<u16>,
+    ipv4_hints: Vec<Ipv4Addr>,
+    ipv6_hints: Vec<Ipv6Addr>,
+    ech_config_list: Option<Vec<u8>>,
+}
+
+impl HttpsRecord {
+    /// Creates a record with the given `SvcPriority`, for the name that was
+    /// looked up and no parameters.
+    ///
+    /// A priority of 0 makes it an alias record, which the client ignores.
+    pub fn new(priority: u16) -> HttpsRecord {
+        HttpsRecord {
+            priority,
+            target: None,
+            alpn: Vec::new(),
+            no_default_alpn: false,
+            port: None,
+            ipv4_hints: Vec::new(),
+            ipv6_hints: Vec::new(),
+            ech_config_list: None,
+        }
+    }
+
+    /// Serves the record from another name than the one looked up.
+    pub fn with_target(mut self, target: impl Into<String>) -> HttpsRecord {
+        let target = target.into();
+        self.target = match target.trim_end_matches('.') {
+            "" => None,
+            name => Some(name.to_owned()),
+        };
+        self
+    }
+
+    /// Adds ALPN protocol IDs to the `alpn` parameter.
+    pub fn with_alpn<I, S>(mut self, protocols: I) -> HttpsRecord
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.alpn.extend(protocols.into_iter().map(Into::into));
+        self
+    }
+
+    /// Sets the `no-default-alpn` parameter, so only the protocols in `alpn`
+    /// are supported.
+    pub fn with_no_default_alpn(mut self) -> HttpsRecord {
+        self.no_default_alpn = true;
+        self
+    }
+
+    /// Sets the `port` parameter.
+    pub fn with_port(mut self, port: u16) -> HttpsRecord {
+        self.port = Some(port);
+        self
+    }
+
+    /// Adds addresses to the `ipv4hint` and `ipv6hint` parameters.
+    pub fn with_ip_hints<I>(mut self, hints: I) -> HttpsRecord
+    where
+        I: IntoIterator<Item = IpAddr>,
+    {
+        for hint in hints {
+            match hint {
+                IpAddr::V4(ip) => self.ipv4_hints.push(ip),
+                IpAddr::V6(ip) => self.ipv6_hints.push(ip),
+            }
+        }
+        self
+    }
+
+    /// Sets the `ech` parameter, an `ECHConfigList`.
+    pub fn with_ech_config_list(mut self, config_list: impl Into<Vec<u8>>) -> HttpsRecord {
+        self.ech_config_list = Some(config_list.into());
+        self
+    }
+
+    /// Returns the `SvcPriority`; lower is preferred.
+    pub fn priority(&self) -> u16 {
+        self.priority
+    }
+
+    /// Returns the name serving the record, or `None` if it's the name that
+    /// was looked up.
+    pub fn target(&self) -> Option<&str> {
+        self.target.as_deref()
+    }
+
+    /// Returns the `alpn` parameter.
+    pub fn alpn(&self) -> &[String] {
+        &self.alpn
+    }
+
+    /// Returns whether the `no-default-alpn` parameter is set.
+    pub fn no_default_alpn(&self) -> bool {
+        self.no_default_alpn
+    }
+
+    /// Returns the `port` parameter.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// Returns the `ipv4hint` and `ipv6hint` addresses.
+    pub fn ip_hints(&self) -> Vec<IpAddr> {
+        let v4 = self.ipv4_hints.iter().copied().map(IpAddr::from);
+        let v6 = self.ipv6_hints.iter().copied().map(IpAddr::from);
+        v6.chain(v4).collect()
+    }
+
+    /// Drops the `ipv4hint` and `ipv6hint` addresses `keep` rejects.
+    pub(crate) fn retain_ip_hints(&mut self, mut keep: impl FnMut(IpAddr) -> bool) {
+        self.ipv4_hints.retain(|&ip| keep(ip.into()));
+        self.ipv6_hints.retain(|&ip| keep(ip.into()));
+    }
+
+    /// Returns the `ech` parameter.
+    pub fn ech_config_list(&self) -> Option<&[u8]> {
+        self.ech_config_list.as_deref()
+    }
+
+    /// Returns whether the service speaks `protocol`. Unless
+    /// `no-default-alpn` is set, that includes `http/1.1`.
+    pub fn supports(&self, protocol: &str) -> bool {
+        let default = protocol == "http/1.1" && !self.no_default_alpn;
+        default || self.alpn.iter().any(|p| p == protocol)
+    }
+}
+
+/// Picks the record to connect with from the ones of a name: the preferred
+/// service record speaking one of `protocols`.
+pub(crate) fn select<'a>(
+    records: &'a [HttpsRecord],
+    protocols: &[&str],
+) -> Option<&'a HttpsRecord> {
+    records
+        .iter()
+        .filter(|record| record.priority > 0)
+        .filter(|record| protocols.iter().any(|p| record.supports(p)))
+        .min_by_key(|record| record.priority)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{select, HttpsRecord};
+
+    #[test]
+    fn record_protocols() {
+        let record = HttpsRecord::new(1).with_alpn(["h3", "h2"]);
+        assert!(record.supports("h3"));
+        assert!(record.supports("http/1.1"));
+
+        let record = record.with_no_default_alpn();
+        assert!(record.supports("h2"));
+        assert!(!record.supports("http/1.1"));
+    }
+
+    #[test]
+    fn record_target() {
+        assert_eq!(HttpsRecord::new(1).with_target(".").target(), None);
+        assert_eq!(
+            HttpsRecord::new(1).with_target("cdn.example.").target(),
+            Some("cdn.example")
+        );
+    }
+
+    #[test]
+    fn selects_preferred_record() {
+        let records = [
+            HttpsRecord::new(0).with_target("alias.example"),
+            HttpsRecord::new(3).with_port(8443),
+            HttpsRecord::new(2).with_alpn(["h3"]).with_no_default_alpn(),
+            HttpsRecord::new(1)
+                .with_alpn(["h2"])
+                .with_no_default_alpn()
+                .with_port(4433),
+        ];
+
+        assert_eq!(
+            select(&records, &["h2", "http/1.1"]).unwrap().port(),
+            Some(4433)
+        );
+        assert_eq!(select(&records, &["http/1.1"]).unwrap().port(), Some(8443));
+        assert_eq!(select(&records, &["h3"]).unwrap().priority(), 2);
+        assert_eq!(select(&records[..1], &["http/1.1"]), None);
+    }
+}
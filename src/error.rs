@@ -116,6 +116,21 @@ impl Error {
         false
     }
 
+    /// Returns true if the error is from a server certificate not matching
+    /// the keys pinned with `ClientBuilder::pin_certificate`.
+    pub fn is_certificate_pin(&self) -> bool {
+        let mut source = self.source();
+
+        while let Some(err) = source {
+            if err.is::<CertificatePinMismatch>() {
+                return true;
+            }
+            source = err.source();
+        }
+
+        false
+    }
+
     /// Returns true if the error is related to the request
     pub fn is_request(&self) -> bool {
         matches!(self.inner.kind, Kind::Request)
@@ -335,6 +350,26 @@ impl fmt::Display for BadScheme {
 
 impl StdError for BadScheme {}
 
+#[derive(Debug)]
+pub(crate) struct CertificatePinMismatch {
+    host: String,
+}
+
+impl CertificatePinMismatch {
+    #[allow(unused)]
+    pub(crate) fn new(host: String) -> CertificatePinMismatch {
+        CertificatePinMismatch { host }
+    }
+}
+
+impl fmt::Display for CertificatePinMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "certificate of {} doesn't match its pinned keys", self.host)
+    }
+}
+
+impl StdError for CertificatePinMismatch {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
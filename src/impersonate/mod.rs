@@ -6,17 +6,21 @@ use boring::ssl::SslConnectorBuilder;
 use http::HeaderMap;
 
 #[cfg(feature = "__impersonate")]
-pub use profile::Impersonate;
+pub use profile::{ChromeVersion, Impersonate};
 
 #[cfg(feature = "__impersonate")]
 pub mod profile;
 
 #[cfg(feature = "__impersonate")]
-pub(crate) use profile::configure_impersonate;
+pub(crate) use profile::{configure_impersonate, get_config_from_ver};
 
 pub(crate) struct ImpersonateSettings {
     pub tls_builder_func: Arc<dyn Fn(bool) -> SslConnectorBuilder + Send + Sync>,
     pub http2: Http2Data,
+    pub application_settings: Option<ApplicationSettings>,
+    // Whether a resumed TLS 1.3 connection sends its first request as 0-RTT
+    // early data. None of the browsers we impersonate do it over TCP yet.
+    pub early_data: bool,
     pub headers: HeaderMap,
     pub gzip: bool,
     pub brotli: bool,
@@ -30,3 +34,124 @@ pub(crate) struct Http2Data {
     pub header_table_size: Option<u32>,
     pub enable_push: Option<bool>,
 }
+
+impl Http2Data {
+    /// Serializes the settings as an HTTP/2 SETTINGS frame, in the same
+    /// order Chrome writes them on the wire.
+    pub(crate) fn settings_frame(&self) -> Vec<u8> {
+        const SETTINGS_FRAME_TYPE: u8 = 0x4;
+
+        let settings = [
+            (0x1, self.header_table_size),
+            (0x2, self.enable_push.map(u32::from)),
+            (0x3, self.max_concurrent_streams),
+            (0x4, self.initial_stream_window_size),
+            (0x6, self.max_header_list_size),
+        ];
+
+        let mut payload = Vec::new();
+        for (id, value) in settings {
+            if let Some(value) = value {
+                payload.extend_from_slice(&u16::to_be_bytes(id));
+                payload.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+
+        let mut frame = Vec::with_capacity(9 + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+        frame.push(SETTINGS_FRAME_TYPE);
+        // flags
+        frame.push(0);
+        // stream id
+        frame.extend_from_slice(&0u32.to_be_bytes());
+        frame.extend_from_slice(&payload);
+        frame
+    }
+}
+
+/// The ALPS (TLS `application_settings`) extension sent in the ClientHello.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ApplicationSettings {
+    /// ALPN protocols the settings are offered for.
+    pub protocols: &'static [&'static str],
+    /// Settings sent for `h2`, other protocols are offered with an empty payload.
+    pub payload: Vec<u8>,
+    /// The extension codepoint, `LEGACY_CODEPOINT` or `NEW_CODEPOINT`.
+    pub codepoint: u16,
+}
+
+impl ApplicationSettings {
+    /// The codepoint Chrome sent ALPS on before Chrome 131.
+    pub(crate) const LEGACY_CODEPOINT: u16 = 17513;
+    /// The codepoint Chrome sends ALPS on since Chrome 131.
+    pub(crate) const NEW_CODEPOINT: u16 = 17613;
+
+    /// ALPS for `h2` carrying the profile's HTTP/2 settings, on `codepoint`.
+    pub(crate) fn h2(http2: &Http2Data, codepoint: u16) -> ApplicationSettings {
+        ApplicationSettings {
+            protocols: &["h2"],
+            payload: http2.settings_frame(),
+            codepoint,
+        }
+    }
+
+    /// Fails unless the linked BoringSSL can send ALPS on the codepoint.
+    pub(crate) fn check(&self) -> crate::Result<()> {
+        // The bundled BoringSSL only implements the legacy codepoint.
+        if self.codepoint == Self::LEGACY_CODEPOINT {
+            Ok(())
+        } else {
+            Err(crate::error::builder(format!(
+                "the TLS backend can't send ALPS on codepoint {}",
+                self.codepoint
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ApplicationSettings, Http2Data};
+
+    #[test]
+    fn settings_frame_chrome() {
+        let http2 = Http2Data {
+            initial_stream_window_size: Some(6291456),
+            initial_connection_window_size: Some(15728640),
+            max_concurrent_streams: None,
+            max_header_list_size: Some(262144),
+            header_table_size: Some(65536),
+            enable_push: Some(false),
+        };
+
+        assert_eq!(
+            http2.settings_frame(),
+            [
+                0, 0, 24, 4, 0, 0, 0, 0, 0, // header
+                0, 1, 0, 1, 0, 0, // HEADER_TABLE_SIZE
+                0, 2, 0, 0, 0, 0, // ENABLE_PUSH
+                0, 4, 0, 96, 0, 0, // INITIAL_WINDOW_SIZE
+                0, 6, 0, 4, 0, 0, // MAX_HEADER_LIST_SIZE
+            ]
+        );
+    }
+
+    #[test]
+    fn application_settings_codepoint() {
+        let http2 = Http2Data {
+            initial_stream_window_size: None,
+            initial_connection_window_size: None,
+            max_concurrent_streams: None,
+            max_header_list_size: None,
+            header_table_size: None,
+            enable_push: None,
+        };
+
+        let legacy = ApplicationSettings::h2(&http2, ApplicationSettings::LEGACY_CODEPOINT);
+        assert!(legacy.check().is_ok());
+        let new = ApplicationSettings::h2(&http2, ApplicationSettings::NEW_CODEPOINT);
+        let err = new.check().unwrap_err();
+        assert!(err.is_builder());
+        assert!(err.to_string().contains("17613"), "{}", err);
+    }
+}
@@ -0,0 +1,144 @@
+//! Profiles synthesized for Chrome majors without a hand-written module
+
+use http::{
+    header::{ACCEPT, ACCEPT_ENCODING, ACCEPT_LANGUAGE, UPGRADE_INSECURE_REQUESTS, USER_AGENT},
+    HeaderMap, HeaderValue,
+};
+use std::sync::Arc;
+
+use crate::impersonate::profile::ChromeVersion;
+use crate::impersonate::{ApplicationSettings, Http2Data, ImpersonateSettings};
+
+use super::{configure_curves_ssl, create_ssl_connector};
+
+pub(crate) fn get_settings(version: ChromeVersion, headers: HeaderMap) -> ImpersonateSettings {
+    let major = version.major();
+
+    let http2 = Http2Data {
+        initial_stream_window_size: Some(6291456),
+        initial_connection_window_size: Some(15728640),
+        // Chrome 117 stopped sending SETTINGS_MAX_CONCURRENT_STREAMS
+        max_concurrent_streams: if major < 117 { Some(1000) } else { None },
+        max_header_list_size: Some(262144),
+        header_table_size: Some(65536),
+        enable_push: Some(false),
+    };
+
+    ImpersonateSettings {
+        tls_builder_func: Arc::new(move |h2| {
+            let mut builder = create_ssl_connector(h2);
+            // X25519Kyber768 is on by default since Chrome 124
+            if major >= 124 {
+                configure_curves_ssl(&mut builder).expect("Failed to configure curves SSL");
+            }
+            builder
+        }),
+        application_settings: Some(ApplicationSettings::h2(
+            &http2,
+            // Chrome 131 moved ALPS to a new codepoint
+            if major >= 131 {
+                ApplicationSettings::NEW_CODEPOINT
+            } else {
+                ApplicationSettings::LEGACY_CODEPOINT
+            },
+        )),
+        early_data: true,
+        http2,
+        headers: create_headers(major, headers),
+        gzip: true,
+        brotli: true,
+    }
+}
+
+fn create_headers(major: u16, mut headers: HeaderMap) -> HeaderMap {
+    headers.insert("sec-ch-ua", brand_list(major).parse().unwrap());
+    headers.insert("sec-ch-ua-mobile", HeaderValue::from_static("?0"));
+    headers.insert(
+        "sec-ch-ua-platform",
+        HeaderValue::from_static("\"Windows\""),
+    );
+    headers.insert(UPGRADE_INSECURE_REQUESTS, HeaderValue::from_static("1"));
+    headers.insert(
+        USER_AGENT,
+        format!(
+            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/{}.0.0.0 Safari/537.36",
+            major
+        )
+        .parse()
+        .unwrap(),
+    );
+    headers.insert(ACCEPT, HeaderValue::from_static("text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7"));
+    headers.insert("sec-fetch-site", HeaderValue::from_static("none"));
+    headers.insert("sec-fetch-mode", HeaderValue::from_static("navigate"));
+    headers.insert("sec-fetch-user", HeaderValue::from_static("?1"));
+    headers.insert("sec-fetch-dest", HeaderValue::from_static("document"));
+    headers.insert(
+        ACCEPT_ENCODING,
+        // zstd is advertised since Chrome 123
+        HeaderValue::from_static(if major >= 123 {
+            "gzip, deflate, br, zstd"
+        } else {
+            "gzip, deflate, br"
+        }),
+    );
+    headers.insert(ACCEPT_LANGUAGE, HeaderValue::from_static("en-US,en;q=0.9"));
+
+    headers
+}
+
+/// Builds the `sec-ch-ua` brand list the way Chromium's
+/// `GenerateBrandVersionList` does, seeded with the major version.
+fn brand_list(major: u16) -> String {
+    const GREASE_CHARS: [char; 11] = [' ', '(', ':', '-', '.', '/', ')', ';', '=', '?', '_'];
+    const GREASE_VERSIONS: [&str; 3] = ["8", "99", "24"];
+    const ORDERS: [[usize; 3]; 6] = [
+        [0, 1, 2],
+        [0, 2, 1],
+        [1, 0, 2],
+        [1, 2, 0],
+        [2, 0, 1],
+        [2, 1, 0],
+    ];
+
+    let seed = major as usize;
+    let grease = format!(
+        "\"Not{}A{}Brand\";v=\"{}\"",
+        GREASE_CHARS[seed % GREASE_CHARS.len()],
+        GREASE_CHARS[(seed + 1) % GREASE_CHARS.len()],
+        GREASE_VERSIONS[seed % GREASE_VERSIONS.len()],
+    );
+    let chromium = format!("\"Chromium\";v=\"{}\"", major);
+    let chrome = format!("\"Google Chrome\";v=\"{}\"", major);
+
+    let order = ORDERS[seed % ORDERS.len()];
+    let mut brands = [String::new(), String::new(), String::new()];
+    brands[order[0]] = grease;
+    brands[order[1]] = chromium;
+    brands[order[2]] = chrome;
+    brands.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::brand_list;
+
+    #[test]
+    fn brand_list_matches_captured() {
+        assert_eq!(
+            brand_list(116),
+            r#""Chromium";v="116", "Not)A;Brand";v="24", "Google Chrome";v="116""#
+        );
+        assert_eq!(
+            brand_list(117),
+            r#""Google Chrome";v="117", "Not;A=Brand";v="8", "Chromium";v="117""#
+        );
+        assert_eq!(
+            brand_list(124),
+            r#""Chromium";v="124", "Google Chrome";v="124", "Not-A.Brand";v="99""#
+        );
+        assert_eq!(
+            brand_list(126),
+            r#""Not/A)Brand";v="8", "Chromium";v="126", "Google Chrome";v="126""#
+        );
+    }
+}
@@ -2,6 +2,7 @@ use boring::ssl::{
     CertCompressionAlgorithm, SslConnector, SslConnectorBuilder, SslCurve, SslMethod, SslVersion,
 };
 
+pub mod generic;
 pub mod v100;
 pub mod v101;
 pub mod v104;
@@ -6,21 +6,28 @@ use http::{
 };
 use std::sync::Arc;
 
-use crate::impersonate::{Http2Data, ImpersonateSettings};
+use crate::impersonate::{ApplicationSettings, Http2Data, ImpersonateSettings};
 
 use super::create_ssl_connector;
 
 pub(crate) fn get_settings(headers: HeaderMap) -> ImpersonateSettings {
+    let http2 = Http2Data {
+        initial_stream_window_size: Some(6291456),
+        initial_connection_window_size: Some(15728640),
+        max_concurrent_streams: Some(1000),
+        max_header_list_size: Some(262144),
+        header_table_size: Some(65536),
+        enable_push: Some(false),
+    };
+
     ImpersonateSettings {
         tls_builder_func: Arc::new(create_ssl_connector),
-        http2: Http2Data {
-            initial_stream_window_size: Some(6291456),
-            initial_connection_window_size: Some(15728640),
-            max_concurrent_streams: Some(1000),
-            max_header_list_size: Some(262144),
-            header_table_size: Some(65536),
-            enable_push: Some(false),
-        },
+        application_settings: Some(ApplicationSettings::h2(
+            &http2,
+            ApplicationSettings::LEGACY_CODEPOINT,
+        )),
+        early_data: true,
+        http2,
         headers: create_headers(headers),
         gzip: true,
         brotli: true,
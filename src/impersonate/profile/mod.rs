@@ -22,6 +22,8 @@ pub(crate) fn configure_impersonate(ver: Impersonate, builder: ClientBuilder) ->
         .http2_max_header_list_size(settings.http2.max_header_list_size)
         .http2_header_table_size(settings.http2.header_table_size)
         .http2_enable_push(settings.http2.enable_push)
+        .application_settings(settings.application_settings)
+        .profile_early_data(settings.early_data)
         .replace_default_headers(settings.headers)
         .brotli(settings.brotli)
         .gzip(settings.gzip)
@@ -36,19 +38,23 @@ fn create_profile_headers(profile: ClientProfile) -> HeaderMap {
 }
 
 macro_rules! impersonate_match {
-    ($ver:expr, $headers:expr, $($variant:pat => $path:path),+) => {
+    ($ver:expr, $headers:expr, $($variant:pat => $path:path),+ $(; $($other:pat => $expr:expr),+)?) => {
         match $ver {
             $(
                 $variant => $path($headers),
             )+
+            $($(
+                $other => $expr,
+            )+)?
         }
     }
 }
 
-fn get_config_from_ver(ver: Impersonate) -> ImpersonateSettings {
+pub(crate) fn get_config_from_ver(ver: Impersonate) -> ImpersonateSettings {
+    let headers = create_profile_headers(ver.profile());
     impersonate_match!(
         ver,
-        create_profile_headers(ver.profile()),
+        headers,
         Impersonate::Chrome99 => chrome::v99::get_settings,
         Impersonate::Chrome100 => chrome::v100::get_settings,
         Impersonate::Chrome101 => chrome::v101::get_settings,
@@ -86,13 +92,18 @@ fn get_config_from_ver(ver: Impersonate) -> ImpersonateSettings {
         Impersonate::OkHttp5 => okhttp::okhttp5::get_settings,
         Impersonate::Edge99 => edge::edge99::get_settings,
         Impersonate::Edge101 => edge::edge101::get_settings,
-        Impersonate::Edge122 => edge::edge122::get_settings
+        Impersonate::Edge122 => edge::edge122::get_settings;
+        Impersonate::Chrome(version) => chrome::generic::get_settings(version, headers)
     )
 }
 
 /// Defines the Chrome version to mimic when setting up a builder
+///
+/// New browser versions are added as variants, so matching on it needs a
+/// wildcard arm.
 #[allow(missing_docs)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub enum Impersonate {
     Chrome99,
     Chrome100,
@@ -132,6 +143,27 @@ pub enum Impersonate {
     Edge99,
     Edge101,
     Edge122,
+    /// A Chrome major without a hand-written profile, see [`Impersonate::chrome`].
+    Chrome(ChromeVersion),
+}
+
+/// A Chrome major version without a hand-written profile, whose profile is
+/// synthesized from its release family.
+///
+/// Obtained through [`Impersonate::chrome`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ChromeVersion(u16);
+
+impl ChromeVersion {
+    /// The first major using the current `sec-ch-ua` GREASE algorithm.
+    const MIN: u16 = 116;
+    /// Chrome 131 moved to X25519MLKEM768, which the bundled BoringSSL lacks.
+    const MAX: u16 = 130;
+
+    /// Get the major version.
+    pub fn major(&self) -> u16 {
+        self.0
+    }
 }
 
 /// Impersonate version from string
@@ -178,7 +210,13 @@ impl FromStr for Impersonate {
             "edge_99" => Ok(Impersonate::Edge99),
             "edge_101" => Ok(Impersonate::Edge101),
             "edge_122" => Ok(Impersonate::Edge122),
-            _ => Err("Invalid Impersonate version"),
+            // Also takes what `Display` writes, like `chrome125`.
+            _ => s
+                .strip_prefix("chrome_")
+                .or_else(|| s.strip_prefix("chrome"))
+                .and_then(|major| major.parse().ok())
+                .and_then(|major| Impersonate::chrome(major).ok())
+                .ok_or("Invalid Impersonate version"),
         }
     }
 }
@@ -188,6 +226,7 @@ impl std::fmt::Display for Impersonate {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use Impersonate as I;
         let name = match self {
+            I::Chrome(version) => return write!(f, "chrome{}", version.major()),
             I::Chrome99 => "chrome99",
             I::Chrome100 => "chrome100",
             I::Chrome101 => "chrome101",
@@ -233,6 +272,48 @@ impl std::fmt::Display for Impersonate {
 }
 
 impl Impersonate {
+    /// Impersonate an arbitrary Chrome major version.
+    ///
+    /// Majors with a hand-written profile resolve to it, like `Chrome120`
+    /// for 120. The others from 116 up to 130 are synthesized from the TLS
+    /// and HTTP/2 family of their release (Kyber key share, ALPS), with a
+    /// matching user agent and `sec-ch-ua` GREASE brand list. Like the
+    /// hand-written profiles, they only permute TLS extensions with
+    /// [`ClientBuilder::permute_extensions`](crate::ClientBuilder::permute_extensions).
+    ///
+    /// # Errors
+    ///
+    /// Fails for versions whose fingerprint can't be reproduced faithfully.
+    pub fn chrome(major: u16) -> crate::Result<Impersonate> {
+        match major {
+            99 => Ok(Impersonate::Chrome99),
+            100 => Ok(Impersonate::Chrome100),
+            101 => Ok(Impersonate::Chrome101),
+            104 => Ok(Impersonate::Chrome104),
+            105 => Ok(Impersonate::Chrome105),
+            106 => Ok(Impersonate::Chrome106),
+            107 => Ok(Impersonate::Chrome107),
+            108 => Ok(Impersonate::Chrome108),
+            109 => Ok(Impersonate::Chrome109),
+            114 => Ok(Impersonate::Chrome114),
+            116 => Ok(Impersonate::Chrome116),
+            117 => Ok(Impersonate::Chrome117),
+            118 => Ok(Impersonate::Chrome118),
+            119 => Ok(Impersonate::Chrome119),
+            120 => Ok(Impersonate::Chrome120),
+            123 => Ok(Impersonate::Chrome123),
+            124 => Ok(Impersonate::Chrome124),
+            126 => Ok(Impersonate::Chrome126),
+            _ if (ChromeVersion::MIN..=ChromeVersion::MAX).contains(&major) => {
+                Ok(Impersonate::Chrome(ChromeVersion(major)))
+            }
+            _ => Err(crate::error::builder(format!(
+                "cannot impersonate Chrome {}",
+                major
+            ))),
+        }
+    }
+
     /// Get the client profile for the given impersonate version
     pub fn profile(&self) -> ClientProfile {
         match self {
@@ -253,7 +334,8 @@ impl Impersonate {
             | Impersonate::Chrome120
             | Impersonate::Chrome123
             | Impersonate::Chrome124
-            | Impersonate::Chrome126 => ClientProfile::Chrome,
+            | Impersonate::Chrome126
+            | Impersonate::Chrome(_) => ClientProfile::Chrome,
 
             Impersonate::SafariIos17_2
             | Impersonate::SafariIos16_5
@@ -282,7 +364,7 @@ impl Impersonate {
 }
 
 /// impersonate client profile
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ClientProfile {
     /// Chrome impersonate client profile
     Chrome,
@@ -307,3 +389,31 @@ impl Into<AgentProfile> for ClientProfile {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Impersonate;
+
+    #[test]
+    fn chrome_picks_hand_written_profiles() {
+        assert_eq!(Impersonate::chrome(116).unwrap(), Impersonate::Chrome116);
+        assert_eq!(Impersonate::chrome(120).unwrap(), Impersonate::Chrome120);
+        assert_eq!(Impersonate::chrome(126).unwrap(), Impersonate::Chrome126);
+        assert!(matches!(
+            Impersonate::chrome(125).unwrap(),
+            Impersonate::Chrome(version) if version.major() == 125
+        ));
+        assert!(Impersonate::chrome(115).is_err());
+        assert!(Impersonate::chrome(131).is_err());
+    }
+
+    #[test]
+    fn chrome_round_trips_through_strings() {
+        for major in (99..=130).filter(|&major| Impersonate::chrome(major).is_ok()) {
+            let ver = Impersonate::chrome(major).unwrap();
+            assert_eq!(ver.to_string(), format!("chrome{}", major));
+            assert_eq!(ver.to_string().parse::<Impersonate>(), Ok(ver));
+            assert_eq!(format!("chrome_{}", major).parse::<Impersonate>(), Ok(ver));
+        }
+    }
+}
@@ -20,6 +20,8 @@ pub(crate) fn get_settings(headers: HeaderMap) -> ImpersonateSettings {
             header_table_size: Some(65536),
             enable_push: None,
         },
+        application_settings: None,
+        early_data: false,
         headers: create_headers(headers),
         gzip: true,
         brotli: true,
@@ -141,6 +141,9 @@
 //! i.e. `let proxy = reqwest::Proxy::http("https://secure.example")?;`
 //! or disabled by calling `ClientBuilder::no_proxy()`.
 //!
+//! Proxy auto-config (PAC) scripts are supported with [`Proxy::pac`], see the
+//! [`pac`] module.
+//!
 //! `socks` feature is required if you have configured socks proxy like this:
 //!
 //! ```bash
@@ -194,9 +197,18 @@
 //! - **json**: Provides serialization and deserialization for JSON bodies.
 //! - **multipart**: Provides functionality for multipart forms.
 //! - **stream**: Adds support for `futures::Stream`.
-//! - **socks**: Provides SOCKS5 proxy support.
+//! - **socks**: Provides SOCKS5, SOCKS4 and SOCKS4a proxy support.
+//! - **pac**: Provides `Proxy::pac`, which runs proxy auto-config scripts
+//!   with the Boa JavaScript engine.
+//! - **digest-auth**: Answers HTTP Digest challenges of servers and proxies.
 //! - **hickory-dns**: Enables a hickory-dns async resolver instead of default
 //!   threadpool using `getaddrinfo`.
+//! - **hickory-dns-over-tls**: Lets the hickory-dns resolver use DNS-over-TLS
+//!   nameservers.
+//! - **hickory-dns-over-quic**: Lets the hickory-dns resolver use DNS-over-QUIC
+//!   nameservers.
+//! - **hickory-dnssec**: Lets the hickory-dns resolver validate answers with
+//!   DNSSEC.
 //!
 //! ## Unstable Features
 //!
@@ -347,7 +359,9 @@ if_hyper! {
     };
     #[cfg(feature = "websocket")]
     pub use self::async_impl::websocket::{UpgradedRequestBuilder, Message, WebSocket, UpgradeResponse};
+    pub use self::connection_info::{ConnectionInfo, Http2Settings};
     pub use self::proxy::{Proxy,NoProxy};
+    pub use self::proxy_pool::{ProxyPool, ProxyPoolBuilder, ProxyStats, ProxyStrategy};
     #[cfg(feature = "__tls")]
     // Re-exports, to be removed in a future release
     pub use tls::{Certificate, Identity};
@@ -359,10 +373,15 @@ if_hyper! {
     #[cfg(feature = "blocking")]
     pub mod blocking;
     mod connect;
+    mod connection_info;
     #[cfg(feature = "cookies")]
     pub mod cookie;
+    #[cfg(feature = "digest-auth")]
+    mod digest;
     pub mod dns;
+    pub mod pac;
     mod proxy;
+    mod proxy_pool;
     pub mod redirect;
     #[cfg(feature = "__tls")]
     pub mod tls;
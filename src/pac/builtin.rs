@@ -0,0 +1,402 @@
+//! PAC scripts run by the Boa JavaScript engine
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+
+use boa_engine::{js_string, Context, JsString, JsValue, NativeFunction, Source};
+
+use super::{PacEvaluator, PacResolver, PacScript};
+use crate::error::BoxError;
+
+/// How many times a loop may run to answer once.
+const MAX_LOOP_ITERATIONS: u64 = 1_000_000;
+/// How deep calls may nest when running.
+const MAX_RECURSION: usize = 256;
+/// How deep brackets may nest in the source.
+const MAX_NESTING: usize = 128;
+/// The stack of the threads scripts run on. Boa's parser recurses for each
+/// nested bracket and operator, such as the terms of a long `||` chain,
+/// which the stacks of the blocking threads are too small for.
+const ENGINE_STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// The PAC functions written in JavaScript.
+const UTILS: &str = include_str!("utils.js");
+
+/// The [`PacEvaluator`] used by [`Proxy::pac`], which runs scripts with the
+/// [Boa](https://boajs.dev) JavaScript engine.
+///
+/// Besides the language, scripts have the PAC functions: `isPlainHostName`,
+/// `dnsDomainIs`, `localHostOrDomainIs`, `isResolvable`, `isInNet`,
+/// `dnsResolve`, `convert_addr`, `myIpAddress`, `dnsDomainLevels`,
+/// `shExpMatch`, `weekdayRange`, `dateRange`, `timeRange` and `alert`. Hosts
+/// are resolved to IPv4 addresses by the resolver of the client.
+///
+/// Each answer runs the script anew, so it can't keep anything between
+/// them. Loops and recursion are bounded, and a script going past the
+/// bounds fails to answer, in which case requests fail.
+///
+/// [`Proxy::pac`]: crate::Proxy::pac
+#[derive(Clone, Debug, Default)]
+pub struct BuiltinEvaluator {
+    _priv: (),
+}
+
+impl BuiltinEvaluator {
+    /// Creates the built-in evaluator.
+    pub fn new() -> BuiltinEvaluator {
+        BuiltinEvaluator { _priv: () }
+    }
+}
+
+impl PacEvaluator for BuiltinEvaluator {
+    fn load(&self, script: &str) -> Result<Box<dyn PacScript>, BoxError> {
+        let script = BoaScript {
+            source: Arc::from(script.trim_start_matches('\u{feff}')),
+        };
+        if nesting(&script.source) > MAX_NESTING {
+            return Err("the PAC script is nested too deeply".into());
+        }
+        // Running it once catches syntax errors, and those of its top level.
+        on_engine_thread(|| {
+            let mut context = script.context(None)?;
+            match find_proxy_for_url(&mut context) {
+                Some(_) => Ok(()),
+                None => Err("the PAC script has no FindProxyForURL function".into()),
+            }
+        })?;
+        Ok(Box::new(script))
+    }
+}
+
+struct BoaScript {
+    source: Arc<str>,
+}
+
+impl BoaScript {
+    /// Returns a context the script has run in, with the PAC functions.
+    fn context(&self, resolver: Option<&PacResolver>) -> Result<Context, BoxError> {
+        let mut context = Context::default();
+        let limits = context.runtime_limits_mut();
+        limits.set_loop_iteration_limit(MAX_LOOP_ITERATIONS);
+        limits.set_recursion_limit(MAX_RECURSION);
+
+        let resolver = resolver.cloned().map(Arc::new);
+        // SAFETY: the closure only captures an `Arc` of the resolver, which
+        // holds nothing the garbage collector traces.
+        let dns_resolve = unsafe {
+            NativeFunction::from_closure(move |_, args, context| {
+                let host = match args.first() {
+                    Some(host) => host.to_string(context)?.to_std_string_escaped(),
+                    None => return Ok(JsValue::null()),
+                };
+                let ip = resolver.as_ref().and_then(|resolver| resolve_v4(resolver, &host));
+                Ok(ip.map_or(JsValue::null(), |ip| js_str(&ip.to_string()).into()))
+            })
+        };
+        let my_ip_address = NativeFunction::from_fn_ptr(|_, _, _| {
+            Ok(js_str(&my_ip_address().to_string()).into())
+        });
+        let alert = NativeFunction::from_fn_ptr(|_, args, context| {
+            if let Some(message) = args.first() {
+                let message = message.to_string(context)?.to_std_string_escaped();
+                log::debug!("PAC script alert: {}", message);
+            }
+            Ok(JsValue::undefined())
+        });
+
+        let run = |context: &mut Context| -> boa_engine::JsResult<()> {
+            context.register_global_builtin_callable(js_string!("dnsResolve"), 1, dns_resolve)?;
+            context.register_global_builtin_callable(
+                js_string!("myIpAddress"),
+                0,
+                my_ip_address,
+            )?;
+            context.register_global_builtin_callable(js_string!("alert"), 1, alert)?;
+            context.eval(Source::from_bytes(UTILS))?;
+            context.eval(Source::from_bytes(self.source.as_bytes()))?;
+            Ok(())
+        };
+        run(&mut context).map_err(|e| format!("PAC script error: {}", e))?;
+        Ok(context)
+    }
+}
+
+impl PacScript for BoaScript {
+    fn find_proxy_for_url(
+        &self,
+        url: &str,
+        host: &str,
+        resolver: &PacResolver,
+    ) -> Result<String, BoxError> {
+        on_engine_thread(|| {
+            let mut context = self.context(Some(resolver))?;
+            let function = find_proxy_for_url(&mut context)
+                .ok_or("the PAC script has no FindProxyForURL function")?;
+            let answer = function
+                .call(
+                    &JsValue::undefined(),
+                    &[js_str(url).into(), js_str(host).into()],
+                    &mut context,
+                )
+                .map_err(|e| format!("PAC script error: {}", e))?;
+            match answer.as_string() {
+                Some(answer) => Ok(answer.to_std_string_escaped()),
+                None => Err(format!("FindProxyForURL returned {}", answer.display()).into()),
+            }
+        })
+    }
+}
+
+/// Runs `f` on a thread with a stack of `ENGINE_STACK_SIZE`.
+fn on_engine_thread<T, F>(f: F) -> Result<T, BoxError>
+where
+    F: FnOnce() -> Result<T, BoxError> + Send,
+    T: Send,
+{
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .name("pac".into())
+            .stack_size(ENGINE_STACK_SIZE)
+            .spawn_scoped(scope, f)?
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+/// Returns the script's `FindProxyForURL`, if it's a function.
+fn find_proxy_for_url(context: &mut Context) -> Option<boa_engine::JsObject> {
+    let global = context.global_object();
+    let function = global
+        .get(js_string!("FindProxyForURL"), context)
+        .ok()?;
+    function.as_callable().cloned()
+}
+
+fn js_str(s: &str) -> JsString {
+    JsString::from(s)
+}
+
+/// Returns how deep brackets nest in `source`, outside of strings and
+/// comments.
+fn nesting(source: &str) -> usize {
+    let (mut depth, mut max) = (0usize, 0);
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '[' | '{' => {
+                depth += 1;
+                max = max.max(depth);
+            }
+            ')' | ']' | '}' => depth = depth.saturating_sub(1),
+            '"' | '\'' | '`' => {
+                while let Some(s) = chars.next() {
+                    match s {
+                        '\\' => {
+                            chars.next();
+                        }
+                        s if s == c => break,
+                        _ => {}
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                chars.by_ref().find(|&c| c == '\n');
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = '\0';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            _ => {}
+        }
+    }
+    max
+}
+
+/// Returns the first IPv4 address of `host`, as PAC scripts only know of
+/// those.
+fn resolve_v4(resolver: &PacResolver, host: &str) -> Option<Ipv4Addr> {
+    if let Ok(ip) = host.parse() {
+        return Some(ip);
+    }
+    if host.is_empty() {
+        return None;
+    }
+    match resolver.resolve(host) {
+        Ok(addrs) => addrs.into_iter().find_map(|ip| match ip {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None,
+        }),
+        Err(e) => {
+            log::debug!("PAC script couldn't resolve {}: {}", host, e);
+            None
+        }
+    }
+}
+
+/// Returns the address of the interface of the default route.
+fn my_ip_address() -> Ipv4Addr {
+    // Connecting a UDP socket sends nothing, it only picks the interface.
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).and_then(|socket| {
+        socket
+            .connect((Ipv4Addr::new(198, 51, 100, 1), 53))
+            .map(|_| socket)
+    });
+    match socket.and_then(|socket| socket.local_addr()) {
+        Ok(SocketAddr::V4(addr)) if !addr.ip().is_unspecified() => *addr.ip(),
+        _ => Ipv4Addr::LOCALHOST,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{Addrs, DynResolver, Name, Resolve, Resolving};
+
+    /// Resolves `*.corp.test` to 10.0.0.1, and nothing else.
+    struct Corp;
+
+    impl Resolve for Corp {
+        fn resolve(&self, name: Name) -> Resolving {
+            let result = if name.as_str().ends_with(".corp.test") {
+                let addrs: Addrs = Box::new(std::iter::once(SocketAddr::from(([10, 0, 0, 1], 0))));
+                Ok(addrs)
+            } else {
+                Err("no such host".into())
+            };
+            Box::pin(futures_util::future::ready(result))
+        }
+    }
+
+    fn answer(script: &str, url: &str, host: &str) -> Result<String, BoxError> {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let resolver = PacResolver {
+            resolver: DynResolver::new(Arc::new(Corp)),
+            runtime: runtime.handle().clone(),
+        };
+        let script = BuiltinEvaluator::new().load(script)?;
+        script.find_proxy_for_url(url, host, &resolver)
+    }
+
+    #[test]
+    fn finds_proxies() {
+        let script = r#"
+            var direct = ["a.test", "b.test"];
+
+            /* Intranet hosts go direct. */
+            function FindProxyForURL(url, host) {
+                if (isPlainHostName(host) || dnsDomainIs(host, ".direct.test")
+                    || isInNet(host, "10.0.0.0", "255.0.0.0"))
+                    return "DIRECT";
+                for (var i = 0; i < direct.length; i++) {
+                    if (host == direct[i]) return "DIRECT";
+                }
+                if (shExpMatch(url, "https://*.secure.test/*"))
+                    return "HTTPS secure.test:443";
+                return "PROXY proxy.test:8080; DIRECT";
+            }
+        "#;
+
+        let answer = |url, host| answer(script, url, host).unwrap();
+        assert_eq!(answer("http://intranet/", "intranet"), "DIRECT");
+        assert_eq!(answer("http://wiki.DIRECT.test/", "wiki.DIRECT.test"), "DIRECT");
+        assert_eq!(answer("http://10.1.2.3/", "10.1.2.3"), "DIRECT");
+        assert_eq!(answer("http://wiki.corp.test/", "wiki.corp.test"), "DIRECT");
+        assert_eq!(answer("http://b.test/", "b.test"), "DIRECT");
+        assert_eq!(
+            answer("https://www.secure.test/", "www.secure.test"),
+            "HTTPS secure.test:443"
+        );
+        assert_eq!(
+            answer("http://11.1.2.3/", "11.1.2.3"),
+            "PROXY proxy.test:8080; DIRECT"
+        );
+    }
+
+    #[test]
+    fn pac_functions() {
+        let script = r#"
+            function FindProxyForURL(url, host) {
+                return [
+                    dnsResolve("wiki.corp.test"),
+                    dnsResolve("www.example.test"),
+                    isResolvable(host),
+                    localHostOrDomainIs("www", "www.corp.test"),
+                    localHostOrDomainIs("www.corp.test", "www.corp.test"),
+                    localHostOrDomainIs("home", "www.corp.test"),
+                    dnsDomainLevels("www.corp.test"),
+                    convert_addr("104.16.41.2"),
+                    shExpMatch("http://a.test/people/ari/", "*/ari/*"),
+                    shExpMatch("ab.test", "?.test"),
+                    shExpMatch("a+b.test", "a+b.*"),
+                    /^\d+\.\d+\.\d+\.\d+$/.test(myIpAddress()),
+                    weekdayRange("SUN", "SAT"),
+                    weekdayRange("XYZ"),
+                    dateRange(1, 31),
+                    dateRange("JAN", "DEC", "GMT"),
+                    dateRange(1995, 1997),
+                    timeRange(0, 24),
+                    timeRange(0, 0, 0, 23, 59, 59, "GMT"),
+                ].join(" ");
+            }
+        "#;
+        assert_eq!(
+            answer(script, "http://wiki.corp.test/", "wiki.corp.test").unwrap(),
+            "10.0.0.1  true true true false 2 1745889538 true false true true \
+             true false true true false true true"
+        );
+    }
+
+    #[test]
+    fn rejects_scripts() {
+        let err = |script: &str| answer(script, "http://a.test/", "a.test").unwrap_err();
+
+        assert!(err("var x = 1;").to_string().contains("no FindProxyForURL"));
+        assert!(err("function FindProxyForURL(url, host) {")
+            .to_string()
+            .contains("SyntaxError"));
+        assert!(
+            err("function FindProxyForURL(url, host) { return proxyFor(host); }")
+                .to_string()
+                .contains("proxyFor is not defined")
+        );
+        assert!(
+            err("function FindProxyForURL(url, host) { while (true) {} }")
+                .to_string()
+                .contains("RuntimeLimit")
+        );
+        let e = err("function FindProxyForURL(url, host) { return FindProxyForURL(url, host); }");
+        assert!(e.to_string().contains("RuntimeLimit"), "{}", e);
+        assert!(err("function FindProxyForURL(url, host) { return 1; }")
+            .to_string()
+            .contains("returned 1"));
+        assert!(err(&format!(
+            "var x = {}1{}; function FindProxyForURL(url, host) {{ return 'DIRECT'; }}",
+            "(".repeat(1000),
+            ")".repeat(1000)
+        ))
+        .to_string()
+        .contains("nested"));
+    }
+
+    #[test]
+    fn long_chains() {
+        let script = format!(
+            "function FindProxyForURL(url, host) {{ return {}false ? 'PROXY a.test' : 'DIRECT'; }}",
+            "host == 'x' || ".repeat(2000)
+        );
+        assert_eq!(answer(&script, "http://a.test/", "a.test").unwrap(), "DIRECT");
+    }
+
+    #[test]
+    fn measures_nesting() {
+        assert_eq!(nesting("f(a[0], { b: 1 })"), 2);
+        assert_eq!(nesting("f('((', \"[[\", `{{`, '\\'(')"), 1);
+        assert_eq!(nesting("// (((\n/* [[[ */ f()"), 1);
+    }
+}
@@ -0,0 +1,477 @@
+//! Proxy auto-config
+//!
+//! [`Proxy::pac`] sends requests where a PAC script's `FindProxyForURL`
+//! says. The script is run by a [`PacEvaluator`]: the [`BuiltinEvaluator`]
+//! of the `pac` feature, or the one given to [`Proxy::pac_with_evaluator`].
+//!
+//! [`Proxy::pac`]: crate::Proxy::pac
+//! [`Proxy::pac_with_evaluator`]: crate::Proxy::pac_with_evaluator
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::future::{BoxFuture, FutureExt, Shared};
+use http::header::HeaderValue;
+use http::Uri;
+use once_cell::sync::OnceCell;
+use tokio::runtime::Handle;
+use tokio::time::Instant;
+use tower_service::Service;
+
+#[cfg(feature = "pac")]
+pub use self::builtin::BuiltinEvaluator;
+use crate::async_impl::client::WeakClient;
+use crate::dns::gai::GaiResolver;
+use crate::dns::resolve::DynResolver;
+use crate::dns::Name;
+use crate::error::BoxError;
+use crate::proxy::{IntoProxyScheme, ProxyScheme};
+use crate::{Client, Url};
+
+#[cfg(feature = "pac")]
+mod builtin;
+
+/// How long a fetched script is used before it's fetched again.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How long a proxy that failed to connect is tried after the others.
+const RETRY_AFTER: Duration = Duration::from_secs(5 * 60);
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Trait for running PAC scripts.
+///
+/// A script is loaded once, when its proxy is created if it was given
+/// inline, and each time it's fetched otherwise. The loaded script then
+/// finds the proxies of each new connection, on a thread that may block,
+/// such as to resolve host names.
+pub trait PacEvaluator: Send + Sync {
+    /// Loads the source of a PAC script.
+    fn load(&self, script: &str) -> Result<Box<dyn PacScript>, BoxError>;
+}
+
+/// A PAC script loaded by a [`PacEvaluator`].
+pub trait PacScript: Send + Sync {
+    /// Calls the script's `FindProxyForURL(url, host)`, returning its answer,
+    /// such as `"PROXY proxy.example:8080; DIRECT"`.
+    ///
+    /// The script's `dnsResolve` and the like should resolve hosts with
+    /// `resolver`.
+    fn find_proxy_for_url(
+        &self,
+        url: &str,
+        host: &str,
+        resolver: &PacResolver,
+    ) -> Result<String, BoxError>;
+}
+
+/// Resolves host names for a [`PacScript`], with the resolver of the client
+/// it's asked for.
+#[derive(Clone)]
+pub struct PacResolver {
+    resolver: DynResolver,
+    runtime: Handle,
+}
+
+impl PacResolver {
+    /// Returns the addresses of `host`, blocking until they're resolved.
+    ///
+    /// # Panics
+    ///
+    /// This panics if called from an asynchronous context, rather than from
+    /// the thread `find_proxy_for_url` is called on.
+    pub fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, BoxError> {
+        let name = Name::from_str(host)?;
+        let mut resolver = self.resolver.clone();
+        let addrs = self
+            .runtime
+            .block_on(async { tokio::time::timeout(RESOLVE_TIMEOUT, resolver.call(name)).await })
+            .map_err(|_| format!("timed out resolving {}", host))??;
+        Ok(addrs.map(|addr| addr.ip()).collect())
+    }
+}
+
+impl fmt::Debug for PacResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PacResolver").finish()
+    }
+}
+
+/// Where a PAC script sends a connection.
+#[derive(Clone, Debug)]
+pub(crate) enum Route {
+    Direct,
+    Proxy(ProxyScheme),
+}
+
+#[derive(Clone)]
+pub(crate) struct Pac {
+    inner: Arc<Inner>,
+    // This auth only applies to the proxies of the script, which have none.
+    auth: Option<HeaderValue>,
+    // Unless the proxy isn't in a client yet.
+    owner: Option<Owner>,
+}
+
+/// What a PAC proxy takes from the client it's in.
+#[derive(Clone)]
+pub(crate) struct Owner {
+    resolver: DynResolver,
+    // The client is built after its proxies.
+    client: Arc<OnceCell<WeakClient>>,
+}
+
+impl Owner {
+    pub(crate) fn new(resolver: DynResolver, client: Arc<OnceCell<WeakClient>>) -> Owner {
+        Owner { resolver, client }
+    }
+}
+
+struct Inner {
+    // Unless the script was given inline.
+    url: Option<Url>,
+    evaluator: Arc<dyn PacEvaluator>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    // The script, and when it was fetched, if it was.
+    script: Option<(Arc<dyn PacScript>, Option<Instant>)>,
+    fetch: Option<Fetch>,
+    // When the proxies that failed to connect last did, by their `Debug`.
+    failures: HashMap<String, Instant>,
+}
+
+type Fetch = Shared<BoxFuture<'static, Result<Arc<dyn PacScript>, Arc<str>>>>;
+
+impl Pac {
+    /// Creates the PAC proxy of the script at a `http`, `https` or `file`
+    /// URL, or of the script itself, which is loaded now.
+    pub(crate) fn new(url_or_script: &str, evaluator: Arc<dyn PacEvaluator>) -> crate::Result<Pac> {
+        let mut state = State::default();
+        let url = match Url::parse(url_or_script) {
+            Ok(url) if matches!(url.scheme(), "http" | "https" | "file") => Some(url),
+            _ => {
+                let script = evaluator
+                    .load(url_or_script)
+                    .map_err(crate::error::builder)?;
+                state.script = Some((script.into(), None));
+                None
+            }
+        };
+        Ok(Pac {
+            inner: Arc::new(Inner {
+                url,
+                evaluator,
+                state: Mutex::new(state),
+            }),
+            auth: None,
+            owner: None,
+        })
+    }
+
+    pub(crate) fn set_auth(&mut self, auth: HeaderValue) {
+        self.auth = Some(auth);
+    }
+
+    /// Makes the script resolve hosts, and be fetched, with the client of
+    /// `owner`.
+    pub(crate) fn set_owner(&mut self, owner: Owner) {
+        self.owner = Some(owner);
+    }
+
+    /// Asks the script where to connect for `dst`, in the order to try. The
+    /// proxies that recently failed to connect come last.
+    pub(crate) async fn routes(&self, dst: &Uri) -> Result<Vec<Route>, BoxError> {
+        let script = self.script().await?;
+        // Like browsers, the script isn't told the path and query.
+        let url = format!(
+            "{}://{}/",
+            dst.scheme_str().unwrap_or("http"),
+            dst.authority().map_or("", |a| a.as_str())
+        );
+        let host = dst.host().unwrap_or_default().to_owned();
+        let resolver = PacResolver {
+            resolver: match self.owner {
+                Some(ref owner) => owner.resolver.clone(),
+                None => DynResolver::new(Arc::new(GaiResolver::new())),
+            },
+            runtime: Handle::current(),
+        };
+        // SOCKS proxies are resolved as their routes are parsed.
+        let (answer, routes) = tokio::task::spawn_blocking(move || {
+            let answer = script.find_proxy_for_url(&url, &host, &resolver)?;
+            let routes = parse_routes(&answer);
+            Ok::<_, BoxError>((answer, routes))
+        })
+        .await??;
+        log::debug!("PAC script answered {:?} for {:?}", answer, dst);
+        if routes.is_empty() {
+            return Err(format!("no usable route in the PAC answer {:?}", answer).into());
+        }
+
+        let mut routes: Vec<Route> = routes
+            .into_iter()
+            .map(|route| match route {
+                Route::Proxy(scheme) => Route::Proxy(scheme.if_no_auth(&self.auth)),
+                Route::Direct => Route::Direct,
+            })
+            .collect();
+        let mut state = self.inner.state.lock().unwrap();
+        let now = Instant::now();
+        state
+            .failures
+            .retain(|_, failed| now.duration_since(*failed) < RETRY_AFTER);
+        // The sort is stable, so the script's order is kept otherwise.
+        routes.sort_by_key(|route| match route {
+            Route::Proxy(scheme) => state.failures.contains_key(&format!("{:?}", scheme)),
+            Route::Direct => false,
+        });
+        Ok(routes)
+    }
+
+    /// Records whether connecting by `route` worked.
+    pub(crate) fn report(&self, route: &Route, connected: bool) {
+        if let Route::Proxy(scheme) = route {
+            let key = format!("{:?}", scheme);
+            let mut state = self.inner.state.lock().unwrap();
+            if connected {
+                state.failures.remove(&key);
+            } else {
+                state.failures.insert(key, Instant::now());
+            }
+        }
+    }
+
+    /// Returns the loaded script, fetching it if it's missing, and in the
+    /// background if it's due for a refresh.
+    async fn script(&self) -> Result<Arc<dyn PacScript>, BoxError> {
+        let fetch = {
+            let mut state = self.inner.state.lock().unwrap();
+            let state = &mut *state;
+            match &state.script {
+                Some((script, None)) => return Ok(script.clone()),
+                Some((script, Some(fetched))) => {
+                    if fetched.elapsed() >= REFRESH_INTERVAL && state.fetch.is_none() {
+                        let fetch = Inner::fetch(&self.inner, self.client());
+                        state.fetch = Some(fetch.clone());
+                        tokio::spawn(fetch);
+                    }
+                    return Ok(script.clone());
+                }
+                None => {}
+            }
+            match &state.fetch {
+                Some(fetch) => fetch.clone(),
+                None => {
+                    let fetch = Inner::fetch(&self.inner, self.client());
+                    state.fetch = Some(fetch.clone());
+                    fetch
+                }
+            }
+        };
+        fetch.await.map_err(|e| e.to_string().into())
+    }
+
+    /// Returns the client the proxy is in, if it's still there.
+    fn client(&self) -> Option<Client> {
+        let owner = self.owner.as_ref()?;
+        owner.client.get()?.upgrade()
+    }
+}
+
+impl Inner {
+    fn fetch(this: &Arc<Inner>, client: Option<Client>) -> Fetch {
+        let this = this.clone();
+        async move {
+            let url = this
+                .url
+                .as_ref()
+                .expect("only scripts at a URL are fetched");
+            let result = this.download(url.clone(), client).await;
+            let mut state = this.state.lock().unwrap();
+            state.fetch = None;
+            match (result, &mut state.script) {
+                (Ok(script), _) => {
+                    state.script = Some((script.clone(), Some(Instant::now())));
+                    Ok(script)
+                }
+                // The script in use is kept until the next refresh.
+                (Err(e), Some((script, fetched))) => {
+                    log::warn!("couldn't refresh the PAC script {}: {}", url, e);
+                    *fetched = Some(Instant::now());
+                    Ok(script.clone())
+                }
+                (Err(e), None) => {
+                    Err(format!("couldn't fetch the PAC script {}: {}", url, e).into())
+                }
+            }
+        }
+        .boxed()
+        .shared()
+    }
+
+    /// Downloads and loads the script, with the settings of `client`, if
+    /// the proxy is in one.
+    async fn download(
+        &self,
+        url: Url,
+        client: Option<Client>,
+    ) -> Result<Arc<dyn PacScript>, BoxError> {
+        let source = if url.scheme() == "file" {
+            let path = url.to_file_path().map_err(|()| "not a file path")?;
+            tokio::task::spawn_blocking(move || std::fs::read_to_string(path)).await??
+        } else {
+            let client = match client {
+                Some(client) => client,
+                None => Client::builder().build()?,
+            };
+            // Not through any proxy, since they may depend on the script.
+            let res = client
+                .get(url)
+                .no_proxy()
+                .timeout(FETCH_TIMEOUT)
+                .send()
+                .await?
+                .error_for_status()?;
+            res.text().await?
+        };
+        let evaluator = self.evaluator.clone();
+        let script = tokio::task::spawn_blocking(move || evaluator.load(&source)).await??;
+        Ok(script.into())
+    }
+}
+
+impl PartialEq for Pac {
+    fn eq(&self, other: &Pac) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner) && self.auth == other.auth
+    }
+}
+
+impl fmt::Debug for Pac {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.inner.url {
+            Some(url) => f.debug_tuple("Pac").field(&url.as_str()).finish(),
+            None => f.write_str("Pac(<script>)"),
+        }
+    }
+}
+
+/// Maps an answer of `FindProxyForURL`, such as
+/// `"PROXY a.example:8080; SOCKS5 b.example:1080; DIRECT"`, onto routes,
+/// skipping the entries that can't be used.
+fn parse_routes(answer: &str) -> Vec<Route> {
+    answer
+        .split(';')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| match parse_route(entry) {
+            Ok(route) => Some(route),
+            Err(e) => {
+                log::debug!("skipping the PAC entry {:?}: {}", entry, e);
+                None
+            }
+        })
+        .collect()
+}
+
+fn parse_route(entry: &str) -> Result<Route, BoxError> {
+    let mut parts = entry.split_whitespace();
+    let kind = parts.next().unwrap_or_default().to_ascii_uppercase();
+    let host = match (kind.as_str(), parts.next(), parts.next()) {
+        ("DIRECT", None, None) => return Ok(Route::Direct),
+        (_, Some(host), None) => host,
+        _ => return Err("malformed entry".into()),
+    };
+    // As in browsers, `SOCKS` is SOCKS4, and SOCKS5 proxies resolve hosts.
+    let scheme = match kind.as_str() {
+        "PROXY" | "HTTP" => "http",
+        "HTTPS" => "https",
+        "SOCKS" | "SOCKS4" => "socks4",
+        "SOCKS5" => "socks5h",
+        _ => return Err("unknown proxy type".into()),
+    };
+    let scheme = format!("{}://{}", scheme, host).into_proxy_scheme()?;
+    Ok(Route::Proxy(scheme))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_routes() {
+        let routes = parse_routes(
+            "PROXY a.test:8080;https b.test; ; BOGUS c.test:1; DIRECT extra; PROXY; DIRECT",
+        );
+        let routes: Vec<String> = routes.iter().map(|r| format!("{:?}", r)).collect();
+        assert_eq!(
+            routes,
+            [
+                "Proxy(http://a.test:8080)",
+                "Proxy(https://b.test)",
+                "Direct"
+            ]
+        );
+    }
+
+    #[cfg(feature = "socks")]
+    #[test]
+    fn parses_socks_routes() {
+        let routes = parse_routes("SOCKS 127.0.0.1; SOCKS5 127.0.0.1:9050");
+        let routes: Vec<String> = routes.iter().map(|r| format!("{:?}", r)).collect();
+        assert_eq!(
+            routes,
+            [
+                "Proxy(socks4://127.0.0.1:1080)",
+                "Proxy(socks5h://127.0.0.1:9050)"
+            ]
+        );
+    }
+
+    /// Answers the script it's given, whatever it's asked.
+    struct Constant;
+
+    impl PacEvaluator for Constant {
+        fn load(&self, script: &str) -> Result<Box<dyn PacScript>, BoxError> {
+            Ok(Box::new(script.to_owned()))
+        }
+    }
+
+    impl PacScript for String {
+        fn find_proxy_for_url(
+            &self,
+            _: &str,
+            _: &str,
+            _: &PacResolver,
+        ) -> Result<String, BoxError> {
+            Ok(self.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn tries_failed_proxies_last() {
+        let pac = Pac::new("PROXY a.test:1; PROXY b.test:2; DIRECT", Arc::new(Constant)).unwrap();
+        let dst = "http://example.test".parse::<Uri>().unwrap();
+        let order = |routes: Vec<Route>| -> Vec<String> {
+            routes.iter().map(|r| format!("{:?}", r)).collect()
+        };
+
+        let routes = pac.routes(&dst).await.unwrap();
+        assert_eq!(
+            order(routes.clone()),
+            ["Proxy(http://a.test:1)", "Proxy(http://b.test:2)", "Direct"]
+        );
+        pac.report(&routes[0], false);
+        assert_eq!(
+            order(pac.routes(&dst).await.unwrap()),
+            ["Proxy(http://b.test:2)", "Direct", "Proxy(http://a.test:1)"]
+        );
+        pac.report(&routes[0], true);
+        assert_eq!(order(pac.routes(&dst).await.unwrap()), order(routes));
+    }
+}
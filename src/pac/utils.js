@@ -0,0 +1,154 @@
+// The PAC functions, but for `dnsResolve`, `myIpAddress` and `alert`, which
+// the evaluator defines before running this.
+
+function dnsDomainIs(host, domain) {
+    host = host.toLowerCase();
+    domain = domain.toLowerCase();
+    return host.length >= domain.length &&
+        host.substring(host.length - domain.length) == domain;
+}
+
+function dnsDomainLevels(host) {
+    return host.split(".").length - 1;
+}
+
+function isPlainHostName(host) {
+    return host.indexOf(".") == -1;
+}
+
+function localHostOrDomainIs(host, hostdom) {
+    host = host.toLowerCase();
+    hostdom = hostdom.toLowerCase();
+    return host == hostdom ||
+        (isPlainHostName(host) && hostdom.lastIndexOf(host + ".", 0) == 0);
+}
+
+function isResolvable(host) {
+    return dnsResolve(host) !== null;
+}
+
+function convert_addr(ipchars) {
+    var bytes = ipchars.split(".");
+    return ((bytes[0] & 0xff) << 24 | (bytes[1] & 0xff) << 16 |
+        (bytes[2] & 0xff) << 8 | (bytes[3] & 0xff)) >>> 0;
+}
+
+function isInNet(host, pattern, mask) {
+    var ip = /^\d+\.\d+\.\d+\.\d+$/.test(host) ? host : dnsResolve(host);
+    if (ip === null) {
+        return false;
+    }
+    mask = convert_addr(mask);
+    return ((convert_addr(ip) & mask) >>> 0) == ((convert_addr(pattern) & mask) >>> 0);
+}
+
+function shExpMatch(str, shexp) {
+    var pattern = shexp.replace(/[.+^${}()|[\]\\]/g, "\\$&")
+        .replace(/\*/g, "[\\s\\S]*")
+        .replace(/\?/g, "[\\s\\S]");
+    return new RegExp("^" + pattern + "$").test(str);
+}
+
+var PAC_WEEKDAYS = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+var PAC_MONTHS = ["JAN", "FEB", "MAR", "APR", "MAY", "JUN",
+    "JUL", "AUG", "SEP", "OCT", "NOV", "DEC"];
+
+// Takes the arguments of a range function, dropping the trailing "GMT" if
+// there's one, and returns the current time in the zone it asks for.
+function pacNow(args) {
+    var now = new Date();
+    var gmt = args.length > 0 && args[args.length - 1] == "GMT";
+    if (gmt) {
+        args.pop();
+    }
+    return {
+        day: gmt ? now.getUTCDay() : now.getDay(),
+        date: gmt ? now.getUTCDate() : now.getDate(),
+        month: gmt ? now.getUTCMonth() : now.getMonth(),
+        year: gmt ? now.getUTCFullYear() : now.getFullYear(),
+        seconds: gmt
+            ? now.getUTCHours() * 3600 + now.getUTCMinutes() * 60 + now.getUTCSeconds()
+            : now.getHours() * 3600 + now.getMinutes() * 60 + now.getSeconds()
+    };
+}
+
+// Whether `value` is between `from` and `to`, which may wrap around.
+function pacInRange(from, value, to) {
+    return from <= to ? from <= value && value <= to : value >= from || value <= to;
+}
+
+function weekdayRange() {
+    var args = Array.prototype.slice.call(arguments);
+    var now = pacNow(args);
+    var from = PAC_WEEKDAYS.indexOf(args[0]);
+    var to = args.length > 1 ? PAC_WEEKDAYS.indexOf(args[1]) : from;
+    return from != -1 && to != -1 && pacInRange(from, now.day, to);
+}
+
+function dateRange() {
+    var args = Array.prototype.slice.call(arguments);
+    var now = pacNow(args);
+    // Days, months and years are told apart by their values, and each
+    // bound gives the same of them.
+    var key = function (values) {
+        var key = 0;
+        for (var i = 0; i < values.length; i++) {
+            var month = PAC_MONTHS.indexOf(values[i]);
+            if (month != -1) {
+                key += month * 100;
+            } else if (values[i] > 31) {
+                key += values[i] * 10000;
+            } else {
+                key += Number(values[i]);
+            }
+        }
+        return key;
+    };
+    var fields = function (values) {
+        return values.map(function (value) {
+            return PAC_MONTHS.indexOf(value) != -1 ? "month" : value > 31 ? "year" : "date";
+        });
+    };
+    var current = function (fields) {
+        return key(fields.map(function (field) {
+            return field == "month" ? PAC_MONTHS[now.month] : now[field];
+        }));
+    };
+
+    if (args.length == 1) {
+        return key(args) == current(fields(args));
+    }
+    if (args.length == 0 || args.length % 2 != 0 || args.length > 6) {
+        return false;
+    }
+    var from = args.slice(0, args.length / 2);
+    var to = args.slice(args.length / 2);
+    if (fields(from).join() != fields(to).join()) {
+        return false;
+    }
+    var value = current(fields(from));
+    if (fields(from).indexOf("year") != -1) {
+        return key(from) <= value && value <= key(to);
+    }
+    return pacInRange(key(from), value, key(to));
+}
+
+function timeRange() {
+    var args = Array.prototype.slice.call(arguments);
+    var now = pacNow(args);
+    args = args.map(Number);
+    switch (args.length) {
+        case 1:
+            return Math.floor(now.seconds / 3600) == args[0];
+        case 2:
+            return pacInRange(args[0] * 3600, now.seconds, args[1] * 3600 - 1);
+        case 4:
+            return pacInRange(args[0] * 3600 + args[1] * 60, now.seconds,
+                args[2] * 3600 + args[3] * 60 - 1);
+        case 6:
+            return pacInRange(args[0] * 3600 + args[1] * 60 + args[2], now.seconds,
+                args[3] * 3600 + args[4] * 60 + args[5]);
+        default:
+            return false;
+    }
+}
@@ -0,0 +1,558 @@
+//! Spreading requests across several proxies
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::proxy::Dst;
+use crate::Proxy;
+
+/// Proxies a `Client` spreads its requests across, set with
+/// [`ClientBuilder::proxy_pool`].
+///
+/// Each request goes through one proxy of the pool, picked by its
+/// [`ProxyStrategy`] among those intercepting the URL, and keeps it through
+/// redirects. Requests whose URL no proxy of the pool intercepts use the
+/// client's proxies, and requests with their own ([`RequestBuilder::proxy`])
+/// don't use the pool.
+///
+/// A proxy failing `max_failures` times in a row is ejected from the pool
+/// for `ejection_time`. Failures are errors connecting to the proxy,
+/// refused `CONNECT` tunnels and SOCKS handshakes, requests timing out, and
+/// `407 Proxy Authentication Required` responses; any other response is a
+/// success. Errors of the origin's TLS handshake, certificate or pins
+/// aren't the proxy's. Once back, a proxy is ejected again on
+/// its first failure, until it succeeds. If every proxy intercepting a URL
+/// is ejected, they're used anyway rather than failing the request.
+///
+/// The pool is shared by its clones, so one kept aside gives the
+/// [`stats`] of the proxies as requests are sent.
+///
+/// ```
+/// # use chromimic as reqwest;
+/// use reqwest::{Proxy, ProxyPool, ProxyStrategy};
+///
+/// # fn run() -> Result<(), reqwest::Error> {
+/// let pool = ProxyPool::builder()
+///     .proxy(Proxy::all("http://proxy1.example:8080")?)
+///     .weighted_proxy(Proxy::all("http://proxy2.example:8080")?, 3)
+///     .strategy(ProxyStrategy::Weighted)
+///     .build()?;
+/// let client = reqwest::Client::builder()
+///     .proxy_pool(pool.clone())
+///     .build()?;
+///
+/// // later
+/// for stats in pool.stats() {
+///     println!("{:?}", stats);
+/// }
+/// # drop(client);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`ClientBuilder::proxy_pool`]: crate::ClientBuilder::proxy_pool
+/// [`RequestBuilder::proxy`]: crate::RequestBuilder::proxy
+/// [`stats`]: ProxyPool::stats
+#[derive(Clone)]
+pub struct ProxyPool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    // Each proxy alone in a list, as the clients routed through it are
+    // keyed by them.
+    proxies: Vec<Arc<Vec<Proxy>>>,
+    weights: Vec<u32>,
+    strategy: ProxyStrategy,
+    max_failures: u32,
+    ejection_time: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    health: Vec<Health>,
+    // The round-robin cursor.
+    next: usize,
+    // The proxy of each host or session, if sticky.
+    sticky: HashMap<String, usize>,
+}
+
+#[derive(Default)]
+struct Health {
+    stats: ProxyStats,
+    consecutive_failures: u32,
+    ejected_until: Option<Instant>,
+}
+
+// Past this many hosts or sessions, the sticky proxies are forgotten.
+const MAX_STICKY_KEYS: usize = 4096;
+
+/// How a [`ProxyPool`] picks the proxy of each request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ProxyStrategy {
+    /// Each proxy in turn.
+    RoundRobin,
+    /// A proxy at random.
+    Random,
+    /// A proxy at random, each one as likely as its weight.
+    Weighted,
+    /// The same proxy for every request to a host, picked in turn for each
+    /// new host.
+    StickyPerHost,
+    /// The same proxy for every request of a session, set with
+    /// [`RequestBuilder::proxy_session`], picked in turn for each new
+    /// session. Requests without a session use each proxy in turn.
+    ///
+    /// [`RequestBuilder::proxy_session`]: crate::RequestBuilder::proxy_session
+    StickyPerSession,
+}
+
+/// A builder for a [`ProxyPool`].
+#[must_use]
+pub struct ProxyPoolBuilder {
+    proxies: Vec<(Proxy, u32)>,
+    strategy: ProxyStrategy,
+    max_failures: u32,
+    ejection_time: Duration,
+}
+
+/// What a [`ProxyPool`] knows of one of its proxies.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProxyStats {
+    requests: u64,
+    successes: u64,
+    failures: u64,
+    ejections: u64,
+    ejected: bool,
+}
+
+/// The proxy of a pool picked for a request, told how it went.
+pub(crate) struct Pick {
+    pool: ProxyPool,
+    index: usize,
+}
+
+impl ProxyPool {
+    /// Creates a builder for a pool of proxies.
+    pub fn builder() -> ProxyPoolBuilder {
+        ProxyPoolBuilder {
+            proxies: Vec::new(),
+            strategy: ProxyStrategy::RoundRobin,
+            max_failures: 3,
+            ejection_time: Duration::from_secs(30),
+        }
+    }
+
+    /// Returns the stats of each proxy, in the order they were added.
+    pub fn stats(&self) -> Vec<ProxyStats> {
+        let now = Instant::now();
+        let state = self.inner.state.lock().unwrap();
+        state
+            .health
+            .iter()
+            .map(|health| ProxyStats {
+                ejected: health.is_ejected(now),
+                ..health.stats.clone()
+            })
+            .collect()
+    }
+
+    /// Returns how many proxies are in the pool.
+    pub(crate) fn len(&self) -> usize {
+        self.inner.proxies.len()
+    }
+
+    /// Whether `other` is this pool, or a clone of it.
+    pub(crate) fn same(&self, other: &ProxyPool) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    /// Picks the proxy of a request to `dst`, if any intercepts it.
+    pub(crate) fn pick<D: Dst>(&self, dst: &D, session: Option<&str>) -> Option<Pick> {
+        let inner = &*self.inner;
+        let candidates: Vec<usize> = (0..inner.proxies.len())
+            .filter(|&i| inner.proxies[i][0].intercept(dst).is_some())
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let now = Instant::now();
+        let mut state = inner.state.lock().unwrap();
+        let healthy: Vec<usize> = candidates
+            .iter()
+            .copied()
+            .filter(|&i| !state.health[i].is_ejected(now))
+            .collect();
+        let candidates = if healthy.is_empty() {
+            candidates
+        } else {
+            healthy
+        };
+
+        let sticky_key = match inner.strategy {
+            ProxyStrategy::StickyPerHost => Some(dst.host().to_ascii_lowercase()),
+            ProxyStrategy::StickyPerSession => session.map(str::to_owned),
+            _ => None,
+        };
+        let index = match inner.strategy {
+            ProxyStrategy::Random => candidates[random_below(candidates.len() as u64) as usize],
+            ProxyStrategy::Weighted => {
+                let total: u64 = candidates
+                    .iter()
+                    .map(|&i| u64::from(inner.weights[i]))
+                    .sum();
+                let mut point = random_below(total);
+                *candidates
+                    .iter()
+                    .find(|&&i| match point.checked_sub(u64::from(inner.weights[i])) {
+                        Some(rest) => {
+                            point = rest;
+                            false
+                        }
+                        None => true,
+                    })
+                    .expect("the point is below the total weight")
+            }
+            _ => match sticky_key
+                .as_ref()
+                .and_then(|key| state.sticky.get(key))
+                .filter(|i| candidates.contains(i))
+            {
+                Some(&i) => i,
+                None => {
+                    // The first one from the cursor on, so that ejected
+                    // proxies don't shift the others' turns.
+                    let start = state.next % inner.proxies.len();
+                    let i = *candidates
+                        .iter()
+                        .find(|&&i| i >= start)
+                        .unwrap_or(&candidates[0]);
+                    state.next = i + 1;
+                    if let Some(key) = sticky_key {
+                        if state.sticky.len() >= MAX_STICKY_KEYS {
+                            state.sticky.clear();
+                        }
+                        state.sticky.insert(key, i);
+                    }
+                    i
+                }
+            },
+        };
+        state.health[index].stats.requests += 1;
+
+        Some(Pick {
+            pool: self.clone(),
+            index,
+        })
+    }
+
+    fn report(&self, index: usize, success: bool) {
+        let now = Instant::now();
+        let mut state = self.inner.state.lock().unwrap();
+        let health = &mut state.health[index];
+        if success {
+            health.stats.successes += 1;
+            health.consecutive_failures = 0;
+            health.ejected_until = None;
+            return;
+        }
+
+        health.stats.failures += 1;
+        health.consecutive_failures = health.consecutive_failures.saturating_add(1);
+        if health.consecutive_failures >= self.inner.max_failures && !health.is_ejected(now) {
+            log::debug!(
+                "ejecting proxy {:?} for {:?}",
+                self.inner.proxies[index][0],
+                self.inner.ejection_time
+            );
+            health.stats.ejections += 1;
+            health.ejected_until = Some(now + self.inner.ejection_time);
+        }
+    }
+}
+
+impl fmt::Debug for ProxyPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyPool")
+            .field("proxies", &self.inner.proxies.len())
+            .field("strategy", &self.inner.strategy)
+            .finish()
+    }
+}
+
+impl ProxyPoolBuilder {
+    /// Adds a proxy, with a weight of 1.
+    pub fn proxy(self, proxy: Proxy) -> ProxyPoolBuilder {
+        self.weighted_proxy(proxy, 1)
+    }
+
+    /// Adds a proxy with a weight, only used by [`ProxyStrategy::Weighted`].
+    pub fn weighted_proxy(mut self, proxy: Proxy, weight: u32) -> ProxyPoolBuilder {
+        self.proxies.push((proxy, weight));
+        self
+    }
+
+    /// Sets how the proxy of each request is picked.
+    ///
+    /// Default is [`ProxyStrategy::RoundRobin`].
+    pub fn strategy(mut self, strategy: ProxyStrategy) -> ProxyPoolBuilder {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Sets how many failures in a row eject a proxy.
+    ///
+    /// Default is 3.
+    pub fn max_failures(mut self, max_failures: u32) -> ProxyPoolBuilder {
+        self.max_failures = max_failures;
+        self
+    }
+
+    /// Sets how long an ejected proxy is left out.
+    ///
+    /// Default is 30 seconds.
+    pub fn ejection_time(mut self, ejection_time: Duration) -> ProxyPoolBuilder {
+        self.ejection_time = ejection_time;
+        self
+    }
+
+    /// Builds the pool.
+    ///
+    /// # Errors
+    ///
+    /// This method fails if no proxy was added, if a weight is zero, if
+    /// `max_failures` is zero, or if a proxy is a [`Proxy::pac`] one.
+    pub fn build(self) -> crate::Result<ProxyPool> {
+        if self.proxies.is_empty() {
+            return Err(crate::error::builder("a proxy pool needs a proxy"));
+        }
+        if self.proxies.iter().any(|&(_, weight)| weight == 0) {
+            return Err(crate::error::builder("proxy weights must be positive"));
+        }
+        if self.max_failures == 0 {
+            return Err(crate::error::builder("max_failures must be positive"));
+        }
+        // Its requests would all go direct.
+        if self.proxies.iter().any(|(proxy, _)| proxy.is_pac()) {
+            return Err(crate::error::builder("PAC proxies can't be pooled"));
+        }
+
+        let (proxies, weights): (Vec<_>, Vec<_>) = self
+            .proxies
+            .into_iter()
+            .map(|(proxy, weight)| (Arc::new(vec![proxy]), weight))
+            .unzip();
+        let state = State {
+            health: proxies.iter().map(|_| Health::default()).collect(),
+            ..State::default()
+        };
+        Ok(ProxyPool {
+            inner: Arc::new(Inner {
+                proxies,
+                weights,
+                strategy: self.strategy,
+                max_failures: self.max_failures,
+                ejection_time: self.ejection_time,
+                state: Mutex::new(state),
+            }),
+        })
+    }
+}
+
+impl fmt::Debug for ProxyPoolBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyPoolBuilder")
+            .field("proxies", &self.proxies)
+            .field("strategy", &self.strategy)
+            .field("max_failures", &self.max_failures)
+            .field("ejection_time", &self.ejection_time)
+            .finish()
+    }
+}
+
+impl ProxyStats {
+    /// Returns how many requests were sent through the proxy.
+    pub fn requests(&self) -> u64 {
+        self.requests
+    }
+
+    /// Returns how many requests through the proxy got a response.
+    pub fn successes(&self) -> u64 {
+        self.successes
+    }
+
+    /// Returns how many requests through the proxy failed to connect, were
+    /// refused by it, or timed out.
+    pub fn failures(&self) -> u64 {
+        self.failures
+    }
+
+    /// Returns how many times the proxy was ejected.
+    pub fn ejections(&self) -> u64 {
+        self.ejections
+    }
+
+    /// Returns whether the proxy is ejected.
+    pub fn is_ejected(&self) -> bool {
+        self.ejected
+    }
+}
+
+impl Health {
+    fn is_ejected(&self, now: Instant) -> bool {
+        self.ejected_until.map_or(false, |until| now < until)
+    }
+}
+
+impl Pick {
+    /// The proxy picked, alone in a list.
+    pub(crate) fn proxies(&self) -> &Arc<Vec<Proxy>> {
+        &self.pool.inner.proxies[self.index]
+    }
+
+    /// Tells the pool a response came through the proxy.
+    pub(crate) fn response(&self, status: http::StatusCode) {
+        let success = status != http::StatusCode::PROXY_AUTHENTICATION_REQUIRED;
+        self.pool.report(self.index, success);
+    }
+
+    /// Tells the pool the request failed at the proxy: it couldn't be
+    /// reached, refused to connect onwards, or timed out.
+    pub(crate) fn failed(&self) {
+        self.pool.report(self.index, false);
+    }
+}
+
+fn random_below(n: u64) -> u64 {
+    crate::util::fast_random() % n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::{StatusCode, Uri};
+
+    fn new_pool(strategy: ProxyStrategy) -> ProxyPool {
+        ProxyPool::builder()
+            .proxy(Proxy::all("http://a.proxy").unwrap())
+            .weighted_proxy(Proxy::all("http://b.proxy").unwrap(), 3)
+            .proxy(Proxy::https("http://c.proxy").unwrap())
+            .strategy(strategy)
+            .build()
+            .unwrap()
+    }
+
+    fn pick(pool: &ProxyPool, url: &str, session: Option<&str>) -> usize {
+        let uri: Uri = url.parse().unwrap();
+        pool.pick(&uri, session).unwrap().index
+    }
+
+    #[test]
+    fn round_robin_skips_proxies_not_intercepting() {
+        let pool = new_pool(ProxyStrategy::RoundRobin);
+        let picks: Vec<_> = (0..4).map(|_| pick(&pool, "http://x.test", None)).collect();
+        assert_eq!(picks, [0, 1, 0, 1]);
+        let picks: Vec<_> = (0..3)
+            .map(|_| pick(&pool, "https://x.test", None))
+            .collect();
+        assert_eq!(picks, [2, 0, 1]);
+
+        let only_https = ProxyPool::builder()
+            .proxy(Proxy::https("http://c.proxy").unwrap())
+            .build()
+            .unwrap();
+        let uri: Uri = "http://x.test".parse().unwrap();
+        assert!(only_https.pick(&uri, None).is_none());
+    }
+
+    #[test]
+    fn weighted() {
+        let pool = new_pool(ProxyStrategy::Weighted);
+        let mut counts = [0; 3];
+        for _ in 0..4000 {
+            counts[pick(&pool, "http://x.test", None)] += 1;
+        }
+        assert_eq!(counts[2], 0);
+        assert!(counts[1] > counts[0] * 2, "{:?}", counts);
+    }
+
+    #[test]
+    fn sticky() {
+        let pool = new_pool(ProxyStrategy::StickyPerHost);
+        let a = pick(&pool, "https://a.test/1", None);
+        let b = pick(&pool, "https://b.test", None);
+        assert_ne!(a, b);
+        assert_eq!(pick(&pool, "https://A.test/2", None), a);
+        assert_eq!(pick(&pool, "https://b.test", None), b);
+
+        let pool = new_pool(ProxyStrategy::StickyPerSession);
+        let one = pick(&pool, "https://a.test", Some("one"));
+        let two = pick(&pool, "https://a.test", Some("two"));
+        assert_ne!(one, two);
+        assert_eq!(pick(&pool, "https://b.test", Some("one")), one);
+        assert_eq!(pick(&pool, "https://b.test", Some("two")), two);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ejects_and_recovers() {
+        let pool = ProxyPool::builder()
+            .proxy(Proxy::all("http://a.proxy").unwrap())
+            .proxy(Proxy::all("http://b.proxy").unwrap())
+            .max_failures(2)
+            .ejection_time(Duration::from_secs(10))
+            .build()
+            .unwrap();
+        let uri: Uri = "http://x.test".parse().unwrap();
+        let fail_a = || {
+            let pick = pool.pick(&uri, None).unwrap();
+            if pick.index == 0 {
+                pick.failed();
+            } else {
+                pick.response(StatusCode::OK);
+            }
+        };
+
+        for _ in 0..4 {
+            fail_a();
+        }
+        assert!(pool.stats()[0].is_ejected());
+        for _ in 0..3 {
+            assert_eq!(pool.pick(&uri, None).unwrap().index, 1);
+        }
+
+        // With every proxy ejected, they're used anyway.
+        let b = pool.pick(&uri, None).unwrap();
+        b.response(StatusCode::PROXY_AUTHENTICATION_REQUIRED);
+        b.response(StatusCode::PROXY_AUTHENTICATION_REQUIRED);
+        let picks: Vec<_> = (0..2)
+            .map(|_| pool.pick(&uri, None).unwrap().index)
+            .collect();
+        assert!(picks.contains(&0) && picks.contains(&1));
+
+        // Back, but ejected again on its first failure.
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(!pool.stats()[0].is_ejected());
+        let a = (0..2)
+            .map(|_| pool.pick(&uri, None).unwrap())
+            .find(|pick| pick.index == 0)
+            .unwrap();
+        a.failed();
+        assert!(pool.stats()[0].is_ejected());
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        a.response(StatusCode::OK);
+        a.failed();
+        let stats = &pool.stats()[0];
+        assert!(!stats.is_ejected());
+        assert_eq!(stats.successes(), 1);
+        assert_eq!(stats.failures(), 4);
+        assert_eq!(stats.ejections(), 2);
+    }
+}
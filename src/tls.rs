@@ -19,7 +19,25 @@ use std::fmt;
 #[cfg(feature = "__boring")]
 use std::sync::Arc;
 
+#[cfg(feature = "__boring")]
+mod ech;
+#[cfg(feature = "__tls")]
+mod pin;
+#[cfg(feature = "__boring")]
+mod session;
+
+#[cfg(feature = "__boring")]
+pub(crate) use self::ech::EchConfigs;
+#[cfg(feature = "__tls")]
+pub(crate) use self::pin::CertificatePins;
+
+#[cfg(feature = "__boring")]
+pub use self::session::TlsSessionCache;
+#[cfg(feature = "__boring")]
+pub(crate) use self::session::SessionKey;
+
 /// Represents a server X509 certificate.
+#[derive(Clone)]
 pub struct Certificate {
     #[cfg(feature = "native-tls-crate")]
     native: native_tls_crate::Certificate,
@@ -29,7 +47,7 @@ pub struct Certificate {
 
 
 #[cfg(feature = "__rustls")]
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 enum Cert {
     Der(Vec<u8>),
     Pem(Vec<u8>),
@@ -38,7 +56,10 @@ enum Cert {
 /// Represents a private key and X509 cert as a client certificate.
 #[derive(Clone)]
 pub struct Identity {
-    #[cfg_attr(not(any(feature = "native-tls", feature = "__rustls")), allow(unused))]
+    #[cfg_attr(
+        not(any(feature = "native-tls", feature = "__rustls", feature = "__boring")),
+        allow(unused)
+    )]
     inner: ClientCert,
 }
 
@@ -53,6 +74,50 @@ enum ClientCert {
         key: rustls::PrivateKey,
         certs: Vec<rustls::Certificate>,
     },
+    #[cfg(feature = "__boring")]
+    Boring(BoringIdentity),
+}
+
+/// A client certificate, its chain and private key, parsed by BoringSSL.
+#[cfg(feature = "__boring")]
+#[derive(Clone)]
+pub(crate) struct BoringIdentity {
+    key: boring::pkey::PKey<boring::pkey::Private>,
+    cert: boring::x509::X509,
+    chain: Vec<boring::x509::X509>,
+}
+
+#[cfg(feature = "__boring")]
+impl BoringIdentity {
+    fn new(
+        key: boring::pkey::PKey<boring::pkey::Private>,
+        cert: boring::x509::X509,
+        chain: Vec<boring::x509::X509>,
+    ) -> crate::Result<BoringIdentity> {
+        let public_key = cert.public_key().map_err(crate::error::builder)?;
+        if !public_key.public_eq(&key) {
+            return Err(crate::error::builder(
+                "private key doesn't match the certificate",
+            ));
+        }
+        Ok(BoringIdentity { key, cert, chain })
+    }
+
+    /// Sets the certificate and key on `builder`.
+    ///
+    /// They're only sent if the server asks for a certificate, so the
+    /// ClientHello stays the one of the profile.
+    pub(crate) fn add_to_boring(
+        &self,
+        builder: &mut boring::ssl::SslConnectorBuilder,
+    ) -> Result<(), boring::error::ErrorStack> {
+        builder.set_certificate(&self.cert)?;
+        for cert in &self.chain {
+            builder.add_extra_chain_cert(cert.clone())?;
+        }
+        builder.set_private_key(&self.key)?;
+        builder.check_private_key()
+    }
 }
 
 impl Certificate {
@@ -116,6 +181,23 @@ impl Certificate {
         })
     }
 
+    /// Whether `other` holds the same certificate.
+    pub(crate) fn same(&self, other: &Certificate) -> bool {
+        #[cfg(feature = "__rustls")]
+        return self.original == other.original;
+        #[cfg(all(feature = "native-tls-crate", not(feature = "__rustls")))]
+        return matches!(
+            (self.native.to_der(), other.native.to_der()),
+            (Ok(a), Ok(b)) if a == b
+        );
+        // Without another backend, there's nothing to compare.
+        #[cfg(not(any(feature = "native-tls-crate", feature = "__rustls")))]
+        {
+            let _ = other;
+            true
+        }
+    }
+
     #[cfg(feature = "native-tls-crate")]
     pub(crate) fn add_to_native_tls(self, tls: &mut native_tls_crate::TlsConnectorBuilder) {
         tls.add_root_certificate(self.native);
@@ -167,9 +249,10 @@ impl Identity {
     /// # Examples
     ///
     /// ```
+    /// # use chromimic as reqwest;
     /// # use std::fs::File;
     /// # use std::io::Read;
-    /// # fn pkcs12() -> Result<(), Box<std::error::Error>> {
+    /// # fn pkcs12() -> Result<(), Box<dyn std::error::Error>> {
     /// let mut buf = Vec::new();
     /// File::open("my-ident.pfx")?
     ///     .read_to_end(&mut buf)?;
@@ -181,15 +264,29 @@ impl Identity {
     ///
     /// # Optional
     ///
-    /// This requires the `native-tls` Cargo feature enabled.
-    #[cfg(feature = "native-tls")]
+    /// This requires the `native-tls` or `boring-tls(-...)` Cargo feature
+    /// enabled. With both, the identity can only be used with native-tls.
+    #[cfg(any(feature = "native-tls", feature = "__boring"))]
     pub fn from_pkcs12_der(der: &[u8], password: &str) -> crate::Result<Identity> {
-        Ok(Identity {
-            inner: ClientCert::Pkcs12(
-                native_tls_crate::Identity::from_pkcs12(der, password)
-                    .map_err(crate::error::builder)?,
-            ),
-        })
+        #[cfg(feature = "native-tls")]
+        let inner = ClientCert::Pkcs12(
+            native_tls_crate::Identity::from_pkcs12(der, password)
+                .map_err(crate::error::builder)?,
+        );
+
+        #[cfg(not(feature = "native-tls"))]
+        let inner = {
+            let parsed = boring::pkcs12::Pkcs12::from_der(der)
+                .and_then(|pkcs12| pkcs12.parse(password))
+                .map_err(crate::error::builder)?;
+            let chain = parsed
+                .chain
+                .map(|chain| chain.into_iter().collect())
+                .unwrap_or_default();
+            ClientCert::Boring(BoringIdentity::new(parsed.pkey, parsed.cert, chain)?)
+        };
+
+        Ok(Identity { inner })
     }
 
     /// Parses a chain of PEM encoded X509 certificates, with the leaf certificate first.
@@ -235,9 +332,10 @@ impl Identity {
     /// # Examples
     ///
     /// ```
+    /// # use chromimic as reqwest;
     /// # use std::fs::File;
     /// # use std::io::Read;
-    /// # fn pem() -> Result<(), Box<std::error::Error>> {
+    /// # fn pem() -> Result<(), Box<dyn std::error::Error>> {
     /// let mut buf = Vec::new();
     /// File::open("my-ident.pem")?
     ///     .read_to_end(&mut buf)?;
@@ -249,9 +347,37 @@ impl Identity {
     ///
     /// # Optional
     ///
-    /// This requires the `rustls-tls(-...)` Cargo feature enabled.
-    #[cfg(feature = "__rustls")]
+    /// This requires the `rustls-tls(-...)` or `boring-tls(-...)` Cargo
+    /// feature enabled.
+    #[cfg(any(feature = "__rustls", feature = "__boring"))]
     pub fn from_pem(buf: &[u8]) -> crate::Result<Identity> {
+        #[cfg(feature = "__rustls")]
+        let identity = Identity::from_pem_rustls(buf)?;
+        #[cfg(not(feature = "__rustls"))]
+        let identity = Identity::from_pem_boring(buf)?;
+        Ok(identity)
+    }
+
+    #[cfg(all(feature = "__boring", not(feature = "__rustls")))]
+    fn from_pem_boring(buf: &[u8]) -> crate::Result<Identity> {
+        use boring::pkey::PKey;
+        use boring::x509::X509;
+
+        let mut certs = X509::stack_from_pem(buf)
+            .map_err(crate::error::builder)?
+            .into_iter();
+        let cert = certs
+            .next()
+            .ok_or_else(|| crate::error::builder("no certificate found in identity PEM"))?;
+        let key = PKey::private_key_from_pem(buf).map_err(crate::error::builder)?;
+
+        Ok(Identity {
+            inner: ClientCert::Boring(BoringIdentity::new(key, cert, certs.collect())?),
+        })
+    }
+
+    #[cfg(feature = "__rustls")]
+    fn from_pem_rustls(buf: &[u8]) -> crate::Result<Identity> {
         use std::io::Cursor;
 
         let (key, certs) = {
@@ -305,6 +431,8 @@ impl Identity {
             }
             #[cfg(feature = "__rustls")]
             ClientCert::Pem { .. } => Err(crate::error::builder("incompatible TLS identity type")),
+            #[cfg(feature = "__boring")]
+            ClientCert::Boring(..) => Err(crate::error::builder("incompatible TLS identity type")),
         }
     }
 
@@ -324,6 +452,36 @@ impl Identity {
             ClientCert::Pkcs12(..) | ClientCert::Pkcs8(..) => {
                 Err(crate::error::builder("incompatible TLS identity type"))
             }
+            #[cfg(feature = "__boring")]
+            ClientCert::Boring(..) => Err(crate::error::builder("incompatible TLS identity type")),
+        }
+    }
+
+    #[cfg(feature = "__boring")]
+    pub(crate) fn into_boring(self) -> crate::Result<BoringIdentity> {
+        match self.inner {
+            ClientCert::Boring(identity) => Ok(identity),
+            #[cfg(feature = "__rustls")]
+            ClientCert::Pem { key, certs } => {
+                use boring::pkey::PKey;
+                use boring::x509::X509;
+
+                let mut certs = certs
+                    .iter()
+                    .map(|cert| X509::from_der(&cert.0))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(crate::error::builder)?
+                    .into_iter();
+                let cert = certs
+                    .next()
+                    .ok_or_else(|| crate::error::builder("no certificate in identity"))?;
+                let key = PKey::private_key_from_der(&key.0).map_err(crate::error::builder)?;
+                BoringIdentity::new(key, cert, certs.collect())
+            }
+            #[cfg(feature = "native-tls")]
+            ClientCert::Pkcs12(..) | ClientCert::Pkcs8(..) => {
+                Err(crate::error::builder("incompatible TLS identity type"))
+            }
         }
     }
 }
@@ -389,6 +547,7 @@ impl Version {
     }
 }
 
+#[derive(Clone)]
 pub(crate) enum TlsBackend {
     // This is the default and HTTP/3 feature does not use it so suppress it.
     #[allow(dead_code)]
@@ -425,6 +584,24 @@ impl fmt::Debug for TlsBackend {
     }
 }
 
+impl TlsBackend {
+    /// Whether both backends are known to produce the same TLS configuration.
+    ///
+    /// Prebuilt connectors can't be compared and are never considered equal.
+    pub(crate) fn same(&self, other: &TlsBackend) -> bool {
+        match (self, other) {
+            #[cfg(feature = "__boring")]
+            (TlsBackend::BoringTls(a), TlsBackend::BoringTls(b)) => Arc::ptr_eq(a, b),
+            #[cfg(feature = "default-tls")]
+            (TlsBackend::Default, TlsBackend::Default) => true,
+            #[cfg(feature = "__rustls")]
+            (TlsBackend::Rustls, TlsBackend::Rustls) => true,
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
+}
+
 impl Default for TlsBackend {
     fn default() -> TlsBackend {
         #[cfg(all(feature = "default-tls", not(feature = "http3")))]
@@ -432,9 +609,14 @@ impl Default for TlsBackend {
             TlsBackend::Default
         }
 
-        #[cfg(any(
-            all(feature = "__rustls", not(feature = "default-tls")),
-            feature = "http3"
+        // BoringSSL stays the default with HTTP/3, which then needs
+        // `use_rustls_tls`.
+        #[cfg(all(
+            any(
+                all(feature = "__rustls", not(feature = "default-tls")),
+                feature = "http3"
+            ),
+            not(all(feature = "__boring", not(feature = "default-tls")))
         ))]
         {
             TlsBackend::Rustls
@@ -508,6 +690,302 @@ impl std::fmt::Debug for TlsInfo {
     }
 }
 
+/// Hyper extension telling whether TLS 1.3 early data was accepted.
+///
+/// Present on responses received over a connection that sent its first
+/// request as 0-RTT early data, see
+/// [`ClientBuilder::tls_early_data`](crate::ClientBuilder::tls_early_data).
+#[cfg(feature = "__boring")]
+#[derive(Clone)]
+pub struct EarlyData {
+    accepted: Arc<std::sync::atomic::AtomicBool>,
+}
+
+#[cfg(feature = "__boring")]
+impl EarlyData {
+    pub(crate) fn new() -> EarlyData {
+        EarlyData {
+            accepted: Arc::default(),
+        }
+    }
+
+    pub(crate) fn set_accepted(&self, accepted: bool) {
+        self.accepted
+            .store(accepted, std::sync::atomic::Ordering::Release);
+    }
+
+    /// Returns `true` if the server accepted the early data.
+    ///
+    /// If it didn't, the request was sent again after the handshake.
+    pub fn accepted(&self) -> bool {
+        self.accepted.load(std::sync::atomic::Ordering::Acquire)
+    }
+}
+
+#[cfg(feature = "__boring")]
+impl std::fmt::Debug for EarlyData {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("EarlyData")
+            .field("accepted", &self.accepted())
+            .finish()
+    }
+}
+
+/// What a TLS handshake negotiated, see
+/// [`ConnectionInfo::tls`](crate::ConnectionInfo::tls).
+#[derive(Clone)]
+pub struct TlsDetails {
+    version: Option<Version>,
+    cipher: Option<&'static str>,
+    group: Option<&'static str>,
+    alpn: Option<Vec<u8>>,
+    session_resumed: bool,
+    ech_accepted: bool,
+    peer_certificates: Vec<Vec<u8>>,
+    ocsp_response: Option<Vec<u8>>,
+    signed_certificate_timestamps: Option<Vec<u8>>,
+    subject: Option<String>,
+    subject_alt_names: Vec<String>,
+    not_after: Option<std::time::SystemTime>,
+}
+
+impl TlsDetails {
+    /// Returns the protocol version.
+    pub fn version(&self) -> Option<Version> {
+        self.version
+    }
+
+    /// Returns the standard name of the cipher suite, like
+    /// `TLS_AES_128_GCM_SHA256`.
+    pub fn cipher(&self) -> Option<&str> {
+        self.cipher
+    }
+
+    /// Returns the name of the key exchange group, like `X25519`.
+    pub fn group(&self) -> Option<&str> {
+        self.group
+    }
+
+    /// Returns the protocol picked by ALPN.
+    pub fn alpn(&self) -> Option<&[u8]> {
+        self.alpn.as_deref()
+    }
+
+    /// Returns `true` if an earlier session was resumed.
+    pub fn session_resumed(&self) -> bool {
+        self.session_resumed
+    }
+
+    /// Returns `true` if the server accepted Encrypted Client Hello.
+    pub fn ech_accepted(&self) -> bool {
+        self.ech_accepted
+    }
+
+    /// Returns the DER encoded certificates the server sent, leaf first.
+    pub fn peer_certificates(&self) -> &[Vec<u8>] {
+        &self.peer_certificates
+    }
+
+    /// Returns the OCSP response stapled by the server.
+    ///
+    /// Only profiles that ask for one, like Chrome's, get it.
+    pub fn ocsp_response(&self) -> Option<&[u8]> {
+        self.ocsp_response.as_deref()
+    }
+
+    /// Returns the `SignedCertificateTimestampList` sent in the handshake.
+    ///
+    /// Only profiles that ask for one, like Chrome's, get it.
+    pub fn signed_certificate_timestamps(&self) -> Option<&[u8]> {
+        self.signed_certificate_timestamps.as_deref()
+    }
+
+    /// Returns the subject of the leaf certificate, like
+    /// `CN=example.com, O=Example`.
+    pub fn subject(&self) -> Option<&str> {
+        self.subject.as_deref()
+    }
+
+    /// Returns the DNS names, IP addresses, emails and URIs in the subject
+    /// alternative names of the leaf certificate.
+    pub fn subject_alt_names(&self) -> &[String] {
+        &self.subject_alt_names
+    }
+
+    /// Returns when the leaf certificate expires.
+    pub fn not_after(&self) -> Option<std::time::SystemTime> {
+        self.not_after
+    }
+}
+
+#[cfg(feature = "__boring")]
+impl TlsDetails {
+    pub(crate) fn from_boring(ssl: &boring::ssl::SslRef) -> TlsDetails {
+        use boring::ssl::SslVersion;
+        use foreign_types::ForeignTypeRef;
+        use std::convert::TryFrom;
+        use std::ffi::CStr;
+
+        let version = ssl.version2().and_then(|version| match version {
+            SslVersion::TLS1 => Some(Version::TLS_1_0),
+            SslVersion::TLS1_1 => Some(Version::TLS_1_1),
+            SslVersion::TLS1_2 => Some(Version::TLS_1_2),
+            SslVersion::TLS1_3 => Some(Version::TLS_1_3),
+            _ => None,
+        });
+
+        // SAFETY: curve names are static strings.
+        let group = match unsafe { boring_sys::SSL_get_curve_id(ssl.as_ptr()) } {
+            0 => None,
+            id => unsafe {
+                let name = boring_sys::SSL_get_curve_name(id);
+                if name.is_null() {
+                    None
+                } else {
+                    CStr::from_ptr(name).to_str().ok()
+                }
+            },
+        };
+
+        let signed_certificate_timestamps = unsafe {
+            let mut data = std::ptr::null();
+            let mut len = 0;
+            boring_sys::SSL_get0_signed_cert_timestamp_list(ssl.as_ptr(), &mut data, &mut len);
+            if data.is_null() || len == 0 {
+                None
+            } else {
+                Some(std::slice::from_raw_parts(data, len).to_vec())
+            }
+        };
+
+        let peer_certificates = ssl
+            .peer_cert_chain()
+            .map(|chain| chain.iter().filter_map(|cert| cert.to_der().ok()).collect())
+            .unwrap_or_default();
+
+        let leaf = ssl.peer_certificate();
+        let subject = leaf.as_ref().map(|cert| {
+            cert.subject_name()
+                .entries()
+                .filter_map(|entry| {
+                    let key = entry.object().nid().short_name().ok()?;
+                    let value = entry.data().as_utf8().ok()?;
+                    Some(format!("{}={}", key, value))
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        });
+        let subject_alt_names = leaf
+            .as_ref()
+            .and_then(|cert| cert.subject_alt_names())
+            .map(|names| {
+                names
+                    .iter()
+                    .filter_map(|name| {
+                        if let Some(ip) = name.ipaddress() {
+                            return ip_to_string(ip);
+                        }
+                        name.dnsname()
+                            .or_else(|| name.email())
+                            .or_else(|| name.uri())
+                            .map(str::to_owned)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let not_after = leaf.as_ref().and_then(|cert| {
+            let mut secs = 0;
+            let ok = unsafe {
+                boring_sys::ASN1_TIME_to_posix(cert.not_after().as_ptr(), &mut secs)
+            };
+            if ok != 1 {
+                return None;
+            }
+            let secs = u64::try_from(secs).ok()?;
+            Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+        });
+
+        TlsDetails {
+            version,
+            cipher: ssl.current_cipher().and_then(|cipher| cipher.standard_name()),
+            group,
+            alpn: ssl.selected_alpn_protocol().map(<[u8]>::to_vec),
+            session_resumed: ssl.session_reused(),
+            ech_accepted: unsafe { boring_sys::SSL_ech_accepted(ssl.as_ptr()) == 1 },
+            peer_certificates,
+            ocsp_response: ssl.ocsp_status().map(<[u8]>::to_vec),
+            signed_certificate_timestamps,
+            subject,
+            subject_alt_names,
+            not_after,
+        }
+    }
+}
+
+#[cfg(feature = "http3")]
+impl TlsDetails {
+    /// QUIC always uses TLS 1.3, and quinn doesn't tell more than the ALPN
+    /// protocol and the certificates.
+    pub(crate) fn from_quic(conn: &quinn::Connection) -> TlsDetails {
+        let alpn = conn
+            .handshake_data()
+            .and_then(|data| data.downcast::<quinn::crypto::rustls::HandshakeData>().ok())
+            .and_then(|data| data.protocol);
+        let peer_certificates = conn
+            .peer_identity()
+            .and_then(|identity| identity.downcast::<Vec<rustls::Certificate>>().ok())
+            .map(|certs| certs.into_iter().map(|cert| cert.0).collect())
+            .unwrap_or_default();
+
+        TlsDetails {
+            version: Some(Version::TLS_1_3),
+            cipher: None,
+            group: None,
+            alpn,
+            session_resumed: false,
+            ech_accepted: false,
+            peer_certificates,
+            ocsp_response: None,
+            signed_certificate_timestamps: None,
+            subject: None,
+            subject_alt_names: Vec::new(),
+            not_after: None,
+        }
+    }
+}
+
+#[cfg(feature = "__boring")]
+fn ip_to_string(ip: &[u8]) -> Option<String> {
+    use std::convert::TryFrom;
+    use std::net::IpAddr;
+
+    let ip = match ip.len() {
+        4 => IpAddr::from(<[u8; 4]>::try_from(ip).ok()?),
+        16 => IpAddr::from(<[u8; 16]>::try_from(ip).ok()?),
+        _ => return None,
+    };
+    Some(ip.to_string())
+}
+
+impl fmt::Debug for TlsDetails {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TlsDetails")
+            .field("version", &self.version)
+            .field("cipher", &self.cipher)
+            .field("group", &self.group)
+            .field(
+                "alpn",
+                &self.alpn.as_deref().map(String::from_utf8_lossy),
+            )
+            .field("session_resumed", &self.session_resumed)
+            .field("ech_accepted", &self.ech_accepted)
+            .field("subject", &self.subject)
+            .field("subject_alt_names", &self.subject_alt_names)
+            .field("not_after", &self.not_after)
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[allow(unused_imports)]
@@ -0,0 +1,119 @@
+//! Encrypted Client Hello for the BoringSSL backend.
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// The `ECHConfigList`s the ClientHello to some hosts is encrypted with.
+#[derive(Clone, Default, PartialEq)]
+pub(crate) struct EchConfigs {
+    // Keyed by lowercase host, without a trailing dot.
+    configs: HashMap<String, Vec<u8>>,
+}
+
+impl EchConfigs {
+    /// Uses `config_list` for `host`, replacing the one it had.
+    pub(crate) fn add(&mut self, host: &str, config_list: Vec<u8>) -> crate::Result<()> {
+        let host = normalize(host);
+        if host.is_empty() || host.starts_with('[') || host.parse::<std::net::IpAddr>().is_ok() {
+            return Err(crate::error::builder(format!(
+                "ECH needs a domain name, got {:?}",
+                host
+            )));
+        }
+        if !is_config_list(&config_list) {
+            return Err(crate::error::builder(format!(
+                "invalid ECHConfigList for {:?}",
+                host
+            )));
+        }
+
+        self.configs.insert(host, config_list);
+        Ok(())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.configs.is_empty()
+    }
+
+    /// Returns the `ECHConfigList` for `host`, if one was added.
+    pub(crate) fn get(&self, host: &str) -> Option<&[u8]> {
+        self.configs.get(&normalize(host)).map(Vec::as_slice)
+    }
+}
+
+impl fmt::Debug for EchConfigs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.configs.keys()).finish()
+    }
+}
+
+fn normalize(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Checks the framing of an `ECHConfigList`: a 16-bit length, then one or
+/// more `ECHConfig`s made of a 16-bit version and a 16-bit length prefixed
+/// body.
+///
+/// Whether BoringSSL supports any of the configs is only known once it's
+/// handed one.
+///
+/// https://datatracker.ietf.org/doc/html/draft-ietf-tls-esni#section-4
+fn is_config_list(data: &[u8]) -> bool {
+    let (len, mut rest) = match split_u16(data) {
+        Some(split) => split,
+        None => return false,
+    };
+    if len as usize != rest.len() || rest.is_empty() {
+        return false;
+    }
+
+    while !rest.is_empty() {
+        let body = split_u16(rest).and_then(|(_version, body)| split_u16(body));
+        match body {
+            Some((len, body)) if body.len() >= len as usize => rest = &body[len as usize..],
+            _ => return false,
+        }
+    }
+    true
+}
+
+fn split_u16(data: &[u8]) -> Option<(u16, &[u8])> {
+    if data.len() < 2 {
+        return None;
+    }
+    Some((u16::from_be_bytes([data[0], data[1]]), &data[2..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_config_list, EchConfigs};
+
+    const LIST: &[u8] = &[
+        0x00, 0x0a, // length
+        0xfe, 0x0d, 0x00, 0x02, 0xaa, 0xbb, // ECHConfig
+        0x12, 0x34, 0x00, 0x00, // ECHConfig of an unknown version
+    ];
+
+    #[test]
+    fn config_list_framing() {
+        assert!(is_config_list(LIST));
+        assert!(!is_config_list(&LIST[..LIST.len() - 1]));
+        assert!(!is_config_list(&[0x00, 0x00]));
+        assert!(!is_config_list(&[0x00, 0x03, 0xfe, 0x0d, 0x00]));
+        assert!(!is_config_list(&[]));
+    }
+
+    #[test]
+    fn configs_by_host() {
+        let mut configs = EchConfigs::default();
+        configs.add("Example.com.", LIST.to_vec()).unwrap();
+        assert_eq!(configs.get("example.com"), Some(LIST));
+        assert_eq!(configs.get("www.example.com"), None);
+
+        configs.add("127.0.0.1", LIST.to_vec()).unwrap_err();
+        configs.add("[::1]", LIST.to_vec()).unwrap_err();
+        configs.add("example.org", vec![0x00]).unwrap_err();
+        assert_eq!(configs.configs.len(), 1);
+    }
+}
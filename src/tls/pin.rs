@@ -0,0 +1,250 @@
+//! Public key pinning.
+
+use std::fmt;
+
+use sha2::{Digest, Sha256};
+
+use crate::error::BoxError;
+
+/// Public key hashes the leaf certificate of some hosts must match.
+#[derive(Clone, Default, PartialEq)]
+pub(crate) struct CertificatePins {
+    pins: Vec<Pin>,
+}
+
+#[derive(Clone, PartialEq)]
+struct Pin {
+    // Lowercase, without the `*.` of wildcard patterns.
+    host: String,
+    wildcard: bool,
+    hashes: Vec<[u8; 32]>,
+}
+
+impl CertificatePins {
+    /// Pins `pattern` to `hashes`, adding to the hashes already pinned for
+    /// the same pattern.
+    pub(crate) fn add(&mut self, pattern: &str, hashes: Vec<[u8; 32]>) -> crate::Result<()> {
+        if hashes.is_empty() {
+            return Err(crate::error::builder(format!(
+                "no SPKI hashes given to pin {:?}",
+                pattern
+            )));
+        }
+
+        let normalized = normalize(pattern);
+        let (wildcard, host) = match normalized.strip_prefix("*.") {
+            Some(host) => (true, host.to_owned()),
+            None => (false, normalized),
+        };
+        if host.is_empty() || host.contains('*') {
+            return Err(crate::error::builder(format!(
+                "invalid certificate pin pattern {:?}",
+                pattern
+            )));
+        }
+
+        match self
+            .pins
+            .iter_mut()
+            .find(|pin| pin.wildcard == wildcard && pin.host == host)
+        {
+            Some(pin) => {
+                for hash in hashes {
+                    if !pin.hashes.contains(&hash) {
+                        pin.hashes.push(hash);
+                    }
+                }
+            }
+            None => self.pins.push(Pin {
+                host,
+                wildcard,
+                hashes,
+            }),
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pins.is_empty()
+    }
+
+    /// Checks the leaf certificate presented by `host` against its pins.
+    ///
+    /// `leaf` returns the DER encoded certificate, and is only called if
+    /// `host` is pinned.
+    pub(crate) fn check<F>(&self, host: &str, leaf: F) -> Result<(), BoxError>
+    where
+        F: FnOnce() -> Option<Vec<u8>>,
+    {
+        let host = normalize(host.trim_start_matches('[').trim_end_matches(']'));
+        let pin = match self.find(&host) {
+            Some(pin) => pin,
+            None => return Ok(()),
+        };
+
+        let matched = leaf()
+            .as_deref()
+            .and_then(spki)
+            .map(|spki| pin.hashes.contains(&Sha256::digest(spki).into()))
+            .unwrap_or(false);
+        if matched {
+            Ok(())
+        } else {
+            Err(Box::new(crate::error::CertificatePinMismatch::new(host)))
+        }
+    }
+
+    /// Returns the pin for `host`: an exact match, or else the longest
+    /// matching wildcard.
+    fn find(&self, host: &str) -> Option<&Pin> {
+        self.pins
+            .iter()
+            .find(|pin| !pin.wildcard && pin.host == host)
+            .or_else(|| {
+                self.pins
+                    .iter()
+                    .filter(|pin| {
+                        pin.wildcard
+                            && host.len() > pin.host.len()
+                            && host.ends_with(&pin.host)
+                            && host.as_bytes()[host.len() - pin.host.len() - 1] == b'.'
+                    })
+                    .max_by_key(|pin| pin.host.len())
+            })
+    }
+}
+
+impl fmt::Debug for CertificatePins {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.pins.iter().map(|pin| {
+                if pin.wildcard {
+                    format!("*.{}", pin.host)
+                } else {
+                    pin.host.clone()
+                }
+            }))
+            .finish()
+    }
+}
+
+fn normalize(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Returns the DER encoded `SubjectPublicKeyInfo` of an X.509 certificate.
+///
+/// https://www.rfc-editor.org/rfc/rfc5280#section-4.1
+fn spki(cert: &[u8]) -> Option<&[u8]> {
+    let (cert, _) = sequence(cert)?;
+    let (tbs, _) = sequence(cert)?;
+
+    let mut rest = tbs;
+    // version, which is optional
+    if rest.first() == Some(&0xa0) {
+        rest = der(rest)?.2;
+    }
+    // serialNumber, signature, issuer, validity and subject
+    for _ in 0..5 {
+        rest = der(rest)?.2;
+    }
+
+    let (tag, _, after) = der(rest)?;
+    if tag != 0x30 {
+        return None;
+    }
+    Some(&rest[..rest.len() - after.len()])
+}
+
+fn sequence(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    match der(data)? {
+        (0x30, contents, rest) => Some((contents, rest)),
+        _ => None,
+    }
+}
+
+/// Splits the first DER element off `data`, returning its tag, its contents
+/// and whatever follows it.
+fn der(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, data) = data.split_first()?;
+    let (&len, mut data) = data.split_first()?;
+
+    let len = if len < 0x80 {
+        len as usize
+    } else {
+        let octets = (len & 0x7f) as usize;
+        if octets == 0 || octets > 4 || data.len() < octets {
+            return None;
+        }
+        let (bytes, rest) = data.split_at(octets);
+        data = rest;
+        bytes.iter().fold(0, |len, &b| (len << 8) | b as usize)
+    };
+
+    if data.len() < len {
+        return None;
+    }
+    let (contents, rest) = data.split_at(len);
+    Some((tag, contents, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{spki, CertificatePins};
+
+    // A minimal certificate: version, serial, signature algorithm, empty
+    // issuer, validity and subject, then the public key info.
+    const CERT: &[u8] = &[
+        0x30, 0x1d, // Certificate
+        0x30, 0x1b, // TBSCertificate
+        0xa0, 0x03, 0x02, 0x01, 0x02, // version
+        0x02, 0x01, 0x01, // serialNumber
+        0x30, 0x00, // signature
+        0x30, 0x00, // issuer
+        0x30, 0x00, // validity
+        0x30, 0x00, // subject
+        0x30, 0x09, 0x30, 0x02, 0x06, 0x00, 0x03, 0x03, 0x00, 0x01, 0x02, // subjectPublicKeyInfo
+    ];
+
+    fn hash(data: &[u8]) -> [u8; 32] {
+        use sha2::Digest;
+        sha2::Sha256::digest(data).into()
+    }
+
+    #[test]
+    fn spki_of_certificate() {
+        assert_eq!(spki(CERT), Some(&CERT[20..]));
+        assert_eq!(spki(&CERT[..CERT.len() - 1]), None);
+        assert_eq!(spki(&[]), None);
+    }
+
+    #[test]
+    fn pins_match_hosts() {
+        let good = hash(&CERT[20..]);
+        let mut pins = CertificatePins::default();
+        pins.add("API.example.com", vec![good]).unwrap();
+        pins.add("*.example.com", vec![[0; 32]]).unwrap();
+
+        let leaf = || Some(CERT.to_vec());
+        pins.check("api.example.com.", leaf).unwrap();
+        pins.check("www.example.com", leaf).unwrap_err();
+        pins.check("a.b.example.com", leaf).unwrap_err();
+        pins.check("api.example.com", || None).unwrap_err();
+        // Wildcards don't cover the domain itself.
+        pins.check("example.com", leaf).unwrap();
+        pins.check("notexample.com", leaf).unwrap();
+
+        pins.add("*.example.com", vec![good]).unwrap();
+        pins.check("www.example.com", leaf).unwrap();
+    }
+
+    #[test]
+    fn pins_reject_bad_patterns() {
+        let mut pins = CertificatePins::default();
+        pins.add("", vec![[0; 32]]).unwrap_err();
+        pins.add("*", vec![[0; 32]]).unwrap_err();
+        pins.add("a.*.com", vec![[0; 32]]).unwrap_err();
+        pins.add("example.com", vec![]).unwrap_err();
+        assert!(pins.is_empty());
+    }
+}
@@ -0,0 +1,324 @@
+//! TLS session resumption for the BoringSSL backend.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use boring::ex_data::Index;
+use boring::ssl::{
+    ConnectConfiguration, Ssl, SslConnectorBuilder, SslSession, SslSessionCacheMode, SslVersion,
+};
+use once_cell::sync::OnceCell;
+
+/// How many sessions are kept for a single host, route and profile.
+const MAX_SESSIONS_PER_HOST: usize = 4;
+/// How many hosts, routes and profiles sessions are kept for, the least
+/// recently used being dropped first.
+const MAX_HOSTS: usize = 1024;
+
+/// A cache of TLS sessions used to resume handshakes with hosts seen before.
+///
+/// Every `Client` using the BoringSSL backend keeps one of these, holding
+/// TLS 1.2 session tickets (or session IDs, for profiles that disable
+/// tickets, like Safari) and TLS 1.3 pre-shared keys per host and port.
+///
+/// Sessions are also kept apart by the route the connection took, that is
+/// the proxy it went through along with its credentials and headers, and by
+/// the impersonated profile. Resuming a session over another route or as
+/// another browser would let the server link the two.
+///
+/// Up to 1024 hosts are remembered, the least recently used ones being
+/// forgotten first.
+///
+/// A cache can be created up front and handed to
+/// [`ClientBuilder::tls_session_cache`](crate::ClientBuilder::tls_session_cache)
+/// to share it between clients, or to [`export`](TlsSessionCache::export)
+/// the sessions and [`import`](TlsSessionCache::import) them in a later
+/// process.
+#[derive(Clone, Default)]
+pub struct TlsSessionCache {
+    inner: Arc<Mutex<Sessions>>,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub(crate) struct SessionKey {
+    host: String,
+    port: u16,
+    route: String,
+    profile: String,
+}
+
+impl SessionKey {
+    /// `route` tells the proxy apart from others, and is empty for direct
+    /// connections. `profile` is the impersonated browser, if any.
+    pub(crate) fn new(host: &str, port: u16, route: String, profile: String) -> SessionKey {
+        SessionKey {
+            host: host.to_owned(),
+            port,
+            route,
+            profile,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Sessions {
+    hosts: HashMap<SessionKey, Entry>,
+    /// Counts the uses of the cache, to tell which entry was used last.
+    clock: u64,
+}
+
+#[derive(Default)]
+struct Entry {
+    sessions: VecDeque<SslSession>,
+    used: u64,
+}
+
+impl TlsSessionCache {
+    /// Creates an empty cache.
+    pub fn new() -> TlsSessionCache {
+        TlsSessionCache::default()
+    }
+
+    /// Returns the number of sessions in the cache.
+    pub fn len(&self) -> usize {
+        self.lock().hosts.values().map(|e| e.sessions.len()).sum()
+    }
+
+    /// Returns `true` if the cache holds no sessions.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes every session from the cache.
+    pub fn clear(&self) {
+        self.lock().hosts.clear();
+    }
+
+    /// Serializes every unexpired session in the cache.
+    ///
+    /// The result can be passed to [`import`](TlsSessionCache::import),
+    /// possibly in another process. It contains secret key material and
+    /// should be stored accordingly.
+    pub fn export(&self) -> Vec<u8> {
+        let now = unix_now();
+        let mut out = Vec::new();
+        for (key, entry) in self.lock().hosts.iter() {
+            for session in entry.sessions.iter().filter(|s| !expired(s, now)) {
+                let der = match session.to_der() {
+                    Ok(der) => der,
+                    Err(_) => continue,
+                };
+                put_str(&mut out, &key.host);
+                out.extend_from_slice(&key.port.to_be_bytes());
+                put_str(&mut out, &key.route);
+                put_str(&mut out, &key.profile);
+                out.extend_from_slice(&(der.len() as u32).to_be_bytes());
+                out.extend_from_slice(&der);
+            }
+        }
+        out
+    }
+
+    /// Adds the sessions produced by [`export`](TlsSessionCache::export).
+    ///
+    /// Expired sessions are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `data` wasn't produced by `export`. Nothing is imported in
+    /// that case.
+    pub fn import(&self, data: &[u8]) -> crate::Result<()> {
+        fn take<'a>(data: &mut &'a [u8], n: usize) -> crate::Result<&'a [u8]> {
+            if data.len() < n {
+                return Err(crate::error::builder("truncated TLS session data"));
+            }
+            let (head, tail) = data.split_at(n);
+            *data = tail;
+            Ok(head)
+        }
+
+        fn take_str(data: &mut &[u8]) -> crate::Result<String> {
+            let len = take(data, 2)?;
+            let len = u16::from_be_bytes([len[0], len[1]]);
+            std::str::from_utf8(take(data, len as usize)?)
+                .map(str::to_owned)
+                .map_err(crate::error::builder)
+        }
+
+        let mut data = data;
+        let mut parsed = Vec::new();
+        while !data.is_empty() {
+            let host = take_str(&mut data)?;
+            let port = take(&mut data, 2)?;
+            let port = u16::from_be_bytes([port[0], port[1]]);
+            let route = take_str(&mut data)?;
+            let profile = take_str(&mut data)?;
+            let len = take(&mut data, 4)?;
+            let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]);
+            let session =
+                SslSession::from_der(take(&mut data, len as usize)?).map_err(crate::error::builder)?;
+            let key = SessionKey {
+                host,
+                port,
+                route,
+                profile,
+            };
+            parsed.push((key, session));
+        }
+
+        let now = unix_now();
+        let mut sessions = self.lock();
+        for (key, session) in parsed {
+            if !expired(&session, now) {
+                sessions.insert(key, session);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn same(&self, other: &TlsSessionCache) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    /// Makes connections from `builder` report new sessions to this cache.
+    ///
+    /// This leaves the ticket options of the profile alone, so a profile
+    /// setting `NO_TICKET` still resumes TLS 1.2 by session ID only.
+    pub(crate) fn attach(&self, builder: &mut SslConnectorBuilder) {
+        builder.set_session_cache_mode(SslSessionCacheMode::CLIENT);
+
+        let cache = self.clone();
+        builder.set_new_session_callback(move |ssl, session| {
+            if let Some(key) = key_index().ok().and_then(|idx| ssl.ex_data(idx)) {
+                cache.lock().insert(key.clone(), session);
+            }
+        });
+    }
+
+    /// Offers a cached session for `key`, if there is one.
+    pub(crate) fn resume(
+        &self,
+        conf: &mut ConnectConfiguration,
+        key: SessionKey,
+    ) -> Result<(), boring::error::ErrorStack> {
+        if let Some(session) = self.lock().take(&key) {
+            // SAFETY: sessions are only ever created by connectors with the
+            // same SSL_METHOD, so they can be used with any of them.
+            unsafe { conf.set_session(&session)? };
+        }
+
+        conf.set_ex_data(key_index()?, key);
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Sessions> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Sessions {
+    fn insert(&mut self, key: SessionKey, session: SslSession) {
+        let now = unix_now();
+        self.hosts.retain(|_, entry| {
+            entry.sessions.retain(|s| !expired(s, now));
+            !entry.sessions.is_empty()
+        });
+        if self.hosts.len() >= MAX_HOSTS && !self.hosts.contains_key(&key) {
+            let oldest = self
+                .hosts
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.hosts.remove(&oldest);
+            }
+        }
+
+        self.clock += 1;
+        let entry = self.hosts.entry(key).or_default();
+        entry.used = self.clock;
+        if entry.sessions.len() == MAX_SESSIONS_PER_HOST {
+            entry.sessions.pop_front();
+        }
+        entry.sessions.push_back(session);
+    }
+
+    fn take(&mut self, key: &SessionKey) -> Option<SslSession> {
+        let now = unix_now();
+        self.clock += 1;
+        let entry = self.hosts.get_mut(key)?;
+        entry.used = self.clock;
+        let sessions = &mut entry.sessions;
+        sessions.retain(|s| !expired(s, now));
+
+        let session = sessions.back().cloned();
+        // TLS 1.3 tickets are single use, so concurrent handshakes mustn't
+        // offer the same one.
+        //
+        // https://www.rfc-editor.org/rfc/rfc8446#appendix-C.4
+        if let Some(ref session) = session {
+            if session.protocol_version() == SslVersion::TLS1_3 {
+                sessions.pop_back();
+            }
+        }
+        if sessions.is_empty() {
+            self.hosts.remove(key);
+        }
+        session
+    }
+}
+
+impl fmt::Debug for TlsSessionCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TlsSessionCache")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+fn key_index() -> Result<Index<Ssl, SessionKey>, boring::error::ErrorStack> {
+    static IDX: OnceCell<Index<Ssl, SessionKey>> = OnceCell::new();
+    IDX.get_or_try_init(Ssl::new_ex_index).copied()
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn expired(session: &SslSession, now: u64) -> bool {
+    session.time() + u64::from(session.timeout()) <= now
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TlsSessionCache;
+
+    #[test]
+    fn import_rejects_garbage() {
+        let cache = TlsSessionCache::new();
+        cache.import(b"\x00\x09localhost").unwrap_err();
+        cache.import(b"\x00\x01a\x01\xbb\x00\x00\x00\x02\x30\x00").unwrap_err();
+        cache
+            .import(b"\x00\x01a\x01\xbb\x00\x00\x00\x00\x00\x00\x00\x02\x30\x00")
+            .unwrap_err();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn export_empty_roundtrip() {
+        let cache = TlsSessionCache::new();
+        assert!(cache.export().is_empty());
+        cache.import(&[]).unwrap();
+        assert!(cache.is_empty());
+    }
+}
@@ -411,6 +411,81 @@ fn update_json_content_type_if_set_manually() {
     assert_eq!("application/json", req.headers().get(CONTENT_TYPE).unwrap());
 }
 
+#[cfg(feature = "__impersonate")]
+#[tokio::test]
+async fn impersonate_per_request() {
+    use reqwest::impersonate::Impersonate;
+
+    let server = server::http(move |req| async move {
+        let ua = req.headers()["user-agent"].to_str().unwrap();
+        match req.uri().path() {
+            "/chrome" => assert!(ua.contains("Chrome/120"), "{}", ua),
+            "/okhttp" => assert!(ua.contains("OkHttp/4.9"), "{}", ua),
+            _ => unreachable!(),
+        }
+        http::Response::default()
+    });
+
+    let client = reqwest::Client::builder()
+        .impersonate(Impersonate::Chrome120)
+        .no_proxy()
+        .build()
+        .expect("client builder");
+
+    let res = client
+        .get(format!("http://{}/chrome", server.addr()))
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let res = client
+        .get(format!("http://{}/okhttp", server.addr()))
+        .impersonate(Impersonate::OkHttp4_9)
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
+#[cfg(feature = "__impersonate")]
+#[tokio::test]
+async fn impersonate_per_request_keeps_default_headers() {
+    use reqwest::impersonate::Impersonate;
+
+    let server = server::http(move |req| async move {
+        assert_eq!(req.headers()["user-agent"], "custom/1.0");
+        assert_eq!(req.headers()["authorization"], "Bearer token");
+        // The rest comes from the request's profile.
+        assert_eq!(
+            req.headers()["accept-language"],
+            "de-DE,de;q=0.9,en-US;q=0.8,en;q=0.7"
+        );
+        http::Response::default()
+    });
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(
+        reqwest::header::AUTHORIZATION,
+        "Bearer token".parse().unwrap(),
+    );
+    let client = reqwest::Client::builder()
+        .impersonate(Impersonate::Chrome120)
+        .default_headers(headers)
+        .user_agent("custom/1.0")
+        .no_proxy()
+        .build()
+        .expect("client builder");
+
+    let res = client
+        .get(format!("http://{}/okhttp", server.addr()))
+        .impersonate(Impersonate::OkHttp4_9)
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
 #[cfg(all(feature = "__tls", not(feature = "rustls-tls-manual-roots")))]
 #[tokio::test]
 async fn test_tls_info() {
@@ -440,3 +515,875 @@ async fn test_tls_info() {
     let tls_info = resp.extensions().get::<reqwest::tls::TlsInfo>();
     assert!(tls_info.is_none());
 }
+
+#[cfg(feature = "__boring")]
+#[tokio::test]
+async fn tls_session_resumption() {
+    let server = server::https(|req| async move {
+        let tls = req
+            .extensions()
+            .get::<support::server::TlsConnection>()
+            .expect("tls connection");
+        http::Response::new(if tls.session_reused { "resumed" } else { "full" }.into())
+    });
+    let url = format!("https://localhost:{}/", server.addr().port());
+
+    async fn handshake(client: &reqwest::Client, url: &str) -> String {
+        let res = client.get(url).send().await.expect("request");
+        res.text().await.expect("text")
+    }
+
+    let cache = reqwest::tls::TlsSessionCache::new();
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .pool_max_idle_per_host(0)
+        .tls_session_cache(cache.clone())
+        .no_proxy()
+        .build()
+        .expect("client builder");
+
+    assert_eq!(handshake(&client, &url).await, "full");
+    assert_eq!(handshake(&client, &url).await, "resumed");
+    assert!(!cache.is_empty());
+
+    // Sessions survive a round trip through `export`.
+    let imported = reqwest::tls::TlsSessionCache::new();
+    imported.import(&cache.export()).expect("import");
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .pool_max_idle_per_host(0)
+        .tls_session_cache(imported)
+        .no_proxy()
+        .build()
+        .expect("client builder");
+    assert_eq!(handshake(&client, &url).await, "resumed");
+
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .pool_max_idle_per_host(0)
+        .tls_session_resumption(false)
+        .no_proxy()
+        .build()
+        .expect("client builder");
+    assert_eq!(handshake(&client, &url).await, "full");
+    assert_eq!(handshake(&client, &url).await, "full");
+}
+
+#[cfg(feature = "__impersonate")]
+#[tokio::test]
+async fn tls_sessions_kept_per_profile() {
+    use reqwest::impersonate::Impersonate;
+
+    let server = server::https(|req| async move {
+        let tls = req
+            .extensions()
+            .get::<support::server::TlsConnection>()
+            .expect("tls connection");
+        http::Response::new(if tls.session_reused { "resumed" } else { "full" }.into())
+    });
+    let url = format!("https://localhost:{}/", server.addr().port());
+
+    let cache = reqwest::tls::TlsSessionCache::new();
+    let client = |ver| {
+        reqwest::Client::builder()
+            .impersonate(ver)
+            .danger_accept_invalid_certs(true)
+            .pool_max_idle_per_host(0)
+            .tls_session_cache(cache.clone())
+            .no_proxy()
+            .build()
+            .expect("client builder")
+    };
+    let chrome = client(Impersonate::Chrome120);
+    let okhttp = client(Impersonate::OkHttp4_9);
+
+    async fn handshake(req: reqwest::RequestBuilder) -> String {
+        req.send().await.expect("request").text().await.expect("text")
+    }
+
+    assert_eq!(handshake(chrome.get(&url)).await, "full");
+    // Another browser, even on a client sharing the cache, doesn't resume
+    // Chrome's session.
+    assert_eq!(handshake(okhttp.get(&url)).await, "full");
+    assert_eq!(handshake(okhttp.get(&url)).await, "resumed");
+    // Nor does a per-request profile.
+    let req = chrome.get(&url).impersonate(Impersonate::OkHttp4_9);
+    assert_eq!(handshake(req).await, "resumed");
+    let req = chrome.get(&url).impersonate(Impersonate::Safari17_2_1);
+    assert_eq!(handshake(req).await, "full");
+    assert_eq!(handshake(chrome.get(&url)).await, "resumed");
+}
+
+#[cfg(feature = "__boring")]
+#[tokio::test]
+async fn boring_connector_built_once() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let server = server::https(|_| async { http::Response::default() });
+    let url = format!("https://localhost:{}/", server.addr().port());
+
+    let built = Arc::new(AtomicUsize::new(0));
+    let counter = built.clone();
+    let client = reqwest::Client::builder()
+        .use_boring_tls(Arc::new(move |_h2| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            boring::ssl::SslConnector::builder(boring::ssl::SslMethod::tls()).unwrap()
+        }))
+        .danger_accept_invalid_certs(true)
+        .pool_max_idle_per_host(0)
+        .no_proxy()
+        .build()
+        .expect("client builder");
+
+    for _ in 0..3 {
+        let res = client.get(&url).send().await.expect("request");
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+    assert_eq!(built.load(Ordering::SeqCst), 1);
+}
+
+#[cfg(feature = "__boring")]
+#[tokio::test]
+async fn tls_early_data() {
+    let server = server::https(|req| async move {
+        let tls = req
+            .extensions()
+            .get::<support::server::TlsConnection>()
+            .expect("tls connection");
+        http::Response::new(if tls.session_reused { "resumed" } else { "full" }.into())
+    });
+    let url = format!("https://localhost:{}/", server.addr().port());
+
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .pool_max_idle_per_host(0)
+        .tls_early_data(true)
+        .no_proxy()
+        .build()
+        .expect("client builder");
+
+    let res = client.get(&url).send().await.expect("request");
+    assert!(res.extensions().get::<reqwest::tls::EarlyData>().is_none());
+    assert_eq!(res.text().await.expect("text"), "full");
+
+    let res = client.get(&url).send().await.expect("request");
+    let early_data = res
+        .extensions()
+        .get::<reqwest::tls::EarlyData>()
+        .expect("early data")
+        .clone();
+    assert!(early_data.accepted());
+    // The details are taken once the handshake completes.
+    let tls = res
+        .extensions()
+        .get::<reqwest::ConnectionInfo>()
+        .and_then(|info| info.tls())
+        .expect("tls details");
+    assert!(tls.session_resumed());
+    assert_eq!(tls.version(), Some(reqwest::tls::Version::TLS_1_3));
+    assert_eq!(res.text().await.expect("text"), "resumed");
+
+    // Other requests wait for the handshake, even idempotent ones.
+    let res = client.post(&url).body("hi").send().await.expect("request");
+    assert!(res.extensions().get::<reqwest::tls::EarlyData>().is_none());
+    assert_eq!(res.text().await.expect("text"), "resumed");
+    let res = client.put(&url).body("hi").send().await.expect("request");
+    assert!(res.extensions().get::<reqwest::tls::EarlyData>().is_none());
+    assert_eq!(res.text().await.expect("text"), "resumed");
+}
+
+#[cfg(feature = "__impersonate")]
+#[tokio::test]
+async fn tls_early_data_follows_the_profile() {
+    use reqwest::impersonate::Impersonate;
+
+    let server = server::https(|_| async { http::Response::default() });
+    let url = format!("https://localhost:{}/", server.addr().port());
+
+    async fn sends_early_data(builder: reqwest::ClientBuilder, url: &str) -> bool {
+        let client = builder
+            .danger_accept_invalid_certs(true)
+            .pool_max_idle_per_host(0)
+            .no_proxy()
+            .build()
+            .expect("client builder");
+        client.get(url).send().await.expect("request");
+        let res = client.get(url).send().await.expect("request");
+        res.extensions().get::<reqwest::tls::EarlyData>().is_some()
+    }
+
+    let chrome = || reqwest::Client::builder().impersonate(Impersonate::Chrome120);
+    assert!(sends_early_data(chrome(), &url).await);
+    assert!(!sends_early_data(chrome().tls_early_data(false), &url).await);
+    let builder = reqwest::Client::builder().tls_early_data(false);
+    assert!(!sends_early_data(builder.impersonate(Impersonate::Chrome120), &url).await);
+    let okhttp = reqwest::Client::builder().impersonate(Impersonate::OkHttp4_9);
+    assert!(!sends_early_data(okhttp, &url).await);
+}
+
+#[cfg(feature = "__boring")]
+#[tokio::test]
+async fn tls_early_data_rejected() {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let early_data = Arc::new(AtomicBool::new(true));
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    let server = server::https_with_early_data(early_data.clone(), move |req| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        async move {
+            let tls = req
+                .extensions()
+                .get::<support::server::TlsConnection>()
+                .expect("tls connection");
+            http::Response::new(format!("{}", tls.early_data_accepted).into())
+        }
+    });
+    let url = format!("https://localhost:{}/", server.addr().port());
+
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .pool_max_idle_per_host(0)
+        .tls_early_data(true)
+        .no_proxy()
+        .build()
+        .expect("client builder");
+
+    let res = client.get(&url).send().await.expect("request");
+    assert_eq!(res.text().await.expect("text"), "false");
+
+    // The server rejects the early data, so the request is sent again after
+    // the handshake, and only that copy is handled.
+    early_data.store(false, Ordering::SeqCst);
+    let res = client.get(&url).send().await.expect("request");
+    let accepted = res
+        .extensions()
+        .get::<reqwest::tls::EarlyData>()
+        .expect("early data")
+        .accepted();
+    assert!(!accepted);
+    assert_eq!(res.text().await.expect("text"), "false");
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+}
+
+#[cfg(feature = "__boring")]
+#[tokio::test]
+async fn pin_certificate() {
+    use boring::hash::{hash, MessageDigest};
+    use boring::x509::X509;
+
+    let server = server::https(|_| async { http::Response::new("pinned".into()) });
+    let url = format!("https://localhost:{}/", server.addr().port());
+
+    let res = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .tls_info(true)
+        .no_proxy()
+        .build()
+        .expect("client builder")
+        .get(&url)
+        .send()
+        .await
+        .expect("request");
+    let der = res
+        .extensions()
+        .get::<reqwest::tls::TlsInfo>()
+        .and_then(|info| info.peer_certificate())
+        .expect("peer certificate")
+        .to_vec();
+    let spki = X509::from_der(&der)
+        .and_then(|cert| cert.public_key())
+        .and_then(|key| key.public_key_to_der())
+        .expect("spki");
+    let mut pin = [0; 32];
+    pin.copy_from_slice(&hash(MessageDigest::sha256(), &spki).expect("hash"));
+
+    let pinned = |hashes: Vec<[u8; 32]>| {
+        reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .pin_certificate("LOCALHOST", hashes)
+            .no_proxy()
+            .build()
+            .expect("client builder")
+    };
+
+    let res = pinned(vec![[0; 32], pin]).get(&url).send().await.expect("request");
+    assert_eq!(res.text().await.expect("text"), "pinned");
+
+    let err = pinned(vec![[0; 32]]).get(&url).send().await.unwrap_err();
+    assert!(err.is_certificate_pin(), "{:?}", err);
+
+    // Other hosts aren't affected.
+    let res = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .pin_certificate("*.localhost", [[0; 32]])
+        .no_proxy()
+        .build()
+        .expect("client builder")
+        .get(&url)
+        .send()
+        .await
+        .expect("request");
+    assert!(res.status().is_success());
+
+    let err = reqwest::Client::builder()
+        .pin_certificate("*.*.example.com", [pin])
+        .build()
+        .unwrap_err();
+    assert!(err.is_builder());
+}
+
+#[cfg(feature = "__boring")]
+#[tokio::test]
+async fn boring_client_certificate() {
+    use boring::pkcs12::Pkcs12;
+    use reqwest::impersonate::Impersonate;
+
+    let server = server::https(|req| async move {
+        let tls = req
+            .extensions()
+            .get::<support::server::TlsConnection>()
+            .expect("tls connection");
+        http::Response::new(tls.client_certificate.clone().unwrap_or_default().into())
+    });
+    let url = format!("https://localhost:{}/", server.addr().port());
+
+    let (cert, key) = support::server::self_signed();
+    let cert_der = cert.to_der().unwrap();
+
+    let mut pem = key.private_key_to_pem_pkcs8().unwrap();
+    pem.extend(cert.to_pem().unwrap());
+    let pkcs12 = Pkcs12::builder()
+        .build("secret", "client", &key, &cert)
+        .and_then(|p12| p12.to_der())
+        .unwrap();
+
+    let identities = vec![
+        reqwest::Identity::from_pem(&pem).expect("pem identity"),
+        reqwest::Identity::from_pkcs12_der(&pkcs12, "secret").expect("pkcs12 identity"),
+    ];
+    for identity in identities {
+        let client = reqwest::Client::builder()
+            .impersonate(Impersonate::Chrome120)
+            .identity(identity)
+            .danger_accept_invalid_certs(true)
+            .no_proxy()
+            .build()
+            .expect("client builder");
+
+        let res = client.get(&url).send().await.expect("request");
+        assert_eq!(res.bytes().await.expect("bytes"), cert_der);
+
+        // Per-request profiles present the certificate too.
+        let res = client
+            .get(&url)
+            .impersonate(Impersonate::Safari17_2_1)
+            .send()
+            .await
+            .expect("request");
+        assert_eq!(res.bytes().await.expect("bytes"), cert_der);
+    }
+
+    let res = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .no_proxy()
+        .build()
+        .expect("client builder")
+        .get(&url)
+        .send()
+        .await
+        .expect("request");
+    assert!(res.bytes().await.expect("bytes").is_empty());
+
+    reqwest::Identity::from_pkcs12_der(&pkcs12, "wrong").unwrap_err();
+
+    // PEM identities are parsed by rustls when it's built too, which
+    // doesn't match keys to certificates.
+    if cfg!(feature = "__rustls") {
+        return;
+    }
+    let (_, other_key) = support::server::self_signed();
+    let mut mismatched = other_key.private_key_to_pem_pkcs8().unwrap();
+    mismatched.extend(cert.to_pem().unwrap());
+    reqwest::Identity::from_pem(&mismatched).unwrap_err();
+}
+
+#[cfg(feature = "__boring")]
+#[tokio::test]
+async fn connection_info() {
+    use std::time::{Duration, SystemTime};
+
+    let server = server::https(|_| async { http::Response::default() });
+    let url = format!("https://localhost:{}/", server.addr().port());
+
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .pool_max_idle_per_host(0)
+        .no_proxy()
+        .build()
+        .expect("client builder");
+
+    let res = client.get(&url).send().await.expect("request");
+    assert_eq!(res.version(), http::Version::HTTP_2);
+    let info = res
+        .extensions()
+        .get::<reqwest::ConnectionInfo>()
+        .expect("connection info")
+        .clone();
+
+    let tls = info.tls().expect("tls details");
+    assert_eq!(tls.version(), Some(reqwest::tls::Version::TLS_1_3));
+    assert!(tls.cipher().expect("cipher").starts_with("TLS_"));
+    assert!(tls.group().is_some());
+    assert_eq!(tls.alpn(), Some(&b"h2"[..]));
+    assert!(!tls.session_resumed());
+    assert!(!tls.ech_accepted());
+    assert_eq!(tls.peer_certificates().len(), 1);
+    assert_eq!(tls.subject(), Some("CN=localhost"));
+    assert!(tls.subject_alt_names().is_empty());
+    let not_after = tls.not_after().expect("not after");
+    assert!(not_after > SystemTime::now());
+    assert!(not_after < SystemTime::now() + Duration::from_secs(2 * 24 * 60 * 60));
+
+    // hyper's server advertises a 1MB stream window.
+    let settings = info.http2_settings().expect("http2 settings");
+    assert_eq!(settings.initial_window_size(), Some(1024 * 1024));
+
+    let res = client
+        .get(&url)
+        .version(http::Version::HTTP_11)
+        .send()
+        .await
+        .expect("request");
+    let info = res.extensions().get::<reqwest::ConnectionInfo>().unwrap();
+    assert!(info.tls().expect("tls details").session_resumed());
+
+    let server = server::http(|_| async { http::Response::default() });
+    let res = reqwest::Client::builder()
+        .no_proxy()
+        .build()
+        .expect("client builder")
+        .get(&format!("http://{}/", server.addr()))
+        .send()
+        .await
+        .expect("request");
+    let info = res.extensions().get::<reqwest::ConnectionInfo>().unwrap();
+    assert!(info.tls().is_none());
+    assert!(info.http2_settings().is_none());
+}
+
+#[cfg(feature = "__impersonate")]
+#[tokio::test]
+async fn encrypted_client_hello() {
+    use server::EchKey;
+
+    let current = EchKey::generate(1, "public.example");
+    let stale = EchKey::generate(2, "public.example");
+    let handler = |req: http::Request<hyper::Body>| async move {
+        let tls = req.extensions().get::<server::TlsConnection>().unwrap();
+        http::Response::new(format!("{}", tls.ech_accepted).into())
+    };
+    let ech_server = server::https_with_ech(&[(&current, true)], handler);
+    let plain_server = server::https(handler);
+
+    let fetch = |config: Vec<u8>, port: u16| async move {
+        let res = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .ech_config("localhost", config)
+            .no_proxy()
+            .build()
+            .expect("client builder")
+            .get(&format!("https://localhost:{}/", port))
+            .send()
+            .await
+            .expect("request");
+        let info = res.extensions().get::<reqwest::ConnectionInfo>().unwrap();
+        let accepted = info.tls().expect("tls details").ech_accepted();
+        assert_eq!(res.text().await.unwrap(), accepted.to_string());
+        accepted
+    };
+
+    assert!(fetch(current.config_list(), ech_server.addr().port()).await);
+    // Rejected, then retried with the config the server sent back.
+    assert!(fetch(stale.config_list(), ech_server.addr().port()).await);
+    // Rejected by a server without ECH, then retried without it.
+    assert!(!fetch(current.config_list(), plain_server.addr().port()).await);
+
+    let err = reqwest::Client::builder()
+        .ech_config("localhost", vec![0x00, 0x01, 0x00])
+        .build()
+        .unwrap_err();
+    assert!(err.is_builder());
+}
+
+#[cfg(feature = "__impersonate")]
+#[tokio::test]
+async fn https_dns_records() {
+    use reqwest::dns::{HttpsRecord, Name, Resolve, Resolving, ResolvingHttps};
+    use server::EchKey;
+    use std::net::SocketAddr;
+
+    struct Records(u16, Vec<u8>);
+
+    impl Resolve for Records {
+        fn resolve(&self, _: Name) -> Resolving {
+            let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+            let addrs: reqwest::dns::Addrs = Box::new(std::iter::once(addr));
+            Box::pin(futures_util::future::ready(Ok(addrs)))
+        }
+
+        fn resolve_https(&self, name: Name) -> ResolvingHttps {
+            let records = match name.as_str() {
+                "ech.test" => vec![
+                    HttpsRecord::new(2).with_port(1),
+                    HttpsRecord::new(1)
+                        .with_alpn(["h2"])
+                        .with_port(self.0)
+                        .with_ech_config_list(self.1.clone()),
+                ],
+                _ => Vec::new(),
+            };
+            Box::pin(futures_util::future::ready(Ok(records)))
+        }
+    }
+
+    let key = EchKey::generate(1, "public.example");
+    let server = server::https_with_ech(&[(&key, true)], |req| async move {
+        let tls = req.extensions().get::<server::TlsConnection>().unwrap();
+        http::Response::new(format!("{}", tls.ech_accepted).into())
+    });
+    let port = server.addr().port();
+    let resolver = std::sync::Arc::new(Records(port, key.config_list()));
+
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .dns_resolver(resolver.clone())
+        .no_proxy()
+        .build()
+        .expect("client builder");
+    let res = client.get("https://ech.test/").send().await.expect("request");
+    assert_eq!(res.version(), http::Version::HTTP_2);
+    assert_eq!(res.text().await.unwrap(), "true");
+
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .dns_resolver(resolver)
+        .https_dns_records(false)
+        .no_proxy()
+        .build()
+        .expect("client builder");
+    let res = client
+        .get(&format!("https://ech.test:{}/", port))
+        .send()
+        .await
+        .expect("request");
+    assert_eq!(res.text().await.unwrap(), "false");
+}
+
+// Pinned hosts stay on TCP, where their pins are checked, even when their
+// HTTPS records advertise h3.
+#[cfg(all(feature = "http3", feature = "__boring"))]
+#[tokio::test]
+async fn pinned_host_ignores_h3_records() {
+    use reqwest::dns::{HttpsRecord, Name, Resolve, Resolving, ResolvingHttps};
+    use std::net::SocketAddr;
+
+    struct Records(u16);
+
+    impl Resolve for Records {
+        fn resolve(&self, _: Name) -> Resolving {
+            let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+            let addrs: reqwest::dns::Addrs = Box::new(std::iter::once(addr));
+            Box::pin(futures_util::future::ready(Ok(addrs)))
+        }
+
+        fn resolve_https(&self, _: Name) -> ResolvingHttps {
+            let record = HttpsRecord::new(1).with_alpn(["h3"]).with_port(self.0);
+            Box::pin(futures_util::future::ready(Ok(vec![record])))
+        }
+    }
+
+    let server = server::https(|_| async { http::Response::new("pinned".into()) });
+    let port = server.addr().port();
+
+    let err = reqwest::Client::builder()
+        .use_rustls_tls()
+        .danger_accept_invalid_certs(true)
+        .dns_resolver(std::sync::Arc::new(Records(port)))
+        .pin_certificate("h3.test", [[0; 32]])
+        .no_proxy()
+        .build()
+        .expect("client builder")
+        .get(&format!("https://h3.test:{}/", port))
+        .send()
+        .await
+        .unwrap_err();
+    assert!(err.is_certificate_pin(), "{:?}", err);
+}
+
+// Requests go over HTTP/3 when HTTPS records advertise it, unless they ask
+// for a version, even HTTP/1.1.
+#[cfg(all(feature = "http3", feature = "__boring"))]
+#[tokio::test]
+async fn explicit_version_skips_h3_records() {
+    use reqwest::dns::{HttpsRecord, Name, Resolve, Resolving, ResolvingHttps};
+    use std::net::SocketAddr;
+
+    struct Records(u16);
+
+    impl Resolve for Records {
+        fn resolve(&self, _: Name) -> Resolving {
+            let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+            let addrs: reqwest::dns::Addrs = Box::new(std::iter::once(addr));
+            Box::pin(futures_util::future::ready(Ok(addrs)))
+        }
+
+        fn resolve_https(&self, _: Name) -> ResolvingHttps {
+            let record = HttpsRecord::new(1).with_alpn(["h3"]).with_port(self.0);
+            Box::pin(futures_util::future::ready(Ok(vec![record])))
+        }
+    }
+
+    let server = server::https(|_| async { http::Response::new("tcp".into()) });
+    let h3 = server::h3("h3").await;
+
+    let client = reqwest::Client::builder()
+        .use_rustls_tls()
+        .danger_accept_invalid_certs(true)
+        .dns_resolver(std::sync::Arc::new(Records(h3.port())))
+        .no_proxy()
+        .build()
+        .expect("client builder");
+    let url = format!("https://h3.test:{}/", server.addr().port());
+
+    let res = client
+        .get(&url)
+        .version(http::Version::HTTP_11)
+        .send()
+        .await
+        .expect("request");
+    assert_ne!(res.version(), http::Version::HTTP_3);
+    assert_eq!(res.text().await.unwrap(), "tcp");
+
+    let res = client.get(&url).send().await.expect("request");
+    assert_eq!(res.version(), http::Version::HTTP_3);
+    assert_eq!(res.text().await.unwrap(), "h3");
+}
+
+#[tokio::test]
+async fn ip_version() {
+    use reqwest::dns::{IpPreference, Name, Resolve, Resolving};
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // The resolver lists IPv4 first.
+    struct DualStack;
+
+    impl Resolve for DualStack {
+        fn resolve(&self, name: Name) -> Resolving {
+            let mut addrs = vec![SocketAddr::from(([127, 0, 0, 1], 0))];
+            if name.as_str() == "dual.test" {
+                addrs.push(SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 0)));
+            }
+            let addrs: reqwest::dns::Addrs = Box::new(addrs.into_iter());
+            Box::pin(futures_util::future::ready(Ok(addrs)))
+        }
+    }
+
+    let v4 = server::http(move |_req| async { http::Response::new("v4".into()) });
+    let port = v4.addr().port();
+    let v6 = server::http_on(([0, 0, 0, 0, 0, 0, 0, 1], port).into(), move |_req| async {
+        http::Response::new("v6".into())
+    });
+
+    let client = |preference: Option<IpPreference>| {
+        let builder = reqwest::Client::builder()
+            .no_proxy()
+            .dns_resolver(Arc::new(DualStack));
+        match preference {
+            Some(preference) => builder.ip_version(preference),
+            None => builder,
+        }
+        .build()
+        .unwrap()
+    };
+    let url = format!("http://dual.test:{}/", port);
+
+    // Whichever address is tried first answers.
+    for (preference, first) in [
+        (None, "v4"),
+        (Some(IpPreference::V4Only), "v4"),
+        (Some(IpPreference::V6Only), "v6"),
+        (Some(IpPreference::PreferV4), "v4"),
+        (Some(IpPreference::PreferV6), "v6"),
+        (
+            Some(IpPreference::HappyEyeballs {
+                delay: Duration::from_millis(50),
+            }),
+            "v6",
+        ),
+    ] {
+        let res = client(preference).get(&url).send().await.unwrap();
+        assert_eq!(res.text().await.unwrap(), first, "{:?}", preference);
+    }
+
+    // Nothing listens on [::1] anymore, so the other version is tried.
+    drop(v6);
+    let res = client(Some(IpPreference::PreferV6)).get(&url).send().await.unwrap();
+    assert_eq!(res.remote_addr(), Some(v4.addr()));
+
+    let err = client(Some(IpPreference::V6Only)).get(&url).send().await.unwrap_err();
+    assert!(err.is_connect());
+
+    let url = format!("http://v4.test:{}/", port);
+    let err = client(Some(IpPreference::V6Only)).get(url).send().await.unwrap_err();
+    assert!(
+        format!("{:?}", err).contains("no IPv6 address for v4.test"),
+        "{:?}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn resolve_to() {
+    let server = server::http(move |req| async move {
+        let port = req.headers()["host"].to_str().unwrap().rsplit(':').next();
+        let port = port.unwrap().to_owned();
+        match req.uri().path() {
+            "/" => http::Response::builder()
+                .status(302)
+                .header("location", format!("http://localhost:{}/done", port))
+                .body(Default::default())
+                .unwrap(),
+            "/done" => {
+                assert_eq!(req.headers()["host"], format!("localhost:{}", port));
+                http::Response::default()
+            }
+            _ => {
+                assert_eq!(req.headers()["host"], format!("pinned.test:{}", port));
+                http::Response::default()
+            }
+        }
+    });
+
+    // The client's own override points nowhere.
+    let client = reqwest::Client::builder()
+        .no_proxy()
+        .resolve("pinned.test", ([127, 0, 0, 2], 0).into())
+        .build()
+        .unwrap();
+
+    let url = format!("http://pinned.test:{}/pinned", server.addr().port());
+    let err = client.get(&url).send().await.unwrap_err();
+    assert!(err.is_connect());
+
+    for addr in [server.addr(), ([127, 0, 0, 1], 0).into()] {
+        let res = client.get(&url).resolve_to(addr).send().await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+        assert_eq!(res.remote_addr(), Some(server.addr()));
+    }
+
+    // Redirected to another origin, the request isn't pinned anymore.
+    let url = format!("http://pinned.test:{}/", server.addr().port());
+    let res = client
+        .get(&url)
+        .resolve_to(server.addr())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.url().path(), "/done");
+}
+
+#[cfg(feature = "hickory-dns")]
+#[tokio::test]
+async fn hickory_config() {
+    use reqwest::dns::HickoryConfig;
+    use std::time::Duration;
+
+    let server = server::http(move |_req| async { http::Response::default() });
+    let config = HickoryConfig::new()
+        .nameserver_udp(support::dns::udp())
+        .timeout(Duration::from_secs(1))
+        .attempts(1)
+        .cache_size(0);
+    let client = reqwest::Client::builder()
+        .no_proxy()
+        .hickory_config(config)
+        .build()
+        .unwrap();
+
+    let url = format!("http://doh.test:{}/", server.addr().port());
+    let res = client.get(url).send().await.unwrap();
+    assert_eq!(res.remote_addr(), Some(server.addr()));
+
+    let url = format!("http://missing.test:{}/", server.addr().port());
+    let err = client.get(url).send().await.unwrap_err();
+    assert!(err.is_connect());
+}
+
+#[tokio::test]
+async fn hosts_file_and_filter_resolvers() {
+    use reqwest::dns::{FilterResolver, HostsFileResolver};
+    use std::sync::Arc;
+
+    let server = server::http(move |_req| async { http::Response::default() });
+    let path = std::env::temp_dir().join(format!("chromimic-test-hosts-{}", std::process::id()));
+    std::fs::write(&path, "127.0.0.1 hosts.test\n").unwrap();
+    let hosts = Arc::new(HostsFileResolver::open(&path).unwrap());
+    std::fs::remove_file(&path).unwrap();
+
+    let url = format!("http://hosts.test:{}/", server.addr().port());
+    let client = reqwest::Client::builder()
+        .no_proxy()
+        .dns_resolver(hosts.clone())
+        .build()
+        .unwrap();
+    let res = client.get(&url).send().await.unwrap();
+    assert_eq!(res.remote_addr(), Some(server.addr()));
+
+    let client = reqwest::Client::builder()
+        .no_proxy()
+        .dns_resolver(Arc::new(FilterResolver::public(hosts)))
+        .build()
+        .unwrap();
+    let err = client.get(&url).send().await.unwrap_err();
+    assert!(err.is_connect());
+    assert!(
+        format!("{:?}", err).contains("no allowed address for hosts.test"),
+        "{:?}",
+        err
+    );
+}
+
+// SOCKS proxies without remote DNS connect to the addresses the client
+// resolves, so the filter applies to them too.
+#[cfg(feature = "socks")]
+#[tokio::test]
+async fn filter_resolver_applies_to_socks5() {
+    use reqwest::dns::{FilterResolver, HostsFileResolver};
+    use std::sync::Arc;
+
+    let path = std::env::temp_dir().join(format!("chromimic-test-socks-{}", std::process::id()));
+    std::fs::write(&path, "127.0.0.1 hosts.test\n").unwrap();
+    let hosts = Arc::new(HostsFileResolver::open(&path).unwrap());
+    std::fs::remove_file(&path).unwrap();
+
+    // Nothing should reach the proxy.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let proxy = format!("socks5://{}", listener.local_addr().unwrap());
+    let client = reqwest::Client::builder()
+        .proxy(reqwest::Proxy::all(&proxy).unwrap())
+        .dns_resolver(Arc::new(FilterResolver::public(hosts)))
+        .build()
+        .unwrap();
+    let err = client.get("http://hosts.test/").send().await.unwrap_err();
+    assert!(err.is_connect());
+    assert!(
+        format!("{:?}", err).contains("no allowed address for hosts.test"),
+        "{:?}",
+        err
+    );
+}
@@ -0,0 +1,249 @@
+#![cfg(not(target_arch = "wasm32"))]
+mod support;
+use chromimic as reqwest;
+use support::server;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// The value of a parameter of a Digest `Authorization` header.
+fn param<'a>(header: &'a str, name: &str) -> &'a str {
+    let start = header.find(&format!(" {}=", name)).expect(name) + name.len() + 2;
+    let value = header[start..].split(',').next().unwrap();
+    value.trim_matches('"')
+}
+
+#[tokio::test]
+async fn answers_digest_challenges() {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counted = requests.clone();
+    let server = server::http(move |req| {
+        counted.fetch_add(1, Ordering::SeqCst);
+        let res = match req.headers().get("authorization") {
+            Some(auth) => {
+                let auth = auth.to_str().unwrap();
+                assert!(auth.starts_with("Digest "));
+                assert_eq!(param(auth, "realm"), "api");
+                assert_eq!(param(auth, "uri"), req.uri().to_string());
+                assert_eq!(param(auth, "algorithm"), "SHA-256");
+                assert_eq!(param(auth, "qop"), "auth");
+                assert_eq!(param(auth, "opaque"), "o");
+                let body = format!("{} {}", param(auth, "username"), param(auth, "nc"));
+                http::Response::new(body.into())
+            }
+            None => http::Response::builder()
+                .status(401)
+                .header("www-authenticate", "Basic realm=\"api\"")
+                .header(
+                    "www-authenticate",
+                    "Digest realm=\"api\", qop=\"auth\", algorithm=SHA-256, \
+                     nonce=\"n1\", opaque=\"o\"",
+                )
+                .body(Default::default())
+                .unwrap(),
+        };
+        async move { res }
+    });
+    let url = format!("http://{}/private?q=1", server.addr());
+
+    let client = reqwest::Client::builder()
+        .digest_auth("Mufasa", "Circle of Life")
+        .build()
+        .unwrap();
+    let res = client.post(&url).body("hello").send().await.unwrap();
+    assert_eq!(res.text().await.unwrap(), "Mufasa 00000001");
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+    // Later requests authorize up front, counting the uses of the nonce.
+    let res = client.get(&url).send().await.unwrap();
+    assert_eq!(res.text().await.unwrap(), "Mufasa 00000002");
+    let res = client
+        .get(&url)
+        .digest_auth("Aladdin", "open sesame")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.text().await.unwrap(), "Aladdin 00000003");
+    assert_eq!(requests.load(Ordering::SeqCst), 4);
+
+    // Without credentials, the challenge is returned.
+    let res = reqwest::Client::new().get(&url).send().await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let client = reqwest::Client::builder()
+        .digest_auth_for_host("example.com", "Mufasa", "Circle of Life")
+        .build()
+        .unwrap();
+    let res = client.get(&url).send().await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+    let client = reqwest::Client::builder()
+        .digest_auth_for_host("127.0.0.1", "Mufasa", "Circle of Life")
+        .build()
+        .unwrap();
+    let res = client.get(&url).send().await.unwrap();
+    assert_eq!(res.text().await.unwrap(), "Mufasa 00000001");
+}
+
+#[tokio::test]
+async fn answers_rejected_digest_once() {
+    let server = server::http(move |_req| async {
+        http::Response::builder()
+            .status(401)
+            .header("www-authenticate", "Digest realm=\"api\", nonce=\"n\"")
+            .body(Default::default())
+            .unwrap()
+    });
+
+    let res = reqwest::Client::new()
+        .get(format!("http://{}/", server.addr()))
+        .digest_auth("Mufasa", "wrong")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn keeps_digest_credentials_to_their_origin() {
+    fn challenge_or_answer(req: &http::Request<hyper::Body>) -> http::Response<hyper::Body> {
+        match req.headers().get("authorization") {
+            Some(auth) => {
+                let auth = auth.to_str().unwrap();
+                http::Response::new(param(auth, "username").to_owned().into())
+            }
+            None => http::Response::builder()
+                .status(401)
+                .header("www-authenticate", "Digest realm=\"api\", nonce=\"n\"")
+                .body(Default::default())
+                .unwrap(),
+        }
+    }
+
+    let other = server::http(move |req| {
+        let res = challenge_or_answer(&req);
+        async move { res }
+    });
+    let other_url = format!("http://{}/private", other.addr());
+    let server = server::http(move |req| {
+        let res = match req.uri().path() {
+            "/elsewhere" => http::Response::builder()
+                .status(302)
+                .header("location", other_url.as_str())
+                .body(Default::default())
+                .unwrap(),
+            "/here" => http::Response::builder()
+                .status(302)
+                .header("location", "/private")
+                .body(Default::default())
+                .unwrap(),
+            _ => challenge_or_answer(&req),
+        };
+        async move { res }
+    });
+
+    let client = reqwest::Client::builder()
+        .digest_auth("Mufasa", "Circle of Life")
+        .build()
+        .unwrap();
+    let res = client
+        .get(format!("http://{}/here", server.addr()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.text().await.unwrap(), "Mufasa");
+
+    // Another origin, here another port, gets no answer to its challenge.
+    let res = client
+        .get(format!("http://{}/elsewhere", server.addr()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.url().port(), Some(other.addr().port()));
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
+
+#[cfg(feature = "__boring")]
+#[tokio::test]
+async fn answers_proxy_digest_challenges() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    async fn read_head(conn: &mut TcpStream) -> String {
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(conn.read_u8().await.unwrap());
+        }
+        String::from_utf8(head).unwrap()
+    }
+
+    let server = server::https(|_| async { http::Response::new("tunneled".into()) });
+    let target = format!("localhost:{}", server.addr().port());
+
+    let port = server.addr().port();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    let connects = Arc::new(AtomicUsize::new(0));
+    let counted = connects.clone();
+    let nonce_counts = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let counts = nonce_counts.clone();
+    let expected_target = target.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut client, _) = listener.accept().await.unwrap();
+            let head = read_head(&mut client).await;
+            assert!(head.starts_with(&format!("CONNECT {} HTTP/1.1\r\n", expected_target)));
+            counted.fetch_add(1, Ordering::SeqCst);
+
+            let auth = match head
+                .lines()
+                .find_map(|line| line.strip_prefix("Proxy-Authorization: "))
+            {
+                Some(auth) => auth.to_owned(),
+                None => {
+                    client
+                        .write_all(
+                            b"HTTP/1.1 407 Proxy Authentication Required\r\n\
+                              Proxy-Authenticate: Digest realm=\"proxy\", nonce=\"p1\", \
+                              qop=\"auth\", algorithm=MD5-sess\r\n\
+                              Content-Length: 0\r\n\
+                              Connection: close\r\n\r\n",
+                        )
+                        .await
+                        .unwrap();
+                    continue;
+                }
+            };
+            assert_eq!(param(&auth, "username"), "Aladdin");
+            assert_eq!(param(&auth, "uri"), expected_target);
+            assert_eq!(param(&auth, "algorithm"), "MD5-sess");
+            counts.lock().unwrap().push(param(&auth, "nc").to_owned());
+
+            tokio::spawn(async move {
+                let mut target = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+                client.write_all(b"HTTP/1.1 200 OK\r\n\r\n").await.unwrap();
+                let _ = tokio::io::copy_bidirectional(&mut client, &mut target).await;
+            });
+        }
+    });
+
+    let proxy = reqwest::Proxy::https(format!("http://{}", proxy_addr))
+        .unwrap()
+        .digest_auth("Aladdin", "open sesame");
+    for _ in 0..2 {
+        // Each client opens its own tunnel, the second one authorized up
+        // front.
+        let res = reqwest::Client::builder()
+            .proxy(proxy.clone())
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap()
+            .get(format!("https://{}/", target))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.text().await.unwrap(), "tunneled");
+    }
+    assert_eq!(connects.load(Ordering::SeqCst), 3);
+    assert_eq!(*nonce_counts.lock().unwrap(), ["00000001", "00000002"]);
+}
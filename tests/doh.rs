@@ -0,0 +1,195 @@
+#![cfg(not(target_arch = "wasm32"))]
+mod support;
+use chromimic as reqwest;
+use support::dns::answer;
+use support::server;
+
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use reqwest::dns::{DohFormat, DohResolver, Name, Resolve};
+
+fn doh_server(queries: Arc<AtomicUsize>) -> server::Server {
+    server::http(move |req| {
+        queries.fetch_add(1, Ordering::SeqCst);
+        async move {
+            assert_eq!(req.headers()["accept"], "application/dns-message");
+            let query = if req.method() == "GET" {
+                let (_, dns) = url::form_urlencoded::parse(req.uri().query().unwrap().as_bytes())
+                    .find(|(key, _)| key == "dns")
+                    .unwrap();
+                URL_SAFE_NO_PAD.decode(dns.as_bytes()).unwrap()
+            } else {
+                assert_eq!(req.headers()["content-type"], "application/dns-message");
+                hyper::body::to_bytes(req.into_body())
+                    .await
+                    .unwrap()
+                    .to_vec()
+            };
+            assert_eq!(query.len() % 128, 0, "query is padded");
+
+            http::Response::builder()
+                .header("content-type", "application/dns-message")
+                .body(answer(&query).into())
+                .unwrap()
+        }
+    })
+}
+
+async fn addrs(resolver: &DohResolver, name: &str) -> Result<Vec<std::net::SocketAddr>, String> {
+    match resolver.resolve(Name::from_str(name).unwrap()).await {
+        Ok(addrs) => Ok(addrs.collect()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tokio::test]
+async fn doh_wire_formats() {
+    let _ = env_logger::try_init();
+
+    let queries = Arc::new(AtomicUsize::new(0));
+    let server = doh_server(queries.clone());
+
+    for format in [DohFormat::Get, DohFormat::Post] {
+        let resolver = DohResolver::builder()
+            .upstream(format!("http://{}/dns-query", server.addr()), format)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            addrs(&resolver, "doh.test").await,
+            Ok(vec![([127, 0, 0, 1], 0).into()])
+        );
+        assert!(addrs(&resolver, "missing.test").await.is_err());
+
+        let records = resolver
+            .resolve_https(Name::from_str("doh.test").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].port(), Some(8443));
+        let records = resolver
+            .resolve_https(Name::from_str("missing.test").unwrap())
+            .await
+            .unwrap();
+        assert!(records.is_empty());
+    }
+    assert_eq!(queries.load(Ordering::SeqCst), 12);
+}
+
+// The racing A and AAAA queries can leave a connection to the failing server
+// that only closes once the runtime gets to it, while its `Server` is dropped.
+#[tokio::test(flavor = "multi_thread")]
+async fn doh_fails_over() {
+    let _ = env_logger::try_init();
+
+    let failures = Arc::new(AtomicUsize::new(0));
+    let failing = {
+        let failures = failures.clone();
+        server::http(move |_req| {
+            failures.fetch_add(1, Ordering::SeqCst);
+            async {
+                http::Response::builder()
+                    .status(500)
+                    .body(Default::default())
+                    .unwrap()
+            }
+        })
+    };
+    let queries = Arc::new(AtomicUsize::new(0));
+    let working = doh_server(queries.clone());
+
+    let resolver = DohResolver::builder()
+        .upstream(
+            format!("http://{}/dns-query", failing.addr()),
+            DohFormat::Post,
+        )
+        .upstream(
+            format!("http://{}/dns-query", working.addr()),
+            DohFormat::Post,
+        )
+        .build()
+        .unwrap();
+
+    assert_eq!(
+        addrs(&resolver, "doh.test").await,
+        Ok(vec![([127, 0, 0, 1], 0).into()])
+    );
+    // The A and AAAA queries both failed over.
+    assert_eq!(failures.load(Ordering::SeqCst), 2);
+
+    // The upstream that answered is now tried first.
+    addrs(&resolver, "doh.test").await.unwrap();
+    assert_eq!(failures.load(Ordering::SeqCst), 2);
+    assert_eq!(queries.load(Ordering::SeqCst), 4);
+
+    // Without any upstream answering, the last error is returned.
+    let resolver = DohResolver::builder()
+        .upstream(
+            format!("http://{}/dns-query", failing.addr()),
+            DohFormat::Get,
+        )
+        .build()
+        .unwrap();
+    let err = addrs(&resolver, "doh.test").await.unwrap_err();
+    assert!(err.contains("500"), "{}", err);
+
+    DohResolver::builder().build().unwrap_err();
+    DohResolver::builder()
+        .upstream("not a url", DohFormat::Get)
+        .build()
+        .unwrap_err();
+}
+
+#[tokio::test]
+async fn doh_resolves_for_client() {
+    let server = server::http(move |req| async move {
+        assert_eq!(req.uri(), "/");
+        http::Response::default()
+    });
+    let doh = doh_server(Arc::new(AtomicUsize::new(0)));
+
+    let resolver = DohResolver::builder()
+        .client(reqwest::Client::builder().no_proxy().build().unwrap())
+        .upstream(format!("http://{}/dns-query", doh.addr()), DohFormat::Get)
+        .build()
+        .unwrap();
+    let client = reqwest::Client::builder()
+        .no_proxy()
+        .dns_resolver(Arc::new(resolver))
+        .build()
+        .unwrap();
+
+    let url = format!("http://doh.test:{}/", server.addr().port());
+    let res = client.get(url).send().await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
+#[cfg(feature = "json")]
+#[tokio::test]
+async fn doh_json() {
+    let server = server::http(move |req| async move {
+        assert_eq!(req.headers()["accept"], "application/dns-json");
+        let query = req.uri().query().unwrap().to_owned();
+        let body = match &*query {
+            "name=doh.test&type=1" => {
+                r#"{"Status":0,"Answer":[{"name":"doh.test.","type":1,"TTL":60,"data":"127.0.0.1"}]}"#
+            }
+            "name=doh.test&type=28" => r#"{"Status":0}"#,
+            _ => panic!("unexpected query: {}", query),
+        };
+        http::Response::new(body.into())
+    });
+
+    let resolver = DohResolver::builder()
+        .upstream(format!("http://{}/resolve", server.addr()), DohFormat::Json)
+        .build()
+        .unwrap();
+    assert_eq!(
+        addrs(&resolver, "doh.test").await,
+        Ok(vec![([127, 0, 0, 1], 0).into()])
+    );
+}
@@ -65,6 +65,38 @@ async fn http_proxy_basic_auth() {
     assert_eq!(res.status(), reqwest::StatusCode::OK);
 }
 
+#[tokio::test]
+async fn http_proxy_headers() {
+    let url = "http://hyper.rs/prox";
+    let server = server::http(move |req| {
+        assert_eq!(req.uri(), url);
+        assert_eq!(req.headers()["x-session-id"], "8f2c");
+        // The request's own header is kept.
+        assert_eq!(req.headers()["x-country"], "fr");
+
+        async { http::Response::default() }
+    });
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert("x-session-id", "8f2c".parse().unwrap());
+    headers.insert("x-country", "us".parse().unwrap());
+    let proxy = reqwest::Proxy::http(format!("http://{}", server.addr()))
+        .unwrap()
+        .headers(headers);
+
+    let res = reqwest::Client::builder()
+        .proxy(proxy)
+        .build()
+        .unwrap()
+        .get(url)
+        .header("x-country", "fr")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
 #[tokio::test]
 async fn http_proxy_basic_auth_parsed() {
     let url = "http://hyper.rs/prox";
@@ -222,3 +254,634 @@ async fn http_over_http() {
     assert_eq!(res.url().as_str(), url);
     assert_eq!(res.status(), reqwest::StatusCode::OK);
 }
+
+#[tokio::test]
+async fn per_request_proxy() {
+    let url = "http://hyper.rs/prox";
+    let client_proxy = server::http(move |req| {
+        assert_eq!(req.uri(), url);
+        assert!(req.headers().get("proxy-authorization").is_none());
+        async {
+            http::Response::builder()
+                .status(201)
+                .body(Default::default())
+                .unwrap()
+        }
+    });
+    let request_proxy = server::http(move |req| {
+        assert_eq!(req.uri(), url);
+        let status = match req.headers().get("proxy-authorization") {
+            Some(auth) if auth == "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==" => 202,
+            _ => 407,
+        };
+        async move {
+            http::Response::builder()
+                .status(status)
+                .body(Default::default())
+                .unwrap()
+        }
+    });
+    let direct = server::http(move |req| {
+        assert_eq!(req.uri(), "/direct");
+        async { http::Response::default() }
+    });
+
+    let client = reqwest::Client::builder()
+        .proxy(reqwest::Proxy::http(format!("http://{}", client_proxy.addr())).unwrap())
+        .build()
+        .unwrap();
+
+    let res = client.get(url).send().await.unwrap();
+    assert_eq!(res.status(), 201);
+
+    let proxy = format!("http://Aladdin:open sesame@{}", request_proxy.addr());
+    let res = client
+        .get(url)
+        .proxy(reqwest::Proxy::http(&proxy).unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 202);
+
+    // Another identity through the same proxy.
+    let proxy = format!("http://Aladdin:wrong@{}", request_proxy.addr());
+    let res = client
+        .get(url)
+        .proxy(reqwest::Proxy::http(&proxy).unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 407);
+
+    let res = client
+        .get(format!("http://{}/direct", direct.addr()))
+        .no_proxy()
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    // The client's proxy is still used by default.
+    let res = client.get(url).send().await.unwrap();
+    assert_eq!(res.status(), 201);
+}
+
+#[tokio::test]
+async fn proxy_pool_ejects_failing_proxies() {
+    let url = "http://hyper.rs/prox";
+    let working = server::http(move |req| {
+        assert_eq!(req.uri(), url);
+        async { http::Response::default() }
+    });
+    let refusing = server::http(move |_req| async {
+        http::Response::builder()
+            .status(407)
+            .body(Default::default())
+            .unwrap()
+    });
+    // Nothing listens there anymore.
+    let dead = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let pool = reqwest::ProxyPool::builder()
+        .proxy(reqwest::Proxy::http(format!("http://{}", dead)).unwrap())
+        .proxy(reqwest::Proxy::http(format!("http://{}", refusing.addr())).unwrap())
+        .proxy(reqwest::Proxy::http(format!("http://{}", working.addr())).unwrap())
+        .max_failures(1)
+        .build()
+        .unwrap();
+    let client = reqwest::Client::builder()
+        .proxy_pool(pool.clone())
+        .build()
+        .unwrap();
+
+    let err = client.get(url).send().await.unwrap_err();
+    assert!(err.is_connect());
+    let res = client.get(url).send().await.unwrap();
+    assert_eq!(res.status(), 407);
+    for _ in 0..3 {
+        let res = client.get(url).send().await.unwrap();
+        assert_eq!(res.status(), 200);
+    }
+
+    let stats = pool.stats();
+    assert!(stats[0].is_ejected());
+    assert!(stats[1].is_ejected());
+    assert!(!stats[2].is_ejected());
+    assert_eq!(
+        stats
+            .iter()
+            .map(|s| (s.requests(), s.successes(), s.failures(), s.ejections()))
+            .collect::<Vec<_>>(),
+        [(1, 0, 1, 1), (1, 0, 1, 1), (3, 3, 0, 0)]
+    );
+
+    // A refused CONNECT tunnel is a failure too.
+    let pool = reqwest::ProxyPool::builder()
+        .proxy(reqwest::Proxy::all(format!("http://{}", refusing.addr())).unwrap())
+        .build()
+        .unwrap();
+    let client = reqwest::Client::builder()
+        .proxy_pool(pool.clone())
+        .build()
+        .unwrap();
+    let err = client.get("https://hyper.rs/").send().await.unwrap_err();
+    assert!(err.is_connect());
+    assert_eq!(pool.stats()[0].failures(), 1);
+}
+
+#[cfg(feature = "pac")]
+#[tokio::test]
+async fn pac_script_fails_over() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let url = "http://hyper.rs/prox";
+    let proxy = server::http(move |req| {
+        assert_eq!(req.uri(), url);
+        async { http::Response::default() }
+    });
+    // Nothing listens there anymore.
+    let dead = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let script = format!(
+        r#"function FindProxyForURL(url, host) {{
+            if (isInNet(host, "127.0.0.0", "255.0.0.0")) return "DIRECT";
+            return "PROXY {}; PROXY {}";
+        }}"#,
+        dead,
+        proxy.addr()
+    );
+    let fetches = Arc::new(AtomicUsize::new(0));
+    let fetched = fetches.clone();
+    let pac_server = server::http(move |req| {
+        let body = match req.uri().path() {
+            "/proxy.pac" => {
+                // The script is fetched by the client it's for.
+                assert_eq!(req.headers()["user-agent"], "pac-test");
+                fetched.fetch_add(1, Ordering::SeqCst);
+                script.clone()
+            }
+            _ => "direct".to_owned(),
+        };
+        async move { http::Response::new(body.into()) }
+    });
+
+    let pac = reqwest::Proxy::pac(&format!("http://{}/proxy.pac", pac_server.addr())).unwrap();
+    let client = reqwest::Client::builder()
+        .user_agent("pac-test")
+        .resolve("intranet.test", pac_server.addr())
+        .proxy(pac)
+        .build()
+        .unwrap();
+
+    for _ in 0..2 {
+        let res = client.get(url).send().await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+    let res = client
+        .get(format!("http://{}/", pac_server.addr()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.text().await.unwrap(), "direct");
+    // The script resolves hosts with the client's resolver too.
+    let res = client
+        .get(format!("http://intranet.test:{}/", pac_server.addr().port()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.text().await.unwrap(), "direct");
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+    // A script that doesn't load is refused.
+    assert!(reqwest::Proxy::pac("function FindProxyForURL(url, host) {").is_err());
+}
+
+#[cfg(feature = "socks")]
+#[tokio::test]
+async fn socks4a_proxy() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = server::http(move |req| {
+        assert_eq!(req.uri(), "/socks");
+        async { http::Response::default() }
+    });
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut client, _) = listener.accept().await.unwrap();
+        let mut header = [0; 8];
+        client.read_exact(&mut header).await.unwrap();
+        assert_eq!(header[..2], [4, 1]);
+        assert_eq!(header[4..], [0, 0, 0, 1]);
+        let port = u16::from_be_bytes([header[2], header[3]]);
+
+        // The user ID, then the host, each ended by a NUL.
+        let mut fields = vec![Vec::new()];
+        while fields.len() < 3 {
+            match client.read_u8().await.unwrap() {
+                0 => fields.push(Vec::new()),
+                b => fields.last_mut().unwrap().push(b),
+            }
+        }
+        assert_eq!(fields[0], b"Aladdin");
+        assert_eq!(fields[1], b"localhost");
+
+        let mut target = tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap();
+        client.write_all(&[0, 0x5a, 0, 0, 0, 0, 0, 0]).await.unwrap();
+        let _ = tokio::io::copy_bidirectional(&mut client, &mut target).await;
+    });
+
+    let proxy = format!("socks4a://Aladdin@{}", proxy_addr);
+    let res = reqwest::Client::builder()
+        .proxy(reqwest::Proxy::all(&proxy).unwrap())
+        .build()
+        .unwrap()
+        .get(format!("http://localhost:{}/socks", server.addr().port()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+}
+
+// Names sent through a `socks5://` proxy are resolved by the client's
+// resolver, which sends the proxy the address.
+#[cfg(feature = "socks")]
+#[tokio::test]
+async fn socks5_resolves_with_the_client_resolver() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = server::http(move |req| {
+        assert_eq!(req.uri(), "/socks");
+        async { http::Response::default() }
+    });
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut client, _) = listener.accept().await.unwrap();
+        let mut methods = [0; 3];
+        client.read_exact(&mut methods).await.unwrap();
+        assert_eq!(methods, [5, 1, 0]);
+        client.write_all(&[5, 0]).await.unwrap();
+
+        // VER, CMD, RSV, then an IPv4 address and port: no name.
+        let mut request = [0; 10];
+        client.read_exact(&mut request).await.unwrap();
+        assert_eq!(request[..8], [5, 1, 0, 1, 127, 0, 0, 1]);
+        let port = u16::from_be_bytes([request[8], request[9]]);
+
+        let mut target = tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap();
+        client
+            .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+            .await
+            .unwrap();
+        let _ = tokio::io::copy_bidirectional(&mut client, &mut target).await;
+    });
+
+    let proxy = format!("socks5://{}", proxy_addr);
+    let res = reqwest::Client::builder()
+        .proxy(reqwest::Proxy::all(&proxy).unwrap())
+        .resolve("socks.test", ([127, 0, 0, 1], 0).into())
+        .build()
+        .unwrap()
+        .get(format!("http://socks.test:{}/socks", server.addr().port()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+}
+
+// HTTP/3 goes through the UDP relay of a SOCKS5 proxy.
+#[cfg(all(feature = "http3", feature = "socks", feature = "__boring"))]
+#[tokio::test]
+async fn http3_socks5_udp_relay() {
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UdpSocket;
+
+    let origin = server::h3("relayed").await;
+    let relay = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let relay_addr = relay.local_addr().unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut control, _) = listener.accept().await.unwrap();
+        let mut methods = [0; 3];
+        control.read_exact(&mut methods).await.unwrap();
+        assert_eq!(methods, [5, 1, 0]);
+        control.write_all(&[5, 0]).await.unwrap();
+        let mut request = [0; 10];
+        control.read_exact(&mut request).await.unwrap();
+        assert_eq!(request[..2], [5, 3]);
+        let mut reply = vec![5, 0, 0, 1, 127, 0, 0, 1];
+        reply.extend_from_slice(&relay_addr.port().to_be_bytes());
+        control.write_all(&reply).await.unwrap();
+
+        // RSV, FRAG, then the IPv4 address and port of the other end.
+        let header = |addr: SocketAddr| match addr {
+            SocketAddr::V4(addr) => {
+                let mut header = vec![0, 0, 0, 1];
+                header.extend_from_slice(&addr.ip().octets());
+                header.extend_from_slice(&addr.port().to_be_bytes());
+                header
+            }
+            SocketAddr::V6(_) => unreachable!(),
+        };
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut client = None;
+        let (mut up, mut down) = (vec![0; 65536], vec![0; 65536]);
+        loop {
+            tokio::select! {
+                res = relay.recv_from(&mut up) => {
+                    let (len, from) = res.unwrap();
+                    client = Some(from);
+                    assert_eq!(up[..10], header(origin)[..]);
+                    upstream.send_to(&up[10..len], origin).await.unwrap();
+                }
+                res = upstream.recv_from(&mut down) => {
+                    let (len, from) = res.unwrap();
+                    let mut datagram = header(from);
+                    datagram.extend_from_slice(&down[..len]);
+                    relay.send_to(&datagram, client.unwrap()).await.unwrap();
+                }
+                // The association ends with the control connection.
+                _ = control.read_u8() => break,
+            }
+        }
+    });
+
+    let proxy = format!("socks5://{}", proxy_addr);
+    let res = reqwest::Client::builder()
+        .use_rustls_tls()
+        .danger_accept_invalid_certs(true)
+        .http3_prior_knowledge()
+        .proxy(reqwest::Proxy::all(&proxy).unwrap())
+        .build()
+        .unwrap()
+        .get(format!("https://127.0.0.1:{}/relayed", origin.port()))
+        .version(http::Version::HTTP_3)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.version(), http::Version::HTTP_3);
+    assert_eq!(res.text().await.unwrap(), "relayed");
+}
+
+// HTTP/3 requests fail over through the routes of a PAC script too, and an
+// HTTP proxy, which can't relay QUIC, isn't counted as failed for it.
+#[cfg(all(feature = "http3", feature = "pac", feature = "__boring"))]
+#[tokio::test]
+async fn http3_pac_fails_over() {
+    let origin = server::h3("direct").await;
+    let pac = reqwest::Proxy::pac(
+        "function FindProxyForURL(url, host) { return 'PROXY 127.0.0.1:1; DIRECT'; }",
+    )
+    .unwrap();
+    let client = reqwest::Client::builder()
+        .use_rustls_tls()
+        .danger_accept_invalid_certs(true)
+        .http3_prior_knowledge()
+        .proxy(pac)
+        .build()
+        .unwrap();
+    let res = client
+        .get(format!("https://127.0.0.1:{}/", origin.port()))
+        .version(http::Version::HTTP_3)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.version(), http::Version::HTTP_3);
+    assert_eq!(res.text().await.unwrap(), "direct");
+}
+
+#[cfg(feature = "__impersonate")]
+#[tokio::test]
+async fn tunnel_follows_the_impersonated_browser() {
+    use reqwest::impersonate::Impersonate;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = server::https(|_| async { http::Response::new("tunneled".into()) });
+    let port = server.addr().port();
+    let target = format!("localhost:{}", port);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    let expected_target = target.clone();
+    tokio::spawn(async move {
+        let (mut client, _) = listener.accept().await.unwrap();
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(client.read_u8().await.unwrap());
+        }
+        let head = String::from_utf8(head).unwrap();
+        let mut lines = head.lines();
+        assert_eq!(
+            lines.next(),
+            Some(&*format!("CONNECT {} HTTP/1.1", expected_target))
+        );
+        let names = lines
+            .filter_map(|line| line.split(": ").next())
+            .filter(|name| !name.is_empty())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["Host", "Proxy-Connection", "User-Agent", "X-Session-Id"]
+        );
+
+        let mut target = tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap();
+        client
+            .write_all(
+                b"HTTP/1.1 200 Connection established\r\n\
+                  X-Exit-Ip: 203.0.113.7\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let _ = tokio::io::copy_bidirectional(&mut client, &mut target).await;
+    });
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert("x-session-id", "8f2c".parse().unwrap());
+    let proxy = reqwest::Proxy::https(format!("http://{}", proxy_addr))
+        .unwrap()
+        .headers(headers);
+    let res = reqwest::Client::builder()
+        .impersonate(Impersonate::Chrome120)
+        .proxy(proxy)
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap()
+        .get(format!("https://{}/", target))
+        .send()
+        .await
+        .unwrap();
+
+    let info = res
+        .extensions()
+        .get::<reqwest::ConnectionInfo>()
+        .expect("connection info");
+    let proxy_headers = info.proxy_headers().expect("proxy headers");
+    assert_eq!(proxy_headers["x-exit-ip"], "203.0.113.7");
+    assert_eq!(res.text().await.unwrap(), "tunneled");
+}
+
+#[cfg(feature = "__boring")]
+#[tokio::test]
+async fn proxy_pool_only_counts_proxy_failures() {
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = server::https(|_| async { http::Response::new("tunneled".into()) });
+    let port = server.addr().port();
+
+    // Tunnels every CONNECT to the server.
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let tunneling = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut client, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut head = Vec::new();
+                while !head.ends_with(b"\r\n\r\n") {
+                    head.push(client.read_u8().await.unwrap());
+                }
+                let mut target = tokio::net::TcpStream::connect(("127.0.0.1", port))
+                    .await
+                    .unwrap();
+                client
+                    .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                    .await
+                    .unwrap();
+                let _ = tokio::io::copy_bidirectional(&mut client, &mut target).await;
+            });
+        }
+    });
+
+    // Never answers.
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let hanging = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (_client, _) = listener.accept().await.unwrap();
+        std::future::pending::<()>().await;
+    });
+
+    let client = |proxy: std::net::SocketAddr| {
+        let pool = reqwest::ProxyPool::builder()
+            .proxy(reqwest::Proxy::all(format!("http://{}", proxy)).unwrap())
+            .max_failures(1)
+            .build()
+            .unwrap();
+        let client = reqwest::Client::builder()
+            .proxy_pool(pool.clone())
+            .danger_accept_invalid_certs(true)
+            .pin_certificate("localhost", [[0; 32]])
+            .timeout(Duration::from_millis(200))
+            .build()
+            .unwrap();
+        (pool, client)
+    };
+    let url = format!("https://localhost:{}/", port);
+
+    // The origin's certificate doesn't match its pin, the proxy did its job.
+    let (pool, tunneled) = client(tunneling);
+    let err = tunneled.get(&url).send().await.unwrap_err();
+    assert!(err.is_certificate_pin(), "{:?}", err);
+    assert_eq!(pool.stats()[0].failures(), 0);
+    assert!(!pool.stats()[0].is_ejected());
+
+    let (pool, hung) = client(hanging);
+    let err = hung.get(&url).send().await.unwrap_err();
+    assert!(err.is_timeout(), "{:?}", err);
+    assert_eq!(pool.stats()[0].failures(), 1);
+    assert!(pool.stats()[0].is_ejected());
+}
+
+// Proxies on the same address but with other credentials or headers may
+// exit elsewhere, so they don't resume each other's TLS sessions.
+#[cfg(feature = "__boring")]
+#[tokio::test]
+async fn tls_sessions_kept_per_proxy_identity() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = server::https(|req| async move {
+        let tls = req
+            .extensions()
+            .get::<support::server::TlsConnection>()
+            .expect("tls connection");
+        http::Response::new(if tls.session_reused { "resumed" } else { "full" }.into())
+    });
+    let port = server.addr().port();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut client, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut head = Vec::new();
+                while !head.ends_with(b"\r\n\r\n") {
+                    head.push(client.read_u8().await.unwrap());
+                }
+                let mut target = tokio::net::TcpStream::connect(("127.0.0.1", port))
+                    .await
+                    .unwrap();
+                client
+                    .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                    .await
+                    .unwrap();
+                let _ = tokio::io::copy_bidirectional(&mut client, &mut target).await;
+            });
+        }
+    });
+
+    let cache = reqwest::tls::TlsSessionCache::new();
+    let client = |proxy: reqwest::Proxy| {
+        reqwest::Client::builder()
+            .proxy(proxy)
+            .danger_accept_invalid_certs(true)
+            .pool_max_idle_per_host(0)
+            .tls_session_cache(cache.clone())
+            .build()
+            .unwrap()
+    };
+    let proxy = || reqwest::Proxy::https(format!("http://{}", proxy_addr)).unwrap();
+    let session = |id: &'static str| {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-session-id", id.parse().unwrap());
+        proxy().headers(headers)
+    };
+    let url = format!("https://localhost:{}/", port);
+
+    async fn handshake(client: &reqwest::Client, url: &str) -> String {
+        client.get(url).send().await.unwrap().text().await.unwrap()
+    }
+
+    let alice = client(proxy().basic_auth("alice", "secret"));
+    let bob = client(proxy().basic_auth("bob", "secret"));
+    assert_eq!(handshake(&alice, &url).await, "full");
+    assert_eq!(handshake(&alice, &url).await, "resumed");
+    assert_eq!(handshake(&bob, &url).await, "full");
+    assert_eq!(handshake(&bob, &url).await, "resumed");
+
+    let first = client(session("1"));
+    let second = client(session("2"));
+    assert_eq!(handshake(&first, &url).await, "full");
+    assert_eq!(handshake(&first, &url).await, "resumed");
+    assert_eq!(handshake(&second, &url).await, "full");
+
+    // The credentials aren't exported with the sessions.
+    let export = cache.export();
+    assert!(!export.windows(7).any(|w| w == b"YWxpY2U"));
+}
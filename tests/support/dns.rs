@@ -0,0 +1,51 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+
+/// Answers a DNS query: `doh.test` has an A record for 127.0.0.1 and an
+/// HTTPS record for port 8443, other names don't exist.
+pub fn answer(query: &[u8]) -> Vec<u8> {
+    // header, then the question up to its type
+    let name_end = 12 + query[12..].iter().position(|&b| b == 0).unwrap();
+    let name = &query[12..name_end];
+    let rtype = u16::from_be_bytes([query[name_end + 1], query[name_end + 2]]);
+
+    let mut msg = query[..name_end + 5].to_vec();
+    msg[2] = 0x81;
+    msg[3] = 0x80;
+    msg[10] = 0;
+    msg[11] = 0; // no OPT record back
+
+    let rdata: &[u8] = match (name, rtype) {
+        (b"\x03doh\x04test", 1) => &[127, 0, 0, 1],
+        (b"\x03doh\x04test", 65) => &[0, 1, 0, 0, 3, 0, 2, 0x20, 0xfb],
+        (b"\x03doh\x04test", _) => return msg,
+        _ => {
+            msg[3] = 0x83; // NXDOMAIN
+            return msg;
+        }
+    };
+    msg[7] = 1;
+    msg.extend_from_slice(&[0xc0, 12]);
+    msg.extend_from_slice(&rtype.to_be_bytes());
+    msg.extend_from_slice(&[0, 1, 0, 0, 0, 60]);
+    msg.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    msg.extend_from_slice(rdata);
+    msg
+}
+
+/// Starts a nameserver answering over UDP with `answer`, for the rest of
+/// the test.
+pub fn udp() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    thread::Builder::new()
+        .name("test-dns-server".into())
+        .spawn(move || {
+            let mut buf = [0; 512];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf) {
+                let _ = socket.send_to(&answer(&buf[..len]), peer);
+            }
+        })
+        .unwrap();
+    addr
+}
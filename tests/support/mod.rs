@@ -1,3 +1,8 @@
+#[cfg(feature = "__boring")]
+#[allow(unused)]
+pub mod cert;
+#[allow(unused)]
+pub mod dns;
 pub mod server;
 
 // TODO: remove once done converting to new support server?
@@ -37,6 +37,16 @@ impl Drop for Server {
 }
 
 pub fn http<F, Fut>(func: F) -> Server
+where
+    F: Fn(http::Request<hyper::Body>) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = http::Response<hyper::Body>> + Send + 'static,
+{
+    http_on(([127, 0, 0, 1], 0).into(), func)
+}
+
+/// Like [`http`], listening on `addr`.
+#[allow(unused)]
+pub fn http_on<F, Fut>(addr: net::SocketAddr, func: F) -> Server
 where
     F: Fn(http::Request<hyper::Body>) -> Fut + Clone + Send + 'static,
     Fut: Future<Output = http::Response<hyper::Body>> + Send + 'static,
@@ -48,7 +58,7 @@ where
             .build()
             .expect("new rt");
         let srv = rt.block_on(async move {
-            hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(hyper::service::make_service_fn(
+            hyper::Server::bind(&addr).serve(hyper::service::make_service_fn(
                 move |_| {
                     let func = func.clone();
                     async move {
@@ -89,3 +99,295 @@ where
     .join()
     .unwrap()
 }
+
+/// Details of the TLS connection a request to an [`https`] server came in on.
+#[cfg(feature = "__boring")]
+#[derive(Clone, Debug)]
+#[allow(unused)]
+pub struct TlsConnection {
+    pub session_reused: bool,
+    pub alpn: Option<Vec<u8>>,
+    /// DER of the certificate the client presented, if any.
+    pub client_certificate: Option<Vec<u8>>,
+    pub ech_accepted: bool,
+    /// Whether the request came in early data the server accepted.
+    pub early_data_accepted: bool,
+}
+
+/// Like [`http`], but behind TLS with a self-signed certificate for
+/// `localhost`, asking clients for an optional certificate. Requests carry
+/// a [`TlsConnection`] extension.
+#[cfg(feature = "__boring")]
+#[allow(unused)]
+pub fn https<F, Fut>(func: F) -> Server
+where
+    F: Fn(http::Request<hyper::Body>) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = http::Response<hyper::Body>> + Send + 'static,
+{
+    https_with_ech(&[], func)
+}
+
+/// Like [`https`], but decrypting Encrypted Client Hellos with `keys`. The
+/// configs of keys paired with `true` are sent as retry configs to clients
+/// whose ECH is rejected.
+#[cfg(feature = "__boring")]
+#[allow(unused)]
+pub fn https_with_ech<F, Fut>(keys: &[(&EchKey, bool)], func: F) -> Server
+where
+    F: Fn(http::Request<hyper::Body>) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = http::Response<hyper::Body>> + Send + 'static,
+{
+    let early_data = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
+    https_server(keys, early_data, func)
+}
+
+/// Like [`https`], but rejecting early data on connections accepted while
+/// `early_data` is false. Their session can still be resumed.
+#[cfg(feature = "__boring")]
+#[allow(unused)]
+pub fn https_with_early_data<F, Fut>(
+    early_data: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    func: F,
+) -> Server
+where
+    F: Fn(http::Request<hyper::Body>) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = http::Response<hyper::Body>> + Send + 'static,
+{
+    https_server(&[], early_data, func)
+}
+
+#[cfg(feature = "__boring")]
+fn https_server<F, Fut>(
+    keys: &[(&EchKey, bool)],
+    early_data: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    func: F,
+) -> Server
+where
+    F: Fn(http::Request<hyper::Body>) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = http::Response<hyper::Body>> + Send + 'static,
+{
+    use boring::ssl::{AlpnError, SslAcceptor, SslMethod, SslVerifyMode};
+
+    let (cert, key) = self_signed();
+    let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).expect("acceptor");
+    acceptor.set_certificate(&cert).unwrap();
+    acceptor.set_private_key(&key).unwrap();
+    acceptor.set_verify_callback(SslVerifyMode::PEER, |_, _| true);
+    acceptor.set_session_id_context(b"support-server").unwrap();
+    acceptor.set_alpn_select_callback(|_, client| {
+        boring::ssl::select_next_proto(b"\x02h2\x08http/1.1", client).ok_or(AlpnError::NOACK)
+    });
+    if !keys.is_empty() {
+        unsafe {
+            let ech_keys = boring_sys::SSL_ECH_KEYS_new();
+            for (key, retry) in keys {
+                let added = boring_sys::SSL_ECH_KEYS_add(
+                    ech_keys,
+                    *retry as _,
+                    key.config.as_ptr(),
+                    key.config.len(),
+                    key.key,
+                );
+                assert_eq!(added, 1, "SSL_ECH_KEYS_add");
+            }
+            assert_eq!(
+                boring_sys::SSL_CTX_set1_ech_keys(acceptor.as_ptr(), ech_keys),
+                1
+            );
+            boring_sys::SSL_ECH_KEYS_free(ech_keys);
+        }
+    }
+    let acceptor = std::sync::Arc::new(acceptor.build());
+
+    thread::spawn(move || {
+        let rt = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("new rt");
+        let listener = rt
+            .block_on(tokio::net::TcpListener::bind(("127.0.0.1", 0)))
+            .expect("bind");
+        let addr = listener.local_addr().unwrap();
+        let (shutdown_tx, mut shutdown_rx) = oneshot::channel();
+
+        let srv = async move {
+            loop {
+                let tcp = tokio::select! {
+                    res = listener.accept() => res.expect("accept").0,
+                    _ = &mut shutdown_rx => break,
+                };
+                let acceptor = acceptor.clone();
+                let func = func.clone();
+                let early_data = early_data.clone();
+                tokio::spawn(async move {
+                    // Connections copy the setting when they're created,
+                    // which `accept` does before it first yields. The
+                    // runtime has a single thread, so no other connection
+                    // gets created in between.
+                    let enabled = early_data.load(std::sync::atomic::Ordering::SeqCst);
+                    unsafe {
+                        boring_sys::SSL_CTX_set_early_data_enabled(
+                            foreign_types::ForeignTypeRef::as_ptr(acceptor.context()),
+                            enabled as _,
+                        )
+                    };
+                    let tls = match tokio_boring::accept(&acceptor, tcp).await {
+                        Ok(tls) => tls,
+                        Err(_) => return,
+                    };
+                    let info = TlsConnection {
+                        session_reused: tls.ssl().session_reused(),
+                        alpn: tls.ssl().selected_alpn_protocol().map(<[u8]>::to_vec),
+                        client_certificate: tls
+                            .ssl()
+                            .peer_certificate()
+                            .and_then(|cert| cert.to_der().ok()),
+                        ech_accepted: unsafe {
+                            boring_sys::SSL_ech_accepted(foreign_types::ForeignTypeRef::as_ptr(
+                                tls.ssl(),
+                            )) == 1
+                        },
+                        early_data_accepted: unsafe {
+                            boring_sys::SSL_early_data_accepted(
+                                foreign_types::ForeignTypeRef::as_ptr(tls.ssl()),
+                            ) == 1
+                        },
+                    };
+                    let svc = hyper::service::service_fn(move |mut req| {
+                        req.extensions_mut().insert(info.clone());
+                        let fut = func(req);
+                        async move { Ok::<_, Infallible>(fut.await) }
+                    });
+                    let _ = hyper::server::conn::Http::new()
+                        .serve_connection(tls, svc)
+                        .await;
+                });
+            }
+        };
+
+        let (panic_tx, panic_rx) = std_mpsc::channel();
+        let tname = format!(
+            "test({})-support-server",
+            thread::current().name().unwrap_or("<unknown>")
+        );
+        thread::Builder::new()
+            .name(tname)
+            .spawn(move || {
+                rt.block_on(srv);
+                let _ = panic_tx.send(());
+            })
+            .expect("thread spawn");
+
+        Server {
+            addr,
+            panic_rx,
+            shutdown_tx: Some(shutdown_tx),
+        }
+    })
+    .join()
+    .unwrap()
+}
+
+#[cfg(feature = "__boring")]
+#[allow(unused)]
+pub use super::cert::self_signed;
+
+/// An HPKE key for a server doing Encrypted Client Hello, with the
+/// `ECHConfig` clients encrypt to it with.
+#[cfg(feature = "__boring")]
+pub struct EchKey {
+    pub config: Vec<u8>,
+    key: *mut boring_sys::EVP_HPKE_KEY,
+}
+
+// BoringSSL's HPKE bindings aren't generated; they're in the linked library.
+#[cfg(feature = "__boring")]
+extern "C" {
+    fn EVP_hpke_x25519_hkdf_sha256() -> *const boring_sys::EVP_HPKE_KEM;
+    fn EVP_HPKE_KEY_new() -> *mut boring_sys::EVP_HPKE_KEY;
+    fn EVP_HPKE_KEY_free(key: *mut boring_sys::EVP_HPKE_KEY);
+    fn EVP_HPKE_KEY_generate(
+        key: *mut boring_sys::EVP_HPKE_KEY,
+        kem: *const boring_sys::EVP_HPKE_KEM,
+    ) -> std::os::raw::c_int;
+}
+
+#[cfg(feature = "__boring")]
+#[allow(unused)]
+impl EchKey {
+    /// Generates an X25519 key, and its config with `public_name` as the
+    /// name clients show on-path observers.
+    pub fn generate(config_id: u8, public_name: &str) -> EchKey {
+        let public_name = std::ffi::CString::new(public_name).unwrap();
+        unsafe {
+            let key = EVP_HPKE_KEY_new();
+            assert_eq!(EVP_HPKE_KEY_generate(key, EVP_hpke_x25519_hkdf_sha256()), 1);
+
+            let mut out = std::ptr::null_mut();
+            let mut len = 0;
+            let marshaled = boring_sys::SSL_marshal_ech_config(
+                &mut out,
+                &mut len,
+                config_id,
+                key,
+                public_name.as_ptr(),
+                0,
+            );
+            assert_eq!(marshaled, 1, "SSL_marshal_ech_config");
+            let config = std::slice::from_raw_parts(out, len).to_vec();
+            boring_sys::OPENSSL_free(out as *mut _);
+
+            EchKey { config, key }
+        }
+    }
+
+    /// Returns an `ECHConfigList` holding just this key's config.
+    pub fn config_list(&self) -> Vec<u8> {
+        let mut list = (self.config.len() as u16).to_be_bytes().to_vec();
+        list.extend_from_slice(&self.config);
+        list
+    }
+}
+
+#[cfg(feature = "__boring")]
+impl Drop for EchKey {
+    fn drop(&mut self) {
+        unsafe { EVP_HPKE_KEY_free(self.key) }
+    }
+}
+
+/// Answers the requests of one HTTP/3 connection with `body`.
+#[cfg(all(feature = "http3", feature = "__boring"))]
+#[allow(unused)]
+pub async fn h3(body: &'static str) -> std::net::SocketAddr {
+    let (cert, key) = self_signed();
+    let mut tls = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![rustls::Certificate(cert.to_der().unwrap())],
+            rustls::PrivateKey(key.private_key_to_der().unwrap()),
+        )
+        .unwrap();
+    tls.alpn_protocols = vec![b"h3".to_vec()];
+    let config = quinn::ServerConfig::with_crypto(std::sync::Arc::new(tls));
+    let endpoint = quinn::Endpoint::server(config, ([127, 0, 0, 1], 0).into()).unwrap();
+    let addr = endpoint.local_addr().unwrap();
+    tokio::spawn(async move {
+        let conn = endpoint.accept().await.unwrap().await.unwrap();
+        let mut conn = h3::server::Connection::<_, bytes::Bytes>::new(
+            h3_quinn::Connection::new(conn),
+        )
+        .await
+        .unwrap();
+        while let Ok(Some((_, mut stream))) = conn.accept().await {
+            stream.send_response(http::Response::new(())).await.unwrap();
+            stream
+                .send_data(bytes::Bytes::from_static(body.as_bytes()))
+                .await
+                .unwrap();
+            stream.finish().await.unwrap();
+        }
+    });
+    addr
+}